@@ -0,0 +1,582 @@
+use std::{fmt::Display, ops::Range};
+
+use lalrpop_util::ParseError;
+use lsp_types::{
+    CompletionItem, CompletionItemKind, Diagnostic, DiagnosticSeverity, NumberOrString,
+};
+use parser::{
+    AstNode, AstNodeType, AstTypeDefinition, CycleDetector, ErrorWithRange, FileId,
+    FunctionExecutionStrategy, InterpreterValue, MemberAccessType, Optimizer, PARSE_ERROR_CODE,
+    Preprocessor, Resolver, Scope, Severity, StageResult, Stages, Symbol, TypeSymbol,
+    TypeSymbolType, ast_grammar, run_stages_collecting,
+};
+
+use crate::line_index::LineIndex;
+
+/// The source of the diagnostics, as shown by the editor
+const DIAGNOSTIC_SOURCE: &str = "ecs";
+
+/// What is known about a document, after it ran through the stages before the interpreter
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    /// None, if the document could not be parsed or preprocessed
+    pub symbols: Option<Symbols>,
+}
+
+/// Runs the stages up to the resolver on the document. Every syntax error is reported,
+/// the stages after the parser report the errors and warnings of all definitions,
+/// up to the first stage with errors
+pub fn analyze(source: &str) -> Analysis {
+    let index = LineIndex::new(source);
+    let diagnostic =
+        |(range, message): (Range<usize>, String), severity, code: Option<&str>| Diagnostic {
+            range: index.range(range),
+            severity: Some(severity),
+            code: code.map(|code| NumberOrString::String(code.to_owned())),
+            source: Some(DIAGNOSTIC_SOURCE.to_owned()),
+            message,
+            ..Default::default()
+        };
+
+    let ast = match ast_grammar::ProgrammParser::new().parse_recovering(source) {
+        (Some(ast), errors) if errors.is_empty() => ast,
+        (_, errors) => {
+            return Analysis {
+                diagnostics: errors
+                    .iter()
+                    .map(|err| {
+                        diagnostic(
+                            parse_error(err),
+                            DiagnosticSeverity::ERROR,
+                            Some(PARSE_ERROR_CODE),
+                        )
+                    })
+                    .collect(),
+                symbols: None,
+            };
+        }
+    };
+
+    let mut collected = Vec::new();
+    let preprocessed = match Preprocessor::new() {
+        Ok(preprocessor) => run_stages_collecting(
+            vec![Stages::Preprocessor(preprocessor)],
+            StageResult::Parsing(ast.clone()),
+            &mut collected,
+        ),
+        Err(err) => {
+            collected.extend(ErrorWithRange::new(err, 0..0).diagnostics());
+            None
+        }
+    };
+    let scope = match preprocessed {
+        Some(StageResult::Preprocessor(scope, nodes)) => {
+            let stages = vec![
+                Stages::CycleDetector(CycleDetector::new()),
+                Stages::Optimizer(Optimizer::new()),
+                Stages::Resolver(Resolver::new()),
+            ];
+            let state = StageResult::Preprocessor(scope.clone(), nodes);
+            run_stages_collecting(stages, state, &mut collected);
+            Some(scope)
+        }
+        Some(_) => unreachable!("the preprocessor is the only stage"),
+        None => None,
+    };
+
+    // NOTE: diagnostics in imported files or native headers have no range in the document
+    let diagnostics = collected
+        .into_iter()
+        .filter(|collected| collected.file == FileId::MAIN)
+        .map(|collected| {
+            let severity = match collected.severity {
+                Severity::Error => DiagnosticSeverity::ERROR,
+                Severity::Warning => DiagnosticSeverity::WARNING,
+                Severity::Note => DiagnosticSeverity::INFORMATION,
+            };
+            let message = (collected.range, collected.message);
+            diagnostic(message, severity, collected.code)
+        })
+        .collect();
+
+    Analysis {
+        diagnostics,
+        symbols: scope.map(|scope| Symbols { ast, scope }),
+    }
+}
+
+/// The range and message of a parse error, worded like the errors of BeautifyError
+fn parse_error<T: Display, E: Display>(err: &ParseError<usize, T, E>) -> (Range<usize>, String) {
+    let expected = |expected: &Vec<String>| {
+        if expected.is_empty() {
+            String::new()
+        } else {
+            format!(", expected {}", expected.join(", "))
+        }
+    };
+
+    match err {
+        ParseError::InvalidToken { location } => {
+            (*location..*location + 1, "invalid token".to_owned())
+        }
+        ParseError::UnrecognizedEof {
+            location,
+            expected: e,
+        } => (
+            *location..*location,
+            format!("unexpected eof{}", expected(e)),
+        ),
+        ParseError::UnrecognizedToken {
+            token: (start, token, end),
+            expected: e,
+        } => (
+            *start..*end,
+            format!("unexpected token {token}{}", expected(e)),
+        ),
+        ParseError::ExtraToken {
+            token: (start, token, end),
+        } => (*start..*end, format!("unexpected extra token {token}")),
+        ParseError::User { error } => (0..0, error.to_string()),
+    }
+}
+
+/// The definitions of a document, after it was preprocessed
+pub struct Symbols {
+    ast: Vec<AstNode>,
+    scope: Scope,
+}
+
+/// A variable of the function around an offset
+struct Local<'a> {
+    name: &'a Symbol,
+    /// None, if it can't be known without running the function
+    type_of: Option<TypeSymbol>,
+    /// The declaration, params have none
+    range: Option<Range<usize>>,
+}
+
+/// An identifier in the source, i.e. under the cursor
+struct Word<'a> {
+    text: &'a str,
+    access: Access<'a>,
+}
+
+enum Access<'a> {
+    /// A variable or a definition
+    Name,
+    /// A member of the value, the identifiers before it lead to, i.e. [p, pos] of p.pos.x
+    Member(Vec<&'a str>),
+    /// A member of a value, that has no name, i.e. of the result of a call
+    Unknown,
+}
+
+/// What the identifiers before a member lead to
+enum Receiver {
+    Value(TypeSymbolType),
+    /// The defined type itself, its statics are called on it
+    Type(TypeSymbolType),
+}
+
+fn is_identifier(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Where the identifier, that ends at the offset, starts
+fn identifier_start(source: &str, end: usize) -> usize {
+    source[..end]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_identifier(*c))
+        .last()
+        .map_or(end, |(i, _)| i)
+}
+
+/// The identifier the offset is in or directly behind. An empty word at the offset, if there is none
+fn word_at(source: &str, offset: usize) -> Word<'_> {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let start = identifier_start(source, offset);
+    let end = source[offset..]
+        .find(|c| !is_identifier(c))
+        .map_or(source.len(), |i| offset + i);
+
+    let mut receiver = Vec::new();
+    let mut before = &source[..start];
+    while let Some(rest) = before.strip_suffix('.') {
+        let name_start = identifier_start(rest, rest.len());
+        if name_start == rest.len() {
+            return Word {
+                text: &source[start..end],
+                access: Access::Unknown,
+            };
+        }
+        receiver.insert(0, &rest[name_start..]);
+        before = &rest[..name_start];
+    }
+
+    Word {
+        text: &source[start..end],
+        access: if receiver.is_empty() {
+            Access::Name
+        } else {
+            Access::Member(receiver)
+        },
+    }
+}
+
+impl Symbols {
+    /// Where the variable or global definition under the cursor is declared
+    pub fn definition(&self, source: &str, offset: usize) -> Option<Range<usize>> {
+        let word = word_at(source, offset);
+        if !matches!(word.access, Access::Name) || word.text.is_empty() {
+            return None;
+        }
+
+        if let Some(local) = self.local(word.text, offset) {
+            return local.range;
+        }
+        // NOTE: buildins have no location in the source
+        if self.is_buildin(word.text) {
+            return None;
+        }
+        self.scope.resolve_location(&word.text.to_owned())
+    }
+
+    /// The type of the variable, definition or member under the cursor
+    pub fn hover(&self, source: &str, offset: usize) -> Option<String> {
+        let word = word_at(source, offset);
+        let name = word.text.to_owned();
+        if name.is_empty() {
+            return None;
+        }
+
+        match word.access {
+            Access::Name => {
+                if let Some(local) = self.local(&name, offset) {
+                    return local.type_of.map(|type_of| format!("{name}: {type_of}"));
+                }
+                if let Some(type_of) = self.scope.resolve_type(&name) {
+                    return Some(match type_of.type_of {
+                        TypeSymbolType::Function(_) | TypeSymbolType::System(_) => {
+                            type_of.to_string()
+                        }
+                        _ => format!("{name}: {type_of}"),
+                    });
+                }
+                self.scope
+                    .resolve_defined_type(&name)
+                    .map(|type_of| type_of.to_string())
+            }
+            Access::Member(path) => match self.receiver(&path, offset)? {
+                Receiver::Value(TypeSymbolType::Struct(struct_type)) => struct_type
+                    .fields
+                    .iter()
+                    .find(|(field, _)| *field == name)
+                    .map(|(field, type_of)| format!("{field}: {type_of}"))
+                    .or_else(|| {
+                        struct_type
+                            .methods
+                            .iter()
+                            .find(|(method, _)| *method == name)
+                            .map(|(_, method)| method.to_string())
+                    }),
+                Receiver::Value(TypeSymbolType::Component(component)) => component
+                    .fields
+                    .iter()
+                    .find(|(field, _)| *field == name)
+                    .map(|(field, type_of)| format!("{field}: {type_of}")),
+                Receiver::Type(TypeSymbolType::Struct(struct_type)) => struct_type
+                    .statics
+                    .iter()
+                    .find(|(function, _)| *function == name)
+                    .map(|(_, function)| function.to_string()),
+                _ => None,
+            },
+            Access::Unknown => None,
+        }
+    }
+
+    /// Members of the value before a dot, otherwise the variables and definitions visible at the offset
+    pub fn completions(&self, source: &str, offset: usize) -> Vec<CompletionItem> {
+        let item = |label: &str, kind, detail: String| CompletionItem {
+            label: label.to_owned(),
+            kind: Some(kind),
+            detail: Some(detail),
+            ..Default::default()
+        };
+
+        let mut items = Vec::new();
+        match word_at(source, offset).access {
+            Access::Name => {
+                for local in self.locals(offset) {
+                    let detail = local
+                        .type_of
+                        .map_or("variable".to_owned(), |t| t.to_string());
+                    items.push(item(local.name, CompletionItemKind::VARIABLE, detail));
+                }
+                for (name, value) in self.scope.iter_values() {
+                    let kind = match value {
+                        InterpreterValue::Function(_) | InterpreterValue::System(_) => {
+                            CompletionItemKind::FUNCTION
+                        }
+                        _ => CompletionItemKind::VARIABLE,
+                    };
+                    let detail = self
+                        .scope
+                        .resolve_type(name)
+                        .map_or(String::new(), |t| t.to_string());
+                    items.push(item(name, kind, detail));
+                }
+                for (name, type_of) in self.scope.iter_defined_types() {
+                    let (kind, detail) = match &type_of.type_of {
+                        TypeSymbolType::Struct(_) => (CompletionItemKind::STRUCT, "struct"),
+                        TypeSymbolType::Component(_) => (CompletionItemKind::STRUCT, "component"),
+                        _ => (CompletionItemKind::KEYWORD, "type"),
+                    };
+                    items.push(item(name, kind, detail.to_owned()));
+                }
+            }
+            Access::Member(path) => match self.receiver(&path, offset) {
+                Some(Receiver::Value(TypeSymbolType::Struct(struct_type))) => {
+                    for (field, type_of) in &struct_type.fields {
+                        items.push(item(field, CompletionItemKind::FIELD, type_of.to_string()));
+                    }
+                    for (method, type_of) in &struct_type.methods {
+                        items.push(item(
+                            method,
+                            CompletionItemKind::METHOD,
+                            type_of.to_string(),
+                        ));
+                    }
+                }
+                Some(Receiver::Value(TypeSymbolType::Component(component))) => {
+                    for (field, type_of) in &component.fields {
+                        items.push(item(field, CompletionItemKind::FIELD, type_of.to_string()));
+                    }
+                }
+                Some(Receiver::Type(TypeSymbolType::Struct(struct_type))) => {
+                    for (function, type_of) in &struct_type.statics {
+                        let detail = type_of.to_string();
+                        items.push(item(function, CompletionItemKind::FUNCTION, detail));
+                    }
+                }
+                _ => (),
+            },
+            Access::Unknown => (),
+        }
+
+        items.sort_by(|a, b| a.label.cmp(&b.label));
+        items
+    }
+
+    fn is_buildin(&self, name: &str) -> bool {
+        let name = name.to_owned();
+        let buildin_function = self.scope.resolve_type(&name).is_some_and(|type_of| {
+            matches!(
+                type_of.type_of,
+                TypeSymbolType::Function(function)
+                    if matches!(function.execution_body, FunctionExecutionStrategy::Buildin(_))
+            )
+        });
+        let primitive = self
+            .scope
+            .resolve_defined_type(&name)
+            .is_some_and(|type_of| {
+                matches!(
+                    type_of.type_of,
+                    TypeSymbolType::Int
+                        | TypeSymbolType::Float
+                        | TypeSymbolType::Bool
+                        | TypeSymbolType::String
+                )
+            });
+        buildin_function || primitive
+    }
+
+    /// The last variable with the name, that is declared before the offset
+    fn local(&self, name: &str, offset: usize) -> Option<Local<'_>> {
+        self.locals(offset)
+            .into_iter()
+            .rev()
+            .find(|local| local.name == name)
+    }
+
+    /// The variables visible at the offset: the params of the functions around it and
+    /// what the outermost of them declares before the offset.
+    /// NOTE: blocks are not tracked, variables declared in a block before the offset are visible after it
+    fn locals(&self, offset: usize) -> Vec<Local<'_>> {
+        let mut locals = Vec::new();
+        let mut struct_name = None;
+        let mut function = None;
+
+        let contains = |node: &&AstNode| node.range.start <= offset && offset <= node.range.end;
+        let mut nodes = self.ast.iter().collect::<Vec<_>>();
+        while let Some(node) = nodes.into_iter().find(contains) {
+            match &node.type_of {
+                AstNodeType::TypeDef {
+                    typename,
+                    typedef: AstTypeDefinition::Struct(_),
+                    ..
+                } => struct_name = Some(typename),
+                AstNodeType::TypeDef {
+                    typedef: AstTypeDefinition::Function(params, _),
+                    ..
+                }
+                | AstNodeType::Closure { params, .. } => {
+                    function.get_or_insert(node);
+                    for (name, type_of) in params {
+                        // NOTE: self is the struct the method is defined in
+                        let type_of = match (&type_of.type_of, struct_name) {
+                            (TypeSymbolType::SelfType, Some(struct_name)) => Some(
+                                TypeSymbol::strong(TypeSymbolType::Symbol(struct_name.clone())),
+                            ),
+                            (TypeSymbolType::SelfType, None) => None,
+                            _ => Some(type_of.clone()),
+                        };
+                        locals.push(Local {
+                            name,
+                            type_of,
+                            range: None,
+                        });
+                    }
+                }
+                AstNodeType::TypeDef {
+                    typedef: AstTypeDefinition::System(params, _),
+                    ..
+                } => {
+                    function.get_or_insert(node);
+                    for (name, _) in params {
+                        locals.push(Local {
+                            name,
+                            type_of: None,
+                            range: None,
+                        });
+                    }
+                }
+                AstNodeType::ForEach { recipient, .. } => locals.push(Local {
+                    name: recipient,
+                    type_of: None,
+                    range: None,
+                }),
+                _ => (),
+            }
+            nodes = node.children();
+        }
+
+        if let Some(function) = function {
+            self.declarations(function, offset, &mut locals);
+        }
+        locals
+    }
+
+    fn declarations<'a>(&self, node: &'a AstNode, offset: usize, locals: &mut Vec<Local<'a>>) {
+        for child in node.children() {
+            if child.range.start >= offset {
+                break;
+            }
+            if let AstNodeType::Declaration {
+                new_symbol,
+                expression,
+                assumed_type,
+                ..
+            } = &child.type_of
+                && child.range.end <= offset
+            {
+                locals.push(Local {
+                    name: new_symbol,
+                    type_of: assumed_type
+                        .clone()
+                        .or_else(|| self.type_of_expression(expression)),
+                    range: Some(child.range.clone()),
+                });
+            }
+            self.declarations(child, offset, locals);
+        }
+    }
+
+    /// The type of an expression, as far as it is known without running it,
+    /// i.e. of literals and calls of functions with a return type
+    fn type_of_expression(&self, node: &AstNode) -> Option<TypeSymbol> {
+        let type_of = match &node.type_of {
+            AstNodeType::Int(_) => TypeSymbolType::Int,
+            AstNodeType::Float(_) => TypeSymbolType::Float,
+            AstNodeType::Bool(_) => TypeSymbolType::Bool,
+            AstNodeType::String(_) | AstNodeType::Interpolation(_) => TypeSymbolType::String,
+            AstNodeType::MemberCall { calls } => match calls.as_slice() {
+                [literal] if matches!(literal.type_of, MemberAccessType::Struct(_)) => {
+                    TypeSymbolType::Symbol(literal.member.clone())
+                }
+                [function] if matches!(function.type_of, MemberAccessType::Function(_)) => {
+                    let type_of = self.scope.resolve_type(&function.member)?;
+                    let TypeSymbolType::Function(function) = type_of.type_of else {
+                        return None;
+                    };
+                    return function.return_type.map(|t| *t);
+                }
+                [defined, function]
+                    if matches!(function.type_of, MemberAccessType::Function(_)) =>
+                {
+                    let Some(Receiver::Type(TypeSymbolType::Struct(struct_type))) =
+                        self.receiver(&[&defined.member], 0)
+                    else {
+                        return None;
+                    };
+                    let (_, function) = struct_type
+                        .statics
+                        .into_iter()
+                        .find(|(name, _)| *name == function.member)?;
+                    return function.return_type.map(|t| *t);
+                }
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(TypeSymbol::strong(type_of))
+    }
+
+    /// The struct or component, the type stands for
+    fn definition_of(&self, type_of: &TypeSymbol) -> Option<TypeSymbolType> {
+        match &type_of.type_of {
+            TypeSymbolType::Symbol(name) => self
+                .scope
+                .resolve_defined_type(name)
+                .map(|type_of| type_of.type_of),
+            TypeSymbolType::Struct(_) | TypeSymbolType::Component(_) => {
+                Some(type_of.type_of.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Follows the identifiers before a member, starting at a variable, a global or a defined type
+    fn receiver(&self, path: &[&str], offset: usize) -> Option<Receiver> {
+        let (head, fields) = path.split_first()?;
+        let head = head.to_string();
+
+        let type_of = match self.local(&head, offset) {
+            Some(local) => local.type_of?,
+            None => match self.scope.resolve_type(&head) {
+                Some(type_of) => type_of,
+                None if fields.is_empty() => {
+                    let defined = self.scope.resolve_defined_type(&head)?;
+                    return Some(Receiver::Type(defined.type_of));
+                }
+                None => return None,
+            },
+        };
+
+        let mut definition = self.definition_of(&type_of)?;
+        for field in fields {
+            let fields = match &definition {
+                TypeSymbolType::Struct(struct_type) => &struct_type.fields,
+                TypeSymbolType::Component(component) => &component.fields,
+                _ => return None,
+            };
+            let (_, type_of) = fields.iter().find(|(name, _)| name == field)?;
+            definition = self.definition_of(type_of)?;
+        }
+        Some(Receiver::Value(definition))
+    }
+}
@@ -0,0 +1,87 @@
+use std::ops::Range;
+
+use lsp_types::Position;
+
+/// Converts between the byte offsets of the parser and the positions of the protocol,
+/// which count lines and utf-16 code units in the line
+pub struct LineIndex<'a> {
+    source: &'a str,
+    /// The offset every line starts at
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self {
+            source,
+            line_starts,
+        }
+    }
+
+    /// NOTE: offsets past the end point at the end, some errors carry placeholder ranges
+    pub fn position(&self, offset: usize) -> Position {
+        let mut offset = offset.min(self.source.len());
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        let line = self.line_starts.partition_point(|start| *start <= offset) - 1;
+        let character = self.source[self.line_starts[line]..offset]
+            .encode_utf16()
+            .count();
+        Position::new(line as u32, character as u32)
+    }
+
+    pub fn range(&self, range: Range<usize>) -> lsp_types::Range {
+        lsp_types::Range::new(self.position(range.start), self.position(range.end))
+    }
+
+    /// NOTE: positions past the end of their line point at the end of the line
+    pub fn offset(&self, position: Position) -> usize {
+        let Some(start) = self.line_starts.get(position.line as usize) else {
+            return self.source.len();
+        };
+        let line = self.source[*start..].split('\n').next().unwrap_or_default();
+
+        let mut units = 0;
+        for (i, c) in line.char_indices() {
+            if units >= position.character as usize {
+                return start + i;
+            }
+            units += c.len_utf16();
+        }
+        start + line.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Position;
+
+    use super::LineIndex;
+
+    #[test]
+    fn converts_offsets_and_positions() {
+        let source = "a := 1;\nb := \"äö\";\nc";
+        let index = LineIndex::new(source);
+
+        for (offset, position) in [
+            (0, Position::new(0, 0)),
+            (5, Position::new(0, 5)),
+            (8, Position::new(1, 0)),
+            (source.find('ö').unwrap(), Position::new(1, 7)),
+            (source.find("\";").unwrap(), Position::new(1, 8)),
+            (source.len(), Position::new(2, 1)),
+        ] {
+            assert_eq!(index.position(offset), position);
+            assert_eq!(index.offset(position), offset);
+        }
+
+        assert_eq!(index.position(source.len() + 10), Position::new(2, 1));
+        assert_eq!(index.offset(Position::new(0, 100)), 7);
+        assert_eq!(index.offset(Position::new(7, 0)), source.len());
+    }
+}
@@ -0,0 +1,30 @@
+//! A language server for editors, that speaks the language server protocol over stdio.
+//! Publishes the diagnostics of the stages up to the resolver,
+//! resolves definitions, shows types on hover and completes members and definitions
+
+mod analysis;
+mod line_index;
+mod server;
+
+use std::process::ExitCode;
+
+use lsp_server::Connection;
+
+fn main() -> ExitCode {
+    let (connection, io_threads) = Connection::stdio();
+
+    let result = serde_json::to_value(server::capabilities())
+        .map_err(Into::into)
+        .and_then(|capabilities| connection.initialize(capabilities).map_err(Into::into))
+        .and_then(|_| server::run(&connection));
+    // NOTE: the writer thread only stops once the connection is dropped
+    drop(connection);
+
+    match result.and_then(|_| io_threads.join().map_err(Into::into)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("language server failed: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
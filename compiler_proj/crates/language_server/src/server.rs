@@ -0,0 +1,188 @@
+use std::{collections::HashMap, error::Error};
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, RequestId, Response};
+use lsp_types::{
+    CompletionOptions, CompletionParams, CompletionResponse, GotoDefinitionParams,
+    GotoDefinitionResponse, Hover, HoverContents, HoverParams, HoverProviderCapability, Location,
+    MarkupContent, MarkupKind, PublishDiagnosticsParams, ServerCapabilities,
+    TextDocumentPositionParams, TextDocumentSyncCapability, TextDocumentSyncKind, Uri,
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+        Notification as NotificationTrait, PublishDiagnostics,
+    },
+    request::{Completion, GotoDefinition, HoverRequest, Request as RequestTrait},
+};
+
+use crate::{
+    analysis::{Symbols, analyze},
+    line_index::LineIndex,
+};
+
+pub fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(lsp_types::OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![".".to_owned()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// An open document
+struct Document {
+    text: String,
+    /// The symbols of the last version, that could be preprocessed.
+    /// NOTE: kept while the document has errors, i.e. while a member access is typed
+    symbols: Option<Symbols>,
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<Uri, Document>,
+}
+
+/// Answers requests until the client shuts the server down
+pub fn run(connection: &Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut server = Server::default();
+
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                let response = server.handle_request(request);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                let method = notification.method.clone();
+                match server.handle_notification(notification) {
+                    Ok(Some(diagnostics)) => {
+                        connection.sender.send(Message::Notification(diagnostics))?
+                    }
+                    Ok(None) => (),
+                    // NOTE: notifications have no response, a malformed one is only logged
+                    Err(err) => eprintln!("skipped the invalid notification {method}: {err}"),
+                }
+            }
+            Message::Response(_) => (),
+        }
+    }
+    Ok(())
+}
+
+impl Server {
+    fn handle_request(&self, request: Request) -> Response {
+        match request.method.as_str() {
+            GotoDefinition::METHOD => self.respond::<GotoDefinition>(request, Self::definition),
+            HoverRequest::METHOD => self.respond::<HoverRequest>(request, Self::hover),
+            Completion::METHOD => self.respond::<Completion>(request, Self::completion),
+            method => Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unsupported request {method}"),
+            ),
+        }
+    }
+
+    fn respond<R: RequestTrait>(
+        &self,
+        request: Request,
+        handler: fn(&Self, R::Params) -> R::Result,
+    ) -> Response {
+        let id: RequestId = request.id.clone();
+        match request.extract::<R::Params>(R::METHOD) {
+            Ok((id, params)) => Response::new_ok(id, handler(self, params)),
+            Err(err) => Response::new_err(id, ErrorCode::InvalidParams as i32, err.to_string()),
+        }
+    }
+
+    /// The document, its symbols and the offset of the position
+    fn locate(
+        &self,
+        position: &TextDocumentPositionParams,
+    ) -> Option<(&Document, &Symbols, usize)> {
+        let document = self.documents.get(&position.text_document.uri)?;
+        let symbols = document.symbols.as_ref()?;
+        let offset = LineIndex::new(&document.text).offset(position.position);
+        Some((document, symbols, offset))
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let position = params.text_document_position_params;
+        let (document, symbols, offset) = self.locate(&position)?;
+        let range = symbols.definition(&document.text, offset)?;
+        Some(GotoDefinitionResponse::Scalar(Location::new(
+            position.text_document.uri,
+            LineIndex::new(&document.text).range(range),
+        )))
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let (document, symbols, offset) = self.locate(&params.text_document_position_params)?;
+        let type_of = symbols.hover(&document.text, offset)?;
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("```\n{type_of}\n```"),
+            }),
+            range: None,
+        })
+    }
+
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let (document, symbols, offset) = self.locate(&params.text_document_position)?;
+        Some(CompletionResponse::Array(
+            symbols.completions(&document.text, offset),
+        ))
+    }
+
+    /// Updates the documents, returns the diagnostics to publish
+    fn handle_notification(
+        &mut self,
+        notification: Notification,
+    ) -> Result<Option<Notification>, serde_json::Error> {
+        let (uri, text) = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: <DidOpenTextDocument as NotificationTrait>::Params =
+                    serde_json::from_value(notification.params)?;
+                (params.text_document.uri, params.text_document.text)
+            }
+            DidChangeTextDocument::METHOD => {
+                let mut params: <DidChangeTextDocument as NotificationTrait>::Params =
+                    serde_json::from_value(notification.params)?;
+                // NOTE: only full syncs are announced, the last change is the whole document
+                let Some(change) = params.content_changes.pop() else {
+                    return Ok(None);
+                };
+                (params.text_document.uri, change.text)
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: <DidCloseTextDocument as NotificationTrait>::Params =
+                    serde_json::from_value(notification.params)?;
+                self.documents.remove(&params.text_document.uri);
+                return Ok(Some(publish(params.text_document.uri, Vec::new())));
+            }
+            _ => return Ok(None),
+        };
+
+        let analysis = analyze(&text);
+        let previous = self.documents.remove(&uri);
+        let symbols = analysis
+            .symbols
+            .or_else(|| previous.and_then(|document| document.symbols));
+        self.documents
+            .insert(uri.clone(), Document { text, symbols });
+        Ok(Some(publish(uri, analysis.diagnostics)))
+    }
+}
+
+fn publish(uri: Uri, diagnostics: Vec<lsp_types::Diagnostic>) -> Notification {
+    Notification::new(
+        PublishDiagnostics::METHOD.to_owned(),
+        PublishDiagnosticsParams::new(uri, diagnostics, None),
+    )
+}
@@ -0,0 +1,258 @@
+use std::{
+    io::BufReader,
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use lsp_server::{Message, Notification, Request, RequestId};
+use serde_json::{Value, json};
+
+const URI: &str = "file:///game.ecs";
+
+const SOURCE: &str = r#"component Position { x: float, y: float, }
+
+struct Point {
+    x: int,
+    y: int,
+    fn len(self, scale: int): int => scale * 2;
+}
+
+fn make(): Point => Point { x: 1, y: 2, };
+
+fn main() {
+    p := make();
+    p.len(1);
+}
+"#;
+
+/// Drives the server binary over stdio, like an editor would
+struct Client {
+    server: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: i32,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut server = Command::new(env!("CARGO_BIN_EXE_language_server"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdin = server.stdin.take().unwrap();
+        let stdout = BufReader::new(server.stdout.take().unwrap());
+        let mut client = Self {
+            server,
+            stdin,
+            stdout,
+            next_id: 0,
+        };
+
+        let result = client.request("initialize", json!({ "capabilities": {} }));
+        assert_eq!(result["capabilities"]["hoverProvider"], true);
+        client.notify("initialized", json!({}));
+        client
+    }
+
+    fn send(&mut self, message: Message) {
+        message.write(&mut self.stdin).unwrap();
+    }
+
+    fn receive(&mut self) -> Message {
+        Message::read(&mut self.stdout).unwrap().unwrap()
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(Message::Notification(Notification::new(
+            method.to_owned(),
+            params,
+        )));
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = RequestId::from(self.next_id);
+        self.send(Message::Request(Request::new(
+            id.clone(),
+            method.to_owned(),
+            params,
+        )));
+
+        loop {
+            if let Message::Response(response) = self.receive() {
+                assert_eq!(response.id, id);
+                assert!(response.error.is_none(), "{:?}", response.error);
+                return response.result.unwrap_or(Value::Null);
+            }
+        }
+    }
+
+    /// Opens or changes the document, returns the diagnostics published for it
+    fn open(&mut self, text: &str) -> Vec<Value> {
+        self.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": { "uri": URI, "languageId": "ecs", "version": 1, "text": text }
+            }),
+        );
+        self.diagnostics()
+    }
+
+    fn change(&mut self, text: &str) -> Vec<Value> {
+        self.notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": URI, "version": 2 },
+                "contentChanges": [{ "text": text }]
+            }),
+        );
+        self.diagnostics()
+    }
+
+    fn diagnostics(&mut self) -> Vec<Value> {
+        match self.receive() {
+            Message::Notification(notification) => {
+                assert_eq!(notification.method, "textDocument/publishDiagnostics");
+                notification.params["diagnostics"]
+                    .as_array()
+                    .unwrap()
+                    .clone()
+            }
+            message => panic!("expected diagnostics, got {message:?}"),
+        }
+    }
+
+    /// Sends a request at the position of the nth occurrence of the pattern in the source
+    fn request_at(&mut self, method: &str, source: &str, pattern: &str, nth: usize) -> Value {
+        let offset = source.match_indices(pattern).nth(nth).unwrap().0;
+        let line = source[..offset].matches('\n').count();
+        let character = offset - source[..offset].rfind('\n').map_or(0, |i| i + 1);
+        self.request(
+            method,
+            json!({
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character }
+            }),
+        )
+    }
+
+    fn shutdown(mut self) {
+        self.request("shutdown", Value::Null);
+        self.notify("exit", Value::Null);
+        assert!(self.server.wait().unwrap().success());
+    }
+}
+
+fn labels(completions: &Value) -> Vec<&str> {
+    completions
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn publishes_diagnostics() {
+    let mut client = Client::start();
+    // a malformed notification is skipped, the server keeps running
+    client.notify("textDocument/didOpen", json!({ "textDocument": 1 }));
+    let diagnostics = client.open(SOURCE);
+    assert!(diagnostics.is_empty(), "{diagnostics:?}");
+
+    let diagnostics = client.change("fn main() {\n    x := ;\n}");
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(diagnostics[0]["code"], "E0018");
+    assert_eq!(
+        diagnostics[0]["range"]["start"],
+        json!({ "line": 1, "character": 9 })
+    );
+    assert!(
+        diagnostics[0]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("unexpected token ;")
+    );
+
+    // the parser recovers at the end of the statement
+    let diagnostics = client.change("fn main() {\n    x := ;\n    y := 1 +;\n}");
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[1]["range"]["start"]["line"], 2);
+
+    let diagnostics = client.change("fn main() {\n    y := x;\n}");
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["code"], "E0016");
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 1);
+
+    // the errors of every definition are published, not only the first one
+    let diagnostics = client.change("fn a() {\n    break;\n}\nfn b() {\n    continue;\n}");
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0]["code"], "E0023");
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 1);
+    assert_eq!(diagnostics[1]["code"], "E0023");
+    assert_eq!(diagnostics[1]["range"]["start"]["line"], 4);
+
+    assert!(client.change(SOURCE).is_empty());
+    client.shutdown();
+}
+
+#[test]
+fn goes_to_definitions() {
+    let mut client = Client::start();
+    client.open(SOURCE);
+
+    // make() in main leads to the function definition
+    let location = client.request_at("textDocument/definition", SOURCE, "make", 1);
+    assert_eq!(location["uri"], URI);
+    assert_eq!(
+        location["range"]["start"],
+        json!({ "line": 8, "character": 0 })
+    );
+
+    // p leads to its declaration
+    let location = client.request_at("textDocument/definition", SOURCE, "p.len", 0);
+    assert_eq!(
+        location["range"]["start"],
+        json!({ "line": 11, "character": 4 })
+    );
+
+    let location = client.request_at("textDocument/definition", SOURCE, "int,", 0);
+    assert_eq!(location, Value::Null);
+    client.shutdown();
+}
+
+#[test]
+fn shows_types_on_hover() {
+    let mut client = Client::start();
+    client.open(SOURCE);
+
+    let hover = |client: &mut Client, pattern, nth| {
+        let hover = client.request_at("textDocument/hover", SOURCE, pattern, nth);
+        hover["contents"]["value"].as_str().unwrap().to_owned()
+    };
+    assert!(hover(&mut client, "p.len", 0).contains("p: Point"));
+    assert!(hover(&mut client, "len(1)", 0).contains("len"));
+    assert!(hover(&mut client, "scale * 2", 0).contains("scale: int"));
+    assert!(hover(&mut client, "Position", 0).contains("Position"));
+    client.shutdown();
+}
+
+#[test]
+fn completes_members_and_definitions() {
+    let mut client = Client::start();
+    client.open(SOURCE);
+
+    // keeps the symbols of the last valid version, while a member is typed
+    let typing = SOURCE.replace("p.len(1);", "p.");
+    assert_eq!(client.change(&typing).len(), 1);
+    let completions = client.request_at("textDocument/completion", &typing, "\n}", 1);
+    assert_eq!(labels(&completions), ["len", "x", "y"]);
+
+    let completions = client.request_at("textDocument/completion", SOURCE, "p :=", 0);
+    let labels = labels(&completions);
+    for label in ["Point", "Position", "main", "make"] {
+        assert!(labels.contains(&label), "{label} missing in {labels:?}");
+    }
+    client.shutdown();
+}
@@ -1,12 +1,17 @@
-use std::{fmt::Debug, ops::Range};
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    ops::Range,
+};
 
 use graphviz_rust::{
     dot_generator::{attr, edge, id, node},
     dot_structures::{Attribute, Edge, EdgeTy, Graph, Id, Node, NodeId, Stmt, Vertex},
 };
+use lalrpop_util::ParseError;
 use rand::distr::{Alphabetic, SampleString};
 
-use crate::TypeSymbol;
+use crate::{FileId, Span, TypeSymbol, TypeSymbolType, ast_grammar};
 
 /// Any symbol, that is not a type definition
 pub type Symbol = String;
@@ -245,6 +250,20 @@ pub enum AssignmentOperations {
     Modulo,
 }
 
+impl AssignmentOperations {
+    /// The operation applied to the old and the new value, None for a plain assignment
+    pub fn infix_operator(&self) -> Option<InfixOperator> {
+        match self {
+            AssignmentOperations::Identity => None,
+            AssignmentOperations::Add => Some(InfixOperator::Plus),
+            AssignmentOperations::Subtract => Some(InfixOperator::Minus),
+            AssignmentOperations::Multiply => Some(InfixOperator::Multiply),
+            AssignmentOperations::Divide => Some(InfixOperator::Divide),
+            AssignmentOperations::Modulo => Some(InfixOperator::Modulo),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum InfixOperator {
     // Computation
@@ -265,12 +284,135 @@ pub enum InfixOperator {
     Or,
 }
 
+impl InfixOperator {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            InfixOperator::Plus => "+",
+            InfixOperator::Minus => "-",
+            InfixOperator::Divide => "/",
+            InfixOperator::Multiply => "*",
+            InfixOperator::Modulo => "%",
+            InfixOperator::Equals => "==",
+            InfixOperator::NotEquals => "!=",
+            InfixOperator::LessThan => "<",
+            InfixOperator::LessThanEquals => "<=",
+            InfixOperator::GreaterThan => ">",
+            InfixOperator::GreaterThanEquals => ">=",
+            InfixOperator::And => "&&",
+            InfixOperator::Or => "||",
+        }
+    }
+
+    pub fn is_arithmetic(&self) -> bool {
+        matches!(
+            self,
+            InfixOperator::Plus
+                | InfixOperator::Minus
+                | InfixOperator::Divide
+                | InfixOperator::Multiply
+                | InfixOperator::Modulo
+        )
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum PrefixOperator {
     Not,    // '!'
     Negate, // '-'
 }
 
+/// Pattern of a match arm
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// _, matches anything
+    Wildcard,
+    /// Matches anything and binds it to the symbol
+    Binding(Symbol),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    None,
+    Some(Box<Pattern>),
+    Ok(Box<Pattern>),
+    Err(Box<Pattern>),
+    /// Destructures a struct or component, i.e. Point { x: 0, y }
+    Struct(Symbol, Vec<(Symbol, Pattern)>),
+}
+
+impl Pattern {
+    /// Matches any value of the type, None if the type is unknown. A struct pattern only matches the
+    /// struct it names, the types of its fields are looked up in the fields of the defined structs
+    pub fn is_irrefutable(
+        &self,
+        type_of: Option<&TypeSymbolType>,
+        structs: &HashMap<Symbol, Vec<(Symbol, TypeSymbol)>>,
+    ) -> bool {
+        match self {
+            Pattern::Wildcard | Pattern::Binding(_) => true,
+            Pattern::Struct(name, fields) => {
+                let declared = match type_of {
+                    Some(TypeSymbolType::Struct(s)) if s.name == *name => Some(&s.fields),
+                    Some(TypeSymbolType::Component(c)) if c.name == *name => Some(&c.fields),
+                    Some(TypeSymbolType::Symbol(s)) if s == name => structs.get(name),
+                    _ => return false,
+                };
+                fields.iter().all(|(field, p)| {
+                    let field_type = declared
+                        .and_then(|declared| declared.iter().find(|(f, _)| f == field))
+                        .map(|(_, t)| &t.type_of);
+                    p.is_irrefutable(field_type, structs)
+                })
+            }
+            _ => false,
+        }
+    }
+
+    /// The symbols the pattern binds, in the order the interpreter binds them
+    pub fn bindings(&self) -> Vec<&Symbol> {
+        match self {
+            Pattern::Binding(symbol) => vec![symbol],
+            Pattern::Some(p) | Pattern::Ok(p) | Pattern::Err(p) => p.bindings(),
+            Pattern::Struct(_, fields) => fields.iter().flat_map(|(_, p)| p.bindings()).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Binding(s) => write!(f, "{s}"),
+            Pattern::Int(i) => write!(f, "{i}"),
+            Pattern::Float(fl) => write!(f, "{fl}"),
+            Pattern::String(s) => write!(f, "{s:?}"),
+            Pattern::Bool(b) => write!(f, "{b}"),
+            Pattern::None => write!(f, "none"),
+            Pattern::Some(p) => write!(f, "some({p})"),
+            Pattern::Ok(p) => write!(f, "ok({p})"),
+            Pattern::Err(p) => write!(f, "err({p})"),
+            Pattern::Struct(name, fields) => write!(
+                f,
+                "{name} {{ {} }}",
+                fields
+                    .iter()
+                    .map(|(field, p)| format!("{field}: {p}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Box<AstNode>>,
+    pub body: Vec<Box<AstNode>>,
+    pub range: Range<usize>,
+}
+
 pub struct StructBody {
     pub functions: Vec<Box<AstNode>>,
     pub attributes: Vec<(Symbol, TypeSymbol)>,
@@ -287,12 +429,29 @@ pub enum MemberAccessType {
     Struct(Vec<(Symbol, Box<AstNode>)>),
 }
 
+/// Location of a local variable, filled in by the resolver.
+/// depth counts the scopes between the use and the declaration, index is the slot inside that scope
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct MemberAccess {
     //a.c(e,f).d
     pub member: Symbol,
     pub type_of: MemberAccessType,
     pub range: Range<usize>,
+    /// None for globals and everything but the first member
+    pub slot: Option<Slot>,
+}
+
+impl MemberAccess {
+    /// The range of the member alone, the range of the access starts with it
+    pub fn name_range(&self) -> Range<usize> {
+        self.range.start..self.range.start + self.member.len()
+    }
 }
 
 impl ToGraphviz for MemberAccess {
@@ -380,12 +539,270 @@ impl ToGraphviz for Vec<MemberAccess> {
 #[derive(Debug, Clone)]
 pub struct AstNode {
     pub range: Range<usize>,
+    /// The file of the range, nodes are parsed from the main file, unless moved into another one
+    pub file: FileId,
     pub type_of: AstNodeType,
 }
 
 impl AstNode {
     pub fn new(range: Range<usize>, type_of: AstNodeType) -> Self {
-        Self { range, type_of }
+        Self {
+            range,
+            file: FileId::MAIN,
+            type_of,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        Span::new(self.file, self.range.clone())
+    }
+
+    /// The direct children of the node, in the order they appear in the source
+    pub fn children(&self) -> Vec<&AstNode> {
+        let mut children = Vec::new();
+        match &self.type_of {
+            AstNodeType::List(nodes)
+            | AstNodeType::Interpolation(nodes)
+            | AstNodeType::TypeDef {
+                execution_body: nodes,
+                ..
+            }
+            | AstNodeType::Closure {
+                execution_body: nodes,
+                ..
+            } => children.extend(nodes.iter().map(Box::as_ref)),
+            AstNodeType::Map(entries) => {
+                for (key, value) in entries {
+                    children.extend([key.as_ref(), value.as_ref()]);
+                }
+            }
+            AstNodeType::Option(Some(node))
+            | AstNodeType::Result(Ok(node))
+            | AstNodeType::Result(Err(node))
+            | AstNodeType::Declaration {
+                expression: node, ..
+            }
+            | AstNodeType::AssignmentOp {
+                expression: node, ..
+            }
+            | AstNodeType::PrefixCall(_, node)
+            | AstNodeType::ReturnStatement { return_value: node }
+            | AstNodeType::Weak(node)
+            | AstNodeType::Propagate(node)
+            | AstNodeType::ExpressionStatement(node) => children.push(node.as_ref()),
+            AstNodeType::InfixCall(left, _, right) => {
+                children.extend([left.as_ref(), right.as_ref()])
+            }
+            AstNodeType::MemberCall { calls } => {
+                for call in calls {
+                    match &call.type_of {
+                        MemberAccessType::Function(params) => {
+                            children.extend(params.iter().map(Box::as_ref))
+                        }
+                        MemberAccessType::Struct(fields) => {
+                            children.extend(fields.iter().map(|(_, value)| value.as_ref()))
+                        }
+                        MemberAccessType::Symbol => (),
+                    }
+                }
+            }
+            AstNodeType::Branch {
+                cond,
+                body,
+                else_if_branches,
+                else_branch,
+            } => {
+                children.push(cond.as_ref());
+                children.extend(body.iter().map(Box::as_ref));
+                for (cond, body) in else_if_branches {
+                    children.push(cond.as_ref());
+                    children.extend(body.iter().map(Box::as_ref));
+                }
+                children.extend(else_branch.iter().flatten().map(Box::as_ref));
+            }
+            AstNodeType::While { cond, body, .. } => {
+                children.push(cond.as_ref());
+                children.extend(body.iter().map(Box::as_ref));
+            }
+            AstNodeType::ForEach { iterable, body, .. } => {
+                children.push(iterable.as_ref());
+                children.extend(body.iter().map(Box::as_ref));
+            }
+            AstNodeType::For {
+                declaration,
+                condition,
+                assignment,
+                body,
+                ..
+            } => {
+                children.extend(
+                    [declaration, condition, assignment]
+                        .into_iter()
+                        .flatten()
+                        .map(Box::as_ref),
+                );
+                children.extend(body.iter().map(Box::as_ref));
+            }
+            AstNodeType::EntityDef {
+                default_components: Some(components),
+                ..
+            } => children.extend(components),
+            AstNodeType::Match { value, arms } => {
+                children.push(value.as_ref());
+                for arm in arms {
+                    children.extend(arm.guard.as_deref());
+                    children.extend(arm.body.iter().map(Box::as_ref));
+                }
+            }
+            AstNodeType::Import(..)
+            | AstNodeType::ImportNative(..)
+            | AstNodeType::Int(_)
+            | AstNodeType::Float(_)
+            | AstNodeType::String(_)
+            | AstNodeType::Bool(_)
+            | AstNodeType::Option(None)
+            | AstNodeType::EntityDeclaration { .. }
+            | AstNodeType::GroupDef { .. }
+            | AstNodeType::Register { .. }
+            | AstNodeType::EntityDef { .. }
+            | AstNodeType::Break(_)
+            | AstNodeType::Continue(_)
+            | AstNodeType::Error => (),
+        }
+        children
+    }
+
+    /// Moves the ranges of the node and all of its children, i.e. for a node parsed from a part of the source
+    pub fn offset_ranges(&mut self, offset: usize) {
+        self.move_to(self.file, offset);
+    }
+
+    /// Moves the node and all of its children into the file, at the offset in it
+    pub fn move_to(&mut self, file: FileId, offset: usize) {
+        fn offset_all(nodes: &mut [Box<AstNode>], file: FileId, offset: usize) {
+            nodes.iter_mut().for_each(|node| node.move_to(file, offset));
+        }
+
+        self.range = self.range.start + offset..self.range.end + offset;
+        self.file = file;
+        match &mut self.type_of {
+            AstNodeType::List(nodes)
+            | AstNodeType::Interpolation(nodes)
+            | AstNodeType::TypeDef {
+                execution_body: nodes,
+                ..
+            }
+            | AstNodeType::Closure {
+                execution_body: nodes,
+                ..
+            } => offset_all(nodes, file, offset),
+            AstNodeType::Map(entries) => {
+                for (key, value) in entries {
+                    key.move_to(file, offset);
+                    value.move_to(file, offset);
+                }
+            }
+            AstNodeType::Option(Some(node))
+            | AstNodeType::Result(Ok(node))
+            | AstNodeType::Result(Err(node))
+            | AstNodeType::Declaration {
+                expression: node, ..
+            }
+            | AstNodeType::AssignmentOp {
+                expression: node, ..
+            }
+            | AstNodeType::PrefixCall(_, node)
+            | AstNodeType::ReturnStatement { return_value: node }
+            | AstNodeType::Weak(node)
+            | AstNodeType::Propagate(node)
+            | AstNodeType::ExpressionStatement(node) => node.move_to(file, offset),
+            AstNodeType::InfixCall(left, _, right) => {
+                left.move_to(file, offset);
+                right.move_to(file, offset);
+            }
+            AstNodeType::MemberCall { calls } => {
+                for call in calls {
+                    call.range = call.range.start + offset..call.range.end + offset;
+                    match &mut call.type_of {
+                        MemberAccessType::Function(params) => offset_all(params, file, offset),
+                        MemberAccessType::Struct(fields) => {
+                            for (_, value) in fields {
+                                value.move_to(file, offset);
+                            }
+                        }
+                        MemberAccessType::Symbol => (),
+                    }
+                }
+            }
+            AstNodeType::Branch {
+                cond,
+                body,
+                else_if_branches,
+                else_branch,
+            } => {
+                cond.move_to(file, offset);
+                offset_all(body, file, offset);
+                for (cond, body) in else_if_branches {
+                    cond.move_to(file, offset);
+                    offset_all(body, file, offset);
+                }
+                if let Some(body) = else_branch {
+                    offset_all(body, file, offset);
+                }
+            }
+            AstNodeType::While { cond, body, .. } => {
+                cond.move_to(file, offset);
+                offset_all(body, file, offset);
+            }
+            AstNodeType::ForEach { iterable, body, .. } => {
+                iterable.move_to(file, offset);
+                offset_all(body, file, offset);
+            }
+            AstNodeType::For {
+                declaration,
+                condition,
+                assignment,
+                body,
+                ..
+            } => {
+                for node in [declaration, condition, assignment].into_iter().flatten() {
+                    node.move_to(file, offset);
+                }
+                offset_all(body, file, offset);
+            }
+            AstNodeType::EntityDef {
+                default_components: Some(components),
+                ..
+            } => {
+                for component in components {
+                    component.move_to(file, offset);
+                }
+            }
+            AstNodeType::Match { value, arms } => {
+                value.move_to(file, offset);
+                for arm in arms {
+                    arm.range = arm.range.start + offset..arm.range.end + offset;
+                    if let Some(guard) = &mut arm.guard {
+                        guard.move_to(file, offset);
+                    }
+                    offset_all(&mut arm.body, file, offset);
+                }
+            }
+            AstNodeType::Import(..)
+            | AstNodeType::ImportNative(..)
+            | AstNodeType::Int(_)
+            | AstNodeType::Float(_)
+            | AstNodeType::String(_)
+            | AstNodeType::Bool(_)
+            | AstNodeType::Option(None)
+            | AstNodeType::EntityDeclaration { .. }
+            | AstNodeType::GroupDef { .. }
+            | AstNodeType::Register { .. }
+            | AstNodeType::EntityDef { .. }
+            | AstNodeType::Break(_)
+            | AstNodeType::Continue(_)
+            | AstNodeType::Error => (),
+        }
     }
 }
 
@@ -396,6 +813,8 @@ pub enum AstNodeType {
     Int(i64),
     Float(f64),
     String(String),
+    /// "hp: {hp}", the text of every part is concatenated. Literal parts are strings
+    Interpolation(Vec<Box<AstNode>>),
     Bool(bool),
     List(Vec<Box<AstNode>>),
     Map(Vec<(Box<AstNode>, Box<AstNode>)>),
@@ -405,6 +824,7 @@ pub enum AstNodeType {
         new_symbol: Symbol,
         expression: Box<AstNode>,
         assumed_type: Option<TypeSymbol>,
+        slot: Option<Slot>,
     },
     EntityDeclaration {
         new_symbol: Symbol,
@@ -413,6 +833,7 @@ pub enum AstNodeType {
         recipient: Symbol,
         operation: AssignmentOperations,
         expression: Box<AstNode>,
+        slot: Option<Slot>,
     },
     TypeDef {
         typename: Symbol,
@@ -431,15 +852,18 @@ pub enum AstNodeType {
         else_branch: Option<Vec<Box<AstNode>>>,
     },
     While {
+        label: Option<Symbol>,
         cond: Box<AstNode>,
         body: Vec<Box<AstNode>>,
     },
     ForEach {
+        label: Option<Symbol>,
         recipient: Symbol,
         iterable: Box<AstNode>,
         body: Vec<Box<AstNode>>,
     },
     For {
+        label: Option<Symbol>,
         declaration: Option<Box<AstNode>>,
         condition: Option<Box<AstNode>>,
         assignment: Option<Box<AstNode>>,
@@ -460,11 +884,35 @@ pub enum AstNodeType {
         default_components: Option<Vec<AstNode>>,
     },
     Weak(Box<AstNode>),
-    // TODO: Break statement in loops,
+    /// Anonymous function, capturing the scope it is created in
+    Closure {
+        params: Vec<(Symbol, TypeSymbol)>,
+        return_type: Option<TypeSymbol>,
+        execution_body: Vec<Box<AstNode>>,
+    },
+    Match {
+        value: Box<AstNode>,
+        arms: Vec<MatchArm>,
+    },
+    /// a?, returns early with the error of a result, otherwise evaluates to its ok value
+    Propagate(Box<AstNode>),
+    /// An expression terminated by a semicolon. Its value is discarded, so only an expression
+    /// without one is the value of its block
+    ExpressionStatement(Box<AstNode>),
+    /// Leaves the innermost loop, or the loop with the given label
+    Break(Option<Symbol>),
+    /// Skips to the next iteration of the innermost loop, or the loop with the given label
+    Continue(Option<Symbol>),
+    /// A statement with a syntax error, the parser skipped it to find the errors after it
+    Error,
 }
 
 impl ToGraphviz for AstNode {
     fn to_graphviz(&self, graph: &mut Graph) -> Node {
+        // NOTE: the graph shows the statements of a block, whether they end with a semicolon doesn't matter
+        if let AstNodeType::ExpressionStatement(expression) = &self.type_of {
+            return expression.to_graphviz(graph);
+        }
         let mut n = node!(self.new_id());
 
         let mut edges = Vec::new();
@@ -478,6 +926,13 @@ impl ToGraphviz for AstNode {
                 vec![attr!("label", &format!("\"string({s})\""))]
             }
             AstNodeType::Bool(b) => vec![attr!("label", &format!("\"bool({b})\""))],
+            AstNodeType::Interpolation(parts) => {
+                for node in parts {
+                    let n_child = node.to_graphviz(graph);
+                    edges.push(edge!(n.id.clone() => n_child.id.clone()));
+                }
+                vec![attr!("label", "interpolation")]
+            }
             AstNodeType::List(ast_nodes) => {
                 for node in ast_nodes {
                     let n_child = node.to_graphviz(graph);
@@ -522,7 +977,7 @@ impl ToGraphviz for AstNode {
             AstNodeType::Declaration {
                 new_symbol,
                 expression: ast_node,
-                assumed_type: _,
+                ..
             } => {
                 let n_child = ast_node.as_ref().to_graphviz(graph);
                 edges.push(edge!(n.id.clone() => n_child.id.clone()));
@@ -540,6 +995,7 @@ impl ToGraphviz for AstNode {
                 recipient,
                 operation,
                 expression: ast_node,
+                ..
             } => {
                 let n_child = ast_node.as_ref().to_graphviz(graph);
                 edges.push(edge!(n.id.clone() => n_child.id.clone()));
@@ -658,7 +1114,7 @@ impl ToGraphviz for AstNode {
 
                 vec![attr!("label", "branch")]
             }
-            AstNodeType::While { cond, body } => {
+            AstNodeType::While { cond, body, .. } => {
                 let cond_node = node!(self.new_id(); attr!("label", "condition"));
                 graph.add_stmt(Stmt::Node(cond_node.clone()));
                 edges.push(edge!(n.id.clone() => cond_node.id.clone()));
@@ -681,6 +1137,7 @@ impl ToGraphviz for AstNode {
                 recipient,
                 iterable,
                 body,
+                ..
             } => {
                 let body_node = node!(self.new_id(); attr!("label", "iterable"));
                 graph.add_stmt(Stmt::Node(body_node.clone()));
@@ -708,6 +1165,7 @@ impl ToGraphviz for AstNode {
                 condition,
                 assignment,
                 body,
+                ..
             } => {
                 if let Some(decl) = declaration {
                     let decl_node = node!(self.new_id(); attr!("label", "declaration"));
@@ -752,11 +1210,80 @@ impl ToGraphviz for AstNode {
                 edges.push(edge!(n.id.clone() => expr_node.id.clone()));
                 vec![attr!("label", "return")]
             }
+            AstNodeType::Match { value, arms } => {
+                let n_child = value.to_graphviz(graph);
+                edges.push(edge!(n.id.clone() => n_child.id.clone()));
+
+                for arm in arms {
+                    let arm_node = node!(self.new_id(); attr!("label", &format!("\"arm({})\"", arm.pattern.to_string().replace('"', "'"))));
+                    graph.add_stmt(Stmt::Node(arm_node.clone()));
+                    edges.push(edge!(n.id.clone() => arm_node.id.clone()));
+
+                    if let Some(guard) = &arm.guard {
+                        let guard_node = guard.to_graphviz(graph);
+                        edges.push(edge!(arm_node.id.clone() => guard_node.id.clone()));
+                    }
+
+                    for expr in &arm.body {
+                        let expr_node = expr.to_graphviz(graph);
+                        edges.push(edge!(arm_node.id.clone() => expr_node.id.clone()));
+                    }
+                }
+
+                vec![attr!("label", "match")]
+            }
+            AstNodeType::Propagate(ast_node) => {
+                let n_child = ast_node.to_graphviz(graph);
+                edges.push(edge!(n.id.clone() => n_child.id.clone()));
+                vec![attr!("label", "propagate")]
+            }
+            AstNodeType::Break(label) => vec![attr!(
+                "label",
+                &format!("\"break({})\"", label.as_deref().unwrap_or_default())
+            )],
+            AstNodeType::Continue(label) => vec![attr!(
+                "label",
+                &format!("\"continue({})\"", label.as_deref().unwrap_or_default())
+            )],
             AstNodeType::Weak(ast_node) => {
                 let expr_node = ast_node.to_graphviz(graph);
                 edges.push(edge!(n.id.clone() => expr_node.id.clone()));
                 vec![attr!("label", "weak")]
             },
+            AstNodeType::Closure {
+                params,
+                return_type,
+                execution_body,
+            } => {
+                for param in params {
+                    let param_node = node!(self.new_id(); attr!("label", param.0));
+                    graph.add_stmt(Stmt::Node(param_node.clone()));
+                    edges.push(edge!(n.id.clone() => param_node.id.clone()));
+
+                    let type_of = param.1.to_graphviz(graph);
+                    edges.push(edge!(param_node.id.clone() => type_of.id.clone()));
+                }
+
+                if let Some(ret_type) = return_type {
+                    let ret_node = node!(self.new_id(); attr!("label", "return"));
+                    graph.add_stmt(Stmt::Node(ret_node.clone()));
+                    edges.push(edge!(n.id.clone() => ret_node.id.clone()));
+
+                    let type_of = ret_type.to_graphviz(graph);
+                    edges.push(edge!(ret_node.id.clone() => type_of.id.clone()));
+                }
+
+                let exec_node = node!(self.new_id(); attr!("label", "execution_body"));
+                graph.add_stmt(Stmt::Node(exec_node.clone()));
+                edges.push(edge!(n.id.clone() => exec_node.id.clone()));
+
+                for stmt in execution_body {
+                    let stmt_node = stmt.to_graphviz(graph);
+                    edges.push(edge!(exec_node.id.clone() => stmt_node.id.clone()));
+                }
+
+                vec![attr!("label", "closure")]
+            }
             _ => vec![attr!("label", "groupDef")]
 ,
         };
@@ -793,34 +1320,98 @@ impl ToGraphviz for Vec<AstNode> {
 
 pub fn apply_string_escapes(s: &str) -> String {
     let mut result = String::new();
-    let s = s.chars().collect::<Vec<char>>();
-
-    let mut i = 0;
-    while i < s.len() {
-        if s[i] == '\\' {
-            if s[i + 1] == '\\' {
-                result.push('\\');
-                i += 1;
-            } else if s[i + 1] == '"' {
-                result.push('\"');
-                i += 1;
-            } else if s[i + 1] == 'n' {
-                result.push('\n');
-                i += 1;
-            } else if s[i + 1] == 'r' {
-                result.push('\r');
-                i += 1;
-            } else if s[i + 1] == 't' {
-                result.push('\t');
-                i += 1;
-            }
-
-            result.push('\\');
-        } else {
-            result.push(s[i]);
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('t') => result.push('\t'),
+            // \\, \", \{ and \}
+            Some(escaped) => result.push(escaped),
+            None => result.push('\\'),
         }
-        i += 1;
     }
 
     result
 }
+
+/// A string literal, including its quotes, that starts at start in the source.
+/// Every {expression} in it is parsed, the literal becomes an interpolation of its text and the expressions
+pub fn string_literal(
+    start: usize,
+    literal: &str,
+) -> Result<AstNode, ParseError<usize, ast_grammar::Token<'_>, &'static str>> {
+    let range = start..start + literal.len();
+    let content = &literal[1..literal.len() - 1];
+    // NOTE: positions in the content are offset by the opening quote
+    let offset = start + 1;
+
+    let mut parts = Vec::new();
+    let mut text_start = 0;
+    let mut chars = content.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '}' => {
+                return Err(ParseError::InvalidToken {
+                    location: offset + i,
+                });
+            }
+            '{' => {
+                if text_start < i {
+                    parts.push(Box::new(AstNode::new(
+                        offset + text_start..offset + i,
+                        AstNodeType::String(apply_string_escapes(&content[text_start..i])),
+                    )));
+                }
+
+                // NOTE: maps in the expression contain braces themselves
+                let mut depth = 1;
+                let end = chars
+                    .by_ref()
+                    .find(|(_, c)| {
+                        match c {
+                            '{' => depth += 1,
+                            '}' => depth -= 1,
+                            _ => (),
+                        }
+                        depth == 0
+                    })
+                    .map(|(end, _)| end)
+                    .ok_or_else(|| ParseError::UnrecognizedEof {
+                        location: offset + content.len(),
+                        expected: vec!["\"}\"".to_owned()],
+                    })?;
+
+                let mut expression = ast_grammar::ReturnableParser::new()
+                    .parse(&content[i + 1..end])
+                    .map_err(|err| err.map_location(|location| location + offset + i + 1))?;
+                expression.offset_ranges(offset + i + 1);
+                parts.push(Box::new(expression));
+                text_start = end + 1;
+            }
+            _ => (),
+        }
+    }
+
+    if parts.is_empty() {
+        return Ok(AstNode::new(
+            range,
+            AstNodeType::String(apply_string_escapes(content)),
+        ));
+    }
+    if text_start < content.len() {
+        parts.push(Box::new(AstNode::new(
+            offset + text_start..offset + content.len(),
+            AstNodeType::String(apply_string_escapes(&content[text_start..])),
+        )));
+    }
+    Ok(AstNode::new(range, AstNodeType::Interpolation(parts)))
+}
@@ -0,0 +1,29 @@
+use std::{path::Path, process::ExitCode};
+
+use parser::{NativeBindings, generate_bindings};
+
+/// Generate the binding cache for every header given on the command line.
+/// This is the only place libclang is needed, the preprocessor only reads the cache.
+fn main() -> ExitCode {
+    let headers = std::env::args().skip(1).collect::<Vec<_>>();
+    if headers.is_empty() {
+        eprintln!("usage: native_bindgen <header.h>...");
+        return ExitCode::FAILURE;
+    }
+
+    for header in headers {
+        let header = Path::new(&header);
+        let result = generate_bindings(header)
+            .and_then(|bindings| bindings.save(&NativeBindings::cache_path(header)));
+
+        match result {
+            Ok(()) => println!("generated {}", NativeBindings::cache_path(header).display()),
+            Err(err) => {
+                eprintln!("{err}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    ExitCode::SUCCESS
+}
@@ -1,15 +1,48 @@
-use std::fmt::Display;
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    io::{self, IsTerminal},
+    ops::Range,
+    rc::Rc,
+};
 
-use annotate_snippets::{AnnotationKind, Level, Patch, Renderer, Snippet, renderer::DecorStyle};
+use annotate_snippets::{
+    AnnotationKind, Group, Level, Patch, Renderer, Snippet, renderer::DecorStyle,
+};
 use lalrpop_util::ParseError;
+use serde_json::{Value, json};
 use thiserror::Error;
 
-use crate::{Symbol, ast_grammar};
+use crate::{FileId, InterpreterValue, SourceMap, Span, Symbol, TokenKind, ast_grammar};
 
 #[derive(Clone, Debug, Error)]
 pub struct ErrorWithRange {
     pub err: Error,
     pub range: std::ops::Range<usize>,
+    pub file: FileId,
+    /// Further ranges, that explain the error.
+    /// NOTE: a boxed slice keeps the error small, notes are only added while the error is passed on
+    pub notes: Box<[Note]>,
+}
+
+/// A range, that explains an error further
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Note {
+    /// A call of a function, that a runtime error left. Calls are added as the error leaves them, the innermost first.
+    /// NOTE: in the file of the error, functions can't be called from other files yet
+    Call(CallFrame),
+    /// A labelled range of any file, i.e. of a native header
+    Label(Span, String),
+    /// A similar name for the unknown one of the error. With the range of the unknown name,
+    /// if it is known, so it can be replaced
+    Suggestion(Option<Range<usize>>, Symbol),
+}
+
+/// A call of a function, with the range it was called from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallFrame {
+    pub name: Symbol,
+    pub range: Range<usize>,
 }
 
 impl std::fmt::Display for ErrorWithRange {
@@ -34,8 +67,8 @@ pub enum Error {
     TypeDoesNotExist(String),
     #[error("stage error, expected stage {0}, got stage {1}")]
     StageError(usize, usize),
-    #[error("main function not found, can't start execution")]
-    MainNotFound,
+    #[error("entrypoint function {0} not found, can't start execution")]
+    MainNotFound(String),
     #[error("expected {0} to be of type {1}, but received {2}")]
     WrongType(Symbol, String, String),
     #[error("Type cannot be deducted, missing type")]
@@ -58,352 +91,959 @@ pub enum Error {
     ParseError(ParseError<usize, ast_grammar::Token<'static>, &'static str>),
     #[error("type is not a scope")]
     IsNotAScope,
+    #[error("native bindings for {0} are missing or outdated, regenerate them with native_bindgen")]
+    NativeBindingsOutdated(String),
+    #[error("native bindings error: {0}")]
+    NativeBindingsError(String),
+    #[error("{0} is not a function and can't be called")]
+    NotCallable(Symbol),
+    #[error("{0} outside of a loop")]
+    LoopControlOutsideLoop(String),
+    #[error("no enclosing loop is labelled {0}")]
+    UnknownLoopLabel(Symbol),
+    #[error("match is not exhaustive, {0} is not covered")]
+    NonExhaustiveMatch(String),
+    /// Unwinds to the calling function, which returns the contained err(..) value
+    #[error("{0:?} was propagated with ? outside of a function")]
+    ErrorPropagation(Box<InterpreterValue>),
+    #[error("{1} has no method {0}")]
+    UnknownMethod(Symbol, String),
+    #[error("{0} expects {1} arguments, but received {2}")]
+    WrongArgumentCount(Symbol, usize, usize),
+    #[error("called unwrap on {0}")]
+    UnwrapFailed(String),
+    #[error("{0}")]
+    ExpectFailed(String),
+    #[error("if is used as a value, but has no else branch")]
+    MissingElse,
+    #[error("branches of if disagree on their type, expected {0}, but received {1}")]
+    BranchTypeMismatch(String, String),
+    #[error("{0} is a weak reference and must be upgraded before it is used")]
+    WeakNotUpgraded(Symbol),
+    #[error("weak reference was dropped, upgrade it to check if it is still alive")]
+    DanglingWeak,
+    #[error("{0} is used before it is declared")]
+    UsedBeforeDeclaration(Symbol),
+    #[error("integer overflow in {0}")]
+    IntegerOverflow(String),
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("{0} is not defined for {1} and {2} in strict mode")]
+    MixedTypes(String, String, String),
+    #[error("{0} are not supported yet")]
+    Unsupported(String),
+    #[error("assertion failed")]
+    AssertionFailed,
 }
 
 pub trait BeautifyError: Display {
-    fn print_error(&self, source: &str);
+    fn print_error_in(&self, sources: &SourceMap);
+    /// Prints the error for a single source without a path
+    fn print_error(&self, source: &str) {
+        self.print_error_in(&SourceMap::single(source));
+    }
     fn panic_error(&self, source: &str) {
         self.print_error(source);
         panic!("{}", self)
     }
 }
 
-impl<T: Display, E: Display> BeautifyError for ParseError<usize, T, E> {
-    fn print_error(&self, source: &str) {
+/// How severe a diagnostic is, only errors stop the script from running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    fn name(self) -> &'static str {
         match self {
-            ParseError::UnrecognizedEof { location, expected } => {
-                let report = &[Level::ERROR.primary_title("unexpected eof").element(
-                    Snippet::source(source).annotation(
-                        AnnotationKind::Primary
-                            .span(*location..*location + 1)
-                            .label(format!("expected, {}", expected.join(", "))),
-                    ),
-                )];
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+}
 
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
-            }
-            ParseError::UnrecognizedToken { token, expected } => {
-                let report = &[
-                    Level::ERROR
-                        .primary_title(format!("unexpected token {}", token.1))
-                        .element(
-                            Snippet::source(source).annotation(
-                                AnnotationKind::Primary
-                                    .span(token.0..token.2)
-                                    .label(format!("expected, {}", expected.join(", "))),
-                            ),
-                        ),
-                    Level::HELP.secondary_title("Possible fix").element(
-                        Snippet::source(source).patch(Patch::new(
-                            token.0..token.0 + 1,
-                            format!("try inserting one of [{}] here", expected.join(", ")),
-                        )),
-                    ),
-                ];
+/// A change of the source, that resolves a diagnostic
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fix {
+    pub message: String,
+    /// Replaced by the replacement, an empty range inserts it. In the file of the diagnostic
+    pub range: Range<usize>,
+    pub replacement: String,
+}
 
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
-            }
-            ParseError::InvalidToken { location } => {
-                let report = &[Level::ERROR.primary_title("invalid token").element(
-                    Snippet::source(source).annotation(
-                        AnnotationKind::Primary
-                            .span(*location..*location + 1)
-                            .label("token does not exist"),
-                    ),
-                )];
+/// An error, warning or note about a range of the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The stable code of the error or warning, notes have none
+    pub code: Option<&'static str>,
+    pub message: String,
+    /// Explains the range
+    pub label: String,
+    pub range: Range<usize>,
+    pub file: FileId,
+    /// Further ranges with their labels, i.e. the calls a runtime error left.
+    /// They can point into other files than the range
+    pub secondary: Vec<(Span, String)>,
+    pub fix: Option<Fix>,
+}
 
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
-            }
-            ParseError::ExtraToken { token } => {
-                let report = &[Level::ERROR
-                    .primary_title("unexpected extra token")
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(token.0..token.2)
-                                .label(format!("token {} is unexpected", token.1)),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
+/// The diagnostics of the stages, in the order they were found
+pub type Diagnostics = Vec<Diagnostic>;
+
+impl Diagnostic {
+    pub fn new(
+        severity: Severity,
+        message: impl Into<String>,
+        label: impl Into<String>,
+        range: Range<usize>,
+    ) -> Self {
+        Self {
+            severity,
+            code: None,
+            message: message.into(),
+            label: label.into(),
+            range,
+            file: FileId::MAIN,
+            secondary: Vec::new(),
+            fix: None,
+        }
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_fix(mut self, fix: Fix) -> Self {
+        self.fix = Some(fix);
+        self
+    }
+
+    pub fn in_file(mut self, file: FileId) -> Self {
+        self.file = file;
+        self
+    }
+
+    /// A snippet for the file of the range, followed by one for every other file of the secondary ranges.
+    /// The fix is a group of its own
+    fn groups<'a>(&'a self, sources: &'a SourceMap) -> Vec<Group<'a>> {
+        let level = match self.severity {
+            Severity::Error => Level::ERROR,
+            Severity::Warning => Level::WARNING,
+            Severity::Note => Level::NOTE,
+        };
+
+        let mut files = vec![self.file];
+        for (span, _) in &self.secondary {
+            if !files.contains(&span.file) {
+                files.push(span.file);
             }
-            ParseError::User { error } => {
-                println!("User error: {}", error);
+        }
+        let snippets = files.into_iter().map(|file| {
+            let source = sources.file(file);
+            let secondary = self
+                .secondary
+                .iter()
+                .filter(|(span, _)| span.file == file)
+                .map(|(span, label)| {
+                    AnnotationKind::Context
+                        .span(span.range.clone())
+                        .label(label)
+                });
+            let snippet = Snippet::source(&source.contents)
+                .path(source.path.as_deref())
+                .annotations(secondary);
+            if file == self.file {
+                snippet.annotation(
+                    AnnotationKind::Primary
+                        .span(self.range.clone())
+                        .label(&self.label),
+                )
+            } else {
+                snippet
             }
+        });
+        let title = level.primary_title(&self.message);
+        let title = match self.code {
+            Some(code) => title.id(code),
+            None => title,
+        };
+        let mut groups = vec![title.elements(snippets)];
+
+        if let Some(fix) = &self.fix {
+            let source = sources.file(self.file);
+            groups.push(
+                Level::HELP.secondary_title(&fix.message).element(
+                    Snippet::source(&source.contents)
+                        .path(source.path.as_deref())
+                        .patch(Patch::new(fix.range.clone(), &fix.replacement)),
+                ),
+            );
+        }
+        groups
+    }
+
+    /// The diagnostic as a JSON object. Lines and columns start at 1, columns count characters
+    pub fn to_json(&self, sources: &SourceMap) -> Value {
+        let span = |file, range: &Range<usize>| {
+            let source = sources.file(file);
+            let (line, column) = source.position(range.start);
+            json!({
+                "file": source.path,
+                "start": range.start,
+                "end": range.end,
+                "line": line,
+                "column": column,
+            })
+        };
+
+        json!({
+            "code": self.code,
+            "severity": self.severity.name(),
+            "message": self.message,
+            "label": self.label,
+            "span": span(self.file, &self.range),
+            "secondary": self
+                .secondary
+                .iter()
+                .map(|(secondary, label)| json!({
+                    "span": span(secondary.file, &secondary.range),
+                    "label": label,
+                }))
+                .collect::<Vec<_>>(),
+            "fix": self.fix.as_ref().map(|fix| json!({
+                "message": fix.message,
+                "span": span(self.file, &fix.range),
+                "replacement": fix.replacement,
+            })),
+        })
+    }
+}
+
+/// Renders the diagnostics together in one report, styled with the colors of a terminal or plain
+pub fn render_diagnostics(sources: &SourceMap, diagnostics: &[Diagnostic], styled: bool) -> String {
+    let report = diagnostics
+        .iter()
+        .flat_map(|diagnostic| diagnostic.groups(sources))
+        .collect::<Vec<_>>();
+    let renderer = if styled {
+        Renderer::styled()
+    } else {
+        Renderer::plain()
+    };
+    renderer.decor_style(DecorStyle::Unicode).render(&report)
+}
+
+/// Renders one JSON object per diagnostic and line, for tools like editor plugins
+pub fn render_json(sources: &SourceMap, diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(|diagnostic| format!("{}\n", diagnostic.to_json(sources)))
+        .collect()
+}
+
+/// Prints the diagnostics to stderr, styled only if it is a terminal
+pub fn print_diagnostics(sources: &SourceMap, diagnostics: &[Diagnostic]) {
+    if diagnostics.is_empty() {
+        return;
+    }
+    let styled = io::stderr().is_terminal();
+    eprintln!("{}", render_diagnostics(sources, diagnostics, styled));
+}
+
+/// The range of the token, that a syntax error is about
+pub fn parse_error_range<T, E>(err: &ParseError<usize, T, E>) -> Range<usize> {
+    match err {
+        ParseError::UnrecognizedEof { location, .. } | ParseError::InvalidToken { location } => {
+            *location..*location + 1
+        }
+        ParseError::UnrecognizedToken { token, .. } | ParseError::ExtraToken { token } => {
+            token.0..token.2
         }
+        ParseError::User { .. } => 0..0,
     }
 }
 
-impl BeautifyError for ErrorWithRange {
-    fn print_error(&self, source: &str) {
-        match &self.err {
-            Error::OperationUnsupported {
-                operation: _,
-                type_of,
-            } => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label(type_of),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
-            }
-            Error::SymbolNotFound(_symbol) => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label("unknown"),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
-            }
-            Error::CantBeEmpty => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label("must not be empty"),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
-            }
-            Error::CantCastAsType(type_of) => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label(format!("can't cast to {}", type_of)),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
-            }
-            Error::CantDerefWeak => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label("is weak, and cannot be dereferenced"),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
-            }
-            Error::CantDowncastToWeak => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label("can't be downcast to weak"),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
-            }
-            Error::CantUpgradeToStrong => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label("can't upgrade to strong"),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
-            }
-            Error::ExpectedValue(value) => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label(format!("expected {value}")),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
-            }
-            Error::MainNotFound => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label("no main function"),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
-            }
-            Error::MissingReturn(_func) => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label("missing return"),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
-            }
-            Error::ParseError(err) => {
-                err.print_error(source);
-            }
-            Error::StageError(should, is) => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label(format!("expected stage {should}, got stage {is}")),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
+/// The code of every syntax error
+pub const PARSE_ERROR_CODE: &str = "E0018";
+
+/// The diagnostics of a syntax error. If a single token would have been accepted instead,
+/// inserting it is the fix, otherwise the accepted tokens are a note
+pub fn parse_diagnostics<T: Display, E: Display>(err: &ParseError<usize, T, E>) -> Diagnostics {
+    let diagnostics = match err {
+        ParseError::UnrecognizedEof { location, expected } => vec![Diagnostic::new(
+            Severity::Error,
+            "unexpected eof",
+            format!("expected, {}", expected.join(", ")),
+            *location..*location + 1,
+        )],
+        ParseError::UnrecognizedToken { token, expected } => {
+            let diagnostic = Diagnostic::new(
+                Severity::Error,
+                format!("unexpected token {}", token.1),
+                format!("expected, {}", expected.join(", ")),
+                token.0..token.2,
+            );
+            let insertion = match expected.as_slice() {
+                [expected] => TokenKind::from_terminal(expected).and_then(TokenKind::text),
+                _ => None,
+            };
+            match insertion {
+                Some(text) => vec![diagnostic.with_fix(Fix {
+                    message: format!("insert {text}"),
+                    range: token.0..token.0,
+                    replacement: text.to_owned(),
+                })],
+                None => vec![
+                    diagnostic,
+                    Diagnostic::new(
+                        Severity::Note,
+                        "possible fix",
+                        format!("try inserting one of [{}] here", expected.join(", ")),
+                        token.0..token.0,
+                    ),
+                ],
             }
-            Error::TypeAlreadyExists(type_of) => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label(format!("{type_of} already exists")),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
+        }
+        ParseError::InvalidToken { location } => vec![Diagnostic::new(
+            Severity::Error,
+            "invalid token",
+            "token does not exist",
+            *location..*location + 1,
+        )],
+        ParseError::ExtraToken { token } => vec![Diagnostic::new(
+            Severity::Error,
+            "unexpected extra token",
+            format!("token {} is unexpected", token.1),
+            token.0..token.2,
+        )],
+        ParseError::User { error } => vec![Diagnostic::new(
+            Severity::Error,
+            format!("User error: {error}"),
+            "",
+            0..0,
+        )],
+    };
+    diagnostics
+        .into_iter()
+        .map(|diagnostic| match diagnostic.severity {
+            Severity::Note => diagnostic,
+            _ => diagnostic.with_code(PARSE_ERROR_CODE),
+        })
+        .collect()
+}
+
+impl<T: Display, E: Display> BeautifyError for ParseError<usize, T, E> {
+    fn print_error_in(&self, sources: &SourceMap) {
+        print_diagnostics(sources, &parse_diagnostics(self));
+    }
+}
+
+impl Error {
+    /// Explains the range of the source, that the error is about
+    pub fn label(&self) -> String {
+        match self {
+            Error::OperationUnsupported { type_of, .. } => type_of.clone(),
+            Error::SymbolNotFound(_symbol) => "unknown".to_owned(),
+            Error::CantBeEmpty => "must not be empty".to_owned(),
+            Error::CantCastAsType(type_of) => format!("can't cast to {type_of}"),
+            Error::CantDerefWeak => "is weak, and cannot be dereferenced".to_owned(),
+            Error::CantDowncastToWeak => "can't be downcast to weak".to_owned(),
+            Error::CantUpgradeToStrong => "can't upgrade to strong".to_owned(),
+            Error::ExpectedValue(value) => format!("expected {value}"),
+            Error::MainNotFound(_) => "no entrypoint function".to_owned(),
+            Error::MissingReturn(_func) => "missing return".to_owned(),
+            // NOTE: parse errors are explained by the diagnostics of the parse error itself
+            Error::ParseError(_) => "syntax error".to_owned(),
+            Error::StageError(should, is) => format!("expected stage {should}, got stage {is}"),
+            Error::TypeAlreadyExists(type_of) => format!("{type_of} already exists"),
+            Error::TypeDeductionError => "wrong type deducted".to_owned(),
+            Error::TypeDoesNotExist(_type_of) => "does not exist".to_owned(),
+            Error::ValueAndTypeDoNotMatch(type_of, _value_of) => format!("{type_of} != value_of"),
+            Error::VariableAlreadyDeclared(_var) => "already declared".to_owned(),
+            Error::WrongType(_, expected, _) => format!("should be of type {expected}"),
+            Error::IsNotAScope => {
+                "should be a scope like type (struct, module, component)".to_owned()
             }
-            Error::TypeDeductionError => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label("wrong type deducted"),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
+            Error::NativeBindingsOutdated(_header) => "bindings are out of date".to_owned(),
+            Error::NativeBindingsError(_) => "while loading native bindings".to_owned(),
+            Error::NotCallable(_) => "should be a function or closure".to_owned(),
+            Error::LoopControlOutsideLoop(_) => {
+                "only allowed inside of while, for and for in loops".to_owned()
             }
-            Error::TypeDoesNotExist(_type_of) => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label("does not exist"),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
+            Error::UsedBeforeDeclaration(_) => "declared later in this function".to_owned(),
+            Error::IntegerOverflow(_) => {
+                "does not fit into an int, use wrapping_ or saturating_ functions to allow it"
+                    .to_owned()
             }
-            Error::ValueAndTypeDoNotMatch(type_of, _value_of) => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label(format!("{type_of} != value_of")),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
+            Error::DivisionByZero => "divisor is 0".to_owned(),
+            Error::MixedTypes(_, _, _) => {
+                "convert one of the operands with int() or float()".to_owned()
             }
-            Error::VariableAlreadyDeclared(_var) => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label("already declared"),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
+            Error::UnknownLoopLabel(_) => "label is not declared by an enclosing loop".to_owned(),
+            Error::NonExhaustiveMatch(_) => {
+                "add an arm for the missing pattern or a wildcard _".to_owned()
             }
-            Error::WrongType(_, expected, _) => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label(format!("should be of type {expected}")),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
+            Error::ErrorPropagation(_) => "? can only be used inside of functions".to_owned(),
+            Error::UnknownMethod(_, _) => "method does not exist".to_owned(),
+            Error::WrongArgumentCount(_, _, _) => "wrong number of arguments".to_owned(),
+            Error::UnwrapFailed(_) => "value is empty or an error".to_owned(),
+            Error::ExpectFailed(_) => "expectation failed here".to_owned(),
+            Error::MissingElse => "add an else branch to produce a value in every case".to_owned(),
+            Error::BranchTypeMismatch(_, _) => {
+                "must have the same type as the other branches".to_owned()
             }
-            Error::IsNotAScope => {
-                let report = &[Level::ERROR
-                    .primary_title(format!("{}", &self.err))
-                    .element(
-                        Snippet::source(source).annotation(
-                            AnnotationKind::Primary
-                                .span(self.range.clone())
-                                .label("should be a scope like type (struct, module, component)"),
-                        ),
-                    )];
-
-                let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
-                println!("{}", renderer.render(report));
+            Error::WeakNotUpgraded(_) => "use .upgrade() to get an option of the value".to_owned(),
+            Error::DanglingWeak => "the referenced value does not exist anymore".to_owned(),
+            Error::Unsupported(_) => "can't be run by the interpreter or the vm".to_owned(),
+            Error::AssertionFailed => "the condition is false".to_owned(),
+        }
+    }
+
+    /// The stable code of the error, explained by explain.
+    /// NOTE: codes are never reused, new errors get the next free code
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::OperationUnsupported { .. } => "E0001",
+            Error::CantDerefWeak => "E0002",
+            Error::VariableAlreadyDeclared(_) => "E0003",
+            Error::ValueAndTypeDoNotMatch(_, _) => "E0004",
+            Error::TypeAlreadyExists(_) => "E0005",
+            Error::TypeDoesNotExist(_) => "E0006",
+            Error::StageError(_, _) => "E0007",
+            Error::MainNotFound(_) => "E0008",
+            Error::WrongType(_, _, _) => "E0009",
+            Error::TypeDeductionError => "E0010",
+            Error::MissingReturn(_) => "E0011",
+            Error::ExpectedValue(_) => "E0012",
+            Error::CantDowncastToWeak => "E0013",
+            Error::CantUpgradeToStrong => "E0014",
+            Error::CantBeEmpty => "E0015",
+            Error::SymbolNotFound(_) => "E0016",
+            Error::CantCastAsType(_) => "E0017",
+            Error::ParseError(_) => PARSE_ERROR_CODE,
+            Error::IsNotAScope => "E0019",
+            Error::NativeBindingsOutdated(_) => "E0020",
+            Error::NativeBindingsError(_) => "E0021",
+            Error::NotCallable(_) => "E0022",
+            Error::LoopControlOutsideLoop(_) => "E0023",
+            Error::UnknownLoopLabel(_) => "E0024",
+            Error::NonExhaustiveMatch(_) => "E0025",
+            Error::ErrorPropagation(_) => "E0026",
+            Error::UnknownMethod(_, _) => "E0027",
+            Error::WrongArgumentCount(_, _, _) => "E0028",
+            Error::UnwrapFailed(_) => "E0029",
+            Error::ExpectFailed(_) => "E0030",
+            Error::MissingElse => "E0031",
+            Error::BranchTypeMismatch(_, _) => "E0032",
+            Error::WeakNotUpgraded(_) => "E0033",
+            Error::DanglingWeak => "E0034",
+            Error::UsedBeforeDeclaration(_) => "E0035",
+            Error::IntegerOverflow(_) => "E0036",
+            Error::DivisionByZero => "E0037",
+            Error::MixedTypes(_, _, _) => "E0038",
+            Error::Unsupported(_) => "E0039",
+            Error::AssertionFailed => "E0040",
+        }
+    }
+}
+
+impl ErrorWithRange {
+    pub fn new(err: Error, range: Range<usize>) -> Self {
+        Self {
+            err,
+            range,
+            file: FileId::MAIN,
+            notes: Box::default(),
+        }
+    }
+
+    fn add_note(&mut self, note: Note) {
+        let mut notes = std::mem::take(&mut self.notes).into_vec();
+        notes.push(note);
+        self.notes = notes.into_boxed_slice();
+    }
+
+    pub fn in_file(mut self, file: FileId) -> Self {
+        self.file = file;
+        self
+    }
+
+    pub fn with_note(mut self, span: Span, label: impl Into<String>) -> Self {
+        self.add_note(Note::Label(span, label.into()));
+        self
+    }
+
+    /// Adds the call of a function to the trace, as the error leaves it
+    pub fn in_call(mut self, name: &Symbol, range: &Range<usize>) -> Self {
+        self.add_note(Note::Call(CallFrame {
+            name: name.clone(),
+            range: range.clone(),
+        }));
+        self
+    }
+
+    /// Suggests a similar name for the unknown symbol or type of the error, other errors stay as they are.
+    /// The range is the one of the unknown name, if the caller knows it
+    pub fn suggest(
+        mut self,
+        range: Option<Range<usize>>,
+        similar: impl FnOnce(&Symbol) -> Option<Symbol>,
+    ) -> Self {
+        let (Error::SymbolNotFound(name) | Error::TypeDoesNotExist(name)) = &self.err else {
+            return self;
+        };
+        if let Some(similar) = similar(name) {
+            self.add_note(Note::Suggestion(range, similar));
+        }
+        self
+    }
+
+    /// Removes the call of the entrypoint, it is not called from the source and has no call site
+    pub fn outside_entrypoint(mut self) -> Self {
+        let mut notes = std::mem::take(&mut self.notes).into_vec();
+        if let Some(index) = notes.iter().rposition(|note| matches!(note, Note::Call(_))) {
+            notes.remove(index);
+        }
+        self.notes = notes.into_boxed_slice();
+        self
+    }
+
+    /// The calls, that the error left, the innermost first
+    pub fn trace(&self) -> Vec<&CallFrame> {
+        self.notes
+            .iter()
+            .filter_map(|note| match note {
+                Note::Call(frame) => Some(frame),
+                Note::Label(..) | Note::Suggestion(..) => None,
+            })
+            .collect()
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        match &self.err {
+            Error::ParseError(err) => parse_diagnostics(err)
+                .into_iter()
+                .map(|diagnostic| diagnostic.in_file(self.file))
+                .collect(),
+            err => {
+                let mut diagnostic = Diagnostic::new(
+                    Severity::Error,
+                    err.to_string(),
+                    err.label(),
+                    self.range.clone(),
+                )
+                .with_code(err.code())
+                .in_file(self.file);
+                for note in &self.notes {
+                    match note {
+                        Note::Call(frame) => diagnostic.secondary.push((
+                            Span::new(self.file, frame.range.clone()),
+                            format!("{} called here", frame.name),
+                        )),
+                        Note::Label(span, label) => {
+                            diagnostic.secondary.push((span.clone(), label.clone()))
+                        }
+                        Note::Suggestion(Some(range), similar) => {
+                            diagnostic.fix = Some(Fix {
+                                message: format!("did you mean {similar}?"),
+                                range: range.clone(),
+                                replacement: similar.clone(),
+                            })
+                        }
+                        Note::Suggestion(None, similar) => {
+                            diagnostic.label =
+                                format!("{}, did you mean {similar}?", diagnostic.label)
+                        }
+                    }
+                }
+                vec![diagnostic]
             }
         }
     }
+}
+
+impl BeautifyError for ErrorWithRange {
+    fn print_error_in(&self, sources: &SourceMap) {
+        print_diagnostics(sources, &self.diagnostics());
+    }
 
     fn panic_error(&self, source: &str) {
         self.print_error(source);
         panic!("{}", self.err)
     }
 }
+
+#[derive(Clone, Debug, Error)]
+pub struct WarningWithRange {
+    pub warning: Warning,
+    pub range: std::ops::Range<usize>,
+}
+
+impl std::fmt::Display for WarningWithRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}{}", self.warning, self.range.start, self.range.end)
+    }
+}
+
+/// Warnings are shared between a stage and its creator, as stages are consumed when they run
+pub type Warnings = Rc<RefCell<Vec<WarningWithRange>>>;
+
+#[derive(Error, Debug, Clone)]
+pub enum Warning {
+    #[error("strong reference cycle {}", .cycle.join(" -> "))]
+    StrongReferenceCycle {
+        cycle: Vec<Symbol>,
+        owner: Symbol,
+        field: Symbol,
+    },
+    #[error("{0} leaked in a reference cycle")]
+    LeakedCycle(String),
+    #[error("unreachable code")]
+    UnreachableCode,
+}
+
+impl Warning {
+    /// The stable code of the warning, explained by explain
+    pub fn code(&self) -> &'static str {
+        match self {
+            Warning::StrongReferenceCycle { .. } => "W0001",
+            Warning::LeakedCycle(_) => "W0002",
+            Warning::UnreachableCode => "W0003",
+        }
+    }
+}
+
+impl WarningWithRange {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let label = match &self.warning {
+            Warning::StrongReferenceCycle { owner, field, .. } => {
+                format!("mark {owner}.{field} as weak to break the cycle")
+            }
+            Warning::LeakedCycle(_) => "created here, but never freed".to_owned(),
+            Warning::UnreachableCode => "never executed, the block is left before".to_owned(),
+        };
+        Diagnostic::new(
+            Severity::Warning,
+            self.warning.to_string(),
+            label,
+            self.range.clone(),
+        )
+        .with_code(self.warning.code())
+    }
+}
+
+impl BeautifyError for WarningWithRange {
+    fn print_error_in(&self, sources: &SourceMap) {
+        print_diagnostics(sources, &[self.diagnostic()]);
+    }
+}
+
+/// The explanations of the error and warning codes
+const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "E0001",
+        "The operator or operation is not defined for the type of its operand, \
+         i.e. adding a bool to a string. Convert the operand or use a method of the type instead.",
+    ),
+    (
+        "E0002",
+        "A weak reference was used like the value it references. \
+         Upgrade it to a strong reference first.",
+    ),
+    (
+        "E0003",
+        "A variable was declared twice in the same scope. \
+         Assign to the existing variable with = or choose another name.",
+    ),
+    (
+        "E0004",
+        "The value of a declaration does not have the type the declaration names, \
+         i.e. `let a: int = \"text\";`.",
+    ),
+    (
+        "E0005",
+        "A struct or component was defined twice. Every type needs a unique name.",
+    ),
+    (
+        "E0006",
+        "A type was used, that is neither built in nor defined by the script or its imports. \
+         Check the spelling or define the type.",
+    ),
+    (
+        "E0007",
+        "A stage of the compiler received the result of another stage, than it runs after. \
+         This is a bug of the compiler, not of the script.",
+    ),
+    (
+        "E0008",
+        "The entrypoint function does not exist. Define `fn main()` \
+         or select another function with --entry.",
+    ),
+    (
+        "E0009",
+        "A value has another type, than its use requires, \
+         i.e. an argument of a function or the condition of an if.",
+    ),
+    (
+        "E0010",
+        "The type of a declaration can't be deduced from its value. \
+         Name the type, i.e. `let a: [int] = [];`.",
+    ),
+    (
+        "E0011",
+        "A function with a return type can end without returning a value. \
+         Return a value on every path of the function.",
+    ),
+    (
+        "E0012",
+        "A function was called without a value for one of its parameters. \
+         Pass a value for every parameter.",
+    ),
+    (
+        "E0013",
+        "Only strong references can be downcast to weak ones, \
+         the value is not a reference or already weak.",
+    ),
+    (
+        "E0014",
+        "The value can't be upgraded to a strong reference, it is not a weak reference.",
+    ),
+    (
+        "E0015",
+        "A declaration or assignment received no value, i.e. the result of a function without \
+         a return type. Only values can be stored in variables.",
+    ),
+    (
+        "E0016",
+        "A name was used, that is not declared in its scope or any enclosing one. \
+         Check the spelling or declare it before its use.",
+    ),
+    (
+        "E0017",
+        "The value can't be used as the type, i.e. a for in loop iterates over a value, \
+         that is not a list.",
+    ),
+    (
+        "E0018",
+        "The source does not follow the grammar of the language, \
+         the diagnostic lists the tokens, that would have been accepted instead.",
+    ),
+    (
+        "E0019",
+        "Members were accessed on a type, that has none. \
+         Only structs, components and modules contain members.",
+    ),
+    (
+        "E0020",
+        "The generated bindings of a native import are missing or were generated for another \
+         version of the header. Regenerate them with native_bindgen.",
+    ),
+    (
+        "E0021",
+        "The bindings of a native import could not be loaded, \
+         i.e. the shared library or one of its symbols is missing.",
+    ),
+    (
+        "E0022",
+        "A value was called like a function, but it is neither a function nor a closure.",
+    ),
+    (
+        "E0023",
+        "break or continue was used outside of a while, for or for in loop.",
+    ),
+    (
+        "E0024",
+        "break or continue names a label, that no enclosing loop declares.",
+    ),
+    (
+        "E0025",
+        "A match has no arm for some values of its subject. \
+         Add arms for the missing patterns or a wildcard `_` arm.",
+    ),
+    (
+        "E0026",
+        "An err(..) was propagated with ? outside of a function, \
+         there is no function to return it from.",
+    ),
+    (
+        "E0027",
+        "The type has no method with the name. Check the spelling or define the method.",
+    ),
+    (
+        "E0028",
+        "A function was called with more or fewer arguments, than it has parameters.",
+    ),
+    (
+        "E0029",
+        "unwrap was called on none or an err(..). Handle the empty case with match \
+         or propagate the error with ?.",
+    ),
+    (
+        "E0030",
+        "expect was called on none or an err(..), the message is the one passed to expect.",
+    ),
+    (
+        "E0031",
+        "An if is used as a value, but has no else branch, \
+         so it has no value if its condition is false.",
+    ),
+    (
+        "E0032",
+        "The branches of an if, that is used as a value, produce values of different types.",
+    ),
+    (
+        "E0033",
+        "A weak reference was used without upgrading it. \
+         .upgrade() returns an option, that is none if the value was freed.",
+    ),
+    (
+        "E0034",
+        "The value of a weak reference was freed. \
+         Upgrade weak references and check the option before using them.",
+    ),
+    (
+        "E0035",
+        "A variable was used before its declaration in the same function. \
+         Move the declaration before its first use.",
+    ),
+    (
+        "E0036",
+        "The result of an integer operation does not fit into an int. \
+         Use the wrapping_ or saturating_ functions, if this is intended.",
+    ),
+    (
+        "E0037",
+        "An integer was divided by 0, or the remainder of a division by 0 was taken.",
+    ),
+    (
+        "E0038",
+        "In strict mode, operators don't convert between int and float. \
+         Convert one of the operands with int() or float().",
+    ),
+    (
+        "E0039",
+        "The syntax is known, but the interpreter and the vm can't run it yet, i.e. map literals. \
+         Use a list of structs with the keys and values instead.",
+    ),
+    (
+        "E0040",
+        "The condition passed to assert is false, the script stops with a runtime error.",
+    ),
+    (
+        "W0001",
+        "Structs reference each other with strong references in a cycle, \
+         so values of them can keep each other alive forever. Mark one of the fields as weak.",
+    ),
+    (
+        "W0002",
+        "Values referenced each other strongly, when they went out of scope, \
+         so they were never freed.",
+    ),
+    (
+        "W0003",
+        "The code is never executed, the block is left by a return, break or continue before it.",
+    ),
+];
+
+/// The explanation of an error or warning code, None for unknown codes
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(code))
+        .map(|(_, explanation)| *explanation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EXPLANATIONS, Error, SourceMap, Warning, explain, parse_diagnostics};
+    use crate::{Diagnostic, ErrorWithRange, FileId, Severity, Span, ast_grammar};
+
+    #[test]
+    fn explains_every_code() {
+        for (i, (code, _)) in EXPLANATIONS.iter().enumerate() {
+            assert!(
+                EXPLANATIONS[..i].iter().all(|(other, _)| other != code),
+                "{code} is explained twice"
+            );
+        }
+        let codes = [
+            Error::DivisionByZero.code(),
+            Error::MixedTypes(String::new(), String::new(), String::new()).code(),
+            Error::ExpectedValue("a".into()).code(),
+            Error::Unsupported("map literals".to_owned()).code(),
+            Error::AssertionFailed.code(),
+            Warning::UnreachableCode.code(),
+        ];
+        assert_eq!(
+            codes,
+            ["E0037", "E0038", "E0012", "E0039", "E0040", "W0003"]
+        );
+        for code in codes {
+            assert!(explain(code).is_some(), "{code} has no explanation");
+        }
+        assert_eq!(explain("e0037"), explain("E0037"));
+        assert_eq!(explain("E9999"), None);
+    }
+
+    #[test]
+    fn renders_json_lines() {
+        let source = "fn main() {\n    x := (1 + 2;\n}";
+        let sources = SourceMap::new(Some("main.ecs".to_owned()), source);
+        let err = ast_grammar::ProgrammParser::new()
+            .parse(source)
+            .unwrap_err();
+        let diagnostics = parse_diagnostics(&err);
+
+        // a single expected token is inserted by the fix
+        assert_eq!(diagnostics.len(), 1);
+        let json = diagnostics[0].to_json(&sources);
+        assert_eq!(json["code"], "E0018");
+        assert_eq!(json["severity"], "error");
+        assert_eq!(json["span"]["file"], "main.ecs");
+        assert_eq!(
+            (&json["span"]["line"], &json["span"]["column"]),
+            (&2.into(), &16.into())
+        );
+        assert_eq!(json["fix"]["replacement"], ")");
+        assert_eq!(json["fix"]["span"]["start"], source.find(';').unwrap());
+
+        let err = ErrorWithRange::new(Error::DivisionByZero, 4..8)
+            .in_call(&"main".into(), &(0..2))
+            .with_note(Span::new(FileId::MAIN, 9..10), "here");
+        let diagnostics: Vec<Diagnostic> = err.diagnostics();
+        let lines = super::render_json(&sources, &diagnostics);
+        assert_eq!(lines.lines().count(), 1);
+        let json: serde_json::Value = serde_json::from_str(lines.trim_end()).unwrap();
+        assert_eq!(json["code"], "E0037");
+        assert_eq!(json["secondary"][0]["label"], "main called here");
+        assert_eq!(json["secondary"][1]["span"]["start"], 9);
+        assert_eq!(json["fix"], serde_json::Value::Null);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn suggestions_become_fixes() {
+        let unknown = || ErrorWithRange::new(Error::SymbolNotFound("sped".to_owned()), 8..12);
+
+        let err = unknown().suggest(Some(8..12), |_| Some("speed".to_owned()));
+        let fix = err.diagnostics()[0].fix.clone().unwrap();
+        assert_eq!((fix.range, fix.replacement.as_str()), (8..12, "speed"));
+
+        // without the range of the name, the label suggests it
+        let err = unknown().suggest(None, |_| Some("speed".to_owned()));
+        let diagnostic = &err.diagnostics()[0];
+        assert_eq!(diagnostic.fix, None);
+        assert_eq!(diagnostic.label, "unknown, did you mean speed?");
+
+        assert!(unknown().suggest(None, |_| None).notes.is_empty());
+        let err =
+            ErrorWithRange::new(Error::DivisionByZero, 0..1).suggest(None, |_| Some("a".into()));
+        assert!(err.notes.is_empty());
+    }
+}
@@ -0,0 +1,1151 @@
+use std::{collections::VecDeque, ops::Range};
+
+use crate::{
+    AssignmentOperations, AstNode, AstNodeType, AstTypeDefinition, GroupSystem, InfixOperator,
+    Lexer, MatchArm, MemberAccess, MemberAccessType, Pattern, PrefixOperator, Query, QueryCond,
+    QueryType, RegisterType, Symbol, TokenKind, TypeSymbol, TypeSymbolType,
+    ast_grammar::{self, Spanned, TokenError},
+};
+
+const INDENT: &str = "    ";
+
+/// Parses the source and prints it back in the canonical layout.
+/// Comments are kept before or after the statement, field, query or match arm they are in,
+/// a comment inside an expression stays behind its token and the expression continues below it
+pub fn format_source(source: &str) -> Result<String, TokenError<'_>> {
+    let ast = ast_grammar::ProgrammParser::new().parse(source)?;
+
+    let mut formatter = Formatter::new(source);
+    formatter.statements(&ast, source.len());
+    let mut formatted = formatter.out;
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+    Ok(formatted)
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    /// Tokens without comments, they tell what the ast does not keep, i.e. semicolons
+    tokens: Vec<Spanned<'a>>,
+    /// The comments, that are not written yet
+    comments: VecDeque<Range<usize>>,
+    out: String,
+    indent: usize,
+    /// Where the last statement or comment, that was written, ends in the source
+    last_end: usize,
+    /// No blank line is kept before the first line of a block
+    block_start: bool,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str) -> Self {
+        let (comments, tokens): (Vec<_>, Vec<_>) =
+            Lexer::new(source).partition(|(_, token, _)| token.0 == TokenKind::Comment);
+
+        Self {
+            source,
+            tokens,
+            comments: comments
+                .into_iter()
+                .map(|(start, _, end)| start..end)
+                .collect(),
+            out: String::new(),
+            indent: 0,
+            last_end: 0,
+            block_start: true,
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        self.out.push_str(text);
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    /// Starts the line of something at the offset. Keeps one blank line before it, if the source had one
+    fn line(&mut self, start: usize) {
+        if self.out.is_empty() {
+            self.block_start = false;
+            return;
+        }
+
+        let gap = self.source.get(self.last_end..start).unwrap_or_default();
+        if !self.block_start && gap.matches('\n').count() > 1 {
+            self.out.push('\n');
+        }
+        self.block_start = false;
+        self.newline();
+    }
+
+    fn comment(&mut self, range: Range<usize>) {
+        let text = self.source[range.clone()].trim_end().to_owned();
+        self.write(&text);
+        self.last_end = self.last_end.max(range.end);
+    }
+
+    /// Writes the comments before the offset on lines of their own
+    fn leading_comments(&mut self, offset: usize) {
+        while let Some(comment) = self.comments.front().filter(|c| c.start < offset).cloned() {
+            self.comments.pop_front();
+            self.line(comment.start);
+            self.comment(comment);
+        }
+    }
+
+    /// Writes the comments behind something, that ends at the offset, i.e. the ones behind its last
+    /// token or its separator. Also the comments inside of it, that were not written yet
+    fn trailing_comments(&mut self, end: usize) {
+        let mut same_line = true;
+        while let Some(comment) = self.comments.front().cloned() {
+            if comment.start >= end && !self.is_behind(end, &comment) {
+                break;
+            }
+
+            self.comments.pop_front();
+            if same_line {
+                self.write(" ");
+            } else {
+                self.newline();
+            }
+            self.comment(comment);
+            same_line = false;
+        }
+    }
+
+    /// Whether the comment is on the line something ends at, with only separators between them
+    fn is_behind(&self, end: usize, comment: &Range<usize>) -> bool {
+        let between = &self.tokens[self.token_index(end)..self.token_index(comment.start)];
+        between
+            .iter()
+            .all(|(_, token, _)| matches!(token.0, TokenKind::Semicolon | TokenKind::Comma))
+            && !self.source[end..comment.start].contains('\n')
+    }
+
+    /// Writes the comments before a part of an expression. A comment behind a token stays there,
+    /// the expression continues on the next line
+    fn inner_comments(&mut self, start: usize) {
+        if self.comments.front().is_none_or(|c| c.start >= start) {
+            return;
+        }
+
+        self.indent += 1;
+        while let Some(comment) = self.comments.front().filter(|c| c.start < start).cloned() {
+            self.comments.pop_front();
+            let own_line = self.source[..comment.start]
+                .trim_end_matches([' ', '\t'])
+                .ends_with('\n');
+            self.out.truncate(self.out.trim_end_matches(' ').len());
+            if own_line {
+                self.newline();
+            } else {
+                self.write(" ");
+            }
+            self.comment(comment);
+        }
+        self.newline();
+        self.indent -= 1;
+    }
+
+    fn token_index(&self, offset: usize) -> usize {
+        self.tokens.partition_point(|(start, _, _)| *start < offset)
+    }
+
+    /// Whether the body of a function is a single expression after =>, instead of a block
+    fn is_arrow_body(&self, body: &[Box<AstNode>]) -> bool {
+        let [expression] = body else {
+            return false;
+        };
+        let index = self.token_index(expression.range.start);
+        self.tokens[..index]
+            .iter()
+            .rev()
+            .map(|(_, token, _)| token.0)
+            .find(|kind| *kind != TokenKind::LParen)
+            == Some(TokenKind::FatArrow)
+    }
+
+    /// Where the closing brace of the first block after the offset is
+    fn closing_brace(&self, offset: usize) -> usize {
+        let mut depth = 0;
+        for (start, token, _) in &self.tokens[self.token_index(offset)..] {
+            match token.0 {
+                TokenKind::LBrace => depth += 1,
+                TokenKind::RBrace if depth == 1 => return *start,
+                TokenKind::RBrace => depth -= 1,
+                _ => (),
+            }
+        }
+        self.source.len()
+    }
+
+    /// Where the opening brace of the block is, that the brace at the offset closes
+    fn opening_brace(&self, closing: usize) -> usize {
+        let mut depth = 0;
+        for (start, token, _) in self.tokens[..self.token_index(closing)].iter().rev() {
+            match token.0 {
+                TokenKind::RBrace => depth += 1,
+                TokenKind::LBrace if depth == 0 => return *start,
+                TokenKind::LBrace => depth -= 1,
+                _ => (),
+            }
+        }
+        0
+    }
+
+    /// Where the last token before the offset ends, i.e. the separator of a member before the next one
+    fn token_end_before(&self, offset: usize) -> usize {
+        self.tokens[..self.token_index(offset)]
+            .last()
+            .map_or(offset, |(_, _, end)| *end)
+    }
+
+    /// Where the members inside the range start, that are nested depth deep in brackets,
+    /// i.e. the fields of a struct by an identifier followed by a colon
+    fn member_starts(&self, range: &Range<usize>, depth: usize, follow: TokenKind) -> Vec<usize> {
+        let tokens = &self.tokens[self.token_index(range.start)..self.token_index(range.end)];
+        let mut current = 0;
+        let mut starts = Vec::new();
+        for (i, (start, token, _)) in tokens.iter().enumerate() {
+            match token.0 {
+                TokenKind::LParen | TokenKind::LBracket | TokenKind::LBrace => current += 1,
+                TokenKind::RParen | TokenKind::RBracket | TokenKind::RBrace => current -= 1,
+                TokenKind::Id
+                    if current == depth
+                        && tokens.get(i + 1).map(|(_, token, _)| token.0) == Some(follow) =>
+                {
+                    starts.push(*start)
+                }
+                _ => (),
+            }
+        }
+        starts
+    }
+
+    fn statements(&mut self, nodes: &[AstNode], end: usize) {
+        for node in nodes {
+            self.statement(node);
+        }
+        self.leading_comments(end);
+    }
+
+    fn statement(&mut self, node: &AstNode) {
+        self.leading_comments(node.range.start);
+        self.line(node.range.start);
+        self.node(node);
+
+        let semicolon = match &node.type_of {
+            AstNodeType::Import(..)
+            | AstNodeType::ImportNative(..)
+            | AstNodeType::Declaration { .. }
+            | AstNodeType::EntityDeclaration { .. }
+            | AstNodeType::AssignmentOp { .. }
+            | AstNodeType::Register { .. }
+            | AstNodeType::EntityDef { .. }
+            | AstNodeType::ReturnStatement { .. }
+            | AstNodeType::Break(_)
+            | AstNodeType::Continue(_)
+            | AstNodeType::ExpressionStatement(_) => true,
+            // NOTE: an expression without semicolon is the value of its block
+            _ => false,
+        };
+        if semicolon {
+            self.write(";");
+        }
+
+        self.last_end = self.last_end.max(node.range.end);
+        self.trailing_comments(node.range.end);
+    }
+
+    /// A block of statements, that is closed by the brace at end
+    fn block(&mut self, body: &[Box<AstNode>], end: usize) {
+        self.write("{");
+        if body.is_empty() && self.comments.front().is_none_or(|c| c.start >= end) {
+            self.write("}");
+            return;
+        }
+
+        self.indent += 1;
+        self.block_start = true;
+        for node in body {
+            self.statement(node);
+        }
+        self.leading_comments(end);
+        self.indent -= 1;
+        self.newline();
+        self.write("}");
+    }
+
+    fn node(&mut self, node: &AstNode) {
+        match &node.type_of {
+            AstNodeType::Import(module, alias) => {
+                self.write(&format!("import {module}{}", alias_text(alias)));
+            }
+            AstNodeType::ImportNative(header, library, alias) => self.write(&format!(
+                "import native {} {}{}",
+                string_text(header),
+                string_text(library),
+                alias_text(alias)
+            )),
+            AstNodeType::Declaration {
+                new_symbol,
+                expression,
+                assumed_type,
+                ..
+            } => {
+                match assumed_type {
+                    Some(type_of) => self.write(&format!("let {new_symbol}: {type_of} = ")),
+                    None => self.write(&format!("{new_symbol} := ")),
+                }
+                self.expression(expression, 0);
+            }
+            AstNodeType::EntityDeclaration { new_symbol } => {
+                self.write(&format!("spawn {new_symbol}"));
+            }
+            AstNodeType::AssignmentOp {
+                recipient,
+                operation,
+                expression,
+                ..
+            } => {
+                let operation = match operation {
+                    AssignmentOperations::Identity => "=",
+                    AssignmentOperations::Add => "+=",
+                    AssignmentOperations::Subtract => "-=",
+                    AssignmentOperations::Multiply => "*=",
+                    AssignmentOperations::Divide => "/=",
+                    AssignmentOperations::Modulo => "%=",
+                };
+                self.write(&format!("{recipient} {operation} "));
+                self.expression(expression, 0);
+            }
+            AstNodeType::TypeDef {
+                typename,
+                typedef,
+                execution_body,
+            } => self.type_def(node, typename, typedef, execution_body),
+            AstNodeType::GroupDef { systems } => {
+                // NOTE: the ast does not keep the name, it is the identifier after group
+                let index = self.token_index(node.range.start);
+                let name = self
+                    .tokens
+                    .get(index + 1)
+                    .map_or("", |(_, token, _)| token.1);
+                let systems = systems
+                    .iter()
+                    .map(|system| match system {
+                        GroupSystem::Single(system) => system.clone(),
+                        GroupSystem::Ordered(first, then) => format!("{first} -> {then}"),
+                    })
+                    .collect::<Vec<_>>();
+                self.write(&format!("group {name} {{{}}}", systems.join(", ")));
+            }
+            AstNodeType::Register { schedule_entity } => {
+                let scheduled = match schedule_entity {
+                    RegisterType::Chain(chain) => chain.join(" -> "),
+                    RegisterType::After(system, other) => format!("{system} after {other}"),
+                    RegisterType::Before(system, other) => format!("{system} before {other}"),
+                };
+                self.write(&format!("register {scheduled}"));
+            }
+            AstNodeType::EntityDef {
+                name,
+                default_components,
+            } => {
+                self.write(&format!("create entity {name}"));
+                if let Some(components) = default_components {
+                    self.write(" with ");
+                    for (i, component) in components.iter().enumerate() {
+                        if i > 0 {
+                            self.write(", ");
+                        }
+                        self.expression(component, 0);
+                    }
+                }
+            }
+            AstNodeType::Branch {
+                cond,
+                body,
+                else_if_branches,
+                else_branch,
+            } => {
+                self.write("if (");
+                self.expression(cond, 0);
+                self.write(") ");
+                self.block(body, self.closing_brace(cond.range.end));
+                for (cond, body) in else_if_branches {
+                    self.write(" else if (");
+                    self.expression(cond, 0);
+                    self.write(") ");
+                    self.block(body, self.closing_brace(cond.range.end));
+                }
+                if let Some(body) = else_branch {
+                    self.write(" else ");
+                    self.block(body, node.range.end - 1);
+                }
+            }
+            AstNodeType::While { label, cond, body } => {
+                self.write(&label_text(label));
+                self.write("while (");
+                self.expression(cond, 0);
+                self.write(") ");
+                self.block(body, node.range.end - 1);
+            }
+            AstNodeType::ForEach {
+                label,
+                recipient,
+                iterable,
+                body,
+            } => {
+                self.write(&label_text(label));
+                self.write(&format!("for ({recipient} in "));
+                self.expression(iterable, 0);
+                self.write(") ");
+                self.block(body, node.range.end - 1);
+            }
+            AstNodeType::For {
+                label,
+                declaration,
+                condition,
+                assignment,
+                body,
+            } => {
+                self.write(&label_text(label));
+                self.write("for (");
+                if let Some(declaration) = declaration {
+                    self.node(declaration);
+                }
+                self.write(";");
+                if let Some(condition) = condition {
+                    self.write(" ");
+                    self.expression(condition, 0);
+                }
+                self.write(";");
+                if let Some(assignment) = assignment {
+                    self.write(" ");
+                    self.node(assignment);
+                }
+                self.write(") ");
+                self.block(body, node.range.end - 1);
+            }
+            AstNodeType::ReturnStatement { return_value } => {
+                self.write("return ");
+                self.expression(return_value, 0);
+            }
+            AstNodeType::Break(label) => self.write(&format!("break{}", jump_label(label))),
+            AstNodeType::Continue(label) => self.write(&format!("continue{}", jump_label(label))),
+            AstNodeType::Match { value, arms } => self.match_arms(node, value, arms),
+            AstNodeType::ExpressionStatement(expression) => self.expression(expression, 0),
+            _ => self.expression(node, 0),
+        }
+    }
+
+    fn type_def(
+        &mut self,
+        node: &AstNode,
+        typename: &Symbol,
+        typedef: &AstTypeDefinition,
+        body: &[Box<AstNode>],
+    ) {
+        match typedef {
+            AstTypeDefinition::Function(params, return_type) => {
+                self.write(&format!(
+                    "fn {typename}({}){}",
+                    params_text(params),
+                    return_type_text(return_type)
+                ));
+                if self.is_arrow_body(body) {
+                    self.write(" => ");
+                    self.expression(&body[0], 0);
+                    self.write(";");
+                } else {
+                    self.write(" ");
+                    self.block(body, node.range.end - 1);
+                }
+            }
+            AstTypeDefinition::Struct(fields) | AstTypeDefinition::Component(fields) => {
+                let keyword = match typedef {
+                    AstTypeDefinition::Struct(_) => "struct",
+                    _ => "component",
+                };
+                self.write(&format!("{keyword} {typename} {{"));
+                self.indent += 1;
+                self.block_start = true;
+
+                // NOTE: fields are kept in reverse order, methods are placed between them by their position
+                let starts = self.member_starts(&node.range, 1, TokenKind::Colon);
+                let mut fields = fields.iter().rev().enumerate().peekable();
+                let mut methods = body.iter().peekable();
+                loop {
+                    let field_start = fields
+                        .peek()
+                        .map(|(i, _)| starts.get(*i).copied().unwrap_or(node.range.start));
+                    let method_start = methods.peek().map(|method| method.range.start);
+                    match (field_start, method_start) {
+                        (Some(start), method) if method.is_none_or(|method| start < method) => {
+                            let (i, (name, type_of)) = fields.next().unwrap();
+                            let next = [starts.get(i + 1).copied(), method]
+                                .into_iter()
+                                .flatten()
+                                .fold(node.range.end - 1, usize::min);
+                            let end = self.token_end_before(next);
+                            self.leading_comments(start);
+                            self.line(start);
+                            self.write(&format!("{name}: {type_of},"));
+                            self.last_end = self.last_end.max(end);
+                            self.trailing_comments(end);
+                        }
+                        (_, Some(_)) => self.statement(methods.next().unwrap()),
+                        _ => break,
+                    }
+                }
+
+                self.leading_comments(node.range.end - 1);
+                self.indent -= 1;
+                self.newline();
+                self.write("}");
+            }
+            AstTypeDefinition::System(params, queries) => {
+                let params = params
+                    .iter()
+                    .map(|(name, type_of)| format!("{name}: {type_of}"))
+                    .collect::<Vec<_>>();
+                self.write(&format!("system {typename}({})", params.join(", ")));
+
+                match queries {
+                    Some(queries) => {
+                        let starts = self.member_starts(&node.range, 0, TokenKind::As);
+                        let body_start = self.opening_brace(node.range.end - 1);
+                        self.newline();
+                        self.write("querying");
+                        self.indent += 1;
+                        self.block_start = true;
+                        for (i, query) in queries.iter().enumerate() {
+                            let start = starts.get(i).copied().unwrap_or(node.range.start);
+                            let end = self
+                                .token_end_before(starts.get(i + 1).copied().unwrap_or(body_start));
+                            self.leading_comments(start);
+                            self.line(start);
+                            self.write(&format!("{},", query_text(query)));
+                            self.last_end = self.last_end.max(end);
+                            self.trailing_comments(end);
+                        }
+                        self.indent -= 1;
+                        self.newline();
+                    }
+                    None => self.write(" "),
+                }
+                self.block(body, node.range.end - 1);
+            }
+            _ => {
+                unreachable!("the grammar only defines functions, structs, components and systems")
+            }
+        }
+    }
+
+    fn match_arms(&mut self, node: &AstNode, value: &AstNode, arms: &[MatchArm]) {
+        self.write("match (");
+        self.expression(value, 0);
+        self.write(") {");
+        let end = node.range.end - 1;
+        if arms.is_empty() && self.comments.front().is_none_or(|c| c.start >= end) {
+            self.write("}");
+            return;
+        }
+
+        self.indent += 1;
+        self.block_start = true;
+        for arm in arms {
+            self.leading_comments(arm.range.start);
+            self.line(arm.range.start);
+            self.write(&pattern_text(&arm.pattern));
+            if let Some(guard) = &arm.guard {
+                self.write(" if ");
+                self.expression(guard, 0);
+            }
+            self.write(" => ");
+            for body in &arm.body {
+                self.expression(body, 0);
+            }
+            self.write(",");
+            self.last_end = self.last_end.max(arm.range.end);
+            self.trailing_comments(arm.range.end);
+        }
+        self.leading_comments(end);
+        self.indent -= 1;
+        self.newline();
+        self.write("}");
+    }
+
+    /// Writes the expression, in parentheses if it binds weaker than the minimum precedence
+    fn expression(&mut self, node: &AstNode, min_precedence: u8) {
+        self.inner_comments(node.range.start);
+        let own_precedence = self.precedence(node);
+        let parentheses = own_precedence < min_precedence;
+        if parentheses {
+            self.write("(");
+        }
+
+        match &node.type_of {
+            // NOTE: literals are kept as written, i.e. escapes and interpolations
+            AstNodeType::Int(_)
+            | AstNodeType::Float(_)
+            | AstNodeType::String(_)
+            | AstNodeType::Interpolation(_) => self.write(&self.source[node.range.clone()]),
+            AstNodeType::Bool(value) => self.write(&value.to_string()),
+            AstNodeType::List(elements) => {
+                self.write("[");
+                self.list(elements.iter().map(Box::as_ref));
+                self.write("]");
+            }
+            AstNodeType::Map(entries) => {
+                self.write("{");
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        self.write(", ");
+                    }
+                    self.expression(key, 0);
+                    self.write(" -> ");
+                    self.expression(value, 0);
+                }
+                self.write("}");
+            }
+            AstNodeType::Option(None) => self.write("none"),
+            AstNodeType::Option(Some(value)) => self.wrapped("some", value),
+            AstNodeType::Result(Ok(value)) => self.wrapped("ok", value),
+            AstNodeType::Result(Err(value)) => self.wrapped("err", value),
+            AstNodeType::InfixCall(left, operator, right) => {
+                // NOTE: all operators are left associative
+                self.expression(left, own_precedence);
+                self.write(&format!(" {} ", operator.symbol()));
+                self.expression(right, own_precedence + 1);
+            }
+            AstNodeType::PrefixCall(operator, value) => {
+                self.write(match operator {
+                    PrefixOperator::Not => "!",
+                    PrefixOperator::Negate => "-",
+                });
+                self.expression(value, own_precedence);
+            }
+            AstNodeType::Propagate(value) => {
+                self.expression(value, own_precedence);
+                self.write("?");
+            }
+            AstNodeType::Weak(value) => {
+                self.write("weak ");
+                self.expression(value, own_precedence);
+            }
+            AstNodeType::MemberCall { calls } => self.member_calls(calls),
+            AstNodeType::Closure {
+                params,
+                return_type,
+                execution_body,
+            } => {
+                self.write(&format!(
+                    "fn ({}){}",
+                    params_text(params),
+                    return_type_text(return_type)
+                ));
+                if self.is_arrow_body(execution_body) {
+                    self.write(" => ");
+                    self.expression(&execution_body[0], 0);
+                } else {
+                    self.write(" ");
+                    self.block(execution_body, node.range.end - 1);
+                }
+            }
+            _ => self.node(node),
+        }
+
+        if parentheses {
+            self.write(")");
+        }
+    }
+
+    /// How strong the expression binds, parentheses are needed around expressions,
+    /// that bind weaker than their position in an expression requires
+    fn precedence(&self, node: &AstNode) -> u8 {
+        match &node.type_of {
+            AstNodeType::InfixCall(_, operator, _) => match operator {
+                InfixOperator::Or => 1,
+                InfixOperator::And => 2,
+                InfixOperator::Equals
+                | InfixOperator::NotEquals
+                | InfixOperator::LessThan
+                | InfixOperator::LessThanEquals
+                | InfixOperator::GreaterThan
+                | InfixOperator::GreaterThanEquals => 3,
+                InfixOperator::Plus | InfixOperator::Minus => 4,
+                InfixOperator::Multiply | InfixOperator::Divide | InfixOperator::Modulo => 5,
+            },
+            AstNodeType::PrefixCall(..) => 6,
+            AstNodeType::Propagate(_) => 7,
+            // NOTE: the body of an arrow closure would take everything after it
+            AstNodeType::Closure { execution_body, .. } if self.is_arrow_body(execution_body) => 0,
+            AstNodeType::Branch { .. } | AstNodeType::Match { .. } => 0,
+            _ => 8,
+        }
+    }
+
+    fn list<'n>(&mut self, elements: impl Iterator<Item = &'n AstNode>) {
+        for (i, element) in elements.enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            self.expression(element, 0);
+        }
+    }
+
+    fn wrapped(&mut self, keyword: &str, value: &AstNode) {
+        self.write(&format!("{keyword}("));
+        self.expression(value, 0);
+        self.write(")");
+    }
+
+    fn member_calls(&mut self, calls: &[MemberAccess]) {
+        for (i, call) in calls.iter().enumerate() {
+            if i > 0 {
+                self.write(".");
+            }
+            self.write(&call.member);
+            match &call.type_of {
+                MemberAccessType::Symbol => (),
+                MemberAccessType::Function(args) => {
+                    self.write("(");
+                    self.list(args.iter().map(Box::as_ref));
+                    self.write(")");
+                }
+                // NOTE: the grammar requires a comma after every field of a struct literal
+                MemberAccessType::Struct(fields) if fields.is_empty() => self.write(" {}"),
+                MemberAccessType::Struct(fields) => {
+                    self.write(" { ");
+                    for (field, value) in fields {
+                        self.write(&format!("{field}: "));
+                        self.expression(value, 0);
+                        self.write(", ");
+                    }
+                    self.write("}");
+                }
+            }
+        }
+    }
+}
+
+fn alias_text(alias: &Option<Symbol>) -> String {
+    alias
+        .as_ref()
+        .map_or(String::new(), |alias| format!(" as {alias}"))
+}
+
+fn label_text(label: &Option<Symbol>) -> String {
+    label
+        .as_ref()
+        .map_or(String::new(), |label| format!("{label}: "))
+}
+
+fn jump_label(label: &Option<Symbol>) -> String {
+    label
+        .as_ref()
+        .map_or(String::new(), |label| format!(" {label}"))
+}
+
+fn return_type_text(return_type: &Option<TypeSymbol>) -> String {
+    return_type
+        .as_ref()
+        .map_or(String::new(), |return_type| format!(": {return_type}"))
+}
+
+fn params_text(params: &[(Symbol, TypeSymbol)]) -> String {
+    params
+        .iter()
+        .map(|(name, type_of)| match type_of.type_of {
+            TypeSymbolType::SelfType if type_of.is_weak => "weak self".to_owned(),
+            TypeSymbolType::SelfType => "self".to_owned(),
+            _ => format!("{name}: {type_of}"),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A string literal with the escapes of the grammar
+fn string_text(text: &str) -> String {
+    let mut literal = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '\\' => literal.push_str("\\\\"),
+            '"' => literal.push_str("\\\""),
+            '\n' => literal.push_str("\\n"),
+            '\t' => literal.push_str("\\t"),
+            '\r' => literal.push_str("\\r"),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+fn pattern_text(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::String(text) => string_text(text),
+        Pattern::Float(value) => format!("{value:?}"),
+        Pattern::Some(inner) => format!("some({})", pattern_text(inner)),
+        Pattern::Ok(inner) => format!("ok({})", pattern_text(inner)),
+        Pattern::Err(inner) => format!("err({})", pattern_text(inner)),
+        Pattern::Struct(name, fields) if fields.is_empty() => format!("{name} {{}}"),
+        Pattern::Struct(name, fields) => {
+            let fields = fields
+                .iter()
+                .map(|(field, pattern)| match pattern {
+                    Pattern::Binding(binding) if binding == field => field.clone(),
+                    pattern => format!("{field}: {}", pattern_text(pattern)),
+                })
+                .collect::<Vec<_>>();
+            format!("{name} {{ {} }}", fields.join(", "))
+        }
+        pattern => pattern.to_string(),
+    }
+}
+
+fn query_text(query: &Query) -> String {
+    let selection = |keyword: &str, components: &[Symbol], condition: &Option<QueryCond>| {
+        let mut selected = components.join(", ");
+        if let Some(condition) = condition {
+            if !selected.is_empty() {
+                selected.push(' ');
+            }
+            selected.push_str(&format!("% {{{}}}", condition_text(condition, 0)));
+        }
+        format!("{keyword} with {{{selected}}}")
+    };
+
+    let type_of = match &query.type_of {
+        QueryType::List { select, condition } => selection("List", &select.components, condition),
+        QueryType::Single { select, condition } => {
+            selection("Single", &select.components, condition)
+        }
+        QueryType::World => "World".to_owned(),
+        QueryType::Resource(resource) => format!("Resource of {resource}"),
+        QueryType::EventReader(event) => format!("EventReader for {event}"),
+        QueryType::EventWriter(event) => format!("EventWriter for {event}"),
+    };
+    format!("{} as {type_of}", query.symbol)
+}
+
+fn condition_text(condition: &QueryCond, min_precedence: u8) -> String {
+    let (precedence, text) = match condition {
+        QueryCond::Component(component) => return component.clone(),
+        QueryCond::Not(inner) => (3, format!("!{}", condition_text(inner, 3))),
+        QueryCond::And(left, right) => (
+            2,
+            format!(
+                "{} && {}",
+                condition_text(left, 2),
+                condition_text(right, 3)
+            ),
+        ),
+        QueryCond::Or(left, right) => (
+            1,
+            format!(
+                "{} || {}",
+                condition_text(left, 1),
+                condition_text(right, 2)
+            ),
+        ),
+    };
+    if precedence < min_precedence {
+        format!("({text})")
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use crate::ast_grammar;
+
+    use super::format_source;
+
+    fn assert_formats(source: &str, expected: &str) {
+        let formatted = format_source(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+    }
+
+    /// The debug output of the ast without the ranges, they change when the source is formatted
+    fn without_ranges(source: &str) -> String {
+        let ast = format!(
+            "{:?}",
+            ast_grammar::ProgrammParser::new().parse(source).unwrap()
+        );
+        let mut parts = ast.split("range: ");
+        let mut stripped = parts.next().unwrap_or_default().to_owned();
+        for part in parts {
+            stripped.push_str(part.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.'));
+        }
+        stripped
+    }
+
+    /// The content of the string literal at the start, with its escapes applied, and the rest after it
+    fn unescape(quoted: &str) -> (String, &str) {
+        let mut literal = String::new();
+        let mut chars = quoted.char_indices();
+        let mut end = quoted.len();
+        while let Some((j, c)) = chars.next() {
+            match c {
+                '"' => {
+                    end = j + 1;
+                    break;
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => literal.push('\n'),
+                    Some('t') => literal.push('\t'),
+                    Some('r') => literal.push('\r'),
+                    Some('\n') => {
+                        // NOTE: a line continuation skips the leading whitespace
+                        let rest = chars.as_str();
+                        let skipped = rest.len() - rest.trim_start().len();
+                        if skipped > 0 {
+                            chars.nth(skipped - 1);
+                        }
+                    }
+                    Some(c) => literal.push(c),
+                    None => (),
+                },
+                c => literal.push(c),
+            }
+        }
+        (literal, &quoted[end..])
+    }
+
+    /// The string literals in the rust source, the tests keep their scripts in them
+    fn string_literals(source: &str) -> Vec<String> {
+        let mut literals = Vec::new();
+        let mut rest = source;
+        while let Some(i) = rest.find(['"', '/', '\'']) {
+            let (head, tail) = rest.split_at(i);
+            rest = if tail.starts_with("//") {
+                tail.split_once('\n').map_or("", |(_, rest)| rest)
+            } else if let Some(quoted) = tail.strip_prefix('\'') {
+                // NOTE: skips char literals like '"', lifetimes have no closing quote
+                match quoted.char_indices().nth(1) {
+                    Some((j, '\'')) => &quoted[j + 1..],
+                    _ if quoted.starts_with('\\') => &quoted[quoted[1..].find('\'').unwrap() + 2..],
+                    _ => quoted,
+                }
+            } else if let Some(quoted) = tail.strip_prefix('"') {
+                let (literal, rest) = match quoted.split_once("\"#") {
+                    Some((literal, rest)) if head.ends_with("r#") => (literal.to_owned(), rest),
+                    _ => unescape(quoted),
+                };
+                literals.push(literal);
+                rest
+            } else {
+                &tail[1..]
+            };
+        }
+        literals
+    }
+
+    /// Every string literal in the sources of the crate
+    fn collect_snippets(dir: &Path, snippets: &mut Vec<String>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                collect_snippets(&path, snippets);
+            } else if path.extension().is_some_and(|e| e == "rs") {
+                snippets.extend(string_literals(&fs::read_to_string(&path).unwrap()));
+            }
+        }
+    }
+
+    #[test]
+    fn formats_layout() {
+        assert_formats(
+            r#"import   math;
+fn add(a:int,b:int):int{a+b}
+fn   twice(a: int): int => a*(1+1);
+struct Point{x:int,y:int, fn len(self):int=>1;}
+component Health{value:int}
+fn main(){p:=Point{x:1,y:2,};
+if(p.x==1&&!(p.y>2)){print("{p.x}");}else{x:=-(1+2);}
+m := match (p) { Point { x: 1, y } if y > 0 => y, _ => 0 };
+for(i:=0;i<10;i+=1){continue;}
+outer: while(true){break outer;}
+f := fn(a: int) => a * 2; g := (fn (a: int) => a);
+}
+system move(dt: Time) querying ps as List with {Position, Velocity % {!Frozen && (A || B)}}, t as Resource of Time { }"#,
+            r#"import math;
+fn add(a: int, b: int): int {
+    a + b
+}
+fn twice(a: int): int => a * (1 + 1);
+struct Point {
+    x: int,
+    y: int,
+    fn len(self): int => 1;
+}
+component Health {
+    value: int,
+}
+fn main() {
+    p := Point { x: 1, y: 2, };
+    if (p.x == 1 && !(p.y > 2)) {
+        print("{p.x}");
+    } else {
+        x := -(1 + 2);
+    }
+    m := match (p) {
+        Point { x: 1, y } if y > 0 => y,
+        _ => 0,
+    };
+    for (i := 0; i < 10; i += 1) {
+        continue;
+    }
+    outer: while (true) {
+        break outer;
+    }
+    f := fn (a: int) => a * 2;
+    g := fn (a: int) => a;
+}
+system move(dt: Time)
+querying
+    ps as List with {Position, Velocity % {!Frozen && (A || B)}},
+    t as Resource of Time,
+{}
+"#,
+        );
+    }
+
+    #[test]
+    fn keeps_comments() {
+        assert_formats(
+            r#"// header
+
+// about main
+fn main() { // first line
+    a := 1;    // one
+
+
+    // before b
+    b := a +  // inside
+        2;
+    // at the end
+}
+struct Point {
+    // the x
+    x: int, // right of x
+    y: int,
+}
+system s() querying
+    // the positions
+    ps as List with {Position} {}
+// trailing"#,
+            r#"// header
+
+// about main
+fn main() {
+    // first line
+    a := 1; // one
+
+    // before b
+    b := a + // inside
+        2;
+    // at the end
+}
+struct Point {
+    // the x
+    x: int, // right of x
+    y: int,
+}
+system s()
+querying
+    // the positions
+    ps as List with {Position},
+{}
+// trailing
+"#,
+        );
+    }
+
+    #[test]
+    fn keeps_trailing_comments_behind_their_token() {
+        assert_formats(
+            r#"fn main() {
+    x := 1 + // mid
+        2; // after
+    y := f(1, // one
+        // own line
+        2);
+    if (x > 1) { x = 2; } else { x = 3; } // end if
+}
+struct Point { x: int, y: int, // y
+}
+system s() querying
+    ps as List with {Position}, // ps
+    t as Resource of Time {} // end"#,
+            r#"fn main() {
+    x := 1 + // mid
+        2; // after
+    y := f(1, // one
+        // own line
+        2);
+    if (x > 1) {
+        x = 2;
+    } else {
+        x = 3;
+    } // end if
+}
+struct Point {
+    x: int,
+    y: int, // y
+}
+system s()
+querying
+    ps as List with {Position}, // ps
+    t as Resource of Time,
+{} // end
+"#,
+        );
+    }
+
+    #[test]
+    fn is_idempotent_on_test_snippets() {
+        let mut sources = Vec::new();
+        collect_snippets(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("src"),
+            &mut sources,
+        );
+
+        let mut formatted_snippets = 0;
+        for source in sources {
+            // NOTE: some snippets are invalid on purpose, or no programs
+            if ast_grammar::ProgrammParser::new().parse(&source).is_err() {
+                continue;
+            }
+
+            let formatted = format_source(&source).unwrap();
+            assert_eq!(
+                format_source(&formatted).unwrap(),
+                formatted,
+                "not idempotent:\n{source}"
+            );
+            assert_eq!(
+                without_ranges(&formatted),
+                without_ranges(&source),
+                "changed the ast:\n{source}"
+            );
+            formatted_snippets += 1;
+        }
+        assert!(
+            formatted_snippets > 100,
+            "only {formatted_snippets} snippets"
+        );
+    }
+}
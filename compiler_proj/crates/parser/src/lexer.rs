@@ -0,0 +1,453 @@
+use std::fmt::Display;
+
+/// Every kind of token of the grammar. Comments are tokens as well, so the formatter keeps them,
+/// the parsers skip them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Comment,
+    Int,
+    Float,
+    String,
+    Id,
+    True,
+    False,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    LAngle,
+    RAngle,
+    Plus,
+    Minus,
+    Asterisk,
+    Slash,
+    Modulo,
+    LessEquals,
+    GreaterEquals,
+    Equals,
+    NotEquals,
+    Assign,
+    AssignAdd,
+    AssignSub,
+    AssignMul,
+    AssignDiv,
+    AssignMod,
+    Declare,
+    And,
+    Or,
+    RightArrow,
+    FatArrow,
+    Fn,
+    System,
+    Struct,
+    Component,
+    Let,
+    While,
+    For,
+    In,
+    If,
+    Match,
+    Underscore,
+    Else,
+    Return,
+    Break,
+    Continue,
+    With,
+    Default,
+    Import,
+    Native,
+    Spawn,
+    As,
+    Ffi,
+    Semicolon,
+    Colon,
+    Dot,
+    Comma,
+    ExclamationMark,
+    QuestionMark,
+    Weak,
+    None,
+    Some,
+    Ok,
+    Err,
+    SelfValue,
+    Querying,
+    ListQuery,
+    SingleQuery,
+    WorldQuery,
+    ResourceQuery,
+    EventReaderQuery,
+    EventWriterQuery,
+    Of,
+    Group,
+    Register,
+    After,
+    Before,
+    Create,
+    Remove,
+    Entity,
+    Trigger,
+    /// Text no token starts with, the parsers report it as invalid token
+    Invalid,
+}
+
+const KEYWORDS: &[(&str, TokenKind)] = &[
+    ("true", TokenKind::True),
+    ("false", TokenKind::False),
+    ("fn", TokenKind::Fn),
+    ("system", TokenKind::System),
+    ("struct", TokenKind::Struct),
+    ("component", TokenKind::Component),
+    ("let", TokenKind::Let),
+    ("while", TokenKind::While),
+    ("for", TokenKind::For),
+    ("in", TokenKind::In),
+    ("if", TokenKind::If),
+    ("match", TokenKind::Match),
+    ("else", TokenKind::Else),
+    ("return", TokenKind::Return),
+    ("break", TokenKind::Break),
+    ("continue", TokenKind::Continue),
+    ("with", TokenKind::With),
+    ("default", TokenKind::Default),
+    ("import", TokenKind::Import),
+    ("native", TokenKind::Native),
+    ("spawn", TokenKind::Spawn),
+    ("as", TokenKind::As),
+    ("ffi", TokenKind::Ffi),
+    ("weak", TokenKind::Weak),
+    ("none", TokenKind::None),
+    ("some", TokenKind::Some),
+    ("ok", TokenKind::Ok),
+    ("err", TokenKind::Err),
+    ("self", TokenKind::SelfValue),
+    ("querying", TokenKind::Querying),
+    ("List", TokenKind::ListQuery),
+    ("Single", TokenKind::SingleQuery),
+    ("World", TokenKind::WorldQuery),
+    ("Resource", TokenKind::ResourceQuery),
+    ("EventReader", TokenKind::EventReaderQuery),
+    ("EventWriter", TokenKind::EventWriterQuery),
+    ("of", TokenKind::Of),
+    ("group", TokenKind::Group),
+    ("register", TokenKind::Register),
+    ("after", TokenKind::After),
+    ("before", TokenKind::Before),
+    ("create", TokenKind::Create),
+    ("remove", TokenKind::Remove),
+    ("entity", TokenKind::Entity),
+    ("trigger", TokenKind::Trigger),
+];
+
+/// NOTE: longer symbols first, the longest symbol at a position is its token
+const SYMBOLS: &[(&str, TokenKind)] = &[
+    ("<=", TokenKind::LessEquals),
+    (">=", TokenKind::GreaterEquals),
+    ("==", TokenKind::Equals),
+    ("!=", TokenKind::NotEquals),
+    ("+=", TokenKind::AssignAdd),
+    ("-=", TokenKind::AssignSub),
+    ("*=", TokenKind::AssignMul),
+    ("/=", TokenKind::AssignDiv),
+    ("%=", TokenKind::AssignMod),
+    (":=", TokenKind::Declare),
+    ("&&", TokenKind::And),
+    ("||", TokenKind::Or),
+    ("->", TokenKind::RightArrow),
+    ("=>", TokenKind::FatArrow),
+    ("(", TokenKind::LParen),
+    (")", TokenKind::RParen),
+    ("[", TokenKind::LBracket),
+    ("]", TokenKind::RBracket),
+    ("{", TokenKind::LBrace),
+    ("}", TokenKind::RBrace),
+    ("<", TokenKind::LAngle),
+    (">", TokenKind::RAngle),
+    ("+", TokenKind::Plus),
+    ("-", TokenKind::Minus),
+    ("*", TokenKind::Asterisk),
+    ("/", TokenKind::Slash),
+    ("%", TokenKind::Modulo),
+    ("=", TokenKind::Assign),
+    ("_", TokenKind::Underscore),
+    (";", TokenKind::Semicolon),
+    (":", TokenKind::Colon),
+    (".", TokenKind::Dot),
+    (",", TokenKind::Comma),
+    ("!", TokenKind::ExclamationMark),
+    ("?", TokenKind::QuestionMark),
+];
+
+/// The names of the terminals in the grammar, parse errors list the expected tokens by them.
+/// Only terminals with a fixed text are listed
+const TERMINALS: &[(&str, TokenKind)] = &[
+    ("BOOLTRUE", TokenKind::True),
+    ("BOOLFALSE", TokenKind::False),
+    ("l_paren", TokenKind::LParen),
+    ("r_paren", TokenKind::RParen),
+    ("l_bracket", TokenKind::LBracket),
+    ("r_bracket", TokenKind::RBracket),
+    ("l_brace", TokenKind::LBrace),
+    ("r_brace", TokenKind::RBrace),
+    ("l_angle", TokenKind::LAngle),
+    ("r_angle", TokenKind::RAngle),
+    ("plus", TokenKind::Plus),
+    ("minus", TokenKind::Minus),
+    ("asterisk", TokenKind::Asterisk),
+    ("slash", TokenKind::Slash),
+    ("modulo", TokenKind::Modulo),
+    ("less_equals", TokenKind::LessEquals),
+    ("greater_equals", TokenKind::GreaterEquals),
+    ("equals", TokenKind::Equals),
+    ("not_equals", TokenKind::NotEquals),
+    ("assign", TokenKind::Assign),
+    ("assign_add", TokenKind::AssignAdd),
+    ("assign_sub", TokenKind::AssignSub),
+    ("assign_mul", TokenKind::AssignMul),
+    ("assign_div", TokenKind::AssignDiv),
+    ("assign_mod", TokenKind::AssignMod),
+    ("declare", TokenKind::Declare),
+    ("and", TokenKind::And),
+    ("or", TokenKind::Or),
+    ("right_arrow", TokenKind::RightArrow),
+    ("fat_arrow", TokenKind::FatArrow),
+    ("fn_term", TokenKind::Fn),
+    ("system_term", TokenKind::System),
+    ("struct_term", TokenKind::Struct),
+    ("component_term", TokenKind::Component),
+    ("let_term", TokenKind::Let),
+    ("while_term", TokenKind::While),
+    ("for_term", TokenKind::For),
+    ("in_term", TokenKind::In),
+    ("if_term", TokenKind::If),
+    ("match_term", TokenKind::Match),
+    ("underscore", TokenKind::Underscore),
+    ("else_term", TokenKind::Else),
+    ("return", TokenKind::Return),
+    ("break_term", TokenKind::Break),
+    ("continue_term", TokenKind::Continue),
+    ("with", TokenKind::With),
+    ("default", TokenKind::Default),
+    ("import", TokenKind::Import),
+    ("native", TokenKind::Native),
+    ("spawn_term", TokenKind::Spawn),
+    ("as_term", TokenKind::As),
+    ("ffi", TokenKind::Ffi),
+    ("semicolon", TokenKind::Semicolon),
+    ("colon", TokenKind::Colon),
+    ("dot", TokenKind::Dot),
+    ("comma", TokenKind::Comma),
+    ("exclamation_mark", TokenKind::ExclamationMark),
+    ("question_mark", TokenKind::QuestionMark),
+    ("weak_term", TokenKind::Weak),
+    ("none_term", TokenKind::None),
+    ("some_term", TokenKind::Some),
+    ("ok_term", TokenKind::Ok),
+    ("err_term", TokenKind::Err),
+    ("self_term", TokenKind::SelfValue),
+    ("querying_term", TokenKind::Querying),
+    ("list_query_term", TokenKind::ListQuery),
+    ("single_query_term", TokenKind::SingleQuery),
+    ("world_query_term", TokenKind::WorldQuery),
+    ("resource_query_term", TokenKind::ResourceQuery),
+    ("evt_reader_query_term", TokenKind::EventReaderQuery),
+    ("evt_writer_query_term", TokenKind::EventWriterQuery),
+    ("of_term", TokenKind::Of),
+    ("group_term", TokenKind::Group),
+    ("register_term", TokenKind::Register),
+    ("after_term", TokenKind::After),
+    ("before_term", TokenKind::Before),
+    ("create_term", TokenKind::Create),
+    ("remove_term", TokenKind::Remove),
+    ("entity_term", TokenKind::Entity),
+    ("trigger_term", TokenKind::Trigger),
+];
+
+impl TokenKind {
+    /// The kind of the terminal with the name of the grammar
+    pub fn from_terminal(name: &str) -> Option<TokenKind> {
+        TERMINALS
+            .iter()
+            .find(|(terminal, _)| *terminal == name)
+            .map(|(_, kind)| *kind)
+    }
+
+    /// The text of keywords and symbols, None for tokens without a fixed text
+    pub fn text(self) -> Option<&'static str> {
+        KEYWORDS
+            .iter()
+            .chain(SYMBOLS)
+            .find(|(_, kind)| *kind == self)
+            .map(|(text, _)| *text)
+    }
+}
+
+/// A token and its text in the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'input>(pub TokenKind, pub &'input str);
+
+impl Display for Token<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.1)
+    }
+}
+
+/// Splits the source into tokens with their ranges, skips whitespace.
+/// Stops after the first invalid token
+pub struct Lexer<'input> {
+    input: &'input str,
+    position: usize,
+}
+
+impl<'input> Lexer<'input> {
+    pub fn new(input: &'input str) -> Self {
+        Self { input, position: 0 }
+    }
+
+    /// The length of the string literal at the start of the rest, None if it is not terminated
+    /// or contains an unknown escape sequence
+    fn string_length(rest: &str) -> Option<usize> {
+        let mut chars = rest.char_indices().skip(1);
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Some(i + 1),
+                '\\' => match chars.next() {
+                    Some((_, '\\' | '"' | 'n' | 't' | 'r' | '{' | '}')) => (),
+                    _ => return None,
+                },
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// The kind and the length of the token at the start of the rest
+    fn next_token(rest: &str) -> (TokenKind, usize) {
+        let is_digit = |c: &u8| c.is_ascii_digit();
+        let bytes = rest.as_bytes();
+
+        if rest.starts_with("//") {
+            let end = rest.find(['\n', '\r']).unwrap_or(rest.len());
+            return (TokenKind::Comment, end);
+        }
+        if rest.starts_with('"') {
+            return match Self::string_length(rest) {
+                Some(length) => (TokenKind::String, length),
+                None => (TokenKind::Invalid, 1),
+            };
+        }
+        if bytes[0].is_ascii_digit() {
+            let int = bytes.iter().take_while(|c| is_digit(c)).count();
+            let fraction = match bytes.get(int) {
+                Some(b'.') => bytes[int + 1..].iter().take_while(|c| is_digit(c)).count(),
+                _ => 0,
+            };
+            return match fraction {
+                0 => (TokenKind::Int, int),
+                fraction => (TokenKind::Float, int + 1 + fraction),
+            };
+        }
+        if bytes[0].is_ascii_alphabetic() {
+            let length = bytes
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || **c == b'_')
+                .count();
+            let kind = KEYWORDS
+                .iter()
+                .find(|(keyword, _)| *keyword == &rest[..length])
+                .map_or(TokenKind::Id, |(_, kind)| *kind);
+            return (kind, length);
+        }
+
+        SYMBOLS
+            .iter()
+            .find(|(symbol, _)| rest.starts_with(symbol))
+            .map_or_else(
+                || (TokenKind::Invalid, rest.chars().next().map_or(1, char::len_utf8)),
+                |(symbol, kind)| (*kind, symbol.len()),
+            )
+    }
+}
+
+impl<'input> Iterator for Lexer<'input> {
+    type Item = (usize, Token<'input>, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.input[self.position..];
+        let start = self.position + (rest.len() - rest.trim_start().len());
+        if start == self.input.len() {
+            self.position = start;
+            return None;
+        }
+
+        let (kind, length) = Self::next_token(&self.input[start..]);
+        let end = start + length;
+        // NOTE: nothing follows an invalid token, like the parsers stop at it
+        self.position = if kind == TokenKind::Invalid {
+            self.input.len()
+        } else {
+            end
+        };
+        Some((start, Token(kind, &self.input[start..end]), end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lexer, TokenKind};
+
+    fn kinds(input: &str) -> Vec<(TokenKind, &str)> {
+        Lexer::new(input)
+            .map(|(_, token, _)| (token.0, token.1))
+            .collect()
+    }
+
+    #[test]
+    fn splits_tokens() {
+        assert_eq!(
+            kinds("fnord := fn(_x) => 1.5 <= 2. // done\n\"a\\\"{b}\""),
+            [
+                (TokenKind::Id, "fnord"),
+                (TokenKind::Declare, ":="),
+                (TokenKind::Fn, "fn"),
+                (TokenKind::LParen, "("),
+                (TokenKind::Underscore, "_"),
+                (TokenKind::Id, "x"),
+                (TokenKind::RParen, ")"),
+                (TokenKind::FatArrow, "=>"),
+                (TokenKind::Float, "1.5"),
+                (TokenKind::LessEquals, "<="),
+                (TokenKind::Int, "2"),
+                (TokenKind::Dot, "."),
+                (TokenKind::Comment, "// done"),
+                (TokenKind::String, "\"a\\\"{b}\""),
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_at_invalid_tokens() {
+        assert_eq!(
+            kinds("a & b"),
+            [(TokenKind::Id, "a"), (TokenKind::Invalid, "&")]
+        );
+        assert_eq!(
+            kinds("\"a\\q\" b"),
+            [(TokenKind::Invalid, "\"")]
+        );
+        assert_eq!(kinds("\"open"), [(TokenKind::Invalid, "\"")]);
+    }
+
+    #[test]
+    fn looks_up_terminals() {
+        assert_eq!(TokenKind::from_terminal("r_paren"), Some(TokenKind::RParen));
+        assert_eq!(
+            TokenKind::from_terminal("in_term").and_then(TokenKind::text),
+            Some("in")
+        );
+        assert_eq!(TokenKind::from_terminal("ID"), None);
+        assert_eq!(TokenKind::Int.text(), None);
+    }
+}
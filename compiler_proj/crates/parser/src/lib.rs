@@ -1,18 +1,136 @@
 pub mod ast;
 pub use ast::*;
 
+pub mod lexer;
+pub use lexer::*;
+
 pub mod errors;
 pub use errors::*;
 
+pub mod source_map;
+pub use source_map::*;
+
+pub mod suggestions;
+pub use suggestions::*;
+
 pub mod stages;
 pub use stages::*;
 
 pub mod types;
 pub use types::*;
 
-use lalrpop_util::lalrpop_mod;
+pub mod native;
+pub use native::*;
+
+pub mod repl;
+pub use repl::*;
+
+pub mod formatter;
+pub use formatter::*;
+
+/// The parsers of the grammar, they read the tokens of the lexer and skip comments
+pub mod ast_grammar {
+    use lalrpop_util::ParseError;
+
+    pub use crate::lexer::{Lexer, Token, TokenKind};
+
+    // NOTE: expanded lalrpop_mod!, the generated parsers take tokens instead of the source
+    #[rustfmt::skip]
+    #[allow(clippy::extra_unused_lifetimes)]
+    #[allow(clippy::needless_lifetimes)]
+    #[allow(clippy::let_unit_value)]
+    #[allow(clippy::just_underscores_and_digits)]
+    #[allow(clippy::ptr_arg, clippy::vec_box)]
+    // NOTE: only the built-in lexer of lalrpop allows unused names in actions
+    #[allow(unused_imports, unused_variables)]
+    mod generated {
+        include!(concat!(env!("OUT_DIR"), "/ast_grammar.rs"));
+    }
+
+    pub type Spanned<'input> = (usize, Token<'input>, usize);
+
+    pub type TokenError<'input> = ParseError<usize, Token<'input>, &'static str>;
+
+    /// The tokens of the source without comments
+    fn tokens(input: &str) -> impl Iterator<Item = Spanned<'_>> {
+        Lexer::new(input).filter(|(_, token, _)| token.0 != TokenKind::Comment)
+    }
+
+    /// The parsers see invalid tokens as unrecognized ones
+    fn invalid_token(err: TokenError<'_>) -> TokenError<'_> {
+        match err {
+            ParseError::UnrecognizedToken {
+                token: (location, Token(TokenKind::Invalid, _), _),
+                ..
+            } => ParseError::InvalidToken { location },
+            err => err,
+        }
+    }
+
+    /// Splits the source into the tokens of the grammar, whitespace and comments are skipped
+    pub fn tokenize(input: &str) -> Result<Vec<Spanned<'_>>, TokenError<'_>> {
+        tokens(input)
+            .map(|spanned| match spanned {
+                (location, Token(TokenKind::Invalid, _), _) => {
+                    Err(ParseError::InvalidToken { location })
+                }
+                spanned => Ok(spanned),
+            })
+            .collect()
+    }
 
-lalrpop_mod!(pub ast_grammar);
+    macro_rules! parser {
+        ($name:ident, $output:ty) => {
+            pub struct $name(generated::$name);
+
+            impl $name {
+                pub fn new() -> Self {
+                    Self(generated::$name::new())
+                }
+
+                /// Parses the input, fails at the first syntax error
+                pub fn parse<'input>(&self, input: &'input str) -> Result<$output, TokenError<'input>> {
+                    let (output, errors) = self.parse_recovering(input);
+                    match (output, errors.into_iter().next()) {
+                        (Some(output), None) => Ok(output),
+                        (_, Some(err)) => Err(err),
+                        (None, None) => unreachable!("a failed parse has an error"),
+                    }
+                }
+
+                /// Parses past syntax errors, to find all of them.
+                /// The output is None, if the parser could not recover from an error
+                pub fn parse_recovering<'input>(
+                    &self,
+                    input: &'input str,
+                ) -> (Option<$output>, Vec<TokenError<'input>>) {
+                    let mut recovered = Vec::new();
+                    let result = self.0.parse(&mut recovered, tokens(input));
+                    let mut errors = recovered
+                        .into_iter()
+                        .map(|recovery| invalid_token(recovery.error))
+                        .collect::<Vec<_>>();
+                    match result {
+                        Ok(output) => (Some(output), errors),
+                        Err(err) => {
+                            errors.push(invalid_token(err));
+                            (None, errors)
+                        }
+                    }
+                }
+            }
+
+            impl Default for $name {
+                fn default() -> Self {
+                    Self::new()
+                }
+            }
+        };
+    }
+
+    parser!(ProgrammParser, Vec<crate::AstNode>);
+    parser!(ReturnableParser, crate::AstNode);
+}
 
 #[cfg(test)]
 mod tests {
@@ -26,7 +144,7 @@ mod tests {
         printer::{DotPrinter, PrinterContext},
     };
 
-    use crate::{AstNodeType, BeautifyError, ToGraphviz, ast_grammar};
+    use crate::{AstNodeType, BeautifyError, Pattern, ToGraphviz, ast_grammar};
 
     #[test]
     fn import_test1() {
@@ -127,6 +245,7 @@ mod tests {
                 new_symbol: _,
                 expression: _,
                 assumed_type: _,
+                slot: _,
             }
         ));
     }
@@ -170,6 +289,7 @@ mod tests {
                 new_symbol: _,
                 expression: _,
                 assumed_type: _,
+                slot: _,
             }
         ));
     }
@@ -697,4 +817,99 @@ mod tests {
             panic!("{}", err)
         }
     }
+
+    #[test]
+    fn arrow_function_test1() {
+        let source = r#"
+                    fn double(a: int): int => a * 2;
+
+                    struct Point {
+                        x: int,
+                        fn scaled(self, f: int): int => f * 2;
+                    }
+
+                    fn main() {
+                        f := fn (x: int): int => x * 2 + 1;
+                        apply(fn (x: int): int => x, 10);
+                    }
+                    "#;
+        let expr = ast_grammar::ProgrammParser::new().parse(source);
+
+        if let Err(err) = expr {
+            err.print_error(source);
+            panic!("{}", err)
+        } else if let Ok(expr) = expr {
+            let AstNodeType::TypeDef { execution_body, .. } = &expr[0].type_of else {
+                panic!("expected function definition");
+            };
+            assert!(execution_body.len() == 1);
+            assert!(matches!(
+                execution_body[0].type_of,
+                AstNodeType::InfixCall(_, _, _)
+            ));
+        }
+    }
+
+    #[test]
+    fn match_syntax() {
+        let source = r#"
+                    fn main() {
+                        a := match (b) {
+                            0 => 1,
+                            n if n < 0 => -1,
+                            _ => 2,
+                        };
+                        match (p) {
+                            Point { x: 0, y } => y,
+                            some(ok(v)) => v,
+                            none => 0,
+                        }
+                        c := parse(a)?;
+                    }
+                    "#;
+        let expr = ast_grammar::ProgrammParser::new().parse(source);
+
+        if let Err(err) = expr {
+            err.print_error(source);
+            panic!("{}", err)
+        } else if let Ok(expr) = expr {
+            let AstNodeType::TypeDef { execution_body, .. } = &expr[0].type_of else {
+                panic!("expected function definition");
+            };
+            let AstNodeType::Match { arms, .. } = &execution_body[1].type_of else {
+                panic!("expected match");
+            };
+            assert!(arms.len() == 3);
+            assert!(matches!(arms[0].pattern, Pattern::Struct(_, _)));
+        }
+    }
+
+    #[test]
+    fn recovers_from_syntax_errors() {
+        let source = r#"fn main() {
+                            a := ;
+                            b := 1 +;
+                        }
+                        struct A { a int }
+                        fn b() {}"#;
+        let (ast, errors) = ast_grammar::ProgrammParser::new().parse_recovering(source);
+        assert_eq!(errors.len(), 3);
+
+        // statements are skipped up to their semicolon, definitions up to their closing brace
+        let ast = ast.unwrap();
+        assert_eq!(ast.len(), 3);
+        let AstNodeType::TypeDef { execution_body, .. } = &ast[0].type_of else {
+            panic!("main is kept");
+        };
+        assert!(execution_body.iter().all(|node| matches!(node.type_of, AstNodeType::Error)));
+        assert!(matches!(ast[1].type_of, AstNodeType::Error));
+        assert!(matches!(ast[2].type_of, AstNodeType::TypeDef { .. }));
+
+        // without recovery, the first error is reported
+        let first = source.find(';').unwrap();
+        assert!(matches!(
+            ast_grammar::ProgrammParser::new().parse(source),
+            Err(lalrpop_util::ParseError::UnrecognizedToken { token: (start, _, _), .. }) if start == first
+        ));
+    }
 }
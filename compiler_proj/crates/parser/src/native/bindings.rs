@@ -0,0 +1,260 @@
+use std::{
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Alias, Error, FunctionExecutionStrategy, FunctionType, InterpreterValue, Scope, StructType,
+    Symbol, TypeSymbol, TypeSymbolType,
+};
+
+/// File extension appended to a header path to find its binding cache, i.e. lib.h -> lib.h.bindings.json
+pub const BINDINGS_EXTENSION: &str = "bindings.json";
+
+/// The subset of C types the language is able to represent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum NativeType {
+    Void,
+    Int,
+    Float,
+    Bool,
+    CString,
+    Pointer(Box<NativeType>),
+    Named(Symbol),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NativeFunction {
+    pub name: Symbol,
+    pub params: Vec<(Symbol, NativeType)>,
+    pub return_type: NativeType,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NativeStruct {
+    pub name: Symbol,
+    pub fields: Vec<(Symbol, NativeType)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NativeEnum {
+    pub name: Symbol,
+    pub variants: Vec<(Symbol, i64)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum NativeConstantValue {
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NativeConstant {
+    pub name: Symbol,
+    pub value: NativeConstantValue,
+}
+
+/// Serialized description of a native header. Is generated once by the binding generator
+/// and loaded by the preprocessor, so libclang is not needed when running a script.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct NativeBindings {
+    pub header: String,
+    /// Hash of the header contents the bindings were generated from
+    pub header_hash: u64,
+    pub functions: Vec<NativeFunction>,
+    pub structs: Vec<NativeStruct>,
+    pub enums: Vec<NativeEnum>,
+    pub constants: Vec<NativeConstant>,
+}
+
+/// FNV-1a over the header contents. std's DefaultHasher is not stable across releases,
+/// so it can't be used for anything written to disk.
+pub fn hash_header(contents: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in contents {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+impl NativeBindings {
+    pub fn new(header: String, header_hash: u64) -> Self {
+        Self {
+            header,
+            header_hash,
+            ..Default::default()
+        }
+    }
+
+    pub fn cache_path(header: &Path) -> PathBuf {
+        let mut path = header.as_os_str().to_owned();
+        path.push(".");
+        path.push(BINDINGS_EXTENSION);
+        PathBuf::from(path)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| Error::NativeBindingsError(format!("{}: {e}", path.display())))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| Error::NativeBindingsError(format!("{}: {e}", path.display())))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| Error::NativeBindingsError(e.to_string()))?;
+        fs::write(path, contents)
+            .map_err(|e| Error::NativeBindingsError(format!("{}: {e}", path.display())))
+    }
+
+    pub fn is_up_to_date(&self, header_contents: &[u8]) -> bool {
+        self.header_hash == hash_header(header_contents)
+    }
+
+    /// Load the cached bindings of a header. If the cache is missing or the header changed since
+    /// the last generation, the bindings are regenerated when the `bindgen` feature is enabled.
+    /// Otherwise, an error is returned, telling the user to run the generator.
+    pub fn load_or_generate(header: &Path) -> Result<Self, Error> {
+        let header_contents = fs::read(header)
+            .map_err(|e| Error::NativeBindingsError(format!("{}: {e}", header.display())))?;
+        let cache_path = Self::cache_path(header);
+
+        if let Ok(cached) = Self::load(&cache_path)
+            && cached.is_up_to_date(&header_contents)
+        {
+            return Ok(cached);
+        }
+
+        #[cfg(feature = "bindgen")]
+        {
+            let generated = crate::native::generate_bindings(header)?;
+            generated.save(&cache_path)?;
+            Ok(generated)
+        }
+
+        #[cfg(not(feature = "bindgen"))]
+        Err(Error::NativeBindingsOutdated(header.display().to_string()))
+    }
+
+    /// Declare all bindings in the scope. If an alias is given, every symbol is prefixed, i.e. ray.Vector2
+    pub fn declare_in_scope(
+        &self,
+        scope: &mut Scope,
+        library: &str,
+        alias: &Option<Alias>,
+        location: Range<usize>,
+    ) -> Result<(), Error> {
+        let qualify = |name: &Symbol| match alias {
+            Some(alias) => format!("{alias}.{name}"),
+            None => name.clone(),
+        };
+
+        for native_struct in &self.structs {
+            let struct_type = TypeSymbol::strong(TypeSymbolType::Struct(StructType {
+                name: qualify(&native_struct.name),
+                fields: native_struct
+                    .fields
+                    .iter()
+                    .map(|(name, type_of)| (name.clone(), type_of.to_type_symbol(&qualify)))
+                    .collect::<Vec<_>>(),
+                methods: vec![],
+                statics: vec![],
+            }));
+            scope.declare_type(
+                qualify(&native_struct.name),
+                struct_type,
+                true,
+                location.clone(),
+            )?;
+        }
+
+        for native_enum in &self.enums {
+            // NOTE: C enums are plain integers, the variants are declared as int constants
+            scope.declare_type(
+                qualify(&native_enum.name),
+                TypeSymbol::strong(TypeSymbolType::Int),
+                true,
+                location.clone(),
+            )?;
+            for (variant, value) in &native_enum.variants {
+                scope.declare_variable(
+                    qualify(variant),
+                    InterpreterValue::new_strong(InterpreterValue::Int(*value)),
+                    TypeSymbol::strong(TypeSymbolType::Int),
+                    true,
+                    true,
+                    location.clone(),
+                )?;
+            }
+        }
+
+        for constant in &self.constants {
+            let (value, type_of) = match &constant.value {
+                NativeConstantValue::Int(i) => (InterpreterValue::Int(*i), TypeSymbolType::Int),
+                NativeConstantValue::Float(f) => {
+                    (InterpreterValue::Float(*f), TypeSymbolType::Float)
+                }
+                NativeConstantValue::String(s) => {
+                    (InterpreterValue::String(s.clone()), TypeSymbolType::String)
+                }
+            };
+            scope.declare_variable(
+                qualify(&constant.name),
+                InterpreterValue::new_strong(value),
+                TypeSymbol::strong(type_of),
+                true,
+                true,
+                location.clone(),
+            )?;
+        }
+
+        for function in &self.functions {
+            let return_type = match function.return_type {
+                NativeType::Void => None,
+                ref type_of => Some(Box::new(type_of.to_type_symbol(&qualify))),
+            };
+            let fun_type = TypeSymbol::strong(TypeSymbolType::Function(FunctionType {
+                name: qualify(&function.name),
+                params: function
+                    .params
+                    .iter()
+                    .map(|(name, type_of)| (name.clone(), type_of.to_type_symbol(&qualify)))
+                    .collect::<Vec<_>>(),
+                return_type,
+                execution_body: FunctionExecutionStrategy::Native(
+                    library.to_owned(),
+                    function.name.clone(),
+                ),
+            }));
+            scope.declare_function(
+                qualify(&function.name),
+                InterpreterValue::Function(qualify(&function.name)),
+                fun_type,
+                true,
+                true,
+                location.clone(),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl NativeType {
+    pub fn to_type_symbol(&self, qualify: &impl Fn(&Symbol) -> Symbol) -> TypeSymbol {
+        match self {
+            NativeType::Int => TypeSymbol::strong(TypeSymbolType::Int),
+            NativeType::Float => TypeSymbol::strong(TypeSymbolType::Float),
+            NativeType::Bool => TypeSymbol::strong(TypeSymbolType::Bool),
+            NativeType::CString => TypeSymbol::strong(TypeSymbolType::String),
+            NativeType::Named(name) => TypeSymbol::strong(TypeSymbolType::Symbol(qualify(name))),
+            // NOTE: raw pointers and void can't be represented in the language
+            NativeType::Void | NativeType::Pointer(_) => TypeSymbol::strong(TypeSymbolType::Any),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::{fs, path::Path};
+
+use clang::{Clang, Entity, EntityKind, EvaluationResult, Index, Type, TypeKind};
+
+use crate::{
+    Error, NativeBindings, NativeConstant, NativeConstantValue, NativeEnum, NativeFunction,
+    NativeStruct, NativeType, Symbol, hash_header,
+};
+
+/// Parse a header with libclang and collect everything declared in it (not in its includes)
+pub fn generate_bindings(header: &Path) -> Result<NativeBindings, Error> {
+    let contents = fs::read(header)
+        .map_err(|e| Error::NativeBindingsError(format!("{}: {e}", header.display())))?;
+
+    let clang = Clang::new().map_err(Error::NativeBindingsError)?;
+    let index = Index::new(&clang, false, false);
+    let tu = index
+        .parser(header)
+        .detailed_preprocessing_record(true)
+        .skip_function_bodies(true)
+        .parse()
+        .map_err(|e| Error::NativeBindingsError(format!("{}: {e}", header.display())))?;
+
+    let mut bindings = NativeBindings::new(header.display().to_string(), hash_header(&contents));
+
+    for entity in tu.get_entity().get_children() {
+        if !entity.is_in_main_file() {
+            continue;
+        }
+
+        match entity.get_kind() {
+            EntityKind::FunctionDecl => {
+                let Some(name) = entity.get_name() else {
+                    continue;
+                };
+                let params = entity
+                    .get_arguments()
+                    .unwrap_or_default()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, arg)| {
+                        (
+                            arg.get_name().unwrap_or_else(|| format!("arg{i}")),
+                            map_type(arg.get_type()),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+
+                bindings.functions.push(NativeFunction {
+                    name,
+                    params,
+                    return_type: map_type(entity.get_result_type()),
+                });
+            }
+            EntityKind::StructDecl => {
+                if let Some(name) = entity.get_name() {
+                    collect_struct(&mut bindings, name, &entity);
+                }
+            }
+            EntityKind::EnumDecl => {
+                if let Some(name) = entity.get_name() {
+                    collect_enum(&mut bindings, name, &entity);
+                }
+            }
+            EntityKind::TypedefDecl => {
+                // typedef struct { ... } Name; and typedef enum { ... } Name;
+                let (Some(name), Some(underlying)) =
+                    (entity.get_name(), entity.get_typedef_underlying_type())
+                else {
+                    continue;
+                };
+                let Some(declaration) = underlying.get_declaration() else {
+                    continue;
+                };
+
+                match declaration.get_kind() {
+                    EntityKind::StructDecl if declaration.get_name().is_none() => {
+                        collect_struct(&mut bindings, name, &declaration)
+                    }
+                    EntityKind::EnumDecl if declaration.get_name().is_none() => {
+                        collect_enum(&mut bindings, name, &declaration)
+                    }
+                    _ => (),
+                }
+            }
+            EntityKind::VarDecl => {
+                if let (Some(name), Some(value)) = (entity.get_name(), entity.evaluate())
+                    && let Some(value) = map_constant(value)
+                {
+                    bindings.constants.push(NativeConstant { name, value });
+                }
+            }
+            EntityKind::MacroDefinition => {
+                if entity.is_function_like_macro() || entity.is_builtin_macro() {
+                    continue;
+                }
+                if let Some(constant) = macro_constant(&entity) {
+                    bindings.constants.push(constant);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(bindings)
+}
+
+fn collect_struct(bindings: &mut NativeBindings, name: Symbol, entity: &Entity) {
+    let mut fields = Vec::new();
+
+    for field in entity.get_children() {
+        if field.get_kind() != EntityKind::FieldDecl {
+            continue;
+        }
+        let Some(field_name) = field.get_name() else {
+            continue;
+        };
+
+        // NOTE: anonymous nested structs get their own binding, named after parent and field
+        let field_type = field.get_type();
+        if let Some(declaration) = field_type.and_then(|t| t.get_declaration())
+            && declaration.get_kind() == EntityKind::StructDecl
+            && declaration.is_anonymous()
+        {
+            let nested_name = format!("{name}_{field_name}");
+            collect_struct(bindings, nested_name.clone(), &declaration);
+            fields.push((field_name, NativeType::Named(nested_name)));
+        } else {
+            fields.push((field_name, map_type(field_type)));
+        }
+    }
+
+    bindings.structs.push(NativeStruct { name, fields });
+}
+
+fn collect_enum(bindings: &mut NativeBindings, name: Symbol, entity: &Entity) {
+    let variants = entity
+        .get_children()
+        .iter()
+        .filter(|c| c.get_kind() == EntityKind::EnumConstantDecl)
+        .filter_map(|c| Some((c.get_name()?, c.get_enum_constant_value()?.0)))
+        .collect::<Vec<_>>();
+
+    bindings.enums.push(NativeEnum { name, variants });
+}
+
+fn map_type(type_of: Option<Type>) -> NativeType {
+    let Some(type_of) = type_of else {
+        return NativeType::Void;
+    };
+
+    // Named records and enums keep their name, everything else is resolved to the canonical type
+    if let Some(declaration) = type_of.get_declaration()
+        && let Some(name) = declaration.get_name()
+        && matches!(
+            declaration.get_kind(),
+            EntityKind::StructDecl | EntityKind::TypedefDecl
+        )
+        && matches!(type_of.get_canonical_type().get_kind(), TypeKind::Record)
+    {
+        return NativeType::Named(name);
+    }
+
+    let canonical = type_of.get_canonical_type();
+    match canonical.get_kind() {
+        TypeKind::Void => NativeType::Void,
+        TypeKind::Bool => NativeType::Bool,
+        TypeKind::CharS
+        | TypeKind::CharU
+        | TypeKind::SChar
+        | TypeKind::UChar
+        | TypeKind::Short
+        | TypeKind::UShort
+        | TypeKind::Int
+        | TypeKind::UInt
+        | TypeKind::Long
+        | TypeKind::ULong
+        | TypeKind::LongLong
+        | TypeKind::ULongLong
+        | TypeKind::Enum => NativeType::Int,
+        TypeKind::Float | TypeKind::Double | TypeKind::LongDouble => NativeType::Float,
+        TypeKind::Pointer => {
+            let pointee = canonical.get_pointee_type();
+            if pointee
+                .map(|p| matches!(p.get_kind(), TypeKind::CharS | TypeKind::CharU))
+                .unwrap_or(false)
+            {
+                NativeType::CString
+            } else {
+                NativeType::Pointer(Box::new(map_type(pointee)))
+            }
+        }
+        _ => NativeType::Named(canonical.get_display_name()),
+    }
+}
+
+fn map_constant(value: EvaluationResult) -> Option<NativeConstantValue> {
+    match value {
+        EvaluationResult::SignedInteger(i) => Some(NativeConstantValue::Int(i)),
+        EvaluationResult::UnsignedInteger(u) => Some(NativeConstantValue::Int(u as i64)),
+        EvaluationResult::Float(f) => Some(NativeConstantValue::Float(f)),
+        EvaluationResult::String(s) => Some(NativeConstantValue::String(
+            s.to_string_lossy().into_owned(),
+        )),
+        _ => None,
+    }
+}
+
+/// Only object like macros with a single literal are supported, i.e. #define WIDTH 800
+fn macro_constant(entity: &Entity) -> Option<NativeConstant> {
+    let name = entity.get_name()?;
+    let tokens = entity.get_range()?.tokenize();
+    let [_, literal] = tokens.as_slice() else {
+        return None;
+    };
+    let literal = literal.get_spelling();
+
+    let value = if let Ok(i) = literal.parse::<i64>() {
+        NativeConstantValue::Int(i)
+    } else if let Ok(f) = literal.trim_end_matches(['f', 'F']).parse::<f64>() {
+        NativeConstantValue::Float(f)
+    } else if literal.starts_with('"') && literal.ends_with('"') && literal.len() >= 2 {
+        NativeConstantValue::String(literal[1..literal.len() - 1].to_owned())
+    } else {
+        return None;
+    };
+
+    Some(NativeConstant { name, value })
+}
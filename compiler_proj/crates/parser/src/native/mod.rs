@@ -0,0 +1,7 @@
+pub mod bindings;
+pub use bindings::*;
+
+#[cfg(feature = "bindgen")]
+pub mod generator;
+#[cfg(feature = "bindgen")]
+pub use generator::*;
@@ -0,0 +1,426 @@
+use std::collections::HashSet;
+
+use lalrpop_util::ParseError;
+
+use crate::{
+    AstNode, Error, ErrorWithRange, Interpreter, InterpreterValue, Preprocessor, Resolver, Stage,
+    StageResult, Stages, Symbol, TypeSymbol, ast_grammar, parse_error_range, run_stages,
+};
+
+const HELP: &str = ":type <expr>    evaluates the expression and shows the type of its value
+:ast <input>    shows the syntax tree of the input, without evaluating it
+:scope          lists everything defined so far
+:help           shows this help
+:quit           exits the repl";
+
+/// What the repl made of an input
+#[derive(Debug)]
+pub enum ReplOutput {
+    /// The input ends inside of a definition or statement, the next line continues it
+    Incomplete,
+    /// The value of an expression
+    Value(InterpreterValue),
+    /// Definitions and statements have no value
+    Done,
+    /// The answer to a command, i.e. :type
+    Text(String),
+    Quit,
+}
+
+/// Evaluates inputs one after another in the same global scope,
+/// so every input sees the definitions and variables of the previous ones.
+/// An input is made of definitions and statements, a single expression or a command, i.e. :type
+pub struct Repl {
+    interpreter: Interpreter,
+    /// The input, that is continued by the next line
+    pending: String,
+    /// All inputs so far, the ranges of the errors point into it
+    source: String,
+    /// Everything in the global scope before the first input, :scope leaves it out
+    buildins: HashSet<Symbol>,
+    /// Arithmetic on an int and a float is rejected before the input is evaluated
+    strict: bool,
+}
+
+impl Repl {
+    pub fn new() -> Result<Self, ErrorWithRange> {
+        let preprocessor = Preprocessor::new().map_err(|err| ErrorWithRange::new(err, 0..1))?;
+        let stages = vec![
+            Stages::Preprocessor(preprocessor),
+            Stages::Resolver(Resolver::new()),
+        ];
+        let mut interpreter = Interpreter::new("main".to_owned());
+        interpreter.init(run_stages(stages, StageResult::Parsing(vec![]))?)?;
+
+        let global_scope = interpreter.get_current_scope();
+        let global_scope = global_scope.borrow();
+        let buildins = global_scope
+            .iter_values()
+            .map(|(name, _)| name)
+            .chain(global_scope.iter_defined_types().map(|(name, _)| name))
+            .cloned()
+            .collect();
+
+        Ok(Self {
+            interpreter,
+            pending: String::new(),
+            source: String::new(),
+            buildins,
+            strict: false,
+        })
+    }
+
+    /// Strict mode: arithmetic on an int and a float is an error, instead of converting the int to a float
+    pub fn with_strict(mut self) -> Self {
+        self.interpreter = self.interpreter.with_strict();
+        self.strict = true;
+        self
+    }
+
+    /// All inputs so far, the source of the errors
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether the last line was not a complete input
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Evaluates a line, or collects it until the lines form a complete input.
+    /// An empty line completes the input anyway, which reports what is missing
+    pub fn eval_line(&mut self, line: &str) -> Result<ReplOutput, ErrorWithRange> {
+        let complete = line.trim().is_empty();
+        self.pending.push_str(line);
+        if !self.pending.ends_with('\n') {
+            self.pending.push('\n');
+        }
+
+        let input = std::mem::take(&mut self.pending);
+        match self.eval_input(&input, complete) {
+            Ok(ReplOutput::Incomplete) => {
+                self.pending = input;
+                Ok(ReplOutput::Incomplete)
+            }
+            result => result,
+        }
+    }
+
+    /// Evaluates a complete input, i.e. a whole script
+    pub fn eval(&mut self, input: &str) -> Result<ReplOutput, ErrorWithRange> {
+        self.eval_input(input, true)
+    }
+
+    fn eval_input(&mut self, input: &str, complete: bool) -> Result<ReplOutput, ErrorWithRange> {
+        let trimmed = input.trim_start();
+        if trimmed.is_empty() {
+            return Ok(ReplOutput::Done);
+        }
+        if let Some(command) = trimmed.strip_prefix(':') {
+            let offset = self.record(input) + input.len() - command.len();
+            return self.command(command, offset);
+        }
+
+        // NOTE: only an expression has a value to print, anything else has to be a complete statement or definition
+        let parsed = match ast_grammar::ReturnableParser::new().parse(input) {
+            Ok(node) => Ok((vec![node], true)),
+            Err(_) => ast_grammar::ProgrammParser::new()
+                .parse(input)
+                .map(|nodes| (nodes, false)),
+        };
+        let (mut nodes, is_expression) = match parsed {
+            Ok(parsed) => parsed,
+            Err(ParseError::UnrecognizedEof { .. }) if !complete => {
+                return Ok(ReplOutput::Incomplete);
+            }
+            Err(err) => {
+                let offset = self.record(input);
+                return Err(parse_error(err, offset));
+            }
+        };
+
+        let offset = self.record(input);
+        for node in &mut nodes {
+            node.offset_ranges(offset);
+        }
+        let value = self.run(nodes)?;
+        if is_expression && !matches!(value, InterpreterValue::Empty) {
+            Ok(ReplOutput::Value(value))
+        } else {
+            Ok(ReplOutput::Done)
+        }
+    }
+
+    /// Appends the input to the source, returns where it starts in there
+    fn record(&mut self, input: &str) -> usize {
+        let offset = self.source.len();
+        self.source.push_str(input);
+        if !self.source.ends_with('\n') {
+            self.source.push('\n');
+        }
+        offset
+    }
+
+    /// Declares the definitions in the global scope and executes the statements in it.
+    /// NOTE: the global scope is only replaced once all definitions passed the checks, a failing input leaves it as it was
+    fn run(&mut self, nodes: Vec<AstNode>) -> Result<InterpreterValue, ErrorWithRange> {
+        let global_scope = self.interpreter.get_current_scope();
+        let mut preprocessor = Preprocessor::with_global_scope(global_scope.borrow().clone());
+        if self.strict {
+            preprocessor = preprocessor.with_strict();
+        }
+        let stages = vec![
+            Stages::Preprocessor(preprocessor),
+            Stages::Resolver(Resolver::new().with_statements()),
+        ];
+        let StageResult::Resolved(scope, statements) =
+            run_stages(stages, StageResult::Parsing(nodes))?
+        else {
+            unreachable!("the resolver is the last stage")
+        };
+
+        // NOTE: the scope is replaced in place, closures created by earlier inputs keep seeing it
+        *global_scope.borrow_mut() = scope;
+        self.interpreter.eval_global(&statements)
+    }
+
+    fn command(&mut self, command: &str, offset: usize) -> Result<ReplOutput, ErrorWithRange> {
+        let (name, argument) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+        let argument = argument.trim_start();
+        let argument_offset = offset + command.len() - argument.len();
+
+        match name {
+            "type" => {
+                let mut expression = ast_grammar::ReturnableParser::new()
+                    .parse(argument)
+                    .map_err(|err| parse_error(err, argument_offset))?;
+                expression.offset_ranges(argument_offset);
+                let value = self.run(vec![expression])?;
+                Ok(ReplOutput::Text(self.type_of(&value)))
+            }
+            "ast" => {
+                let ast = match ast_grammar::ReturnableParser::new().parse(argument) {
+                    Ok(expression) => format!("{expression:#?}"),
+                    Err(_) => ast_grammar::ProgrammParser::new()
+                        .parse(argument)
+                        .map(|nodes| format!("{nodes:#?}"))
+                        .map_err(|err| parse_error(err, argument_offset))?,
+                };
+                Ok(ReplOutput::Text(ast))
+            }
+            "scope" => Ok(ReplOutput::Text(self.scope())),
+            "help" => Ok(ReplOutput::Text(HELP.to_owned())),
+            "quit" | "q" => Ok(ReplOutput::Quit),
+            _ => Ok(ReplOutput::Text(format!(
+                "unknown command :{name}, try :help"
+            ))),
+        }
+    }
+
+    fn type_of(&self, value: &InterpreterValue) -> String {
+        let type_of = match value {
+            InterpreterValue::Function(name) | InterpreterValue::System(name) => self
+                .interpreter
+                .get_current_scope()
+                .borrow()
+                .resolve_type(name),
+            InterpreterValue::Empty => return "no value".to_owned(),
+            _ => Option::<TypeSymbol>::from(value.clone()),
+        };
+        type_of.map_or("unknown".to_owned(), |type_of| type_of.to_string())
+    }
+
+    /// The globals defined by the inputs, sorted by name
+    fn scope(&self) -> String {
+        let global_scope = self.interpreter.get_current_scope();
+        let global_scope = global_scope.borrow();
+
+        let mut lines = Vec::new();
+        for (name, type_of) in global_scope.iter_defined_types() {
+            if !self.buildins.contains(name) {
+                lines.push((name, type_of.to_string()));
+            }
+        }
+        for (name, value) in global_scope.iter_values() {
+            if self.buildins.contains(name) {
+                continue;
+            }
+            let type_of = self.type_of(value);
+            let line = match value {
+                InterpreterValue::Function(_) | InterpreterValue::System(_) => type_of,
+                _ => match value.to_text() {
+                    Ok(text) => format!("{name}: {type_of} = {text}"),
+                    Err(_) => format!("{name}: {type_of}"),
+                },
+            };
+            lines.push((name, line));
+        }
+
+        lines.sort();
+        lines
+            .into_iter()
+            .map(|(_, line)| line)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Moves the error to where the input starts in the source of the repl.
+/// NOTE: the error outlives the input, so the text of its tokens is leaked
+fn parse_error(
+    err: ParseError<usize, ast_grammar::Token<'_>, &'static str>,
+    offset: usize,
+) -> ErrorWithRange {
+    let err = err.map_location(|location| location + offset).map_token(
+        |ast_grammar::Token(kind, text)| ast_grammar::Token(kind, &*text.to_owned().leak()),
+    );
+    let range = parse_error_range(&err);
+    ErrorWithRange::new(Error::ParseError(err), range)
+}
+
+#[cfg(test)]
+mod tests {
+    use lalrpop_util::ParseError;
+
+    use crate::{Error, InterpreterValue, Repl, ReplOutput};
+
+    fn eval_lines(repl: &mut Repl, lines: &[&str]) -> ReplOutput {
+        let mut output = ReplOutput::Done;
+        for line in lines {
+            output = repl.eval_line(line).unwrap_or_else(|err| {
+                panic!("{line} failed with {:?}", err.err);
+            });
+        }
+        output
+    }
+
+    fn value_of(repl: &mut Repl, line: &str) -> String {
+        match eval_lines(repl, &[line]) {
+            ReplOutput::Value(value) => value.to_text().unwrap(),
+            output => panic!("{line} has no value, got {output:?}"),
+        }
+    }
+
+    #[test]
+    fn keeps_global_scope() {
+        let mut repl = Repl::new().unwrap();
+
+        assert!(matches!(
+            eval_lines(&mut repl, &["a := 10;"]),
+            ReplOutput::Done
+        ));
+        assert!(matches!(
+            eval_lines(&mut repl, &["a += 5;"]),
+            ReplOutput::Done
+        ));
+        assert_eq!(value_of(&mut repl, "a * 2"), "30");
+
+        eval_lines(&mut repl, &["fn twice(x: int): int { x * 2 }"]);
+        eval_lines(&mut repl, &["struct Point { x: int, y: int, }"]);
+        assert_eq!(value_of(&mut repl, "twice(a)"), "30");
+        assert_eq!(value_of(&mut repl, "Point { x: a, y: 2, }.x"), "15");
+        assert_eq!(value_of(&mut repl, "[a, twice(2)]"), "[15, 4]");
+
+        // NOTE: functions may be redefined, i.e. to fix them
+        eval_lines(&mut repl, &["fn twice(x: int): int { x + x + 0 }"]);
+        assert_eq!(value_of(&mut repl, "twice(1)"), "2");
+    }
+
+    #[test]
+    fn prints_structs() {
+        let mut repl = Repl::new().unwrap();
+        eval_lines(&mut repl, &["struct Point { x: int, y: int, }"]);
+        eval_lines(&mut repl, &["p := Point { x: 1, y: 2, };"]);
+
+        // NOTE: the struct holds itself as self, it is no field
+        assert_eq!(value_of(&mut repl, "p"), "Point { x: 1, y: 2 }");
+        assert_eq!(value_of(&mut repl, "some(p)"), "some(Point { x: 1, y: 2 })");
+        assert_eq!(value_of(&mut repl, "[p]"), "[Point { x: 1, y: 2 }]");
+    }
+
+    #[test]
+    fn continues_incomplete_input() {
+        let mut repl = Repl::new().unwrap();
+
+        assert!(matches!(
+            eval_lines(&mut repl, &["fn add(a: int, b: int): int {"]),
+            ReplOutput::Incomplete
+        ));
+        assert!(repl.is_pending());
+        assert!(matches!(
+            eval_lines(&mut repl, &["  a + b", "}"]),
+            ReplOutput::Done
+        ));
+        assert!(!repl.is_pending());
+        assert_eq!(value_of(&mut repl, "add(1, 2)"), "3");
+
+        // NOTE: an empty line reports what is missing
+        assert!(matches!(
+            eval_lines(&mut repl, &["b := 1"]),
+            ReplOutput::Incomplete
+        ));
+        let err = repl.eval_line("").unwrap_err();
+        assert!(matches!(
+            err.err,
+            Error::ParseError(ParseError::UnrecognizedEof { .. })
+        ));
+        assert!(!repl.is_pending());
+    }
+
+    #[test]
+    fn errors_point_into_all_inputs() {
+        let mut repl = Repl::new().unwrap();
+        eval_lines(&mut repl, &["fn div(a: int, b: int): int { a / b }"]);
+        eval_lines(&mut repl, &["x := 1;"]);
+
+        let err = repl.eval_line("div(x, 0)").unwrap_err();
+        assert!(matches!(err.err, Error::DivisionByZero));
+        assert_eq!(&repl.source()[err.range], "a / b");
+
+        let err = repl.eval_line("x +* 1").unwrap_err();
+        let Error::ParseError(ParseError::UnrecognizedToken { token, .. }) = err.err else {
+            panic!("expected a parse error, got {:?}", err.err);
+        };
+        assert_eq!(&repl.source()[token.0..token.2], "*");
+
+        // NOTE: failed inputs leave the scope intact
+        assert!(repl.eval_line("fn broken(): int { missing }").is_err());
+        assert_eq!(value_of(&mut repl, "div(x, 1)"), "1");
+    }
+
+    #[test]
+    fn commands() {
+        let mut repl = Repl::new().unwrap();
+        eval_lines(&mut repl, &["fn inc(a: int): int { a + 1 }"]);
+        eval_lines(&mut repl, &["n := inc(1);"]);
+
+        let text = |output| match output {
+            ReplOutput::Text(text) => text,
+            output => panic!("expected text, got {output:?}"),
+        };
+        assert_eq!(text(eval_lines(&mut repl, &[":type n + 0.5"])), "float");
+        assert_eq!(text(eval_lines(&mut repl, &[":type some(n)"])), "int?");
+        assert_eq!(
+            text(eval_lines(&mut repl, &[":scope"])),
+            "fn inc(a: int): int\nn: int = 2"
+        );
+        assert!(text(eval_lines(&mut repl, &[":ast 1 + n"])).contains("InfixCall"));
+        assert!(matches!(
+            eval_lines(&mut repl, &[":quit"]),
+            ReplOutput::Quit
+        ));
+
+        let err = repl.eval_line(":type 1 +").unwrap_err();
+        assert!(matches!(err.err, Error::ParseError(_)));
+        assert!(matches!(
+            repl.eval(":type inc").unwrap(),
+            ReplOutput::Text(text) if text.starts_with("fn inc")
+        ));
+        assert!(matches!(
+            repl.eval("n").unwrap(),
+            ReplOutput::Value(InterpreterValue::Strong(_))
+        ));
+    }
+}
@@ -0,0 +1,110 @@
+use std::{cell::RefCell, ops::Range, rc::Rc};
+
+/// Identifies a file of a source map. The script itself is the main file, the first one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FileId(pub usize);
+
+impl FileId {
+    pub const MAIN: FileId = FileId(0);
+}
+
+/// A range of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub file: FileId,
+    pub range: Range<usize>,
+}
+
+impl Span {
+    pub fn new(file: FileId, range: Range<usize>) -> Self {
+        Self { file, range }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    /// Shown above the lines of the file, a source without a path i.e. of the repl has none
+    pub path: Option<String>,
+    pub contents: String,
+}
+
+impl SourceFile {
+    /// The line and column of the offset, both start at 1. Columns count characters
+    pub fn position(&self, offset: usize) -> (usize, usize) {
+        let before = &self.contents[..offset.min(self.contents.len())];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    }
+}
+
+/// All files, that ranges of diagnostics can point into, i.e. the script and native headers
+#[derive(Debug, Clone)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+/// The source map is shared between the stages, that add files, and their creator
+pub type SharedSourceMap = Rc<RefCell<SourceMap>>;
+
+impl SourceMap {
+    /// A source map with the main file
+    pub fn new(path: Option<String>, contents: impl Into<String>) -> Self {
+        Self {
+            files: vec![SourceFile {
+                path,
+                contents: contents.into(),
+            }],
+        }
+    }
+
+    /// A source map of a single source without a path
+    pub fn single(contents: impl Into<String>) -> Self {
+        Self::new(None, contents)
+    }
+
+    /// Adds the file, if no file with the path was added before
+    pub fn add(&mut self, path: impl Into<String>, contents: impl Into<String>) -> FileId {
+        let path = path.into();
+        if let Some(file) = self.find(&path) {
+            return file;
+        }
+        self.files.push(SourceFile {
+            path: Some(path),
+            contents: contents.into(),
+        });
+        FileId(self.files.len() - 1)
+    }
+
+    pub fn find(&self, path: &str) -> Option<FileId> {
+        self.files
+            .iter()
+            .position(|file| file.path.as_deref() == Some(path))
+            .map(FileId)
+    }
+
+    /// NOTE: panics for ids of other source maps
+    pub fn file(&self, file: FileId) -> &SourceFile {
+        &self.files[file.0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileId, SourceMap};
+
+    #[test]
+    fn assigns_file_ids() {
+        let mut sources = SourceMap::new(Some("main.ecs".to_owned()), "fn main() {}");
+        let header = sources.add("raylib.h", "void InitWindow(void);");
+        assert_eq!(header, FileId(1));
+        assert_eq!(sources.add("raylib.h", ""), header);
+        assert_eq!(sources.find("main.ecs"), Some(FileId::MAIN));
+        assert_eq!(sources.file(header).contents, "void InitWindow(void);");
+        assert_eq!(sources.find("other.h"), None);
+        assert_eq!(sources.file(header).position(5), (1, 6));
+        assert_eq!(sources.file(FileId::MAIN).position(100), (1, 13));
+    }
+}
@@ -17,13 +17,140 @@ pub fn println(scope: Rc<RefCell<Scope>>) -> Result<IsReturn, Error> {
 pub fn assert(scope: Rc<RefCell<Scope>>) -> Result<IsReturn, Error> {
     let scope = scope.borrow();
     if let Some(attr) = scope.resolve_value(&"attr".to_string()) {
-        assert!(attr.as_bool()?);
-        Ok(IsReturn::Return(InterpreterValue::Empty))
+        if attr.as_bool()? {
+            Ok(IsReturn::Return(InterpreterValue::Empty))
+        } else {
+            Err(Error::AssertionFailed)
+        }
     } else {
         Err(Error::SymbolNotFound("attr".to_string()))
     }
 }
 
+/// The params a and b of the int arithmetic buildins
+fn int_operands(scope: Rc<RefCell<Scope>>) -> Result<(i64, i64), Error> {
+    let scope = scope.borrow();
+    let operand = |name: &str| {
+        let Some(value) = scope.resolve_value(&name.to_string()) else {
+            return Err(Error::SymbolNotFound(name.to_string()));
+        };
+        match InterpreterValue::preprocess_single(value)? {
+            InterpreterValue::Int(i) => Ok(i),
+            other => Err(Error::WrongType(
+                name.to_string(),
+                "int".to_string(),
+                other.to_string(),
+            )),
+        }
+    };
+
+    Ok((operand("a")?, operand("b")?))
+}
+
+/// Int arithmetic, that wraps around instead of failing on an overflow
+pub fn wrapping_add(scope: Rc<RefCell<Scope>>) -> Result<IsReturn, Error> {
+    let (a, b) = int_operands(scope)?;
+    Ok(IsReturn::Return(InterpreterValue::Int(a.wrapping_add(b))))
+}
+
+pub fn wrapping_sub(scope: Rc<RefCell<Scope>>) -> Result<IsReturn, Error> {
+    let (a, b) = int_operands(scope)?;
+    Ok(IsReturn::Return(InterpreterValue::Int(a.wrapping_sub(b))))
+}
+
+pub fn wrapping_mul(scope: Rc<RefCell<Scope>>) -> Result<IsReturn, Error> {
+    let (a, b) = int_operands(scope)?;
+    Ok(IsReturn::Return(InterpreterValue::Int(a.wrapping_mul(b))))
+}
+
+/// Int arithmetic, that stays at the smallest or largest int instead of failing on an overflow
+pub fn saturating_add(scope: Rc<RefCell<Scope>>) -> Result<IsReturn, Error> {
+    let (a, b) = int_operands(scope)?;
+    Ok(IsReturn::Return(InterpreterValue::Int(a.saturating_add(b))))
+}
+
+pub fn saturating_sub(scope: Rc<RefCell<Scope>>) -> Result<IsReturn, Error> {
+    let (a, b) = int_operands(scope)?;
+    Ok(IsReturn::Return(InterpreterValue::Int(a.saturating_sub(b))))
+}
+
+pub fn saturating_mul(scope: Rc<RefCell<Scope>>) -> Result<IsReturn, Error> {
+    let (a, b) = int_operands(scope)?;
+    Ok(IsReturn::Return(InterpreterValue::Int(a.saturating_mul(b))))
+}
+
+/// The param x of the conversion buildins
+fn conversion_operand(scope: Rc<RefCell<Scope>>) -> Result<InterpreterValue, Error> {
+    let scope = scope.borrow();
+    let Some(value) = scope.resolve_value(&"x".to_string()) else {
+        return Err(Error::SymbolNotFound("x".to_string()));
+    };
+    InterpreterValue::preprocess_single(value)
+}
+
+/// Converts a number or bool to an int. Floats are truncated towards zero
+pub fn int(scope: Rc<RefCell<Scope>>) -> Result<IsReturn, Error> {
+    let value = match conversion_operand(scope)? {
+        InterpreterValue::Int(i) => i,
+        // NOTE: i64::MAX is not representable as a float, it is rounded up to 2^63
+        InterpreterValue::Float(f) if f >= i64::MIN as f64 && f < i64::MAX as f64 => f as i64,
+        InterpreterValue::Float(f) => return Err(Error::IntegerOverflow(format!("int({f})"))),
+        InterpreterValue::Bool(b) => b as i64,
+        other => {
+            return Err(Error::WrongType(
+                "x".to_string(),
+                "int, float or bool".to_string(),
+                other.to_text()?,
+            ));
+        }
+    };
+    Ok(IsReturn::Return(InterpreterValue::Int(value)))
+}
+
+pub fn float(scope: Rc<RefCell<Scope>>) -> Result<IsReturn, Error> {
+    let value = match conversion_operand(scope)? {
+        InterpreterValue::Int(i) => i as f64,
+        InterpreterValue::Float(f) => f,
+        other => {
+            return Err(Error::WrongType(
+                "x".to_string(),
+                "int or float".to_string(),
+                other.to_text()?,
+            ));
+        }
+    };
+    Ok(IsReturn::Return(InterpreterValue::Float(value)))
+}
+
+pub fn string(scope: Rc<RefCell<Scope>>) -> Result<IsReturn, Error> {
+    let text = conversion_operand(scope)?.to_text()?;
+    Ok(IsReturn::Return(InterpreterValue::String(text)))
+}
+
+/// Parses a decimal int, the error describes why the string is not an int
+pub fn parse_int(scope: Rc<RefCell<Scope>>) -> Result<IsReturn, Error> {
+    let text = match conversion_operand(scope)? {
+        InterpreterValue::String(s) => s,
+        other => {
+            return Err(Error::WrongType(
+                "x".to_string(),
+                "string".to_string(),
+                other.to_text()?,
+            ));
+        }
+    };
+
+    let parsed = match text.parse::<i64>() {
+        Ok(i) => Ok(Box::new(InterpreterValue::new_strong(
+            InterpreterValue::Int(i),
+        ))),
+        Err(e) => Err(Box::new(InterpreterValue::new_strong(
+            InterpreterValue::String(format!("{text:?} is not an int: {e}")),
+        ))),
+    };
+    Ok(IsReturn::Return(InterpreterValue::Result(parsed)))
+}
+
 pub struct BuildinFunctionDescription {
     name: String,
     callback: BuildinCallback,
@@ -64,5 +191,49 @@ pub fn register_buildin(scope: &mut Scope) -> Result<(), Error> {
     };
     assert_descriptor.add_to_scope(scope)?;
 
+    let int_arithmetic: [(&str, BuildinCallback); 6] = [
+        ("wrapping_add", wrapping_add),
+        ("wrapping_sub", wrapping_sub),
+        ("wrapping_mul", wrapping_mul),
+        ("saturating_add", saturating_add),
+        ("saturating_sub", saturating_sub),
+        ("saturating_mul", saturating_mul),
+    ];
+    for (name, callback) in int_arithmetic {
+        BuildinFunctionDescription {
+            name: name.to_string(),
+            callback,
+            params: vec![
+                ("a".to_string(), TypeSymbol::strong(TypeSymbolType::Int)),
+                ("b".to_string(), TypeSymbol::strong(TypeSymbolType::Int)),
+            ],
+            return_type: Some(Box::new(TypeSymbol::strong(TypeSymbolType::Int))),
+        }
+        .add_to_scope(scope)?;
+    }
+
+    let conversions: [(&str, BuildinCallback, TypeSymbolType); 4] = [
+        ("int", int, TypeSymbolType::Int),
+        ("float", float, TypeSymbolType::Float),
+        ("string", string, TypeSymbolType::String),
+        (
+            "parse_int",
+            parse_int,
+            TypeSymbolType::Result(
+                Box::new(TypeSymbol::strong(TypeSymbolType::Int)),
+                Box::new(TypeSymbol::strong(TypeSymbolType::String)),
+            ),
+        ),
+    ];
+    for (name, callback, return_type) in conversions {
+        BuildinFunctionDescription {
+            name: name.to_string(),
+            callback,
+            params: vec![("x".to_string(), TypeSymbol::strong(TypeSymbolType::Any))],
+            return_type: Some(Box::new(TypeSymbol::strong(return_type))),
+        }
+        .add_to_scope(scope)?;
+    }
+
     Ok(())
 }
@@ -0,0 +1,1050 @@
+use std::{collections::HashMap, ops::Range};
+
+use crate::{
+    AssignmentOperations, AstNode, AstNodeType, Error, ErrorWithRange, FunctionExecutionStrategy,
+    FunctionType, InfixOperator, InterpreterValue, MatchArm, MemberAccess, MemberAccessType,
+    Pattern, PrefixOperator, Scope, Stage, StageResult, Symbol, TypeSymbol, TypeSymbolType,
+    similar_field,
+};
+
+/// A single operation of the stack machine. Jump targets are indices into the code of the function
+#[derive(Debug, Clone)]
+pub enum Instruction {
+    /// Pushes the constant with the index of the constant pool
+    Constant(usize),
+    /// Pushes the empty value, which is the value of statements
+    Empty,
+    Pop,
+    PopN(usize),
+    LoadLocal(usize),
+    StoreLocal(usize),
+    /// Slot of a frame captured by a closure, the depth counts the frames up from the current one
+    LoadOuter(usize, usize),
+    StoreOuter(usize, usize),
+    LoadGlobal(usize),
+    StoreGlobal(usize),
+    Infix(InfixOperator),
+    Prefix(PrefixOperator),
+    /// Combines the old value on top of the stack with the assigned value below it
+    Assign(AssignmentOperations),
+    Downgrade,
+    MakeList(usize),
+    /// Joins the text of the values on the stack into a string
+    Concat(usize),
+    WrapSome,
+    WrapOk,
+    WrapErr,
+    /// Builds the struct literal with the index, its field values are on the stack in the order of the literal
+    MakeStruct(usize),
+    /// Creates a closure of the function, that captures the current frame
+    MakeClosure(usize),
+    /// Field of the value on top of the stack, the name is a constant
+    GetField(usize),
+    /// Calls a compiled function with the number of arguments on the stack
+    Call(usize, usize),
+    CallBuildin(usize, usize),
+    /// Calls the value below the arguments, the name of the called symbol is a constant
+    CallValue(usize, usize),
+    /// Calls a method of the value below the arguments, the name of the method is a constant
+    CallMethod(usize, usize),
+    Jump(usize),
+    JumpIfFalse(usize),
+    /// Replaces the iterable on top of the stack with the list and the position of the iteration
+    IterStart,
+    /// Stores the next element into the slot, or jumps to the target, if the iteration is done
+    IterNext(usize, usize),
+    /// Binds the value on top of the stack to the pattern with the index, or jumps to the target, if it does not match
+    MatchPattern(usize, usize),
+    NoMatch,
+    Propagate,
+    Return,
+    /// Returns the value of the last expression of the body, which must exist for functions with a return type
+    ReturnLast,
+}
+
+/// A function lowered to bytecode. Its params occupy the first slots of its frame
+#[derive(Debug, Default)]
+pub struct Function {
+    pub name: Symbol,
+    pub params: Vec<Symbol>,
+    pub returns_value: bool,
+    pub slots: usize,
+    pub code: Vec<Instruction>,
+    /// The source range of every instruction
+    pub ranges: Vec<Range<usize>>,
+}
+
+/// A function, that can be called by its name
+#[derive(Debug, Clone, Copy)]
+pub enum Callee {
+    Function(usize),
+    Buildin(usize),
+}
+
+/// A struct or component literal with the order its fields are assigned in
+#[derive(Debug)]
+pub struct StructLiteral {
+    pub type_of: TypeSymbol,
+    pub fields: Vec<Symbol>,
+}
+
+/// A match pattern with the slots of its bindings
+#[derive(Debug)]
+pub struct CompiledPattern {
+    pub pattern: Pattern,
+    pub bindings: Vec<(Symbol, usize)>,
+}
+
+#[derive(Debug, Default)]
+pub struct Program {
+    pub functions: Vec<Function>,
+    pub callees: HashMap<Symbol, Callee>,
+    pub buildins: Vec<FunctionType>,
+    pub constants: Vec<InterpreterValue>,
+    pub globals: Vec<InterpreterValue>,
+    pub patterns: Vec<CompiledPattern>,
+    pub structs: Vec<StructLiteral>,
+}
+
+/// Where a symbol lives at runtime
+enum Resolved {
+    Local(usize),
+    Outer(usize, usize),
+    Global(usize),
+}
+
+struct LoopContext {
+    label: Option<Symbol>,
+    // Stack depth at the start of the body, break and continue drop everything above it
+    depth: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// The function that is currently compiled, closures push a new one
+#[derive(Default)]
+struct FunctionContext {
+    function: Function,
+    scopes: Vec<HashMap<Symbol, usize>>,
+    loops: Vec<LoopContext>,
+    // Static depth of the operand stack
+    depth: usize,
+    // The furthest target of a jump, code before it can't be removed anymore
+    last_target: usize,
+}
+
+/// Lowers the functions of the preprocessed program to bytecode.
+/// Variables are resolved to the slots of their frame, literals are moved into the constant pool
+pub struct Compiler {
+    global_scope: Scope,
+    // NOTE: boxed, so the stage stays small while it is not running
+    program: Box<Program>,
+    global_index: HashMap<Symbol, usize>,
+    contexts: Vec<FunctionContext>,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            global_scope: Scope::default(),
+            program: Box::default(),
+            global_index: HashMap::new(),
+            contexts: Vec::new(),
+        }
+    }
+
+    fn context(&mut self) -> &mut FunctionContext {
+        self.contexts
+            .last_mut()
+            .expect("must be present while a function is compiled")
+    }
+
+    fn stack_effect(&self, instruction: &Instruction) -> isize {
+        match instruction {
+            Instruction::Constant(_)
+            | Instruction::Empty
+            | Instruction::LoadLocal(_)
+            | Instruction::LoadOuter(_, _)
+            | Instruction::LoadGlobal(_)
+            | Instruction::MakeClosure(_)
+            | Instruction::IterStart => 1,
+            Instruction::Pop
+            | Instruction::StoreLocal(_)
+            | Instruction::StoreOuter(_, _)
+            | Instruction::StoreGlobal(_)
+            | Instruction::Infix(_)
+            | Instruction::Assign(_)
+            | Instruction::JumpIfFalse(_)
+            | Instruction::Return
+            | Instruction::ReturnLast => -1,
+            Instruction::PopN(n) => -(*n as isize),
+            Instruction::MakeList(n) | Instruction::Concat(n) => 1 - *n as isize,
+            Instruction::MakeStruct(index) => {
+                1 - self.program.structs[*index].fields.len() as isize
+            }
+            Instruction::Call(_, n) | Instruction::CallBuildin(_, n) => 1 - *n as isize,
+            Instruction::CallValue(_, n) | Instruction::CallMethod(_, n) => -(*n as isize),
+            Instruction::Prefix(_)
+            | Instruction::Downgrade
+            | Instruction::WrapSome
+            | Instruction::WrapOk
+            | Instruction::WrapErr
+            | Instruction::GetField(_)
+            | Instruction::Jump(_)
+            | Instruction::IterNext(_, _)
+            | Instruction::MatchPattern(_, _)
+            | Instruction::NoMatch
+            | Instruction::Propagate => 0,
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction, range: &Range<usize>) -> usize {
+        let effect = self.stack_effect(&instruction);
+        let context = self.context();
+        context.depth = context.depth.saturating_add_signed(effect);
+        context.function.code.push(instruction);
+        context.function.ranges.push(range.clone());
+        context.function.code.len() - 1
+    }
+
+    /// Index of the next instruction, i.e. the target of a backwards jump
+    fn next_index(&mut self) -> usize {
+        self.context().function.code.len()
+    }
+
+    /// Points the jump at the index to the target
+    fn patch(&mut self, at: usize, target: usize) {
+        let context = self.context();
+        context.last_target = context.last_target.max(target);
+        match &mut context.function.code[at] {
+            Instruction::Jump(t)
+            | Instruction::JumpIfFalse(t)
+            | Instruction::IterNext(_, t)
+            | Instruction::MatchPattern(_, t) => *t = target,
+            other => unreachable!("{other:?} is not a jump"),
+        }
+    }
+
+    /// Drops the value of a statement, statements without a value don't push one in the first place
+    fn emit_pop(&mut self, range: &Range<usize>) {
+        let context = self.context();
+        // NOTE: a jump to the end must still land after the value of the statement
+        if let Some(Instruction::Empty) = context.function.code.last()
+            && context.last_target < context.function.code.len()
+        {
+            context.function.code.pop();
+            context.function.ranges.pop();
+            context.depth -= 1;
+        } else {
+            self.emit(Instruction::Pop, range);
+        }
+    }
+
+    fn add_constant(&mut self, value: InterpreterValue) -> usize {
+        let existing = self
+            .program
+            .constants
+            .iter()
+            .position(|c| match (c, &value) {
+                (InterpreterValue::Int(a), InterpreterValue::Int(b)) => a == b,
+                (InterpreterValue::Bool(a), InterpreterValue::Bool(b)) => a == b,
+                (InterpreterValue::String(a), InterpreterValue::String(b)) => a == b,
+                (InterpreterValue::Option(None), InterpreterValue::Option(None)) => true,
+                _ => false,
+            });
+
+        existing.unwrap_or_else(|| {
+            self.program.constants.push(value);
+            self.program.constants.len() - 1
+        })
+    }
+
+    fn name_constant(&mut self, name: &Symbol) -> usize {
+        self.add_constant(InterpreterValue::String(name.clone()))
+    }
+
+    fn push_scope(&mut self) {
+        self.context().scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        self.context().scopes.pop();
+    }
+
+    /// Declares a variable in the innermost scope and returns its slot.
+    /// NOTE: every declaration owns a slot of the frame, so a loop reuses the slots of its body in every iteration
+    fn declare(&mut self, symbol: &Symbol, range: &Range<usize>) -> Result<usize, ErrorWithRange> {
+        let context = self.context();
+        let slot = context.function.slots;
+        let scope = context
+            .scopes
+            .last_mut()
+            .expect("a function has at least the scope of its params");
+        if scope.contains_key(symbol) {
+            return Err(ErrorWithRange::new(
+                Error::VariableAlreadyDeclared(symbol.clone()),
+                range.clone(),
+            ));
+        }
+
+        scope.insert(symbol.clone(), slot);
+        context.function.slots += 1;
+        Ok(slot)
+    }
+
+    fn resolve(&self, symbol: &Symbol) -> Option<Resolved> {
+        for (depth, context) in self.contexts.iter().rev().enumerate() {
+            if let Some(slot) = context.scopes.iter().rev().find_map(|s| s.get(symbol)) {
+                return Some(if depth == 0 {
+                    Resolved::Local(*slot)
+                } else {
+                    Resolved::Outer(depth, *slot)
+                });
+            }
+        }
+
+        self.global_index.get(symbol).map(|g| Resolved::Global(*g))
+    }
+
+    fn emit_load(&mut self, symbol: &Symbol, range: &Range<usize>) -> Result<(), ErrorWithRange> {
+        let instruction = match self.resolve(symbol) {
+            Some(Resolved::Local(slot)) => Instruction::LoadLocal(slot),
+            Some(Resolved::Outer(depth, slot)) => Instruction::LoadOuter(depth, slot),
+            Some(Resolved::Global(index)) => Instruction::LoadGlobal(index),
+            None => {
+                return Err(ErrorWithRange::new(
+                    Error::SymbolNotFound(symbol.clone()),
+                    range.clone(),
+                ));
+            }
+        };
+        self.emit(instruction, range);
+        Ok(())
+    }
+
+    fn emit_store(&mut self, symbol: &Symbol, range: &Range<usize>) -> Result<(), ErrorWithRange> {
+        let instruction = match self.resolve(symbol) {
+            Some(Resolved::Local(slot)) => Instruction::StoreLocal(slot),
+            Some(Resolved::Outer(depth, slot)) => Instruction::StoreOuter(depth, slot),
+            Some(Resolved::Global(index)) => Instruction::StoreGlobal(index),
+            None => {
+                return Err(ErrorWithRange::new(
+                    Error::SymbolNotFound(symbol.clone()),
+                    range.clone(),
+                ));
+            }
+        };
+        self.emit(instruction, range);
+        Ok(())
+    }
+
+    /// Compiles a function body into a new function and returns it
+    pub fn compile_function(
+        &mut self,
+        name: &Symbol,
+        params: &[(Symbol, TypeSymbol)],
+        returns_value: bool,
+        body: &[Box<AstNode>],
+        range: &Range<usize>,
+    ) -> Result<Function, ErrorWithRange> {
+        self.contexts.push(FunctionContext {
+            function: Function {
+                name: name.clone(),
+                params: params.iter().map(|(p, _)| p.clone()).collect(),
+                returns_value,
+                ..Function::default()
+            },
+            ..FunctionContext::default()
+        });
+
+        // NOTE: the body shares the scope of the params, like in the interpreter
+        self.push_scope();
+        for (param, _) in params {
+            self.declare(param, range)?;
+        }
+        let compiled = self.compile_nodes(body, range);
+        self.emit(Instruction::ReturnLast, range);
+
+        let context = self.contexts.pop().expect("pushed above");
+        compiled.map(|_| context.function)
+    }
+
+    /// Compiles a block in its own scope, its value is the value of its last expression
+    fn compile_block(
+        &mut self,
+        nodes: &[Box<AstNode>],
+        range: &Range<usize>,
+    ) -> Result<(), ErrorWithRange> {
+        self.push_scope();
+        let res = self.compile_nodes(nodes, range);
+        self.pop_scope();
+        res
+    }
+
+    fn compile_nodes(
+        &mut self,
+        nodes: &[Box<AstNode>],
+        range: &Range<usize>,
+    ) -> Result<(), ErrorWithRange> {
+        let Some((last, init)) = nodes.split_last() else {
+            self.emit(Instruction::Empty, range);
+            return Ok(());
+        };
+
+        for node in init {
+            self.compile_node(node)?;
+            self.emit_pop(&node.range);
+        }
+        self.compile_node(last)
+    }
+
+    /// Compiles a node, which leaves exactly one value on the stack
+    pub fn compile_node(&mut self, node: &AstNode) -> Result<(), ErrorWithRange> {
+        let range = &node.range;
+        match &node.type_of {
+            AstNodeType::Int(i) => {
+                let index = self.add_constant(InterpreterValue::Int(*i));
+                self.emit(Instruction::Constant(index), range);
+            }
+            AstNodeType::Float(f) => {
+                let index = self.add_constant(InterpreterValue::Float(*f));
+                self.emit(Instruction::Constant(index), range);
+            }
+            AstNodeType::Bool(b) => {
+                let index = self.add_constant(InterpreterValue::Bool(*b));
+                self.emit(Instruction::Constant(index), range);
+            }
+            AstNodeType::String(s) => {
+                let index = self.add_constant(InterpreterValue::String(s.clone()));
+                self.emit(Instruction::Constant(index), range);
+            }
+            AstNodeType::List(values) => {
+                for value in values {
+                    self.compile_node(value)?;
+                }
+                self.emit(Instruction::MakeList(values.len()), range);
+            }
+            AstNodeType::Interpolation(parts) => {
+                for part in parts {
+                    self.compile_node(part)?;
+                }
+                self.emit(Instruction::Concat(parts.len()), range);
+            }
+            AstNodeType::Weak(inner) => {
+                self.compile_node(inner)?;
+                self.emit(Instruction::Downgrade, range);
+            }
+            AstNodeType::Option(None) => {
+                let index = self.add_constant(InterpreterValue::Option(None));
+                self.emit(Instruction::Constant(index), range);
+            }
+            AstNodeType::Option(Some(inner)) => {
+                self.compile_node(inner)?;
+                self.emit(Instruction::WrapSome, range);
+            }
+            AstNodeType::Result(Ok(inner)) => {
+                self.compile_node(inner)?;
+                self.emit(Instruction::WrapOk, range);
+            }
+            AstNodeType::Result(Err(inner)) => {
+                self.compile_node(inner)?;
+                self.emit(Instruction::WrapErr, range);
+            }
+            AstNodeType::Closure {
+                params,
+                return_type,
+                execution_body,
+            } => {
+                let function = self.compile_function(
+                    &String::new(),
+                    params,
+                    return_type.is_some(),
+                    execution_body,
+                    range,
+                )?;
+                self.program.functions.push(function);
+                let index = self.program.functions.len() - 1;
+                self.emit(Instruction::MakeClosure(index), range);
+            }
+            AstNodeType::InfixCall(left, op, right) => {
+                self.compile_node(left)?;
+                self.compile_node(right)?;
+                self.emit(Instruction::Infix(op.clone()), range);
+            }
+            AstNodeType::PrefixCall(op, right) => {
+                self.compile_node(right)?;
+                self.emit(Instruction::Prefix(op.clone()), range);
+            }
+            AstNodeType::Declaration {
+                new_symbol,
+                expression,
+                ..
+            } => {
+                // NOTE: declared after the expression, which may still refer to a shadowed variable
+                self.compile_node(expression)?;
+                let slot = self.declare(new_symbol, range)?;
+                self.emit(Instruction::StoreLocal(slot), &expression.range);
+                self.emit(Instruction::Empty, range);
+            }
+            AstNodeType::AssignmentOp {
+                recipient,
+                operation,
+                expression,
+                ..
+            } => {
+                self.compile_node(expression)?;
+                self.emit_load(recipient, range)?;
+                self.emit(Instruction::Assign(operation.clone()), &expression.range);
+                self.emit_store(recipient, range)?;
+                self.emit(Instruction::Empty, range);
+            }
+            AstNodeType::MemberCall { calls } => self.compile_member_call(node, calls)?,
+            AstNodeType::ReturnStatement { return_value } => {
+                self.compile_node(return_value)?;
+                self.emit(Instruction::Return, range);
+                // NOTE: unreachable, but the statement counts as a value like any other
+                self.context().depth += 1;
+            }
+            AstNodeType::Branch {
+                cond,
+                body,
+                else_if_branches,
+                else_branch,
+            } => {
+                let depth = self.context().depth;
+                let mut ends = Vec::new();
+                let branches = std::iter::once((cond, body))
+                    .chain(else_if_branches.iter().map(|(cond, body)| (cond, body)));
+                for (cond, body) in branches {
+                    self.compile_node(cond)?;
+                    let skip = self.emit(Instruction::JumpIfFalse(0), &cond.range);
+                    self.compile_block(body, range)?;
+                    ends.push(self.emit(Instruction::Jump(0), range));
+                    let next = self.next_index();
+                    self.patch(skip, next);
+                    self.context().depth = depth;
+                }
+
+                match else_branch {
+                    Some(body) => self.compile_block(body, range)?,
+                    None => {
+                        self.emit(Instruction::Empty, range);
+                    }
+                }
+                let end = self.next_index();
+                for jump in ends {
+                    self.patch(jump, end);
+                }
+            }
+            AstNodeType::While { label, cond, body } => {
+                let start = self.next_index();
+                self.compile_node(cond)?;
+                let exit = self.emit(Instruction::JumpIfFalse(0), &cond.range);
+                self.compile_loop_body(label, body, range, start, exit)?;
+                self.emit(Instruction::Empty, range);
+            }
+            AstNodeType::For {
+                label,
+                declaration,
+                condition,
+                assignment,
+                body,
+            } => {
+                self.push_scope();
+                if let Some(init) = declaration {
+                    let AstNodeType::Declaration { .. } = &init.type_of else {
+                        return Err(ErrorWithRange::new(
+                            Error::OperationUnsupported {
+                                operation: "for loop declaration".to_owned(),
+                                type_of: "must be declaration".to_owned(),
+                            },
+                            init.range.clone(),
+                        ));
+                    };
+                    self.compile_node(init)?;
+                    self.emit_pop(&init.range);
+                }
+
+                let start = self.next_index();
+                let exit = match condition {
+                    Some(cond) => {
+                        self.compile_node(cond)?;
+                        Some(self.emit(Instruction::JumpIfFalse(0), &cond.range))
+                    }
+                    None => None,
+                };
+                let depth = self.context().depth;
+                self.context().loops.push(LoopContext {
+                    label: label.clone(),
+                    depth,
+                    breaks: Vec::new(),
+                    continues: Vec::new(),
+                });
+                self.compile_block(body, range)?;
+                self.emit_pop(range);
+
+                let step_index = self.next_index();
+                if let Some(step) = assignment {
+                    let AstNodeType::AssignmentOp { .. } = &step.type_of else {
+                        return Err(ErrorWithRange::new(
+                            Error::OperationUnsupported {
+                                operation: "for loop assignment".to_owned(),
+                                type_of: "must be assignment".to_owned(),
+                            },
+                            step.range.clone(),
+                        ));
+                    };
+                    self.compile_node(step)?;
+                    self.emit_pop(&step.range);
+                }
+                self.emit(Instruction::Jump(start), range);
+                self.finish_loop(exit, step_index);
+                self.pop_scope();
+                self.emit(Instruction::Empty, range);
+            }
+            AstNodeType::ForEach {
+                label,
+                recipient,
+                iterable,
+                body,
+            } => {
+                self.compile_node(iterable)?;
+                self.emit(Instruction::IterStart, &iterable.range);
+                self.push_scope();
+                let slot = self.declare(recipient, range)?;
+                let start = self.emit(Instruction::IterNext(slot, 0), &iterable.range);
+                self.compile_loop_body(label, body, range, start, start)?;
+                self.pop_scope();
+                self.emit(Instruction::PopN(2), range);
+                self.emit(Instruction::Empty, range);
+            }
+            AstNodeType::Match { value, arms } => self.compile_match(value, arms)?,
+            AstNodeType::Propagate(inner) => {
+                self.compile_node(inner)?;
+                self.emit(Instruction::Propagate, &inner.range);
+            }
+            AstNodeType::ExpressionStatement(expression) => {
+                self.compile_node(expression)?;
+                self.emit_pop(range);
+                self.emit(Instruction::Empty, range);
+            }
+            AstNodeType::Break(label) | AstNodeType::Continue(label) => {
+                let is_break = matches!(node.type_of, AstNodeType::Break(_));
+                let depth = self.context().depth;
+                let Some(target) = self
+                    .context()
+                    .loops
+                    .iter()
+                    .rposition(|l| label.is_none() || l.label == *label)
+                else {
+                    return Err(ErrorWithRange::new(
+                        match label {
+                            Some(label) => Error::UnknownLoopLabel(label.clone()),
+                            None => Error::LoopControlOutsideLoop(
+                                if is_break { "break" } else { "continue" }.to_owned(),
+                            ),
+                        },
+                        range.clone(),
+                    ));
+                };
+
+                let loop_depth = self.context().loops[target].depth;
+                if depth > loop_depth {
+                    self.emit(Instruction::PopN(depth - loop_depth), range);
+                }
+                let jump = self.emit(Instruction::Jump(0), range);
+                let context = self.context();
+                if is_break {
+                    context.loops[target].breaks.push(jump);
+                } else {
+                    context.loops[target].continues.push(jump);
+                }
+                // NOTE: unreachable, but the statement counts as a value like any other
+                context.depth = depth + 1;
+            }
+            // NOTE: like the interpreter, as long as values are not hashable
+            AstNodeType::Map(_) => {
+                return Err(ErrorWithRange::new(
+                    Error::Unsupported("map literals".to_owned()),
+                    range.clone(),
+                ));
+            }
+            _ => {
+                return Err(ErrorWithRange::new(
+                    Error::OperationUnsupported {
+                        operation: format!("{:?}", &node.type_of),
+                        type_of: "".to_owned(),
+                    },
+                    range.clone(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compiles the body of a while or for each loop, which jumps back to start, until the exit jump is taken
+    fn compile_loop_body(
+        &mut self,
+        label: &Option<Symbol>,
+        body: &[Box<AstNode>],
+        range: &Range<usize>,
+        start: usize,
+        exit: usize,
+    ) -> Result<(), ErrorWithRange> {
+        let depth = self.context().depth;
+        self.context().loops.push(LoopContext {
+            label: label.clone(),
+            depth,
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+        self.compile_block(body, range)?;
+        self.emit_pop(range);
+        self.emit(Instruction::Jump(start), range);
+        self.finish_loop(Some(exit), start);
+        Ok(())
+    }
+
+    /// Points the exit, break and continue jumps of the innermost loop to their targets
+    fn finish_loop(&mut self, exit: Option<usize>, continue_target: usize) {
+        let end = self.next_index();
+        let finished = self.context().loops.pop().expect("pushed by the loop");
+        if let Some(exit) = exit {
+            self.patch(exit, end);
+        }
+        for jump in finished.breaks {
+            self.patch(jump, end);
+        }
+        for jump in finished.continues {
+            self.patch(jump, continue_target);
+        }
+        self.context().depth = finished.depth;
+    }
+
+    fn compile_match(&mut self, value: &AstNode, arms: &[MatchArm]) -> Result<(), ErrorWithRange> {
+        self.compile_node(value)?;
+        let depth = self.context().depth;
+        let mut ends = Vec::new();
+
+        for arm in arms {
+            self.push_scope();
+            let mut bindings = Vec::new();
+            for symbol in arm.pattern.bindings() {
+                bindings.push((symbol.clone(), self.declare(symbol, &arm.range)?));
+            }
+            self.program.patterns.push(CompiledPattern {
+                pattern: arm.pattern.clone(),
+                bindings,
+            });
+            let pattern = self.program.patterns.len() - 1;
+
+            let mut next_arm = vec![self.emit(Instruction::MatchPattern(pattern, 0), &value.range)];
+            if let Some(guard) = &arm.guard {
+                self.compile_node(guard)?;
+                next_arm.push(self.emit(Instruction::JumpIfFalse(0), &guard.range));
+            }
+            self.emit(Instruction::Pop, &value.range);
+            // NOTE: the body shares the scope of the bindings
+            self.compile_nodes(&arm.body, &arm.range)?;
+            ends.push(self.emit(Instruction::Jump(0), &arm.range));
+            self.pop_scope();
+
+            let next = self.next_index();
+            for jump in next_arm {
+                self.patch(jump, next);
+            }
+            self.context().depth = depth;
+        }
+
+        self.emit(Instruction::NoMatch, &value.range);
+        let end = self.next_index();
+        for jump in ends {
+            self.patch(jump, end);
+        }
+        Ok(())
+    }
+
+    /// Member call represents any type of member call, a, a.b, a.b().c, a.b(a()).c, etc
+    fn compile_member_call(
+        &mut self,
+        node: &AstNode,
+        calls: &[MemberAccess],
+    ) -> Result<(), ErrorWithRange> {
+        let Some((first, rest)) = calls.split_first() else {
+            return Err(ErrorWithRange::new(
+                Error::OperationUnsupported {
+                    operation: "member call".to_owned(),
+                    type_of: "must be at least one member call".to_owned(),
+                },
+                node.range.clone(),
+            ));
+        };
+
+        match &first.type_of {
+            MemberAccessType::Symbol => self.emit_load(&first.member, &first.range)?,
+            MemberAccessType::Function(params) => self.compile_call(first, params)?,
+            MemberAccessType::Struct(fields) => self.compile_struct_literal(first, fields)?,
+        }
+
+        for call in rest {
+            let name = self.name_constant(&call.member);
+            match &call.type_of {
+                MemberAccessType::Symbol => {
+                    self.emit(Instruction::GetField(name), &call.range);
+                }
+                MemberAccessType::Function(params) => {
+                    for param in params {
+                        self.compile_node(param)?;
+                    }
+                    self.emit(Instruction::CallMethod(name, params.len()), &call.range);
+                }
+                MemberAccessType::Struct(_) => {
+                    return Err(ErrorWithRange::new(Error::IsNotAScope, call.range.clone()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Declared functions are called directly, all other values are called, after they were loaded
+    fn compile_call(
+        &mut self,
+        call: &MemberAccess,
+        params: &[Box<AstNode>],
+    ) -> Result<(), ErrorWithRange> {
+        let callee = match self.resolve(&call.member) {
+            Some(Resolved::Global(_)) => self.program.callees.get(&call.member).copied(),
+            _ => None,
+        };
+
+        if callee.is_none() {
+            self.emit_load(&call.member, &call.range)?;
+        }
+        for param in params {
+            self.compile_node(param)?;
+        }
+
+        let instruction = match callee {
+            Some(Callee::Function(index)) => Instruction::Call(index, params.len()),
+            Some(Callee::Buildin(index)) => Instruction::CallBuildin(index, params.len()),
+            None => Instruction::CallValue(self.name_constant(&call.member), params.len()),
+        };
+        self.emit(instruction, &call.range);
+        Ok(())
+    }
+
+    fn compile_struct_literal(
+        &mut self,
+        call: &MemberAccess,
+        fields: &[(Symbol, Box<AstNode>)],
+    ) -> Result<(), ErrorWithRange> {
+        // NOTE: resolve defined type here, not variable type, as this is a defined type
+        let Some(type_of) = self.global_scope.resolve_defined_type(&call.member) else {
+            return Err(ErrorWithRange::new(
+                Error::SymbolNotFound(call.member.clone()),
+                call.range.clone(),
+            )
+            .suggest(Some(call.name_range()), |name| {
+                self.global_scope.similar_type(name)
+            }));
+        };
+        let declared = match &type_of.type_of {
+            TypeSymbolType::Struct(s) => &s.fields,
+            TypeSymbolType::Component(c) => &c.fields,
+            _ => {
+                return Err(ErrorWithRange::new(Error::IsNotAScope, call.range.clone()));
+            }
+        };
+
+        for (field, value) in fields {
+            if !declared.iter().any(|(name, _)| name == field) {
+                return Err(ErrorWithRange::new(
+                    Error::SymbolNotFound(field.clone()),
+                    value.range.clone(),
+                )
+                .suggest(None, |name| similar_field(name, declared)));
+            }
+        }
+        if let Some((missing, _)) = declared
+            .iter()
+            .find(|(name, _)| !fields.iter().any(|(field, _)| field == name))
+        {
+            return Err(ErrorWithRange::new(
+                Error::ExpectedValue(missing.clone()),
+                call.range.clone(),
+            ));
+        }
+
+        for (_, value) in fields {
+            self.compile_node(value)?;
+        }
+        self.program.structs.push(StructLiteral {
+            type_of: type_of.clone(),
+            fields: fields.iter().map(|(field, _)| field.clone()).collect(),
+        });
+        let index = self.program.structs.len() - 1;
+        self.emit(Instruction::MakeStruct(index), &call.range);
+        Ok(())
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stage for Compiler {
+    fn init(&mut self, prev_stage_result: StageResult) -> Result<(), ErrorWithRange> {
+        match prev_stage_result {
+            // NOTE: the slots of the resolver are not needed, locals are resolved while compiling
+            StageResult::Preprocessor(global_scope, _) | StageResult::Resolved(global_scope, _) => {
+                self.global_scope = global_scope;
+                Ok(())
+            }
+            _ => Err(ErrorWithRange::new(
+                Error::StageError(2, prev_stage_result.into()),
+                0..1,
+            )),
+        }
+    }
+
+    fn run(mut self) -> Result<StageResult, ErrorWithRange> {
+        // NOTE: sorted, so the program is the same for every run
+        let mut globals = self
+            .global_scope
+            .iter_values()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect::<Vec<_>>();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+
+        // Functions are indexed before any body is compiled, as they may call each other
+        let mut bodies = Vec::new();
+        for (name, value) in globals {
+            self.global_index
+                .insert(name.clone(), self.program.globals.len());
+            self.program.globals.push(value.clone());
+
+            let InterpreterValue::Function(_) = value else {
+                continue;
+            };
+            let Some(TypeSymbol {
+                type_of: TypeSymbolType::Function(fn_type),
+                ..
+            }) = self.global_scope.resolve_type(&name)
+            else {
+                continue;
+            };
+
+            match &fn_type.execution_body {
+                FunctionExecutionStrategy::Interpreted(_) => {
+                    self.program
+                        .callees
+                        .insert(name, Callee::Function(self.program.functions.len()));
+                    self.program.functions.push(Function::default());
+                    bodies.push(fn_type);
+                }
+                FunctionExecutionStrategy::Buildin(_) => {
+                    self.program
+                        .callees
+                        .insert(name, Callee::Buildin(self.program.buildins.len()));
+                    self.program.buildins.push(fn_type);
+                }
+                // NOTE: called through their value, which fails like in the interpreter
+                FunctionExecutionStrategy::Native(_, _) => (),
+            }
+        }
+
+        for (index, fn_type) in bodies.into_iter().enumerate() {
+            let FunctionExecutionStrategy::Interpreted(body) = &fn_type.execution_body else {
+                unreachable!("only interpreted functions are compiled");
+            };
+            let range = self
+                .global_scope
+                .resolve_location(&fn_type.name)
+                .unwrap_or(0..1);
+            self.program.functions[index] = self.compile_function(
+                &fn_type.name,
+                &fn_type.params,
+                fn_type.return_type.is_some(),
+                body,
+                &range,
+            )?;
+        }
+
+        Ok(StageResult::Compiled(*self.program))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Callee, Compiler, Instruction, InterpreterValue, Parser, Preprocessor, Program,
+        StageResult, Stages, run_stages,
+    };
+
+    fn compile(source: &str) -> Program {
+        let stages = vec![
+            Stages::Parser(Parser::default()),
+            Stages::Preprocessor(Preprocessor::new().unwrap()),
+            Stages::Compiler(Compiler::new()),
+        ];
+
+        match run_stages(stages, StageResult::PreParse(source.to_owned())) {
+            Ok(StageResult::Compiled(program)) => program,
+            Ok(_) => panic!("the compiler must produce a program"),
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    #[test]
+    fn locals_are_resolved_to_slots() {
+        let program = compile(
+            r#"
+            fn main() {
+                a := 1;
+                b := a + 1;
+                c := fn (): int => a + b;
+            }
+            "#,
+        );
+
+        let Some(Callee::Function(main)) = program.callees.get("main") else {
+            panic!("main must be compiled");
+        };
+        let main = &program.functions[*main];
+        assert_eq!(main.slots, 3);
+        assert!(
+            main.code
+                .iter()
+                .any(|i| matches!(i, Instruction::LoadLocal(0)))
+        );
+
+        let closure = program
+            .functions
+            .iter()
+            .find(|f| f.name.is_empty())
+            .expect("the closure is compiled into its own function");
+        assert!(
+            closure
+                .code
+                .iter()
+                .any(|i| matches!(i, Instruction::LoadOuter(1, 0)))
+        );
+        assert!(
+            closure
+                .code
+                .iter()
+                .any(|i| matches!(i, Instruction::LoadOuter(1, 1)))
+        );
+
+        let ones = program
+            .constants
+            .iter()
+            .filter(|c| matches!(c, InterpreterValue::Int(1)))
+            .count();
+        assert_eq!(ones, 1);
+    }
+}
@@ -0,0 +1,247 @@
+use std::{collections::HashMap, ops::Range, rc::Rc};
+
+use crate::{
+    AstNode, ComponentType, Error, ErrorWithRange, Scope, Stage, StageResult, StructType, Symbol,
+    TypeSymbol, TypeSymbolType, Warning, WarningWithRange, Warnings,
+};
+
+/// A strong reference from a field of a struct or component to another one
+struct Edge {
+    field: Symbol,
+    to: Symbol,
+}
+
+/// Warns about structs and components, that can own themselves through strong references only.
+/// Such cycles are never freed, as everything is reference counted. Does not change the ast
+#[derive(Default)]
+pub struct CycleDetector {
+    ast: Vec<AstNode>,
+    global_scope: Scope,
+    warnings: Warnings,
+}
+
+impl CycleDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The warnings found by this stage, available after it ran
+    pub fn warnings(&self) -> Warnings {
+        Rc::clone(&self.warnings)
+    }
+}
+
+impl Stage for CycleDetector {
+    fn init(&mut self, prev_stage_result: StageResult) -> Result<(), ErrorWithRange> {
+        match prev_stage_result {
+            StageResult::Preprocessor(global_scope, ast) => {
+                self.global_scope = global_scope;
+                self.ast = ast;
+                Ok(())
+            }
+            _ => Err(ErrorWithRange::new(
+                Error::StageError(2, prev_stage_result.into()),
+                0..1,
+            )),
+        }
+    }
+
+    fn shared_warnings(&self) -> Option<Warnings> {
+        Some(self.warnings())
+    }
+
+    fn run(self) -> Result<StageResult, ErrorWithRange> {
+        self.warnings
+            .borrow_mut()
+            .extend(find_strong_cycles(&self.global_scope));
+
+        Ok(StageResult::Preprocessor(self.global_scope, self.ast))
+    }
+}
+
+/// Builds the ownership graph of all structs and components, that the preprocessor declared in the
+/// global scope, and returns a warning for every strong cycle in it
+pub fn find_strong_cycles(global_scope: &Scope) -> Vec<WarningWithRange> {
+    let mut definitions: Vec<(&Symbol, Range<usize>, Vec<Edge>)> = Vec::new();
+    for (typename, type_of) in global_scope.iter_defined_types() {
+        let (TypeSymbolType::Struct(StructType { fields, .. })
+        | TypeSymbolType::Component(ComponentType { fields, .. })) = &type_of.type_of
+        else {
+            continue;
+        };
+
+        let mut edges = Vec::new();
+        for (field, type_of) in fields {
+            let mut referenced = Vec::new();
+            strong_references(type_of, &mut referenced);
+            edges.extend(referenced.into_iter().map(|to| Edge {
+                field: field.clone(),
+                to,
+            }));
+        }
+        let range = global_scope.resolve_location(typename).unwrap_or(0..1);
+        definitions.push((typename, range, edges));
+    }
+    // NOTE: in definition order, so warnings are reported deterministically
+    definitions
+        .sort_by(|(a, a_range, _), (b, b_range, _)| (a_range.start, a).cmp(&(b_range.start, b)));
+
+    let index_of = definitions
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _, _))| ((*name).clone(), i))
+        .collect::<HashMap<_, _>>();
+    let graph = definitions
+        .iter()
+        .map(|(_, _, edges)| {
+            edges
+                .iter()
+                .filter_map(|edge| index_of.get(&edge.to).map(|to| (&edge.field, *to)))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut cycles = Vec::new();
+    let mut visited = vec![false; graph.len()];
+    for start in 0..graph.len() {
+        if !visited[start] {
+            let mut path = Vec::new();
+            find_back_edges(&graph, start, &mut visited, &mut path, &mut cycles);
+        }
+    }
+
+    cycles
+        .into_iter()
+        .map(|(cycle, owner, field)| {
+            let mut names = cycle
+                .iter()
+                .map(|i| definitions[*i].0.clone())
+                .collect::<Vec<_>>();
+            // Close the cycle, i.e. Node -> Node
+            names.push(names[0].clone());
+
+            WarningWithRange {
+                warning: Warning::StrongReferenceCycle {
+                    cycle: names,
+                    owner: definitions[owner].0.clone(),
+                    field: field.clone(),
+                },
+                range: definitions[owner].1.clone(),
+            }
+        })
+        .collect()
+}
+
+/// All types a field of this type owns, weak references and everything behind them is not owned
+fn strong_references(type_of: &TypeSymbol, referenced: &mut Vec<Symbol>) {
+    if type_of.is_weak {
+        return;
+    }
+
+    match &type_of.type_of {
+        TypeSymbolType::Symbol(name) => referenced.push(name.clone()),
+        TypeSymbolType::List(inner) | TypeSymbolType::Option(inner) => {
+            strong_references(inner, referenced)
+        }
+        TypeSymbolType::Map(a, b) | TypeSymbolType::Result(a, b) => {
+            strong_references(a, referenced);
+            strong_references(b, referenced);
+        }
+        _ => (),
+    }
+}
+
+/// Depth first search, every edge back into the current path closes a cycle.
+/// Cycles are reported as the definitions on the path, the definition owning the closing field and that field
+fn find_back_edges<'a>(
+    graph: &[Vec<(&'a Symbol, usize)>],
+    current: usize,
+    visited: &mut Vec<bool>,
+    path: &mut Vec<usize>,
+    cycles: &mut Vec<(Vec<usize>, usize, &'a Symbol)>,
+) {
+    visited[current] = true;
+    path.push(current);
+
+    for (field, to) in &graph[current] {
+        if let Some(position) = path.iter().position(|p| p == to) {
+            cycles.push((path[position..].to_vec(), current, *field));
+        } else if !visited[*to] {
+            find_back_edges(graph, *to, visited, path, cycles);
+        }
+    }
+
+    path.pop();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CycleDetector, Parser, Preprocessor, StageResult, Stages, Warning, run_stages};
+
+    /// The cycles the stage warns about, after the preprocessor declared the types
+    fn cycles(source: &str) -> Vec<(Vec<String>, String, String)> {
+        let detector = CycleDetector::new();
+        let warnings = detector.warnings();
+        let stages = vec![
+            Stages::Parser(Parser::default()),
+            Stages::Preprocessor(Preprocessor::new().unwrap()),
+            Stages::CycleDetector(detector),
+        ];
+        run_stages(stages, StageResult::PreParse(source.to_owned())).unwrap();
+
+        warnings
+            .borrow()
+            .iter()
+            .map(|w| match &w.warning {
+                Warning::StrongReferenceCycle {
+                    cycle,
+                    owner,
+                    field,
+                } => (cycle.clone(), owner.clone(), field.clone()),
+                other => panic!("unexpected warning {other}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn self_referencing_struct() {
+        let found = cycles("struct Node { value: int, next: Node?, }");
+        assert_eq!(
+            found,
+            vec![(
+                vec!["Node".to_owned(), "Node".to_owned()],
+                "Node".to_owned(),
+                "next".to_owned()
+            )]
+        );
+
+        assert!(cycles("struct Node { value: int, next: weak Node?, }").is_empty());
+    }
+
+    #[test]
+    fn indirect_cycle() {
+        let found = cycles(
+            r#"
+            struct Parent { children: [Child], }
+            struct Child { parent: Parent, }
+            struct Health { value: int, }
+            "#,
+        );
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, vec!["Parent", "Child", "Parent"]);
+        assert_eq!(
+            (found[0].1.as_str(), found[0].2.as_str()),
+            ("Child", "parent")
+        );
+
+        assert!(
+            cycles(
+                r#"
+                struct Parent { children: [Child], }
+                struct Child { parent: weak Parent, }
+                "#
+            )
+            .is_empty()
+        );
+    }
+}
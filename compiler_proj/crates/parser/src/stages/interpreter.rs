@@ -2,13 +2,15 @@ use std::{
     cell::RefCell,
     collections::{HashMap, HashSet},
     iter::zip,
-    rc::Rc,
+    ops::Range,
+    rc::{Rc, Weak},
 };
 
 use crate::{
-    AssignmentOperations, AstNode, AstNodeType, Error, ErrorWithRange, FunctionExecutionStrategy,
-    FunctionType, InfixOperator, InterpreterValue, MemberAccess, MemberAccessType, PrefixOperator,
-    Scope, Stage, StageResult, Symbol, TypeSymbol, TypeSymbolType,
+    AssignmentOperations, AstNode, AstNodeType, CapturedScope, Error, ErrorWithRange,
+    FunctionExecutionStrategy, FunctionType, InfixOperator, InterpreterValue, Jit, MatchArm,
+    MemberAccess, MemberAccessType, Pattern, PrefixOperator, Scope, Slot, Stage, StageResult,
+    Symbol, TypeSymbol, TypeSymbolType, Warning, WarningWithRange, Warnings, similar_field,
 };
 
 macro_rules! scoped {
@@ -41,11 +43,12 @@ macro_rules! with_parent_scope {
     }};
 }
 
+/// Leaves the current block on return, break and continue
 macro_rules! return_on_return {
     ($res:expr) => {
         match $res {
-            IsReturn::Return(_) => return Ok($res),
             IsReturn::NoReturn(_) => (),
+            _ => return Ok($res),
         }
     };
 }
@@ -53,6 +56,10 @@ macro_rules! return_on_return {
 pub enum IsReturn {
     NoReturn(InterpreterValue),
     Return(InterpreterValue),
+    /// Break out of the loop with the label, or the innermost loop
+    Break(Option<Symbol>),
+    /// Continue the loop with the label, or the innermost loop
+    Continue(Option<Symbol>),
 }
 
 impl IsReturn {
@@ -60,18 +67,91 @@ impl IsReturn {
         match self {
             IsReturn::NoReturn(v) => v,
             IsReturn::Return(v) => v,
+            IsReturn::Break(_) | IsReturn::Continue(_) => InterpreterValue::Empty,
         }
     }
 }
 
+/// How a loop proceeds after its body was evaluated
+enum LoopFlow {
+    Next,
+    Break,
+    Exit(IsReturn),
+}
+
+impl LoopFlow {
+    fn of(res: IsReturn, label: &Option<Symbol>) -> Self {
+        let targets_loop = |target: &Option<Symbol>| target.is_none() || target == label;
+
+        match res {
+            IsReturn::NoReturn(_) => LoopFlow::Next,
+            IsReturn::Continue(target) if targets_loop(&target) => LoopFlow::Next,
+            IsReturn::Break(target) if targets_loop(&target) => LoopFlow::Break,
+            // NOTE: return or break/continue of an outer loop
+            _ => LoopFlow::Exit(res),
+        }
+    }
+}
+
+/// Signature of a callable value and the scope it captured, if any
+pub type Callable = (TypeSymbol, Option<Rc<RefCell<Scope>>>);
+
+/// Calls a function value with a single argument, that was evaluated from the range
+pub type ValueCaller<'a> = dyn FnMut(
+        &InterpreterValue,
+        InterpreterValue,
+        &Range<usize>,
+    ) -> Result<InterpreterValue, ErrorWithRange>
+    + 'a;
+
+/// The buildin methods of weak references with their number of arguments
+const WEAK_METHODS: &[(&str, usize)] = &[("upgrade", 0)];
+
+/// The buildin methods of options with their number of arguments
+const OPTION_METHODS: &[(&str, usize)] = &[
+    ("is_some", 0),
+    ("is_none", 0),
+    ("unwrap", 0),
+    ("unwrap_or", 1),
+    ("expect", 1),
+    ("map", 1),
+    ("and_then", 1),
+    ("ok_or", 1),
+];
+
+/// The buildin methods of results with their number of arguments
+const RESULT_METHODS: &[(&str, usize)] = &[
+    ("is_ok", 0),
+    ("is_err", 0),
+    ("unwrap", 0),
+    ("unwrap_or", 1),
+    ("expect", 1),
+    ("map", 1),
+    ("map_err", 1),
+    ("and_then", 1),
+];
+
 pub struct Environment {
     scope: Rc<RefCell<Scope>>,
 }
 
+/// A scope that can be part of a reference cycle, i.e. the scope of a struct or the environment of a closure
+struct TrackedScope {
+    scope: Weak<RefCell<Scope>>,
+    description: String,
+    range: Range<usize>,
+}
+
 pub struct Interpreter {
     environments: Vec<Environment>,
     ast: Vec<AstNode>,
     entrypoint_fn: Symbol,
+    // Only tracked with the cycle collector enabled
+    tracked_scopes: Option<Vec<TrackedScope>>,
+    warnings: Warnings,
+    // Compiles hot functions to machine code, if enabled
+    jit: Option<Box<Jit>>,
+    strict: bool,
 }
 
 impl Interpreter {
@@ -80,6 +160,94 @@ impl Interpreter {
             environments: vec![],
             ast: Vec::new(),
             entrypoint_fn,
+            tracked_scopes: None,
+            warnings: Warnings::default(),
+            jit: None,
+            strict: false,
+        }
+    }
+
+    /// Compiles functions over ints, floats and bools to machine code, once they were called threshold times.
+    /// Stays purely interpreted, if the host is not supported by the jit
+    pub fn with_jit(mut self, threshold: usize) -> Self {
+        self.jit = Jit::new(threshold)
+            .ok()
+            .map(|jit| if self.strict { jit.with_strict() } else { jit })
+            .map(Box::new);
+        self
+    }
+
+    /// Strict mode: arithmetic on an int and a float is an error, instead of converting the int to a float
+    pub fn with_strict(mut self) -> Self {
+        self.strict = true;
+        self.jit = self.jit.map(|jit| Box::new(jit.with_strict()));
+        self
+    }
+
+    pub fn jit(&self) -> Option<&Jit> {
+        self.jit.as_deref()
+    }
+
+    /// Debug mode: reports all values, that are leaked in reference cycles, when the program exits.
+    /// Every scope that could be part of a cycle is tracked, which slows down execution
+    pub fn with_cycle_collector(mut self) -> Self {
+        self.tracked_scopes = Some(Vec::new());
+        self
+    }
+
+    /// The warnings of the cycle collector, available after the interpreter ran
+    pub fn warnings(&self) -> Warnings {
+        Rc::clone(&self.warnings)
+    }
+
+    fn track_scope(&mut self, scope: &Rc<RefCell<Scope>>, description: String, range: Range<usize>) {
+        let Some(tracked) = &mut self.tracked_scopes else {
+            return;
+        };
+
+        // NOTE: forget freed scopes before growing, which keeps long running loops from growing the list indefinitely
+        if tracked.len() == tracked.capacity() {
+            tracked.retain(|t| t.scope.strong_count() > 0);
+        }
+        tracked.push(TrackedScope {
+            scope: Rc::downgrade(scope),
+            description,
+            range,
+        });
+    }
+
+    /// Frees all tracked scopes, that are still alive after all environments are dropped, and warns about them
+    fn collect_cycles(&mut self) {
+        let Some(tracked) = self.tracked_scopes.take() else {
+            return;
+        };
+        self.environments.clear();
+
+        // NOTE: structs own themselves through self by design, only cycles created by the program are reported
+        for t in &tracked {
+            if let Some(scope) = t.scope.upgrade() {
+                let value = scope.borrow_mut().remove_value(&"self".to_owned());
+                drop(value);
+            }
+        }
+
+        let mut leaked: Vec<(Rc<RefCell<Scope>>, &TrackedScope)> = Vec::new();
+        for t in &tracked {
+            if let Some(scope) = t.scope.upgrade()
+                && !leaked.iter().any(|(l, _)| Rc::ptr_eq(l, &scope))
+            {
+                leaked.push((scope, t));
+            }
+        }
+
+        for (scope, t) in leaked {
+            self.warnings.borrow_mut().push(WarningWithRange {
+                warning: Warning::LeakedCycle(t.description.clone()),
+                range: t.range.clone(),
+            });
+
+            let values = scope.borrow_mut().take_values();
+            drop(values);
         }
     }
 
@@ -103,11 +271,20 @@ impl Interpreter {
         self.environments.pop();
     }
 
-    pub fn eval_symbol(&mut self, symbol: &Symbol) -> Result<InterpreterValue, Error> {
+    /// Evaluates a variable, by its slot if the resolver assigned one, else by its name
+    pub fn eval_symbol(
+        &mut self,
+        symbol: &Symbol,
+        slot: &Option<Slot>,
+    ) -> Result<InterpreterValue, Error> {
         let scope = self.get_current_scope();
         let scope = scope.borrow();
 
-        if let Some(val) = scope.resolve_value(symbol) {
+        let value = match slot {
+            Some(slot) => scope.resolve_slot(slot),
+            None => scope.resolve_value(symbol),
+        };
+        if let Some(val) = value {
             Ok(val)
         } else {
             Err(Error::SymbolNotFound(symbol.clone()))
@@ -123,34 +300,12 @@ impl Interpreter {
         let lval = self.eval_node(left)?.unwrap();
         let rval = self.eval_node(right)?.unwrap();
 
-        let new_val = match op {
-            InfixOperator::Plus => lval + rval,
-            InfixOperator::Minus => lval - rval,
-            InfixOperator::Multiply => lval * rval,
-            InfixOperator::Divide => lval / rval,
-            InfixOperator::Modulo => lval % rval,
-            InfixOperator::And => lval.logical_and(rval),
-            InfixOperator::Or => lval.logical_or(rval),
-            InfixOperator::Equals => lval.equals(rval),
-            InfixOperator::NotEquals => lval.not_equals(rval),
-            InfixOperator::LessThan => lval.less_than(rval),
-            InfixOperator::LessThanEquals => lval.less_than_equals(rval),
-            InfixOperator::GreaterThan => lval.greater_than(rval),
-            InfixOperator::GreaterThanEquals => lval.greater_than_equals(rval),
-        };
+        let new_val = lval.apply_infix(op, rval, self.strict);
 
-        if let Ok(v) = new_val {
-            Ok(v.make_reference_counted().map_err(|e| ErrorWithRange {
-                err: e,
-                range: left.range.clone(),
-            })?)
-        } else {
-            let e = new_val.unwrap_err();
-            Err(ErrorWithRange {
-                err: e,
-                range: left.range.clone(),
-            })
-        }
+        // NOTE: errors of the operation itself, i.e. an overflow, point at the whole expression
+        new_val
+            .and_then(InterpreterValue::make_reference_counted)
+            .map_err(|e| ErrorWithRange::new(e, left.range.start..right.range.end))
     }
 
     pub fn eval_prefix_call(
@@ -166,16 +321,11 @@ impl Interpreter {
         };
 
         if let Ok(v) = new_val {
-            Ok(v.make_reference_counted().map_err(|e| ErrorWithRange {
-                err: e,
-                range: right.range.clone(),
-            })?)
+            Ok(v.make_reference_counted()
+                .map_err(|e| ErrorWithRange::new(e, right.range.clone()))?)
         } else {
             let e = new_val.unwrap_err();
-            Err(ErrorWithRange {
-                err: e,
-                range: right.range.clone(),
-            })
+            Err(ErrorWithRange::new(e, right.range.clone()))
         }
     }
 
@@ -185,62 +335,49 @@ impl Interpreter {
         new_symbol: &Symbol,
         expression: &AstNode,
         assumed_type: &Option<TypeSymbol>,
+        slot: &Option<Slot>,
     ) -> Result<(), ErrorWithRange> {
         let value = self.eval_node(expression)?.unwrap();
         if let InterpreterValue::Empty = value {
-            return Err(ErrorWithRange {
-                err: Error::CantBeEmpty,
-                range: expression.range.clone(),
-            });
+            return Err(ErrorWithRange::new(
+                Error::CantBeEmpty,
+                expression.range.clone(),
+            ));
         }
 
         let scope = self.get_current_scope();
         let mut scope = scope.borrow_mut();
 
-        if let Some(type_of) = assumed_type {
+        let type_of = if let Some(type_of) = assumed_type {
             // The user provided a type. Check if the types align. if yes, everything is ok, else throw error
             // TODO: Type checking
-            let decl_var = scope.declare_variable(
+            type_of.clone()
+        } else {
+            // Here, the type is not actually provided by the developer, hence, automatic type coercion must occur
+            let type_of: Option<TypeSymbol> = match &value {
+                InterpreterValue::Function(name) => scope.resolve_type(name),
+                _ => value.clone().into(),
+            };
+            type_of.ok_or_else(|| {
+                ErrorWithRange::new(Error::TypeDeductionError, expression.range.clone())
+            })?
+        };
+
+        let decl_var = match slot {
+            Some(slot) => scope.declare_slot(slot.index, value, type_of),
+            None => scope.declare_variable(
                 new_symbol.clone(),
                 value,
-                type_of.clone(),
+                type_of,
                 false,
                 false,
                 node.range.clone(),
-            );
-            if let Err(e) = decl_var {
-                return Err(ErrorWithRange {
-                    err: e,
-                    range: expression.range.clone(),
-                });
-            }
-        } else {
-            // Here, the type is not actually provided by the developer, hence, automatic type coercion must occur
-            let type_of: Option<TypeSymbol> = value.clone().into();
-            if let Some(type_of) = type_of {
-                let decl_var = scope.declare_variable(
-                    new_symbol.clone(),
-                    value,
-                    type_of,
-                    false,
-                    false,
-                    node.range.clone(),
-                );
-                if let Err(e) = decl_var {
-                    return Err(ErrorWithRange {
-                        err: e,
-                        range: expression.range.clone(),
-                    });
-                }
-            } else {
-                return Err(ErrorWithRange {
-                    err: Error::TypeDeductionError,
-                    range: expression.range.clone(),
-                });
-            }
-        }
-
-        Ok(())
+            ),
+        };
+        decl_var.map_err(|e| {
+            ErrorWithRange::new(e, expression.range.clone())
+                .suggest(None, |name| scope.similar_type(name))
+        })
     }
 
     pub fn eval_assignment_op(
@@ -249,76 +386,63 @@ impl Interpreter {
         recipient: &Symbol,
         op: &AssignmentOperations,
         expression: &AstNode,
+        slot: &Option<Slot>,
     ) -> Result<(), ErrorWithRange> {
         let value = self.eval_node(expression)?.unwrap();
         if let InterpreterValue::Empty = value {
-            return Err(ErrorWithRange {
-                err: Error::CantBeEmpty,
-                range: expression.range.clone(),
-            });
+            return Err(ErrorWithRange::new(
+                Error::CantBeEmpty,
+                expression.range.clone(),
+            ));
         }
 
         let scope = self.get_current_scope();
         let mut scope = scope.borrow_mut();
-        if let Some(old_value) = scope.resolve_value(recipient) {
-            if let InterpreterValue::Entity(_e) =
-                old_value.deref().map_err(|err| ErrorWithRange {
-                    err,
-                    range: expression.range.clone(),
-                })?
+        let old_value = match slot {
+            Some(slot) => scope.resolve_slot(slot),
+            None => scope.resolve_value(recipient),
+        };
+        if let Some(old_value) = old_value {
+            if let InterpreterValue::Entity(_e) = old_value
+                .deref()
+                .map_err(|err| ErrorWithRange::new(err, expression.range.clone()))?
             {
-                if let InterpreterValue::Component(_, _) =
-                    value.deref().map_err(|err| ErrorWithRange {
-                        err,
-                        range: expression.range.clone(),
-                    })?
+                if let InterpreterValue::Component(_, _) = value
+                    .deref()
+                    .map_err(|err| ErrorWithRange::new(err, expression.range.clone()))?
                 {
                     // TODO: manipulate entity here, using value as a component
                     // TODO: split logic up only in the case of assignment add operation
                 } else {
-                    Err(ErrorWithRange {
-                        err: Error::OperationUnsupported {
+                    Err(ErrorWithRange::new(
+                        Error::OperationUnsupported {
                             operation: "assignment operation".to_owned(),
                             type_of: "must assign component to entity".to_owned(),
                         },
-                        range: expression.range.clone(),
-                    })?;
+                        expression.range.clone(),
+                    ))?;
                 }
             } else {
-                let new_value = match op {
-                    AssignmentOperations::Add => old_value + value,
-                    AssignmentOperations::Subtract => old_value - value,
-                    AssignmentOperations::Multiply => old_value * value,
-                    AssignmentOperations::Divide => old_value / value,
-                    AssignmentOperations::Modulo => old_value % value,
-                    AssignmentOperations::Identity => Ok(value),
+                let new_value = match op.infix_operator() {
+                    Some(op) => old_value.apply_infix(&op, value, self.strict),
+                    None => Ok(value),
                 }
-                .map_err(|err| ErrorWithRange {
-                    err,
-                    range: expression.range.clone(),
-                });
-
-                let new_value = new_value?;
-                scope
-                    .set_value(
-                        recipient,
-                        new_value
-                            .make_reference_counted()
-                            .map_err(|err| ErrorWithRange {
-                                err,
-                                range: expression.range.clone(),
-                            })?,
-                    )
-                    .map_err(|err| ErrorWithRange {
-                        err,
-                        range: expression.range.clone(),
-                    })?;
+                .map_err(|err| ErrorWithRange::new(err, expression.range.clone()));
+
+                let new_value = new_value?
+                    .make_reference_counted()
+                    .map_err(|err| ErrorWithRange::new(err, expression.range.clone()))?;
+                match slot {
+                    Some(slot) => scope.set_slot(recipient, slot, new_value),
+                    None => scope.set_value(recipient, new_value),
+                }
+                .map_err(|err| ErrorWithRange::new(err, expression.range.clone()))?;
             }
         } else {
-            return Err(ErrorWithRange {
-                err: Error::SymbolNotFound(recipient.clone()),
-                range: node.range.clone(),
-            });
+            return Err(ErrorWithRange::new(
+                Error::SymbolNotFound(recipient.clone()),
+                node.range.clone(),
+            ));
         }
 
         Ok(())
@@ -326,13 +450,180 @@ impl Interpreter {
 
     pub fn eval_weak(&mut self, inner: &AstNode) -> Result<InterpreterValue, ErrorWithRange> {
         let val = self.eval_node(inner)?.unwrap();
-        if let InterpreterValue::Strong(rc) = val {
-            Ok(InterpreterValue::Weak(Rc::downgrade(&rc)))
-        } else {
-            Err(ErrorWithRange {
-                err: Error::MainNotFound,
-                range: inner.range.clone(),
-            })
+        val.downgrade()
+            .map_err(|err| ErrorWithRange::new(err, inner.range.clone()))
+    }
+
+    /// Creates a closure, that captures the current scope by reference
+    pub fn eval_closure(
+        &mut self,
+        params: &[(Symbol, TypeSymbol)],
+        return_type: &Option<TypeSymbol>,
+        execution_body: &[Box<AstNode>],
+    ) -> InterpreterValue {
+        let fn_type = FunctionType {
+            name: String::new(),
+            params: params.to_vec(),
+            return_type: return_type.clone().map(Box::new),
+            execution_body: FunctionExecutionStrategy::Interpreted(execution_body.to_vec()),
+        };
+
+        InterpreterValue::new_strong(InterpreterValue::Closure(
+            fn_type,
+            CapturedScope::Strong(self.get_current_scope()),
+        ))
+    }
+
+    /// Resolves the signature of a called value and the environment it has to be executed in.
+    /// Declared functions run in the global scope, closures in the scope they captured
+    pub fn resolve_callable(
+        scope: &Scope,
+        member: &Symbol,
+        callee: InterpreterValue,
+    ) -> Result<Callable, Error> {
+        match InterpreterValue::preprocess_single(callee)? {
+            // NOTE: resolve the type of the function itself, the variable may only hold its signature
+            InterpreterValue::Function(name) => scope
+                .resolve_type(&name)
+                .map(|fn_type| (fn_type, None))
+                .ok_or(Error::SymbolNotFound(name)),
+            InterpreterValue::Closure(fn_type, environment) => Ok((
+                TypeSymbol::strong(TypeSymbolType::Function(fn_type)),
+                Some(environment.upgrade().ok_or(Error::DanglingWeak)?),
+            )),
+            _ => Err(Error::NotCallable(member.clone())),
+        }
+    }
+
+    /// Evaluates the first arm, whose pattern matches and whose guard holds
+    pub fn eval_match(
+        &mut self,
+        value: &AstNode,
+        arms: &[MatchArm],
+    ) -> Result<IsReturn, ErrorWithRange> {
+        let matched = self.eval_node(value)?.unwrap();
+
+        for arm in arms {
+            let mut bindings = Vec::new();
+            if !Self::match_pattern(&arm.pattern, &matched, &mut bindings)
+                .map_err(|err| ErrorWithRange::new(err, value.range.clone()))?
+            {
+                continue;
+            }
+
+            let res = scoped!(self, {
+                {
+                    let scope = self.get_current_scope();
+                    let mut scope = scope.borrow_mut();
+                    // NOTE: the resolver places the bindings in the first slots, in the order of Pattern::bindings
+                    for (index, (_, bound)) in bindings.into_iter().enumerate() {
+                        let type_of: Option<TypeSymbol> = bound.clone().into();
+                        scope
+                            .declare_slot(
+                                index,
+                                bound,
+                                type_of.unwrap_or(TypeSymbol::strong(TypeSymbolType::Any)),
+                            )
+                            .map_err(|err| ErrorWithRange::new(err, arm.range.clone()))?;
+                    }
+                }
+
+                let guard_holds = match &arm.guard {
+                    Some(guard) => self
+                        .eval_node(guard)?
+                        .unwrap()
+                        .as_bool()
+                        .map_err(|err| ErrorWithRange::new(err, guard.range.clone()))?,
+                    None => true,
+                };
+
+                if guard_holds {
+                    Some(self.eval_nodes(&arm.body)?)
+                } else {
+                    None
+                }
+            });
+
+            if let Some(res) = res {
+                return Ok(res);
+            }
+        }
+
+        Err(ErrorWithRange::new(
+            Error::NonExhaustiveMatch(matched.to_string()),
+            value.range.clone(),
+        ))
+    }
+
+    /// Checks if the value matches the pattern and collects the bound symbols
+    pub fn match_pattern(
+        pattern: &Pattern,
+        value: &InterpreterValue,
+        bindings: &mut Vec<(Symbol, InterpreterValue)>,
+    ) -> Result<bool, Error> {
+        if let Pattern::Wildcard = pattern {
+            return Ok(true);
+        }
+        if let Pattern::Binding(symbol) = pattern {
+            bindings.push((symbol.clone(), value.clone()));
+            return Ok(true);
+        }
+
+        let matches = match (pattern, InterpreterValue::preprocess_single(value.clone())?) {
+            (Pattern::Int(p), InterpreterValue::Int(v)) => *p == v,
+            (Pattern::Float(p), InterpreterValue::Float(v)) => *p == v,
+            (Pattern::String(p), InterpreterValue::String(v)) => *p == v,
+            (Pattern::Bool(p), InterpreterValue::Bool(v)) => *p == v,
+            (Pattern::None, InterpreterValue::Option(None)) => true,
+            (Pattern::Some(p), InterpreterValue::Option(Some(v)))
+            | (Pattern::Ok(p), InterpreterValue::Result(Ok(v)))
+            | (Pattern::Err(p), InterpreterValue::Result(Err(v))) => {
+                Self::match_pattern(p, &v, bindings)?
+            }
+            (Pattern::Struct(name, fields), InterpreterValue::Struct(type_name, scope))
+            | (Pattern::Struct(name, fields), InterpreterValue::Component(type_name, scope)) => {
+                if *name != type_name {
+                    return Ok(false);
+                }
+
+                for (field, p) in fields {
+                    let Some(field_value) = scope.borrow().resolve_value(field) else {
+                        return Err(Error::SymbolNotFound(field.clone()));
+                    };
+                    if !Self::match_pattern(p, &field_value, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            _ => false,
+        };
+
+        Ok(matches)
+    }
+
+    /// a? returns the error of a result from the current function, otherwise it unwraps the ok value.
+    /// NOTE: the error unwinds through the error channel, as ? may be nested in any expression
+    pub fn eval_propagate(&mut self, inner: &AstNode) -> Result<IsReturn, ErrorWithRange> {
+        let value = self.eval_node(inner)?.unwrap();
+        let res = InterpreterValue::preprocess_single(value)
+            .map_err(|err| ErrorWithRange::new(err, inner.range.clone()))?;
+
+        match res {
+            InterpreterValue::Result(Ok(v)) => Ok(IsReturn::NoReturn(*v)),
+            InterpreterValue::Result(Err(e)) => Err(ErrorWithRange::new(
+                Error::ErrorPropagation(Box::new(InterpreterValue::new_strong(
+                    InterpreterValue::Result(Err(e)),
+                ))),
+                inner.range.clone(),
+            )),
+            _ => Err(ErrorWithRange::new(
+                Error::OperationUnsupported {
+                    operation: "?".to_owned(),
+                    type_of: "can only be applied to results".to_owned(),
+                },
+                inner.range.clone(),
+            )),
         }
     }
 
@@ -345,50 +636,44 @@ impl Interpreter {
     ) -> Result<IsReturn, ErrorWithRange> {
         // NOTE: Cannot be return, hence safe to unwrap
         let cond1 = self.eval_node(cond)?.unwrap();
+        let cond1 = InterpreterValue::preprocess_single(cond1)
+            .map_err(|err| ErrorWithRange::new(err, cond.range.clone()))?;
 
         let InterpreterValue::Bool(cond1) = cond1 else {
-            return Err(ErrorWithRange {
-                err: Error::OperationUnsupported {
+            return Err(ErrorWithRange::new(
+                Error::OperationUnsupported {
                     operation: "if condition".to_owned(),
                     type_of: "must be bool".to_owned(),
                 },
-                range: cond.range.clone(),
-            });
+                cond.range.clone(),
+            ));
         };
 
+        // NOTE: the value of the taken branch is the value of the if
         if cond1 {
-            let res = scoped!(self, { self.eval_nodes(body)? });
-
-            return_on_return!(res);
+            return scoped!(self, { self.eval_nodes(body) });
         } else {
-            let mut executed_case = false;
-
             for elif in else_ifs {
                 let cond = self.eval_node(elif.0.as_ref())?.unwrap();
+                let cond = InterpreterValue::preprocess_single(cond)
+                    .map_err(|err| ErrorWithRange::new(err, elif.0.range.clone()))?;
                 let InterpreterValue::Bool(cond) = cond else {
-                    return Err(ErrorWithRange {
-                        err: Error::OperationUnsupported {
+                    return Err(ErrorWithRange::new(
+                        Error::OperationUnsupported {
                             operation: "elseif condition".to_owned(),
                             type_of: "must be bool".to_owned(),
                         },
-                        range: elif.0.range.clone(),
-                    });
+                        elif.0.range.clone(),
+                    ));
                 };
 
                 if cond {
-                    let res = scoped!(self, { self.eval_nodes(&elif.1)? });
-
-                    return_on_return!(res);
-                    executed_case = true;
-                    break;
+                    return scoped!(self, { self.eval_nodes(&elif.1) });
                 }
             }
 
-            if !executed_case && else_branch.is_some() {
-                let else_branch = else_branch.as_ref().expect("checked");
-
-                let res = scoped!(self, { self.eval_nodes(else_branch)? });
-                return_on_return!(res);
+            if let Some(else_branch) = else_branch {
+                return scoped!(self, { self.eval_nodes(else_branch) });
             }
         }
 
@@ -397,21 +682,26 @@ impl Interpreter {
 
     pub fn eval_while(
         &mut self,
+        label: &Option<Symbol>,
         cond: &AstNode,
         body: &Vec<Box<AstNode>>,
     ) -> Result<IsReturn, ErrorWithRange> {
         loop {
             let cond1 = self.eval_node(cond)?.unwrap();
 
-            if !cond1.as_bool().map_err(|e| ErrorWithRange {
-                err: e,
-                range: cond.range.clone(),
-            })? {
+            if !cond1
+                .as_bool()
+                .map_err(|e| ErrorWithRange::new(e, cond.range.clone()))?
+            {
                 break;
             }
 
-            let res = scoped!(self, { self.eval_nodes(body)? });
-            return_on_return!(res);
+            let res = scoped!(self, { self.eval_nodes(body) })?;
+            match LoopFlow::of(res, label) {
+                LoopFlow::Next => (),
+                LoopFlow::Break => break,
+                LoopFlow::Exit(res) => return Ok(res),
+            }
         }
 
         Ok(IsReturn::NoReturn(InterpreterValue::Empty))
@@ -419,124 +709,146 @@ impl Interpreter {
 
     pub fn eval_for(
         &mut self,
+        label: &Option<Symbol>,
         init: &Option<Box<AstNode>>,
         cond: &Option<Box<AstNode>>,
         step: &Option<Box<AstNode>>,
         body: &Vec<Box<AstNode>>,
     ) -> Result<IsReturn, ErrorWithRange> {
-        scoped!(self, {
-            // Init condition
-            if let Some(init) = init.as_ref() {
-                match &init.type_of {
-                    AstNodeType::Declaration {
-                        new_symbol: _,
-                        expression: _,
-                        assumed_type: _,
-                    } => {
-                        self.eval_node(init.as_ref())?;
-                    }
-                    _ => {
-                        return Err(ErrorWithRange {
-                            err: Error::OperationUnsupported {
-                                operation: "for loop declaration".to_owned(),
-                                type_of: "must be declaration".to_owned(),
-                            },
-                            range: init.range.clone(),
-                        });
-                    }
+        // NOTE: the loop is left by returns, they must not skip popping the scope of its declaration
+        scoped!(self, { self.eval_for_loop(label, init, cond, step, body) })
+    }
+
+    /// The loop of a for, in the scope of its declaration
+    pub fn eval_for_loop(
+        &mut self,
+        label: &Option<Symbol>,
+        init: &Option<Box<AstNode>>,
+        cond: &Option<Box<AstNode>>,
+        step: &Option<Box<AstNode>>,
+        body: &Vec<Box<AstNode>>,
+    ) -> Result<IsReturn, ErrorWithRange> {
+        // Init condition
+        if let Some(init) = init.as_ref() {
+            match &init.type_of {
+                AstNodeType::Declaration { .. } => {
+                    self.eval_node(init.as_ref())?;
+                }
+                _ => {
+                    return Err(ErrorWithRange::new(
+                        Error::OperationUnsupported {
+                            operation: "for loop declaration".to_owned(),
+                            type_of: "must be declaration".to_owned(),
+                        },
+                        init.range.clone(),
+                    ));
                 }
             }
+        }
 
-            loop {
-                if let Some(cond) = cond.as_ref() {
-                    let cond1 = self.eval_node(cond.as_ref())?.unwrap();
+        loop {
+            if let Some(cond) = cond.as_ref() {
+                let cond1 = self.eval_node(cond.as_ref())?.unwrap();
 
-                    if !cond1.as_bool().map_err(|e| ErrorWithRange {
-                        err: e,
-                        range: cond.range.clone(),
-                    })? {
-                        break;
-                    }
+                if !cond1
+                    .as_bool()
+                    .map_err(|e| ErrorWithRange::new(e, cond.range.clone()))?
+                {
+                    break;
                 }
+            }
 
-                let res = scoped!(self, { self.eval_nodes(body)? });
-                return_on_return!(res);
-
-                if let Some(step) = step.as_ref() {
-                    match &step.type_of {
-                        AstNodeType::AssignmentOp {
-                            recipient: _,
-                            operation: _,
-                            expression: _,
-                        } => {
-                            self.eval_node(step.as_ref())?;
-                        }
-                        _ => {
-                            return Err(ErrorWithRange {
-                                err: Error::OperationUnsupported {
-                                    operation: "for loop assignment".to_owned(),
-                                    type_of: "must be assignment".to_owned(),
-                                },
-                                range: step.range.clone(),
-                            });
-                        }
+            let res = scoped!(self, { self.eval_nodes(body) })?;
+            match LoopFlow::of(res, label) {
+                LoopFlow::Next => (),
+                LoopFlow::Break => break,
+                LoopFlow::Exit(res) => return Ok(res),
+            }
+
+            if let Some(step) = step.as_ref() {
+                match &step.type_of {
+                    AstNodeType::AssignmentOp { .. } => {
+                        self.eval_node(step.as_ref())?;
+                    }
+                    _ => {
+                        return Err(ErrorWithRange::new(
+                            Error::OperationUnsupported {
+                                operation: "for loop assignment".to_owned(),
+                                type_of: "must be assignment".to_owned(),
+                            },
+                            step.range.clone(),
+                        ));
                     }
                 }
             }
-        });
+        }
 
         Ok(IsReturn::NoReturn(InterpreterValue::Empty))
     }
 
     pub fn eval_for_each(
         &mut self,
-        node: &AstNode,
-        recipient: &Symbol,
+        label: &Option<Symbol>,
         iterable: &AstNode,
         body: &Vec<Box<AstNode>>,
     ) -> Result<IsReturn, ErrorWithRange> {
         let iterable1 = self.eval_node(iterable)?.unwrap();
 
-        for entry in iterable1.as_list().map_err(|e| ErrorWithRange {
-            err: e,
-            range: iterable.range.clone(),
-        })? {
-            scoped!(self, {
+        for entry in iterable1
+            .as_list()
+            .map_err(|e| ErrorWithRange::new(e, iterable.range.clone()))?
+        {
+            // NOTE: the flow is handled outside of the scopes, so they are always popped
+            let flow = scoped!(self, {
                 let Some(type_of) = entry.clone().into() else {
-                    return Err(ErrorWithRange {
-                        err: Error::OperationUnsupported {
+                    return Err(ErrorWithRange::new(
+                        Error::OperationUnsupported {
                             operation: "foreach".to_owned(),
                             type_of: "non list type".to_owned(),
                         },
-                        range: iterable.range.clone(),
-                    });
+                        iterable.range.clone(),
+                    ));
                 };
 
+                // NOTE: the recipient is the only variable of the iteration scope
                 self.get_current_scope()
                     .borrow_mut()
-                    .declare_variable(
-                        recipient.clone(),
-                        entry,
-                        type_of,
-                        true,
-                        false,
-                        node.range.clone(),
-                    )
-                    .map_err(|e| ErrorWithRange {
-                        err: e,
-                        range: iterable.range.clone(),
-                    })?;
+                    .declare_slot(0, entry, type_of)
+                    .map_err(|e| ErrorWithRange::new(e, iterable.range.clone()))?;
 
-                scoped!(self, {
-                    let res = self.eval_nodes(body)?;
-                    return_on_return!(res);
-                });
+                let res = scoped!(self, { self.eval_nodes(body) })?;
+                LoopFlow::of(res, label)
             });
+
+            match flow {
+                LoopFlow::Next => (),
+                LoopFlow::Break => break,
+                LoopFlow::Exit(res) => return Ok(res),
+            }
         }
 
         Ok(IsReturn::NoReturn(InterpreterValue::Empty))
     }
 
+    /// Joins the text of all parts into a string
+    pub fn eval_interpolation(
+        &mut self,
+        parts: &[Box<AstNode>],
+    ) -> Result<InterpreterValue, ErrorWithRange> {
+        let mut text = String::new();
+
+        for part in parts {
+            let value = self.eval_wrapped(part)?;
+            text.push_str(
+                &value
+                    .to_text()
+                    .map_err(|err| ErrorWithRange::new(err, part.range.clone()))?,
+            );
+        }
+
+        Ok(InterpreterValue::new_strong(InterpreterValue::String(text)))
+    }
+
     pub fn eval_list(
         &mut self,
         values: &Vec<Box<AstNode>>,
@@ -550,22 +862,12 @@ impl Interpreter {
         Ok(InterpreterValue::List(list_elems))
     }
 
+    /// NOTE: maps need hashable interpreter values, only primitives like bool, string and int could be keys
     pub fn eval_map(
         &mut self,
         _values: &Vec<(Box<AstNode>, Box<AstNode>)>,
     ) -> Result<InterpreterValue, Error> {
-        unimplemented!(
-            "not planned, because of time limitations. It is needed to actually have hashable types for this features. This is currently not implemented"
-        )
-        // let mut map = HashMap::new();
-
-        // for value in values {
-        //         "Implement hashable interpreter value, consisting of only primitives like bool, string and int (float will be unsupported)"
-        //     );
-        //     // map.insert(self.eval_node(value.0.as_ref())?.unwrap(), self.eval_node(value.1.as_ref())?.unwrap());
-        // }
-
-        // Ok(InterpreterValue::Map(map))
+        Err(Error::Unsupported("map literals".to_owned()))
     }
 
     /// Member call represents any type of member call, a, a.b, a.b().c, a.b(a()).c, etc
@@ -576,75 +878,93 @@ impl Interpreter {
     ) -> Result<IsReturn, ErrorWithRange> {
         // mutably borrow here, to allow for more complex pointer casting;
         let mut current_scope = Some(Rc::clone(&self.get_current_scope()));
+        // The value of the previous call, methods of values without a scope are buildin
+        let mut receiver: Option<InterpreterValue> = None;
 
-        assert!(calls.len() == 1, "currently, only one call is supported");
-
-        let mut last_res = Err(ErrorWithRange {
-            err: Error::OperationUnsupported {
+        let mut last_res = Err(ErrorWithRange::new(
+            Error::OperationUnsupported {
                 operation: "member call".to_owned(),
                 type_of: "must be at least one member call".to_owned(),
             },
-            range: node.range.clone(),
-        });
+            node.range.clone(),
+        ));
 
         for call in calls {
             let res = match &call.type_of {
+                MemberAccessType::Function(params)
+                    if receiver.as_ref().is_some_and(Self::has_buildin_methods) =>
+                {
+                    let receiver = receiver.take().expect("checked by the guard");
+                    let res = self.eval_buildin_method(receiver, call, params)?;
+                    current_scope = res.clone().into();
+                    IsReturn::NoReturn(res)
+                }
                 MemberAccessType::Function(params) => {
-                    let fn_type = {
+                    let callable = {
                         // Scoped to free borrowed refcell
                         let Some(local_scope) = &current_scope else {
-                            return Err(ErrorWithRange {
-                                err: Error::IsNotAScope,
-                                range: call.range.clone(),
-                            });
+                            return Err(ErrorWithRange::new(
+                                Error::IsNotAScope,
+                                call.range.clone(),
+                            ));
                         };
 
-                        local_scope.borrow().resolve_type(&call.member)
+                        let local_scope = local_scope.borrow();
+                        let callee = match &call.slot {
+                            Some(slot) => local_scope.resolve_slot(slot),
+                            None => local_scope.resolve_value(&call.member),
+                        };
+                        callee
+                            .map(|callee| Self::resolve_callable(&local_scope, &call.member, callee))
+                            .transpose()
+                            .map_err(|err| ErrorWithRange::new(err, call.range.clone()))?
                     };
 
-                    if let Some(fn_type) = fn_type {
+                    if let Some((fn_type, environment)) = callable {
                         // TODO: this will not work, since the scopes are not right. The params must come from the actual scope, while the function itself must get executed in its local scope.
-                        let res = self.call_function(&call.member, params, fn_type)?;
+                        let res = self.call_function(
+                            &call.member,
+                            params,
+                            fn_type,
+                            environment,
+                            &call.range,
+                        )?;
                         // Set current scope here. it must be checked before every execution
                         current_scope = res.clone().into();
                         IsReturn::NoReturn(res)
                     } else {
-                        Err(ErrorWithRange {
-                            err: Error::SymbolNotFound(call.member.clone()),
-                            range: call.range.clone(),
-                        })?
+                        let scope = current_scope.as_ref().expect("checked for the callee");
+                        Err(ErrorWithRange::new(
+                            Error::SymbolNotFound(call.member.clone()),
+                            call.range.clone(),
+                        )
+                        .suggest(Some(call.name_range()), |name| {
+                            scope.borrow().similar_name(name)
+                        }))?
                     }
                 }
                 MemberAccessType::Symbol => {
                     let Some(local_scope) = &current_scope else {
-                        return Err(ErrorWithRange {
-                            err: Error::IsNotAScope,
-                            range: call.range.clone(),
-                        });
+                        return Err(ErrorWithRange::new(Error::IsNotAScope, call.range.clone()));
                     };
 
                     let res = with_scope!(self, local_scope, {
-                        self.eval_symbol(&call.member).map_err(|e| ErrorWithRange {
-                            err: e,
-                            range: call.range.clone(),
+                        self.eval_symbol(&call.member, &call.slot).map_err(|e| {
+                            ErrorWithRange::new(e, call.range.clone())
+                                .suggest(Some(call.range.clone()), |name| {
+                                    local_scope.borrow().similar_name(name)
+                                })
                         })
                     })?;
                     current_scope = res.clone().into();
                     IsReturn::NoReturn(res)
                 }
                 MemberAccessType::Struct(fields_to_assign) => {
-                    let struct_type = {
-                        // Scoped to free borrowed refcell
-                        let Some(local_scope) = &current_scope else {
-                            return Err(ErrorWithRange {
-                                err: Error::IsNotAScope,
-                                range: call.range.clone(),
-                            });
-                        };
-
-                        // NOTE: resolve defined type here, not variable type, as this is a defined type
-                        local_scope.borrow().resolve_defined_type(&call.member)
+                    let Some(type_scope) = current_scope.clone() else {
+                        return Err(ErrorWithRange::new(Error::IsNotAScope, call.range.clone()));
                     };
+                    // NOTE: resolve defined type here, not variable type, as this is a defined type
+                    let struct_type = type_scope.borrow().resolve_defined_type(&call.member);
 
                     if let Some(struct_type) = struct_type {
                         match &struct_type.type_of {
@@ -664,20 +984,24 @@ impl Interpreter {
                                         let value = self.eval_node(value_node)?.unwrap();
                                         struct_scope
                                             .borrow_mut()
-                                            .declare_variable(
+                                            .declare_field(
                                                 field.clone(),
                                                 value,
                                                 fields_of_struct_type[field].clone(),
-                                                true,
-                                                false,
+                                                &type_scope.borrow(),
                                                 value_node.range.clone(),
                                             )
-                                            .map_err(|err| ErrorWithRange {
-                                                err,
-                                                range: value_node.range.clone(),
+                                            .map_err(|err| {
+                                                ErrorWithRange::new(err, value_node.range.clone())
                                             })?;
                                     } else {
-                                        todo!("throw error here, as field does not exist")
+                                        return Err(ErrorWithRange::new(
+                                            Error::SymbolNotFound(field.clone()),
+                                            value_node.range.clone(),
+                                        )
+                                        .suggest(None, |name| {
+                                            similar_field(name, &struct_type_def.fields)
+                                        }));
                                     }
                                 }
 
@@ -693,26 +1017,24 @@ impl Interpreter {
                                     Rc::clone(&struct_scope),
                                 )
                                 .make_reference_counted()
-                                .map_err(|err| ErrorWithRange {
-                                    err,
-                                    range: call.range.clone(),
-                                })?;
+                                .map_err(|err| ErrorWithRange::new(err, call.range.clone()))?;
 
                                 struct_scope
                                     .borrow_mut()
-                                    .declare_variable(
+                                    .declare_field(
                                         "self".to_owned(),
                                         struct_value.clone(),
                                         struct_type.clone(),
-                                        true,
-                                        false,
+                                        &type_scope.borrow(),
                                         call.range.clone(),
                                     )
-                                    .map_err(|err| ErrorWithRange {
-                                        err,
-                                        range: call.range.clone(),
-                                    })?;
+                                    .map_err(|err| ErrorWithRange::new(err, call.range.clone()))?;
 
+                                self.track_scope(
+                                    &struct_scope,
+                                    format!("struct {}", struct_type_def.name),
+                                    call.range.clone(),
+                                );
                                 current_scope = Some(struct_scope);
                                 IsReturn::NoReturn(struct_value)
                             }
@@ -732,20 +1054,24 @@ impl Interpreter {
                                         let value = self.eval_node(value_node)?.unwrap();
                                         struct_scope
                                             .borrow_mut()
-                                            .declare_variable(
+                                            .declare_field(
                                                 field.clone(),
                                                 value,
                                                 fields_of_struct_type[field].clone(),
-                                                true,
-                                                false,
+                                                &type_scope.borrow(),
                                                 value_node.range.clone(),
                                             )
-                                            .map_err(|err| ErrorWithRange {
-                                                err,
-                                                range: value_node.range.clone(),
+                                            .map_err(|err| {
+                                                ErrorWithRange::new(err, value_node.range.clone())
                                             })?;
                                     } else {
-                                        todo!("throw error here, as field does not exist")
+                                        return Err(ErrorWithRange::new(
+                                            Error::SymbolNotFound(field.clone()),
+                                            value_node.range.clone(),
+                                        )
+                                        .suggest(None, |name| {
+                                            similar_field(name, &struct_type_def.fields)
+                                        }));
                                     }
                                 }
 
@@ -761,45 +1087,192 @@ impl Interpreter {
                                     Rc::clone(&struct_scope),
                                 )
                                 .make_reference_counted()
-                                .map_err(|err| ErrorWithRange {
-                                    err,
-                                    range: call.range.clone(),
-                                })?;
+                                .map_err(|err| ErrorWithRange::new(err, call.range.clone()))?;
 
                                 struct_scope
                                     .borrow_mut()
-                                    .declare_variable(
+                                    .declare_field(
                                         "self".to_owned(),
                                         struct_value.clone(),
                                         struct_type.clone(),
-                                        true,
-                                        false,
+                                        &type_scope.borrow(),
                                         call.range.clone(),
                                     )
-                                    .map_err(|err| ErrorWithRange {
-                                        err,
-                                        range: call.range.clone(),
-                                    })?;
+                                    .map_err(|err| ErrorWithRange::new(err, call.range.clone()))?;
 
+                                self.track_scope(
+                                    &struct_scope,
+                                    format!("component {}", struct_type_def.name),
+                                    call.range.clone(),
+                                );
                                 current_scope = Some(struct_scope);
                                 IsReturn::NoReturn(struct_value)
                             }
                             _ => todo!("error here, cause type is not a struct like"),
                         }
                     } else {
-                        Err(ErrorWithRange {
-                            err: Error::SymbolNotFound(call.member.clone()),
-                            range: call.range.clone(),
-                        })?
+                        let scope = current_scope.as_ref().expect("checked for the type");
+                        Err(ErrorWithRange::new(
+                            Error::SymbolNotFound(call.member.clone()),
+                            call.range.clone(),
+                        )
+                        .suggest(Some(call.name_range()), |name| {
+                            scope.borrow().similar_type(name)
+                        }))?
                     }
                 }
             };
+            if let IsReturn::NoReturn(value) = &res {
+                receiver = Some(value.clone());
+            }
             last_res = Ok(res);
         }
 
         last_res
     }
 
+    pub fn has_buildin_methods(value: &InterpreterValue) -> bool {
+        matches!(value, InterpreterValue::Weak(_))
+            || matches!(
+                InterpreterValue::preprocess_single(value.clone()),
+                Ok(InterpreterValue::Option(_) | InterpreterValue::Result(_))
+            )
+    }
+
+    /// Evaluates the buildin methods of weak references, options and results, i.e. a.unwrap_or(0) or a.map(fn (x: int): int => x * 2)
+    pub fn eval_buildin_method(
+        &mut self,
+        receiver: InterpreterValue,
+        call: &MemberAccess,
+        params: &[Box<AstNode>],
+    ) -> Result<InterpreterValue, ErrorWithRange> {
+        let receiver = Self::buildin_method_receiver(receiver, &call.member, params.len())
+            .map_err(|err| ErrorWithRange::new(err, call.range.clone()))?;
+
+        let mut args = Vec::new();
+        for param in params {
+            args.push((self.eval_node(param.as_ref())?.unwrap(), param.range.clone()));
+        }
+
+        Self::apply_buildin_method(
+            receiver,
+            &call.member,
+            &args,
+            &call.range,
+            &mut |f, arg, range| self.call_value(&call.member, f, arg, range),
+        )
+    }
+
+    /// Checks, that the receiver has the buildin method with this number of arguments and returns the receiver the method is applied to
+    pub fn buildin_method_receiver(
+        receiver: InterpreterValue,
+        member: &Symbol,
+        arg_count: usize,
+    ) -> Result<InterpreterValue, Error> {
+        // NOTE: weak references must not be upgraded implicitly, upgrading is their only method
+        let receiver = match receiver {
+            InterpreterValue::Weak(_) => receiver,
+            _ => InterpreterValue::preprocess_single(receiver)?,
+        };
+
+        let methods = match &receiver {
+            InterpreterValue::Weak(_) => WEAK_METHODS,
+            InterpreterValue::Option(_) => OPTION_METHODS,
+            _ => RESULT_METHODS,
+        };
+        let Some((_, arity)) = methods.iter().find(|(name, _)| name == member) else {
+            return Err(Error::UnknownMethod(member.clone(), receiver.to_string()));
+        };
+        if *arity != arg_count {
+            return Err(Error::WrongArgumentCount(member.clone(), *arity, arg_count));
+        }
+
+        Ok(receiver)
+    }
+
+    /// Applies a buildin method to a receiver checked by buildin_method_receiver.
+    /// call calls a function value with a single argument, i.e. the callback of map
+    pub fn apply_buildin_method(
+        receiver: InterpreterValue,
+        member: &Symbol,
+        args: &[(InterpreterValue, Range<usize>)],
+        range: &Range<usize>,
+        call: &mut ValueCaller,
+    ) -> Result<InterpreterValue, ErrorWithRange> {
+        let with_range = |err| ErrorWithRange::new(err, range.clone());
+
+        let value = match (member.as_str(), &receiver, args) {
+            ("upgrade", InterpreterValue::Weak(_), []) => {
+                receiver.upgrade_to_option().map_err(with_range)?
+            }
+            ("is_some", InterpreterValue::Option(v), []) => InterpreterValue::Bool(v.is_some()),
+            ("is_none", InterpreterValue::Option(v), []) => InterpreterValue::Bool(v.is_none()),
+            ("is_ok", InterpreterValue::Result(v), []) => InterpreterValue::Bool(v.is_ok()),
+            ("is_err", InterpreterValue::Result(v), []) => InterpreterValue::Bool(v.is_err()),
+            (
+                "unwrap" | "unwrap_or" | "expect",
+                InterpreterValue::Option(Some(v)) | InterpreterValue::Result(Ok(v)),
+                _,
+            ) => *v.clone(),
+            ("unwrap", _, []) => return Err(with_range(Error::UnwrapFailed(receiver.to_string()))),
+            ("unwrap_or", _, [(default, _)]) => default.clone(),
+            ("expect", _, [(message, _)]) => {
+                return Err(with_range(Error::ExpectFailed(message.to_string())));
+            }
+            ("map", InterpreterValue::Option(Some(v)), [(f, range)]) => {
+                let mapped = call(f, *v.clone(), range)?;
+                InterpreterValue::Option(Some(Box::new(mapped)))
+            }
+            ("map", InterpreterValue::Result(Ok(v)), [(f, range)]) => {
+                let mapped = call(f, *v.clone(), range)?;
+                InterpreterValue::Result(Ok(Box::new(mapped)))
+            }
+            ("map_err", InterpreterValue::Result(Err(e)), [(f, range)]) => {
+                let mapped = call(f, *e.clone(), range)?;
+                InterpreterValue::Result(Err(Box::new(mapped)))
+            }
+            // NOTE: the callback must return an option or result itself, which is not flattened
+            (
+                "and_then",
+                InterpreterValue::Option(Some(v)) | InterpreterValue::Result(Ok(v)),
+                [(f, range)],
+            ) => call(f, *v.clone(), range)?,
+            ("map" | "map_err" | "and_then", _, [_]) => receiver.clone(),
+            ("ok_or", InterpreterValue::Option(v), [(err, _)]) => InterpreterValue::Result(
+                v.clone().ok_or_else(|| Box::new(err.clone())),
+            ),
+            _ => unreachable!("checked against the buildin methods"),
+        };
+
+        InterpreterValue::preprocess_single(value)
+            .map(InterpreterValue::new_strong)
+            .map_err(with_range)
+    }
+
+    /// Calls a function or closure value with a single already evaluated argument
+    fn call_value(
+        &mut self,
+        member: &Symbol,
+        callee: &InterpreterValue,
+        arg: InterpreterValue,
+        range: &Range<usize>,
+    ) -> Result<InterpreterValue, ErrorWithRange> {
+        let (fn_type, environment) = {
+            let scope = self.get_current_scope();
+            let scope = scope.borrow();
+            Self::resolve_callable(&scope, member, callee.clone())
+                .map_err(|err| ErrorWithRange::new(err, range.clone()))?
+        };
+
+        self.call_function_with_values(
+            member,
+            vec![(arg, range.clone())],
+            fn_type,
+            environment,
+            range,
+        )
+    }
+
     pub fn eval_node(&mut self, node: &AstNode) -> Result<IsReturn, ErrorWithRange> {
         let evaluated = match &node.type_of {
             // Primitives
@@ -815,14 +1288,38 @@ impl Interpreter {
             AstNodeType::String(s) => IsReturn::NoReturn(InterpreterValue::new_strong(
                 InterpreterValue::String(s.clone()),
             )),
-            AstNodeType::List(values) => IsReturn::NoReturn(self.eval_list(values)?),
-            AstNodeType::Map(values) => {
-                IsReturn::NoReturn(self.eval_map(values).map_err(|e| ErrorWithRange {
-                    err: e,
-                    range: node.range.clone(),
-                })?)
+            AstNodeType::Interpolation(parts) => {
+                IsReturn::NoReturn(self.eval_interpolation(parts)?)
             }
+            AstNodeType::List(values) => IsReturn::NoReturn(self.eval_list(values)?),
+            AstNodeType::Map(values) => IsReturn::NoReturn(
+                self.eval_map(values)
+                    .map_err(|e| ErrorWithRange::new(e, node.range.clone()))?,
+            ),
             AstNodeType::Weak(inner) => IsReturn::NoReturn(self.eval_weak(inner.as_ref())?),
+            AstNodeType::Option(inner) => {
+                let inner = match inner {
+                    Some(inner) => Some(Box::new(self.eval_wrapped(inner.as_ref())?)),
+                    None => None,
+                };
+                IsReturn::NoReturn(InterpreterValue::new_strong(InterpreterValue::Option(inner)))
+            }
+            AstNodeType::Result(inner) => {
+                let inner = match inner {
+                    Ok(v) => Ok(Box::new(self.eval_wrapped(v.as_ref())?)),
+                    Err(e) => Err(Box::new(self.eval_wrapped(e.as_ref())?)),
+                };
+                IsReturn::NoReturn(InterpreterValue::new_strong(InterpreterValue::Result(inner)))
+            }
+            AstNodeType::Closure {
+                params,
+                return_type,
+                execution_body,
+            } => {
+                let environment = self.get_current_scope();
+                self.track_scope(&environment, "closure environment".to_owned(), node.range.clone());
+                IsReturn::NoReturn(self.eval_closure(params, return_type, execution_body))
+            }
             // Infix call and prefix calls
             AstNodeType::InfixCall(left, op, right) => {
                 IsReturn::NoReturn(self.eval_infix_call(left.as_ref(), op, right.as_ref())?)
@@ -836,16 +1333,18 @@ impl Interpreter {
                 new_symbol,
                 expression,
                 assumed_type,
+                slot,
             } => {
-                self.eval_declaration(node, new_symbol, expression.as_ref(), assumed_type)?;
+                self.eval_declaration(node, new_symbol, expression.as_ref(), assumed_type, slot)?;
                 IsReturn::NoReturn(InterpreterValue::Empty)
             }
             AstNodeType::AssignmentOp {
                 recipient,
                 operation,
                 expression,
+                slot,
             } => {
-                self.eval_assignment_op(node, recipient, operation, expression.as_ref())?;
+                self.eval_assignment_op(node, recipient, operation, expression.as_ref(), slot)?;
                 IsReturn::NoReturn(InterpreterValue::Empty)
             }
             // Member call can be anything that is of the form a.b.c.d(a,b).c etc. a() and a are also member calls with length 1
@@ -864,107 +1363,209 @@ impl Interpreter {
                 else_if_branches,
                 else_branch,
             } => self.eval_branch(cond.as_ref(), body, else_if_branches, else_branch)?,
-            AstNodeType::While { cond, body } => self.eval_while(cond.as_ref(), body)?,
+            AstNodeType::While { label, cond, body } => {
+                self.eval_while(label, cond.as_ref(), body)?
+            }
             AstNodeType::For {
+                label,
                 declaration,
                 condition,
                 assignment,
                 body,
-            } => self.eval_for(declaration, condition, assignment, body)?,
+            } => self.eval_for(label, declaration, condition, assignment, body)?,
             AstNodeType::ForEach {
-                recipient,
+                label,
                 iterable,
                 body,
-            } => self.eval_for_each(node, recipient, iterable, body)?,
+                ..
+            } => self.eval_for_each(label, iterable, body)?,
+            AstNodeType::Match { value, arms } => self.eval_match(value.as_ref(), arms)?,
+            AstNodeType::Propagate(inner) => self.eval_propagate(inner.as_ref())?,
+            // NOTE: a return of a? still leaves the function, only the value is discarded
+            AstNodeType::ExpressionStatement(expression) => match self.eval_node(expression)? {
+                IsReturn::NoReturn(_) => IsReturn::NoReturn(InterpreterValue::Empty),
+                flow => flow,
+            },
+            AstNodeType::Break(label) => IsReturn::Break(label.clone()),
+            AstNodeType::Continue(label) => IsReturn::Continue(label.clone()),
             _ => Err(Error::OperationUnsupported {
                 operation: format!("{:?}", &node.type_of),
                 type_of: "".to_owned(),
             })
-            .map_err(|err| ErrorWithRange {
-                err,
-                range: node.range.clone(),
-            })?,
+            .map_err(|err| ErrorWithRange::new(err, node.range.clone()))?,
         };
 
         Ok(evaluated)
     }
 
-    pub fn eval_nodes(&mut self, nodes: &Vec<Box<AstNode>>) -> Result<IsReturn, ErrorWithRange> {
-        for node in nodes {
-            let res = self.eval_node(node.as_ref())?;
-
-            // Early exit until function call is reached
-            return_on_return!(res);
+    /// Evaluates the value wrapped by some(..), ok(..) or err(..), which must not be empty
+    fn eval_wrapped(&mut self, node: &AstNode) -> Result<InterpreterValue, ErrorWithRange> {
+        let value = self.eval_node(node)?.unwrap();
+        if let InterpreterValue::Empty = value {
+            return Err(ErrorWithRange::new(Error::CantBeEmpty, node.range.clone()));
         }
+        Ok(value)
+    }
 
-        Ok(IsReturn::NoReturn(InterpreterValue::Empty))
+    /// Evaluates statements in the global scope, i.e. an input of the repl, and returns the value of the last one.
+    /// NOTE: an error leaves the environments of the calls it occurred in behind, they are dropped
+    pub fn eval_global(&mut self, nodes: &[AstNode]) -> Result<InterpreterValue, ErrorWithRange> {
+        let mut last_value = InterpreterValue::Empty;
+        for node in nodes {
+            match self.eval_node(node) {
+                Ok(res) => last_value = res.unwrap(),
+                Err(err) => {
+                    self.environments.truncate(1);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(last_value)
+    }
+
+    /// Evaluates a block. If no return is reached, the value of the last expression is the value of the block
+    pub fn eval_nodes(&mut self, nodes: &Vec<Box<AstNode>>) -> Result<IsReturn, ErrorWithRange> {
+        let mut last_value = InterpreterValue::Empty;
+
+        for node in nodes {
+            let res = self.eval_node(node.as_ref())?;
+
+            // Early exit until function call is reached
+            return_on_return!(res);
+            last_value = res.unwrap();
+        }
+
+        Ok(IsReturn::NoReturn(last_value))
     }
 
+    /// Calls a function from the range of the call, errors of its body trace back to that range
     pub fn call_function(
         &mut self,
         fn_name: &Symbol,
         params: &Vec<Box<AstNode>>,
         fn_signature: TypeSymbol,
+        environment: Option<Rc<RefCell<Scope>>>,
+        range: &Range<usize>,
     ) -> Result<InterpreterValue, ErrorWithRange> {
-        if let TypeSymbolType::Function(fn_type) = &fn_signature.type_of {
-            // TODO: add error handling
-            let mut evaled_params = Vec::new();
+        // TODO: add error handling
+        let mut evaled_params = Vec::new();
+
+        for param in params {
+            evaled_params.push((self.eval_node(param.as_ref())?.unwrap(), param.range.clone()));
+        }
+
+        self.call_function_with_values(fn_name, evaled_params, fn_signature, environment, range)
+    }
 
-            for param in params {
-                evaled_params.push((param, self.eval_node(param.as_ref())?));
+    /// Calls a function with already evaluated params, each with the range it was evaluated from
+    pub fn call_function_with_values(
+        &mut self,
+        fn_name: &Symbol,
+        params: Vec<(InterpreterValue, Range<usize>)>,
+        fn_signature: TypeSymbol,
+        environment: Option<Rc<RefCell<Scope>>>,
+        range: &Range<usize>,
+    ) -> Result<InterpreterValue, ErrorWithRange> {
+        if let TypeSymbolType::Function(fn_type) = &fn_signature.type_of {
+            // NOTE: closures and buildins are never compiled
+            if let (Some(jit), None, FunctionExecutionStrategy::Interpreted(_)) =
+                (&mut self.jit, &environment, &fn_type.execution_body)
+            {
+                let globals = self.environments[0].scope.borrow();
+                if let Some(value) = jit.try_call(fn_type, &params, &globals) {
+                    return Ok(value);
+                }
             }
 
-            // Create a new stack entry with its own scope
-            let result = scoped!(self, {
+            // Create a new stack entry with its own scope, closures are parented to their captured scope.
+            // NOTE: declared functions can't see the locals of their caller, their scope must not keep the caller alive
+            let call_scope = &Rc::new(RefCell::new(Scope::new_parented(
+                environment.unwrap_or_else(|| Rc::clone(&self.environments[0].scope)),
+            )));
+            let result = with_scope!(self, call_scope, {
                 // scoped to free refcell borrow_mut
                 {
                     let scope = self.get_current_scope();
                     let mut scope_mut = scope.borrow_mut();
-                    for ((param_node, value), (param, type_of)) in
-                        zip(evaled_params, &fn_type.params)
+                    for (index, ((value, param_range), (param, type_of))) in
+                        zip(params, &fn_type.params).enumerate()
                     {
                         // TODO: Type check here
-                        let value = value.unwrap();
                         if let InterpreterValue::Empty = value {
-                            return Err(ErrorWithRange {
-                                err: Error::ExpectedValue(param.to_owned()),
-                                range: 1..2,
-                            });
+                            return Err(ErrorWithRange::new(
+                                Error::ExpectedValue(param.to_owned()),
+                                param_range,
+                            ));
                         }
 
-                        scope_mut
-                            .declare_variable(
+                        // NOTE: the resolver places the params in the first slots, buildins look them up by name
+                        match &fn_type.execution_body {
+                            FunctionExecutionStrategy::Interpreted(_) => {
+                                scope_mut.declare_slot(index, value, type_of.clone())
+                            }
+                            _ => scope_mut.declare_variable(
                                 param.clone(),
                                 value,
                                 type_of.clone(),
                                 true,
                                 false,
-                                param_node.range.clone(),
-                            )
-                            .map_err(|e| ErrorWithRange {
-                                err: e,
-                                range: param_node.range.clone(),
-                            })?;
+                                param_range.clone(),
+                            ),
+                        }
+                        .map_err(|e| ErrorWithRange::new(e, param_range))?;
                     }
                 }
+                let depth = self.environments.len();
                 match &fn_type.execution_body {
-                    FunctionExecutionStrategy::Interpreted(body) => self.eval_nodes(body)?,
+                    FunctionExecutionStrategy::Interpreted(body) => match self.eval_nodes(body) {
+                        Err(ErrorWithRange {
+                            err: Error::ErrorPropagation(value),
+                            ..
+                        }) => {
+                            // NOTE: ? leaves all nested scopes of the function at once
+                            self.environments.truncate(depth);
+                            IsReturn::Return(*value)
+                        }
+                        res => res.map_err(|err| err.in_call(fn_name, range))?,
+                    },
                     FunctionExecutionStrategy::Buildin(callback) => {
-                        callback(self.get_current_scope()).map_err(|e| ErrorWithRange {
-                            err: e,
-                            range: 1..2,
-                        })?
+                        callback(self.get_current_scope())
+                            .map_err(|e| ErrorWithRange::new(e, range.clone()))?
+                    }
+                    FunctionExecutionStrategy::Native(library, symbol) => {
+                        return Err(ErrorWithRange::new(
+                            Error::OperationUnsupported {
+                                operation: "native call".to_owned(),
+                                type_of: format!("{symbol} of {library} can't be called yet"),
+                            },
+                            range.clone(),
+                        ));
                     }
                 }
             });
 
-            match result {
-                IsReturn::NoReturn(InterpreterValue::Empty) => Ok(InterpreterValue::Empty),
-                IsReturn::Return(v) => Ok(v),
-                _ => Err(ErrorWithRange {
-                    err: Error::MissingReturn(fn_name.clone()),
-                    range: 1..2,
-                }),
+            // NOTE: the last expression of the body is returned implicitly, it is discarded if the function has no return type
+            match (result, &fn_type.return_type) {
+                (IsReturn::Return(v), _) => Ok(v),
+                (IsReturn::NoReturn(_), None) => Ok(InterpreterValue::Empty),
+                (IsReturn::NoReturn(InterpreterValue::Empty), Some(_)) => {
+                    // NOTE: points at the definition of the function, like the vm
+                    let location = self
+                        .get_current_scope()
+                        .borrow()
+                        .resolve_location(fn_name)
+                        .unwrap_or_else(|| range.clone());
+                    Err(
+                        ErrorWithRange::new(Error::MissingReturn(fn_name.clone()), location)
+                            .in_call(fn_name, range),
+                    )
+                }
+                (IsReturn::NoReturn(v), Some(_)) => Ok(v),
+                // NOTE: rejected by the preprocessor, can only occur in buildin bodies
+                (IsReturn::Break(_) | IsReturn::Continue(_), _) => Err(ErrorWithRange::new(
+                    Error::LoopControlOutsideLoop("break or continue".to_owned()),
+                    range.clone(),
+                )),
             }
         } else {
             unimplemented!("error here")
@@ -975,7 +1576,7 @@ impl Interpreter {
 impl Stage for Interpreter {
     fn init(&mut self, prev_stage_result: StageResult) -> Result<(), ErrorWithRange> {
         match prev_stage_result {
-            StageResult::Preprocessor(global_scope, ast) => {
+            StageResult::Resolved(global_scope, ast) => {
                 self.ast = ast;
 
                 self.environments = vec![Environment {
@@ -984,18 +1585,27 @@ impl Stage for Interpreter {
 
                 Ok(())
             }
-            _ => Err(ErrorWithRange {
-                err: Error::StageError(1, prev_stage_result.into()),
-                range: 0..1,
-            }),
+            _ => Err(ErrorWithRange::new(
+                Error::StageError(5, prev_stage_result.into()),
+                0..1,
+            )),
         }
     }
 
+    fn shared_warnings(&self) -> Option<Warnings> {
+        Some(self.warnings())
+    }
+
     fn run(mut self) -> Result<StageResult, ErrorWithRange> {
         let main_fn = self
             .get_current_scope()
             .borrow()
             .resolve_value(&self.entrypoint_fn);
+        let location = self
+            .get_current_scope()
+            .borrow()
+            .resolve_location(&self.entrypoint_fn)
+            .unwrap_or(0..0);
         if let Some(main) = main_fn {
             if let InterpreterValue::Function(_) = main {
                 let main_fn = self
@@ -1003,10 +1613,12 @@ impl Stage for Interpreter {
                     .borrow()
                     .resolve_type(&self.entrypoint_fn)
                     .expect("must be present if value is present");
-                self.call_function(&self.entrypoint_fn.clone(), &vec![], main_fn)?;
+                let entrypoint_fn = self.entrypoint_fn.clone();
+                self.call_function(&entrypoint_fn, &vec![], main_fn, None, &location)
+                    .map_err(ErrorWithRange::outside_entrypoint)?;
             } else {
-                return Err(ErrorWithRange {
-                    err: Error::WrongType(
+                return Err(ErrorWithRange::new(
+                    Error::WrongType(
                         self.entrypoint_fn.clone(),
                         TypeSymbolType::Function(FunctionType {
                             name: "main".to_string(),
@@ -1021,25 +1633,29 @@ impl Stage for Interpreter {
                             .expect("must be present if value is presen")
                             .to_string(),
                     ),
-                    range: 1..1,
-                });
+                    location,
+                ));
             }
         } else {
-            return Err(ErrorWithRange {
-                err: Error::MainNotFound,
-                range: 1..1,
-            });
+            return Err(ErrorWithRange::new(
+                Error::MainNotFound(self.entrypoint_fn.clone()),
+                // NOTE: there is no node to point at
+                0..0,
+            ));
         }
 
+        self.collect_cycles();
         Ok(StageResult::Interpretation)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use lalrpop_util::ParseError;
+
     use crate::{
-        BeautifyError, Interpreter, Parser, Preprocessor, StageResult, Stages, ast_grammar,
-        run_stages,
+        BeautifyError, Compiler, Error, Interpreter, Note, Optimizer, Parser, Preprocessor,
+        Resolver, StageResult, Stages, Vm, Warning, ast_grammar, run_stages,
     };
 
     #[test]
@@ -1055,6 +1671,7 @@ mod tests {
 
         let stages = vec![
             Stages::Preprocessor(Preprocessor::new().unwrap()),
+            Stages::Resolver(Resolver::new()),
             Stages::Interpreter(Interpreter::new("main".to_string())),
         ];
 
@@ -1063,32 +1680,6 @@ mod tests {
         let _ = run_stages(stages, state).unwrap();
     }
 
-    #[test]
-    fn test_basic_interpretation2() {
-        let source = r#"
-           fn main() {
-            a = 10;
-            a += 20;
-            println(a);
-           }
-           "#;
-
-        let ast = ast_grammar::ProgrammParser::new().parse(source).unwrap();
-
-        let stages = vec![
-            Stages::Preprocessor(Preprocessor::new().unwrap()),
-            Stages::Interpreter(Interpreter::new("main".to_string())),
-        ];
-
-        let state = StageResult::Parsing(ast);
-
-        let result = run_stages(stages, state);
-
-        if let Err(err) = result {
-            err.print_error(source);
-        }
-    }
-
     #[test]
     fn function_definition_and_returning() {
         let source = r#"
@@ -1107,6 +1698,7 @@ mod tests {
         let stages = vec![
             Stages::Parser(Parser::default()),
             Stages::Preprocessor(Preprocessor::new().unwrap()),
+            Stages::Resolver(Resolver::new()),
             Stages::Interpreter(Interpreter::new("main".to_string())),
         ];
 
@@ -1130,6 +1722,7 @@ mod tests {
         let stages = vec![
             Stages::Parser(Parser::default()),
             Stages::Preprocessor(Preprocessor::new().unwrap()),
+            Stages::Resolver(Resolver::new()),
             Stages::Interpreter(Interpreter::new("main".to_string())),
         ];
 
@@ -1151,6 +1744,7 @@ mod tests {
         let stages = vec![
             Stages::Parser(Parser::default()),
             Stages::Preprocessor(Preprocessor::new().unwrap()),
+            Stages::Resolver(Resolver::new()),
             Stages::Interpreter(Interpreter::new("main".to_string())),
         ];
 
@@ -1159,32 +1753,829 @@ mod tests {
         let _ = run_stages(stages, state).unwrap();
     }
 
+    /// Runs the program with the interpreter and the vm, which must agree on the outcome
+    fn run_source(source: &str) -> Result<StageResult, crate::ErrorWithRange> {
+        run_source_with(
+            source,
+            Interpreter::new("main".to_string()),
+            Vm::new("main".to_string()),
+        )
+    }
+
+    fn run_source_with(
+        source: &str,
+        interpreter: Interpreter,
+        vm: Vm,
+    ) -> Result<StageResult, crate::ErrorWithRange> {
+        let stages = vec![
+            Stages::Parser(Parser::default()),
+            Stages::Preprocessor(Preprocessor::new().unwrap()),
+            Stages::Resolver(Resolver::new()),
+            Stages::Interpreter(interpreter),
+        ];
+
+        let result = run_stages(stages, StageResult::PreParse(source.to_owned()));
+        if let Err(err) = &result {
+            err.print_error(source);
+        }
+
+        let compiled_stages = vec![
+            Stages::Parser(Parser::default()),
+            Stages::Preprocessor(Preprocessor::new().unwrap()),
+            Stages::Optimizer(Optimizer::new()),
+            Stages::Resolver(Resolver::new()),
+            Stages::Optimizer(Optimizer::new()),
+            Stages::Compiler(Compiler::new()),
+            Stages::Vm(vm),
+        ];
+        let compiled_result = run_stages(compiled_stages, StageResult::PreParse(source.to_owned()));
+        match (&result, &compiled_result) {
+            (Ok(_), Ok(_)) => (),
+            (Err(err), Err(compiled_err)) => {
+                assert_eq!(
+                    std::mem::discriminant(&err.err),
+                    std::mem::discriminant(&compiled_err.err),
+                    "the vm failed with {compiled_err}, the interpreter with {err}"
+                );
+                assert_eq!(err.range, compiled_err.range, "{err} is reported elsewhere by the vm");
+                assert_eq!(err.notes, compiled_err.notes);
+            }
+            (_, Err(compiled_err)) => {
+                compiled_err.print_error(source);
+                panic!("only the vm failed with {compiled_err}");
+            }
+            (Err(err), _) => panic!("only the interpreter failed with {err}"),
+        }
+
+        result
+    }
+
+    #[test]
+    fn closure_captures_scope() {
+        let source = r#"
+           fn main() {
+                offset := 10;
+                add := fn (x: int): int { return x + offset; };
+                offset = 20;
+                assert(add(1) == 21);
+           }
+           "#;
+
+        run_source(source).unwrap();
+    }
+
+    #[test]
+    fn closure_outlives_creating_function() {
+        let source = r#"
+           fn make_counter(): fn(): int {
+                count := 0;
+                return fn (): int {
+                    count += 1;
+                    return count;
+                };
+           }
+
+           fn main() {
+                counter := make_counter();
+                counter();
+                assert(counter() == 2);
+           }
+           "#;
+
+        run_source(source).unwrap();
+    }
+
+    #[test]
+    fn function_typed_parameters() {
+        let source = r#"
+           fn apply(f: fn(int): int, value: int): int {
+                return f(value);
+           }
+
+           fn double(x: int): int {
+                return x * 2;
+           }
+
+           fn main() {
+                assert(apply(double, 4) == 8);
+                assert(apply(fn (x: int): int { return x - 1; }, 4) == 3);
+
+                g := double;
+                assert(g(5) == 10);
+           }
+           "#;
+
+        run_source(source).unwrap();
+    }
+
+    #[test]
+    fn calling_non_function_fails() {
+        let source = r#"
+           fn main() {
+                a := 10;
+                a();
+           }
+           "#;
+
+        let Err(err) = run_source(source) else {
+            panic!("calling an int must fail");
+        };
+        assert!(matches!(err.err, crate::Error::NotCallable(_)));
+    }
+
+    #[test]
+    fn arrow_functions_and_implicit_return() {
+        let source = r#"
+           fn double(x: int): int => x * 2;
+
+           fn last_expression(x: int): int {
+                y := x + 1;
+                y * 10
+           }
+
+           fn main() {
+                triple := fn (x: int): int => x * 3;
+                assert(double(2) == 4);
+                assert(last_expression(1) == 20);
+                assert(triple(double(1)) == 6);
+           }
+           "#;
+
+        run_source(source).unwrap();
+    }
+
     #[test]
-    fn loop3() {
+    fn missing_return_value() {
         let source = r#"
+           fn nothing(x: int): int {
+                y := x;
+           }
+
            fn main() {
-                res := 0;
-                for (a in [10, 20, 30, 40]) {
-                    res += a;
+                nothing(1);
+           }
+           "#;
+
+        let Err(err) = run_source(source) else {
+            panic!("a function without a value must fail");
+        };
+        assert!(matches!(err.err, crate::Error::MissingReturn(_)));
+
+        // NOTE: the semicolon discards the value, only an expression without one is returned
+        for body in ["g();", "if (true) { g(); } else { 1 }"] {
+            let source =
+                format!("fn g(): int => 1;\nfn f(): int {{ {body} }}\nfn main() {{ f(); }}");
+            let Err(err) = run_source(&source) else {
+                panic!("{body} must not return a value");
+            };
+            assert!(matches!(err.err, crate::Error::MissingReturn(_)), "{body}");
+        }
+        run_source("fn g(): int => 1;\nfn f(): int { g() }\nfn main() { assert(f() == 1); }")
+            .unwrap();
+    }
+
+    #[test]
+    fn break_and_continue() {
+        let source = r#"
+           fn main() {
+                sum := 0;
+                for (i := 0; i < 10; i += 1) {
+                    if (i % 2 == 0) {
+                        continue;
+                    }
+                    if (i > 6) {
+                        break;
+                    }
+                    sum += i;
+                }
+                assert(sum == 9);
+
+                n := 0;
+                while (true) {
+                    n += 1;
+                    if (n == 3) {
+                        break;
+                    }
                 }
-                assert(res == true);
+                assert(n == 3);
            }
-           "#
-        .to_owned();
+           "#;
+
+        run_source(source).unwrap();
+    }
+
+    #[test]
+    fn labelled_break_and_continue() {
+        let source = r#"
+           fn main() {
+                count := 0;
+                outer: for (a in [1, 2, 3]) {
+                    for (b in [1, 2, 3]) {
+                        if (b == 2) {
+                            continue outer;
+                        }
+                        if (a == 3) {
+                            break outer;
+                        }
+                        count += 1;
+                    }
+                }
+                assert(count == 2);
+           }
+           "#;
+
+        run_source(source).unwrap();
+    }
+
+    #[test]
+    fn labelled_break_and_continue_of_for_and_while() {
+        let source = r#"
+           fn main() {
+                count := 0;
+                outer: for (a := 0; a < 3; a += 1) {
+                    for (b := 0; b < 3; b += 1) {
+                        if (b == 1) {
+                            continue outer;
+                        }
+                        if (a == 2) {
+                            break outer;
+                        }
+                        count += 1;
+                    }
+                }
+                assert(count == 2);
+
+                a := 0;
+                outer: while (a < 3) {
+                    a += 1;
+                    b := 0;
+                    while (b < 3) {
+                        b += 1;
+                        if (b == 2) {
+                            continue outer;
+                        }
+                        if (a == 3) {
+                            break outer;
+                        }
+                        count += 1;
+                    }
+                }
+                assert(count == 4);
+
+                outer: while (true) {
+                    for (i := 0; i < 3; i += 1) {
+                        if (i == 1) {
+                            break outer;
+                        }
+                        count += 1;
+                    }
+                }
+                assert(count == 5);
+           }
+           "#;
+
+        run_source(source).unwrap();
+    }
+
+    #[test]
+    fn return_from_for() {
+        let source = r#"
+           fn find(): int {
+                for (i := 0; i < 10; i += 1) {
+                    if (i == 3) {
+                        return i;
+                    }
+                }
+                return 0 - 1;
+           }
+
+           fn main() {
+                x := 5;
+                y := find();
+                assert(x == 5);
+                assert(x + y == 8);
+           }
+           "#;
+
+        run_source(source).unwrap();
+    }
+
+    #[test]
+    fn match_expression() {
+        let source = r#"
+           fn sign(a: int): int => match (a) {
+                0 => 0,
+                n if n < 0 => 0 - 1,
+                _ => 1,
+           };
+
+           fn main() {
+                assert(sign(0) == 0);
+                assert(sign(0 - 5) == 0 - 1);
+                assert(sign(7) == 1);
+
+                name := match ("b") {
+                    "a" => 1,
+                    "b" => 2,
+                    _ => 3,
+                };
+                assert(name == 2);
+
+                y := match (4) {
+                    0 => 0,
+                    x if x > 10 => x,
+                    x => x * 2,
+                };
+                assert(y == 8);
+
+                flag := match (false) {
+                    true => 1,
+                    false => 2,
+                };
+                assert(flag == 2);
+           }
+           "#;
+
+        run_source(source).unwrap();
+    }
+
+    #[test]
+    fn options_and_results() {
+        let source = r#"
+           fn find(list: [int], value: int): int? {
+                for (a in list) {
+                    if (a == value) {
+                        return some(a);
+                    }
+                }
+                return none;
+           }
+
+           fn parse(a: int): int!string {
+                if (a < 0) {
+                    return err("negative");
+                }
+                return ok(a);
+           }
+
+           fn add_parsed(a: int, b: int): int!string => ok(parse(a)? + parse(b)?);
+
+           fn main() {
+                found := find([1, 2, 3], 2);
+                assert(found.is_some());
+                assert(found == some(2));
+                assert(find([1, 2, 3], 4).is_none());
+                assert(find([1], 4).unwrap_or(0) == 0);
+                assert(found.map(fn (x: int): int => x * 10).unwrap() == 20);
+                assert(found.and_then(fn (x: int): int? => none) == none);
+                assert(found.expect("must be found") == 2);
+
+                assert(parse(1).is_ok());
+                assert(parse(0 - 1).is_err());
+                assert(parse(0 - 1) == err("negative"));
+                assert(parse(0 - 1).map_err(fn (e: string): int => 1) == err(1));
+                missing := find([], 1);
+                assert(missing.ok_or("missing") == err("missing"));
+                assert(add_parsed(1, 2).unwrap() == 3);
+                assert(add_parsed(1, 0 - 2) == err("negative"));
+
+                value := match (found) {
+                    some(x) => x,
+                    none => 0,
+                };
+                assert(value == 2);
+           }
+           "#;
+
+        run_source(source).unwrap();
+    }
+
+    #[test]
+    fn failed_unwrap() {
+        let source = r#"
+           fn main() {
+                a := none;
+                a.unwrap();
+           }
+           "#;
+
+        let Err(err) = run_source(source) else {
+            panic!("unwrapping none must fail");
+        };
+        assert!(matches!(err.err, Error::UnwrapFailed(_)));
+    }
+
+    #[test]
+    fn if_expression() {
+        let source = r#"
+           fn max(a: int, b: int): int {
+                if (a > b) { a } else { b }
+           }
+
+           fn main() {
+                c := if (1 == 2) { 1 } else { 2 };
+                assert(c == 2);
+
+                d := if (c == 1) {
+                    "one"
+                } else if (c == 2) {
+                    e := c * 2;
+                    "two"
+                } else {
+                    "many"
+                };
+                assert(d == "two");
+
+                assert(max(3, 7) == 7);
+                assert(max(if (true) { 9 } else { 0 }, 7) == 9);
+           }
+           "#;
+
+        run_source(source).unwrap();
+    }
+
+    #[test]
+    fn checked_int_arithmetic() {
+        let failing = [
+            ("a := 9223372036854775807; b := a + 1;", "a + 1", "overflow"),
+            ("a := 0 - 9223372036854775807; b := a - 2;", "a - 2", "overflow"),
+            ("a := 4611686018427387904; b := a * 2;", "a * 2", "overflow"),
+            ("a := 0; b := 1 / a;", "1 / a", "zero"),
+            ("a := 0; b := 1 % a;", "1 % a", "zero"),
+        ];
+
+        for (body, at, kind) in failing {
+            let source = format!("fn main() {{ {body} }}");
+            let Err(err) = run_source(&source) else {
+                panic!("{body} must fail");
+            };
+            match kind {
+                "overflow" => assert!(matches!(err.err, Error::IntegerOverflow(_)), "{body}"),
+                _ => assert!(matches!(err.err, Error::DivisionByZero), "{body}"),
+            }
+            assert_eq!(&source[err.range.clone()], at);
+        }
+
+        let source = r#"
+           fn main() {
+                max := 9223372036854775807;
+                min := 0 - max - 1;
+                assert(wrapping_add(max, 1) == min);
+                assert(wrapping_sub(min, 1) == max);
+                assert(wrapping_mul(max, 2) == -2);
+                assert(saturating_add(max, 1) == max);
+                assert(saturating_sub(min, 1) == min);
+                assert(saturating_mul(min, 2) == min);
+                assert(wrapping_add(1, 2) == 3);
+           }
+           "#;
+
+        run_source(source).unwrap();
+    }
+
+    #[test]
+    fn ieee_floats() {
+        let source = r#"
+           fn main() {
+                nan := 0.0 / 0.0;
+                inf := 1.0 / 0.0;
+                assert(nan != nan);
+                assert((nan == nan) == false);
+                assert((nan < 1.0) == false);
+                assert((nan <= 1.0) == false);
+                assert((nan > 1.0) == false);
+                assert((nan >= 1) == false);
+                assert(inf > 1000000.0);
+                assert(0.0 - inf < 0);
+                assert(1 <= 1);
+                assert(1 <= 1.5);
+                assert(2.0 >= 2);
+                assert((2 >= 3) == false);
+           }
+           "#;
+
+        run_source(source).unwrap();
+    }
+
+    #[test]
+    fn explicit_conversions() {
+        let source = r#"
+           fn main() {
+                assert(int(2.9) == 2);
+                assert(int(-2.9) == -2);
+                assert(int(true) == 1);
+                assert(float(3) == 3.0);
+                assert(string(12) == "12");
+                assert(string(1.5) + "s" == "1.5s");
+                assert(string(some(1)) == "some(1)");
+                match (parse_int("-42")) {
+                    ok(n) => assert(n == -42),
+                    err(_) => assert(false),
+                }
+                match (parse_int("4x2")) {
+                    ok(_) => assert(false),
+                    err(e) => assert(e != ""),
+                }
+           }
+           "#;
+
+        run_source(source).unwrap();
+
+        let failing = [
+            r#"a := "hp: " + 1;"#,
+            r#"a := 1.5 + "s";"#,
+            "a := int(1.0 / 0.0);",
+            r#"a := float("1");"#,
+            "a := parse_int(1);",
+        ];
+        for body in failing {
+            let source = format!("fn main() {{ {body} }}");
+            let Err(err) = run_source(&source) else {
+                panic!("{body} must fail");
+            };
+            assert!(
+                matches!(
+                    err.err,
+                    Error::OperationUnsupported { .. }
+                        | Error::IntegerOverflow(_)
+                        | Error::WrongType(..)
+                ),
+                "{body} failed with {}",
+                err.err
+            );
+        }
+    }
+
+    #[test]
+    fn strict_mode() {
+        let source = r#"
+           fn main() {
+                a := 1;
+                b := a + 1.5;
+                a += 0.5;
+           }
+           "#;
+        run_source(source).unwrap();
+
+        let strict = |source: &str| {
+            run_source_with(
+                source,
+                Interpreter::new("main".to_string()).with_strict(),
+                Vm::new("main".to_string()).with_strict(),
+            )
+        };
+
+        let failing = [
+            ("a := 1; b := a + 1.5;", "a + 1.5"),
+            ("a := 1.5; b := 2 * a;", "2 * a"),
+            ("a := 1; a += 0.5;", "0.5"),
+        ];
+        for (body, at) in failing {
+            let source = format!("fn main() {{ {body} }}");
+            let Err(err) = strict(&source) else {
+                panic!("{body} must fail in strict mode");
+            };
+            assert!(matches!(err.err, Error::MixedTypes(..)), "{body}");
+            assert_eq!(&source[err.range.clone()], at);
+        }
+
+        let source = r#"
+           fn main() {
+                a := 1;
+                b := float(a) + 1.5;
+                c := a + int(1.5);
+                assert(b == 2.5 && c == 2);
+                assert(a < 1.5);
+           }
+           "#;
+        strict(source).unwrap();
+    }
+
+    #[test]
+    fn string_interpolation() {
+        let source = r#"
+           struct Stats {
+                hp: int,
+           }
+
+           fn main() {
+                hp := 10;
+                name := "orc";
+                assert("{name} hp: {hp}" == "orc hp: 10");
+                assert("{hp * 2}{1.5}!" == "201.5!");
+                assert("{some(hp)}" == "some(10)");
+                assert("{ Stats { hp: 3, }.hp }" == "3");
+                assert("\{hp\}" == "\{" + "hp\}");
+                assert("a\tb" == "a	b");
+           }
+           "#;
+
+        run_source(source).unwrap();
+
+        // the expressions point into the whole source
+        let source = r#"fn main() { a := "hp: {missing}"; }"#;
+        let Err(err) = run_source(source) else {
+            panic!("unknown symbols in interpolations must be reported");
+        };
+        assert_eq!(&source[err.range.clone()], "missing");
+
+        let source = r#"fn main() { a := "hp: {1 +}"; }"#;
+        let Err(err) = run_source(source) else {
+            panic!("invalid expressions in interpolations must be reported");
+        };
+        let Error::ParseError(ParseError::UnrecognizedEof { location, .. }) = err.err else {
+            panic!("expected a parse error, got {}", err.err);
+        };
+        assert_eq!(&source[location..location + 1], "}");
+
+        for source in [r#"fn main() { a := "{1"; }"#, r#"fn main() { a := "1}"; }"#] {
+            assert!(matches!(
+                run_source(source).map(|_| ()).unwrap_err().err,
+                Error::ParseError(_)
+            ));
+        }
+    }
 
-        let source_safe = source.clone();
+    #[test]
+    fn unsupported_constructs() {
+        let source = r#"fn main() { m := {"a" -> 1}; println(m); }"#;
+        let err = run_source(source).unwrap_err();
+        assert!(matches!(err.err, Error::Unsupported(_)));
+        assert_eq!(&source[err.range.clone()], r#"{"a" -> 1}"#);
+    }
 
+    #[test]
+    fn cycle_collector() {
+        let source = r#"
+           struct Point {
+                x: int,
+           }
+
+           struct Button {
+                on_click: fn(): int,
+           }
+
+           fn make(): int {
+                p := Point { x: 1, };
+                p.x
+           }
+
+           fn make_counter(): fn(): int {
+                count := 0;
+                return fn (): int {
+                    count += 1;
+                    return count;
+                };
+           }
+
+           fn press(): int {
+                clicks := 1;
+                button := Button { on_click: fn (): int => clicks, };
+                clicks
+           }
+
+           fn main() {
+                f := fn (a: int): int => a + 1;
+                assert(f(make()) == 2);
+                counter := make_counter();
+                assert(counter() + counter() == 3);
+                assert(press() == 1);
+           }
+           "#;
+
+        let interpreter = Interpreter::new("main".to_string()).with_cycle_collector();
+        let warnings = interpreter.warnings();
         let stages = vec![
             Stages::Parser(Parser::default()),
             Stages::Preprocessor(Preprocessor::new().unwrap()),
-            Stages::Interpreter(Interpreter::new("main".to_string())),
+            Stages::Resolver(Resolver::new()),
+            Stages::Interpreter(interpreter),
         ];
+        run_stages(stages, StageResult::PreParse(source.to_owned())).unwrap();
 
-        let state = StageResult::PreParse(source);
+        // only the button owns the scope its closure captured. Closures in locals and returned
+        // closures don't own their scope, the structs only own themselves through self
+        let warnings = warnings
+            .borrow()
+            .iter()
+            .map(|w| match &w.warning {
+                Warning::LeakedCycle(description) => {
+                    (description.clone(), &source[w.range.clone()])
+                }
+                other => panic!("expected a leaked cycle, got {other:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            [
+                ("closure environment".to_owned(), "fn (): int => clicks"),
+                (
+                    "struct Button".to_owned(),
+                    "Button { on_click: fn (): int => clicks, }"
+                ),
+            ]
+        );
+    }
 
-        let result = run_stages(stages, state);
-        if let Err(occured_error) = result {
-            occured_error.print_error(&source_safe);
+    #[test]
+    fn weak_upgrade() {
+        let source = r#"
+           fn main() {
+                a := 10;
+                w := weak a;
+                assert(w.upgrade() == some(10));
+                value := match (w.upgrade()) {
+                    some(v) => v,
+                    none => 0,
+                };
+                assert(value == 10);
+
+                // the only strong reference to 30 is dropped
+                b := 30;
+                dangling := weak b;
+                b = 40;
+                assert(dangling.upgrade().is_none());
+                println(dangling);
+           }
+           "#;
+
+        run_source(source).unwrap();
+    }
+
+    #[test]
+    fn suggests_similar_names() {
+        fn suggestion(source: &str) -> (Option<&str>, String) {
+            let err = run_source(source).unwrap_err();
+            match &*err.notes {
+                [Note::Suggestion(range, similar)] => (
+                    range.as_ref().map(|range| &source[range.clone()]),
+                    similar.clone(),
+                ),
+                notes => panic!("expected a suggestion, got {notes:?}"),
+            }
         }
+        let definitions = r#"
+           struct Player {
+                health: int,
+           }
+           component Position { x: float, y: float, }
+           "#;
+
+        let source = format!(
+            "{definitions} fn main() {{ p := Player {{ health: 1, }}; println(p.helth); }}"
+        );
+        assert_eq!(suggestion(&source), (Some("helth"), "health".to_owned()));
+
+        let source = format!("{definitions} fn main() {{ p := Positon {{ x: 1.0, y: 2.0, }}; }}");
+        assert_eq!(
+            suggestion(&source),
+            (Some("Positon"), "Position".to_owned())
+        );
+
+        // the name of a field is not part of the ast, the help is part of the label
+        let source = format!("{definitions} fn main() {{ p := Player {{ helth: 1, }}; }}");
+        assert_eq!(suggestion(&source), (None, "health".to_owned()));
+    }
+
+    #[test]
+    fn runtime_errors_trace_calls() {
+        let source = r#"
+           fn divide(a: int, b: int): int => a / b;
+           fn half(a: int): int => divide(a, 0) + 1;
+           fn main() {
+                x := half(4);
+           }
+           "#;
+
+        let err = run_source(source).unwrap_err();
+        assert!(matches!(err.err, Error::DivisionByZero));
+        assert_eq!(&source[err.range.clone()], "a / b");
+        let trace = err
+            .trace()
+            .iter()
+            .map(|frame| (frame.name.as_str(), &source[frame.range.clone()]))
+            .collect::<Vec<_>>();
+        assert_eq!(trace, [("divide", "divide(a, 0)"), ("half", "half(4)")]);
+
+        // errors of buildins point at their call
+        let source =
+            "fn check(a: float): int => int(a); fn main() { x := check(10000000000000000000.0); }";
+        let err = run_source(source).unwrap_err();
+        assert_eq!(&source[err.range.clone()], "int(a)");
+        assert_eq!(err.trace().len(), 1);
+        assert_eq!(
+            &source[err.trace()[0].range.clone()],
+            "check(10000000000000000000.0)"
+        );
+
+        let source = "fn main() { x := ; }";
+        let err = run_source(source).unwrap_err();
+        assert_eq!(&source[err.range.clone()], ";");
+
+        // a failed assert is a runtime error, not a crash of the interpreter
+        let source = "fn main() { a := 1; assert(a == 1); assert(a == 2); }";
+        let err = run_source(source).unwrap_err();
+        assert!(matches!(err.err, Error::AssertionFailed));
+        assert_eq!(&source[err.range.clone()], "assert(a == 2)");
     }
 }
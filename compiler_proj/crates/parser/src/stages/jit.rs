@@ -0,0 +1,1256 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+use cranelift_codegen::{
+    Context,
+    ir::{
+        AbiParam, Block, InstBuilder, MemFlags, Signature, Type, UserFuncName, Value,
+        condcodes::{FloatCC, IntCC},
+        types,
+    },
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{FuncId, Linkage, Module, default_libcall_names};
+
+use crate::{
+    AstNode, AstNodeType, Error, FunctionExecutionStrategy, FunctionType, InfixOperator,
+    InterpreterValue, MemberAccessType, PrefixOperator, Scope, Symbol, TypeSymbol, TypeSymbolType,
+};
+
+/// Number of calls after which a function is compiled to machine code
+pub const DEFAULT_JIT_THRESHOLD: usize = 100;
+
+/// The construct is not supported by the jit, the function stays interpreted
+#[derive(Debug)]
+struct Unsupported;
+
+/// The values the jit can work with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum JitType {
+    Int,
+    Float,
+    Bool,
+}
+
+impl JitType {
+    fn of(type_of: &TypeSymbol) -> Option<Self> {
+        if type_of.is_weak {
+            return None;
+        }
+
+        match &type_of.type_of {
+            TypeSymbolType::Int => Some(JitType::Int),
+            TypeSymbolType::Float => Some(JitType::Float),
+            TypeSymbolType::Bool => Some(JitType::Bool),
+            TypeSymbolType::Symbol(s) => match s.as_str() {
+                "int" => Some(JitType::Int),
+                "float" => Some(JitType::Float),
+                "bool" => Some(JitType::Bool),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn ir(self) -> Type {
+        match self {
+            JitType::Int => types::I64,
+            JitType::Float => types::F64,
+            JitType::Bool => types::I8,
+        }
+    }
+
+    /// The value as it is passed to a compiled function
+    fn encode(self, value: &InterpreterValue) -> Option<u64> {
+        match (self, value) {
+            (JitType::Int, InterpreterValue::Int(i)) => Some(*i as u64),
+            (JitType::Float, InterpreterValue::Float(f)) => Some(f.to_bits()),
+            (JitType::Bool, InterpreterValue::Bool(b)) => Some(*b as u64),
+            _ => None,
+        }
+    }
+
+    fn decode(self, raw: u64) -> InterpreterValue {
+        match self {
+            JitType::Int => InterpreterValue::Int(raw as i64),
+            JitType::Float => InterpreterValue::Float(f64::from_bits(raw)),
+            JitType::Bool => InterpreterValue::Bool(raw != 0),
+        }
+    }
+}
+
+/// Signature of a function, that only takes and returns ints, floats and bools
+#[derive(Debug, Clone)]
+struct JitSignature {
+    params: Vec<JitType>,
+    ret: Option<JitType>,
+}
+
+impl JitSignature {
+    fn of(fn_type: &FunctionType) -> Option<Self> {
+        let FunctionExecutionStrategy::Interpreted(_) = &fn_type.execution_body else {
+            return None;
+        };
+
+        Some(Self {
+            params: fn_type
+                .params
+                .iter()
+                .map(|(_, type_of)| JitType::of(type_of))
+                .collect::<Option<Vec<_>>>()?,
+            ret: match &fn_type.return_type {
+                Some(type_of) => Some(JitType::of(type_of)?),
+                None => None,
+            },
+        })
+    }
+
+    /// Params and the bail flag, functions without a return type return 0
+    fn internal(&self, module: &JITModule) -> Signature {
+        let mut sig = module.make_signature();
+        for param in &self.params {
+            sig.params.push(AbiParam::new(param.ir()));
+        }
+        sig.params
+            .push(AbiParam::new(module.target_config().pointer_type()));
+        sig.returns
+            .push(AbiParam::new(self.ret.map_or(types::I64, JitType::ir)));
+        sig
+    }
+}
+
+/// extern "C" fn(args: *const u64, bail: *mut u8) -> u64, called from the interpreter
+type EntryFn = extern "C" fn(*const u64, *mut u8) -> u64;
+
+enum JitState {
+    Compiled {
+        entry: *const u8,
+        signature: JitSignature,
+    },
+    Unsupported,
+}
+
+/// Compiles hot functions over ints, floats and bools to machine code.
+/// Compiled code bails out on everything the interpreter would report, i.e. overflows or a division by zero.
+/// As compiled functions can't have side effects, the interpreter then simply runs the call again
+pub struct Jit {
+    module: JITModule,
+    threshold: usize,
+    calls: HashMap<Symbol, usize>,
+    states: HashMap<Symbol, JitState>,
+    ids: HashMap<Symbol, FuncId>,
+    strict: bool,
+}
+
+impl Jit {
+    pub fn new(threshold: usize) -> Result<Self, Error> {
+        let unsupported = |reason: String| Error::OperationUnsupported {
+            operation: "jit".to_owned(),
+            type_of: reason,
+        };
+
+        let mut flag_builder = settings::builder();
+        flag_builder
+            .set("use_colocated_libcalls", "false")
+            .map_err(|e| unsupported(e.to_string()))?;
+        flag_builder
+            .set("is_pic", "false")
+            .map_err(|e| unsupported(e.to_string()))?;
+        let isa = cranelift_native::builder()
+            .map_err(|e| unsupported(e.to_owned()))?
+            .finish(settings::Flags::new(flag_builder))
+            .map_err(|e| unsupported(e.to_string()))?;
+
+        Ok(Self {
+            module: JITModule::new(JITBuilder::with_isa(isa, default_libcall_names())),
+            threshold,
+            calls: HashMap::new(),
+            states: HashMap::new(),
+            ids: HashMap::new(),
+            strict: false,
+        })
+    }
+
+    /// Leaves arithmetic on an int and a float to the interpreter, which rejects it in strict mode
+    pub fn with_strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Whether the function was compiled to machine code
+    pub fn is_compiled(&self, name: &Symbol) -> bool {
+        matches!(self.states.get(name), Some(JitState::Compiled { .. }))
+    }
+
+    /// Counts the call and runs the compiled function, once it is hot.
+    /// Returns None, if the call has to be interpreted
+    pub fn try_call(
+        &mut self,
+        fn_type: &FunctionType,
+        params: &[(InterpreterValue, Range<usize>)],
+        globals: &Scope,
+    ) -> Option<InterpreterValue> {
+        let calls = self.calls.entry(fn_type.name.clone()).or_default();
+        *calls += 1;
+        if *calls < self.threshold && !self.states.contains_key(&fn_type.name) {
+            return None;
+        }
+
+        if !self.states.contains_key(&fn_type.name) {
+            // NOTE: everything, that could not be compiled, is interpreted from now on
+            if self.compile(&fn_type.name, globals).is_err() {
+                self.states
+                    .insert(fn_type.name.clone(), JitState::Unsupported);
+            }
+        }
+
+        let Some(JitState::Compiled { entry, signature }) = self.states.get(&fn_type.name) else {
+            return None;
+        };
+
+        let mut args = Vec::new();
+        for ((value, _), type_of) in params.iter().zip(&signature.params) {
+            // NOTE: weak references keep their semantics in the interpreter
+            if let InterpreterValue::Weak(_) = value {
+                return None;
+            }
+            let value = InterpreterValue::preprocess_single(value.clone()).ok()?;
+            args.push(type_of.encode(&value)?);
+        }
+        if args.len() != signature.params.len() {
+            return None;
+        }
+
+        let mut bail = 0u8;
+        // SAFETY: the entry was compiled with the signature of EntryFn, and args holds a value for every param
+        let entry = unsafe { std::mem::transmute::<*const u8, EntryFn>(*entry) };
+        let raw = entry(args.as_ptr(), &mut bail);
+        if bail != 0 {
+            return None;
+        }
+
+        Some(match signature.ret {
+            Some(ret) => InterpreterValue::new_strong(ret.decode(raw)),
+            None => InterpreterValue::Empty,
+        })
+    }
+
+    fn declare(&mut self, name: &Symbol, signature: &JitSignature) -> Result<FuncId, Unsupported> {
+        if let Some(id) = self.ids.get(name) {
+            return Ok(*id);
+        }
+
+        let sig = signature.internal(&self.module);
+        let id = self
+            .module
+            .declare_function(name, Linkage::Local, &sig)
+            .map_err(|_| Unsupported)?;
+        self.ids.insert(name.clone(), id);
+        Ok(id)
+    }
+
+    /// Compiles the function and all functions it calls, which are not compiled yet.
+    /// Nothing is defined, unless all of them can be compiled
+    fn compile(&mut self, root: &Symbol, globals: &Scope) -> Result<(), Unsupported> {
+        let mut pending = vec![root.clone()];
+        let mut seen = HashSet::new();
+        let mut batch = Vec::new();
+        let mut builder_context = FunctionBuilderContext::new();
+
+        while let Some(name) = pending.pop() {
+            match self.states.get(&name) {
+                Some(JitState::Compiled { .. }) => continue,
+                Some(JitState::Unsupported) => return Err(Unsupported),
+                None if !seen.insert(name.clone()) => continue,
+                None => (),
+            }
+
+            let fn_type = jit_function(globals, &name).ok_or(Unsupported)?;
+            let signature = JitSignature::of(&fn_type).ok_or(Unsupported)?;
+            let id = self.declare(&name, &signature)?;
+
+            let mut ctx = self.module.make_context();
+            ctx.func.signature = signature.internal(&self.module);
+            ctx.func.name = UserFuncName::user(0, id.as_u32());
+            let callees = Translator::translate(
+                self,
+                &mut ctx,
+                &mut builder_context,
+                &fn_type,
+                &signature,
+                globals,
+            )?;
+            pending.extend(callees);
+            batch.push((name, id, signature, ctx));
+        }
+
+        let mut entries = Vec::new();
+        for (name, id, signature, mut ctx) in batch {
+            self.module
+                .define_function(id, &mut ctx)
+                .map_err(|_| Unsupported)?;
+            let entry = self.define_entry(&name, id, &signature, &mut builder_context)?;
+            entries.push((name, entry, signature));
+        }
+        self.module
+            .finalize_definitions()
+            .map_err(|_| Unsupported)?;
+
+        for (name, entry, signature) in entries {
+            let entry = self.module.get_finalized_function(entry);
+            self.states
+                .insert(name, JitState::Compiled { entry, signature });
+        }
+        Ok(())
+    }
+
+    /// Defines the entry of a compiled function, that reads its arguments from an array
+    fn define_entry(
+        &mut self,
+        name: &Symbol,
+        id: FuncId,
+        signature: &JitSignature,
+        builder_context: &mut FunctionBuilderContext,
+    ) -> Result<FuncId, Unsupported> {
+        let pointer = self.module.target_config().pointer_type();
+        let mut sig = self.module.make_signature();
+        sig.params.push(AbiParam::new(pointer));
+        sig.params.push(AbiParam::new(pointer));
+        sig.returns.push(AbiParam::new(types::I64));
+        let entry_id = self
+            .module
+            .declare_function(&format!("{name}$entry"), Linkage::Local, &sig)
+            .map_err(|_| Unsupported)?;
+
+        let mut ctx = self.module.make_context();
+        ctx.func.signature = sig;
+        ctx.func.name = UserFuncName::user(0, entry_id.as_u32());
+        {
+            let mut builder = FunctionBuilder::new(&mut ctx.func, builder_context);
+            let block = builder.create_block();
+            builder.append_block_params_for_function_params(block);
+            builder.switch_to_block(block);
+            builder.seal_block(block);
+            let (args, bail) = (
+                builder.block_params(block)[0],
+                builder.block_params(block)[1],
+            );
+
+            let mut call_args = Vec::new();
+            for (i, param) in signature.params.iter().enumerate() {
+                let raw = builder
+                    .ins()
+                    .load(types::I64, MemFlags::trusted(), args, (i * 8) as i32);
+                call_args.push(match param {
+                    JitType::Int => raw,
+                    JitType::Float => builder.ins().bitcast(types::F64, MemFlags::new(), raw),
+                    JitType::Bool => builder.ins().ireduce(types::I8, raw),
+                });
+            }
+            call_args.push(bail);
+
+            let callee = self.module.declare_func_in_func(id, builder.func);
+            let call = builder.ins().call(callee, &call_args);
+            let ret = builder.inst_results(call)[0];
+            let ret = match signature.ret {
+                Some(JitType::Float) => builder.ins().bitcast(types::I64, MemFlags::new(), ret),
+                Some(JitType::Bool) => builder.ins().uextend(types::I64, ret),
+                Some(JitType::Int) | None => ret,
+            };
+            builder.ins().return_(&[ret]);
+            builder.finalize();
+        }
+
+        self.module
+            .define_function(entry_id, &mut ctx)
+            .map_err(|_| Unsupported)?;
+        Ok(entry_id)
+    }
+}
+
+/// The declared function with this name, if it could be compiled
+fn jit_function(globals: &Scope, name: &Symbol) -> Option<FunctionType> {
+    match globals.resolve_type(name)?.type_of {
+        TypeSymbolType::Function(fn_type) => Some(fn_type),
+        _ => None,
+    }
+}
+
+/// The value of a node. Never is the value of return, break and continue, the code after them is unreachable
+enum Typed {
+    Value(Value, JitType),
+    Empty,
+    Never,
+}
+
+struct LoopBlocks {
+    label: Option<Symbol>,
+    continue_block: Block,
+    exit_block: Block,
+}
+
+/// Translates the body of a single function to cranelift ir
+struct Translator<'a, 'b> {
+    jit: &'a mut Jit,
+    builder: FunctionBuilder<'b>,
+    globals: &'a Scope,
+    scopes: Vec<HashMap<Symbol, (Variable, JitType)>>,
+    variables: u32,
+    loops: Vec<LoopBlocks>,
+    ret: Option<JitType>,
+    return_block: Block,
+    bail: Value,
+    bail_block: Block,
+    callees: Vec<Symbol>,
+}
+
+impl<'a, 'b> Translator<'a, 'b> {
+    /// Returns the functions called by this one
+    fn translate(
+        jit: &'a mut Jit,
+        ctx: &'b mut Context,
+        builder_context: &'b mut FunctionBuilderContext,
+        fn_type: &FunctionType,
+        signature: &JitSignature,
+        globals: &'a Scope,
+    ) -> Result<Vec<Symbol>, Unsupported> {
+        let FunctionExecutionStrategy::Interpreted(body) = &fn_type.execution_body else {
+            return Err(Unsupported);
+        };
+
+        let mut builder = FunctionBuilder::new(&mut ctx.func, builder_context);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        let return_block = builder.create_block();
+        let ret_ir = signature.ret.map_or(types::I64, JitType::ir);
+        builder.append_block_param(return_block, ret_ir);
+        let bail_block = builder.create_block();
+        builder.switch_to_block(entry);
+
+        let params = builder.block_params(entry).to_vec();
+        let mut translator = Translator {
+            jit,
+            builder,
+            globals,
+            scopes: vec![HashMap::new()],
+            variables: 0,
+            loops: Vec::new(),
+            ret: signature.ret,
+            return_block,
+            bail: *params.last().expect("the bail flag is always passed"),
+            bail_block,
+            callees: Vec::new(),
+        };
+        for ((name, _), (value, type_of)) in fn_type
+            .params
+            .iter()
+            .zip(params.iter().zip(&signature.params))
+        {
+            translator.declare(name, *value, *type_of)?;
+        }
+
+        // NOTE: the body shares the scope of the params, like in the interpreter
+        let value = translator.translate_nodes(body)?;
+        match (value, signature.ret) {
+            (Typed::Value(v, type_of), Some(ret)) if type_of == ret => {
+                translator.builder.ins().jump(return_block, &[v]);
+            }
+            (Typed::Never, _) | (_, None) => {
+                let zero = translator.zero(ret_ir);
+                translator.builder.ins().jump(return_block, &[zero]);
+            }
+            // NOTE: a missing return value is reported by the interpreter
+            _ => return Err(Unsupported),
+        }
+
+        let Translator {
+            mut builder,
+            bail,
+            callees,
+            ..
+        } = translator;
+
+        builder.switch_to_block(return_block);
+        let ret = builder.block_params(return_block)[0];
+        builder.ins().return_(&[ret]);
+
+        builder.switch_to_block(bail_block);
+        let one = builder.ins().iconst(types::I8, 1);
+        builder.ins().store(MemFlags::trusted(), one, bail, 0);
+        let zero = match ret_ir {
+            types::F64 => builder.ins().f64const(0.0),
+            ty => builder.ins().iconst(ty, 0),
+        };
+        builder.ins().return_(&[zero]);
+
+        builder.seal_all_blocks();
+        builder.finalize();
+        Ok(callees)
+    }
+
+    fn zero(&mut self, ty: Type) -> Value {
+        match ty {
+            types::F64 => self.builder.ins().f64const(0.0),
+            ty => self.builder.ins().iconst(ty, 0),
+        }
+    }
+
+    fn declare(
+        &mut self,
+        name: &Symbol,
+        value: Value,
+        type_of: JitType,
+    ) -> Result<(), Unsupported> {
+        let scope = self.scopes.last_mut().expect("functions have a scope");
+        // NOTE: redeclaring a variable in the same scope is an error of the interpreter
+        if scope.contains_key(name) {
+            return Err(Unsupported);
+        }
+
+        let variable = Variable::from_u32(self.variables);
+        self.variables += 1;
+        self.builder.declare_var(variable, type_of.ir());
+        self.builder.def_var(variable, value);
+        scope.insert(name.clone(), (variable, type_of));
+        Ok(())
+    }
+
+    fn resolve(&self, name: &Symbol) -> Option<(Variable, JitType)> {
+        self.scopes.iter().rev().find_map(|s| s.get(name)).copied()
+    }
+
+    /// Continues in a block without predecessors, after control left the current one
+    fn unreachable(&mut self) -> Typed {
+        let block = self.builder.create_block();
+        self.builder.seal_block(block);
+        self.builder.switch_to_block(block);
+        Typed::Never
+    }
+
+    /// Leaves the function through the bail block, if the condition holds
+    fn bail_if(&mut self, cond: Value) {
+        let next = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(cond, self.bail_block, &[], next, &[]);
+        self.builder.seal_block(next);
+        self.builder.switch_to_block(next);
+    }
+
+    fn value(&mut self, node: &AstNode) -> Result<(Value, JitType), Unsupported> {
+        match self.translate_node(node)? {
+            Typed::Value(v, type_of) => Ok((v, type_of)),
+            _ => Err(Unsupported),
+        }
+    }
+
+    fn bool_value(&mut self, node: &AstNode) -> Result<Value, Unsupported> {
+        match self.value(node)? {
+            (v, JitType::Bool) => Ok(v),
+            _ => Err(Unsupported),
+        }
+    }
+
+    fn translate_block(&mut self, nodes: &[Box<AstNode>]) -> Result<Typed, Unsupported> {
+        self.scopes.push(HashMap::new());
+        let res = self.translate_nodes(nodes);
+        self.scopes.pop();
+        res
+    }
+
+    fn translate_nodes(&mut self, nodes: &[Box<AstNode>]) -> Result<Typed, Unsupported> {
+        let mut last = Typed::Empty;
+        let mut left = false;
+        for node in nodes {
+            last = self.translate_node(node)?;
+            left |= matches!(last, Typed::Never);
+        }
+
+        Ok(if left { Typed::Never } else { last })
+    }
+
+    fn translate_node(&mut self, node: &AstNode) -> Result<Typed, Unsupported> {
+        let typed = match &node.type_of {
+            AstNodeType::Int(i) => {
+                Typed::Value(self.builder.ins().iconst(types::I64, *i), JitType::Int)
+            }
+            AstNodeType::Float(f) => Typed::Value(self.builder.ins().f64const(*f), JitType::Float),
+            AstNodeType::Bool(b) => Typed::Value(
+                self.builder.ins().iconst(types::I8, *b as i64),
+                JitType::Bool,
+            ),
+            AstNodeType::MemberCall { calls } => match calls.as_slice() {
+                [call] => match &call.type_of {
+                    MemberAccessType::Symbol => {
+                        let (variable, type_of) = self.resolve(&call.member).ok_or(Unsupported)?;
+                        Typed::Value(self.builder.use_var(variable), type_of)
+                    }
+                    MemberAccessType::Function(params) => {
+                        self.translate_call(&call.member, params)?
+                    }
+                    MemberAccessType::Struct(_) => return Err(Unsupported),
+                },
+                _ => return Err(Unsupported),
+            },
+            AstNodeType::InfixCall(left, op, right) => {
+                let left = self.value(left)?;
+                let right = self.value(right)?;
+                self.translate_infix(left, op, right)?
+            }
+            AstNodeType::PrefixCall(op, right) => {
+                let (v, type_of) = self.value(right)?;
+                match (op, type_of) {
+                    (PrefixOperator::Not, JitType::Bool) => {
+                        Typed::Value(self.builder.ins().bxor_imm(v, 1), JitType::Bool)
+                    }
+                    (PrefixOperator::Negate, JitType::Int) => {
+                        let overflows = self.builder.ins().icmp_imm(IntCC::Equal, v, i64::MIN);
+                        self.bail_if(overflows);
+                        Typed::Value(self.builder.ins().ineg(v), JitType::Int)
+                    }
+                    (PrefixOperator::Negate, JitType::Float) => {
+                        Typed::Value(self.builder.ins().fneg(v), JitType::Float)
+                    }
+                    _ => return Err(Unsupported),
+                }
+            }
+            AstNodeType::Declaration {
+                new_symbol,
+                expression,
+                assumed_type,
+                ..
+            } => {
+                let (v, type_of) = self.value(expression)?;
+                // NOTE: the interpreter keeps the value as it is, even if another type was declared
+                if assumed_type
+                    .as_ref()
+                    .is_some_and(|assumed| JitType::of(assumed) != Some(type_of))
+                {
+                    return Err(Unsupported);
+                }
+                self.declare(new_symbol, v, type_of)?;
+                Typed::Empty
+            }
+            AstNodeType::AssignmentOp {
+                recipient,
+                operation,
+                expression,
+                ..
+            } => {
+                let value = self.value(expression)?;
+                let (variable, type_of) = self.resolve(recipient).ok_or(Unsupported)?;
+                let old = (self.builder.use_var(variable), type_of);
+                let new_value = match operation.infix_operator() {
+                    Some(op) => self.translate_infix(old, &op, value)?,
+                    None => Typed::Value(value.0, value.1),
+                };
+                // NOTE: variables can change their type in the interpreter, but not in registers
+                match new_value {
+                    Typed::Value(v, new_type) if new_type == type_of => {
+                        self.builder.def_var(variable, v)
+                    }
+                    _ => return Err(Unsupported),
+                }
+                Typed::Empty
+            }
+            AstNodeType::ReturnStatement { return_value } => {
+                let (v, type_of) = self.value(return_value)?;
+                if self.ret != Some(type_of) {
+                    return Err(Unsupported);
+                }
+                self.builder.ins().jump(self.return_block, &[v]);
+                self.unreachable()
+            }
+            AstNodeType::Branch { .. } => self.translate_branch(node)?,
+            AstNodeType::ExpressionStatement(expression) => {
+                match self.translate_node(expression)? {
+                    Typed::Never => Typed::Never,
+                    _ => Typed::Empty,
+                }
+            }
+            AstNodeType::While { label, cond, body } => {
+                let header = self.builder.create_block();
+                let body_block = self.builder.create_block();
+                let exit = self.builder.create_block();
+                self.builder.ins().jump(header, &[]);
+
+                self.builder.switch_to_block(header);
+                let cond = self.bool_value(cond)?;
+                self.builder.ins().brif(cond, body_block, &[], exit, &[]);
+
+                self.builder.switch_to_block(body_block);
+                self.translate_loop_body(label, body, header, exit)?;
+                self.builder.ins().jump(header, &[]);
+
+                self.builder.switch_to_block(exit);
+                Typed::Empty
+            }
+            AstNodeType::For {
+                label,
+                declaration,
+                condition,
+                assignment,
+                body,
+            } => {
+                self.scopes.push(HashMap::new());
+                if let Some(init) = declaration {
+                    let AstNodeType::Declaration { .. } = &init.type_of else {
+                        return Err(Unsupported);
+                    };
+                    self.translate_node(init)?;
+                }
+
+                let header = self.builder.create_block();
+                let body_block = self.builder.create_block();
+                let step = self.builder.create_block();
+                let exit = self.builder.create_block();
+                self.builder.ins().jump(header, &[]);
+
+                self.builder.switch_to_block(header);
+                match condition {
+                    Some(cond) => {
+                        let cond = self.bool_value(cond)?;
+                        self.builder.ins().brif(cond, body_block, &[], exit, &[]);
+                    }
+                    None => {
+                        self.builder.ins().jump(body_block, &[]);
+                    }
+                }
+
+                self.builder.switch_to_block(body_block);
+                self.translate_loop_body(label, body, step, exit)?;
+                self.builder.ins().jump(step, &[]);
+
+                self.builder.switch_to_block(step);
+                if let Some(assignment) = assignment {
+                    let AstNodeType::AssignmentOp { .. } = &assignment.type_of else {
+                        return Err(Unsupported);
+                    };
+                    self.translate_node(assignment)?;
+                }
+                self.builder.ins().jump(header, &[]);
+
+                self.builder.switch_to_block(exit);
+                self.scopes.pop();
+                Typed::Empty
+            }
+            AstNodeType::Break(label) | AstNodeType::Continue(label) => {
+                let target = self
+                    .loops
+                    .iter()
+                    .rev()
+                    .find(|l| label.is_none() || l.label == *label)
+                    .ok_or(Unsupported)?;
+                let block = match node.type_of {
+                    AstNodeType::Break(_) => target.exit_block,
+                    _ => target.continue_block,
+                };
+                self.builder.ins().jump(block, &[]);
+                self.unreachable()
+            }
+            _ => return Err(Unsupported),
+        };
+
+        Ok(typed)
+    }
+
+    fn translate_loop_body(
+        &mut self,
+        label: &Option<Symbol>,
+        body: &[Box<AstNode>],
+        continue_block: Block,
+        exit_block: Block,
+    ) -> Result<(), Unsupported> {
+        self.loops.push(LoopBlocks {
+            label: label.clone(),
+            continue_block,
+            exit_block,
+        });
+        self.translate_block(body)?;
+        self.loops.pop();
+        Ok(())
+    }
+
+    /// The value of an if is the value of the taken branch. Branches of different types have no value
+    fn translate_branch(&mut self, node: &AstNode) -> Result<Typed, Unsupported> {
+        let AstNodeType::Branch {
+            cond,
+            body,
+            else_if_branches,
+            else_branch,
+        } = &node.type_of
+        else {
+            return Err(Unsupported);
+        };
+
+        let merge = self.builder.create_block();
+        let mut results: HashMap<JitType, Variable> = HashMap::new();
+        let mut types = Vec::new();
+
+        let branches = std::iter::once((cond, body))
+            .chain(else_if_branches.iter().map(|(cond, body)| (cond, body)));
+        for (cond, body) in branches {
+            let cond = self.bool_value(cond)?;
+            let then_block = self.builder.create_block();
+            let next = self.builder.create_block();
+            self.builder.ins().brif(cond, then_block, &[], next, &[]);
+            self.builder.seal_block(then_block);
+            self.builder.seal_block(next);
+
+            self.builder.switch_to_block(then_block);
+            let value = self.translate_block(body)?;
+            types.push(self.define_result(value, &mut results));
+            self.builder.ins().jump(merge, &[]);
+            self.builder.switch_to_block(next);
+        }
+
+        let value = match else_branch {
+            Some(body) => self.translate_block(body)?,
+            None => Typed::Empty,
+        };
+        types.push(self.define_result(value, &mut results));
+        self.builder.ins().jump(merge, &[]);
+        self.builder.switch_to_block(merge);
+
+        let reached = types.into_iter().flatten().collect::<Vec<_>>();
+        if reached.is_empty() {
+            self.builder.seal_block(merge);
+            return Ok(Typed::Never);
+        }
+        match reached.as_slice() {
+            [Some(first), rest @ ..] if rest.iter().all(|t| *t == Some(*first)) => {
+                let variable = results[first];
+                Ok(Typed::Value(self.builder.use_var(variable), *first))
+            }
+            _ => Ok(Typed::Empty),
+        }
+    }
+
+    /// Stores the value of a branch in the result variable of its type.
+    /// None if the branch left the if, Some(None) if it has no value
+    fn define_result(
+        &mut self,
+        value: Typed,
+        results: &mut HashMap<JitType, Variable>,
+    ) -> Option<Option<JitType>> {
+        match value {
+            Typed::Never => None,
+            Typed::Empty => Some(None),
+            Typed::Value(v, type_of) => {
+                let variable = *results.entry(type_of).or_insert_with(|| {
+                    let variable = Variable::from_u32(self.variables);
+                    self.variables += 1;
+                    self.builder.declare_var(variable, type_of.ir());
+                    variable
+                });
+                self.builder.def_var(variable, v);
+                Some(Some(type_of))
+            }
+        }
+    }
+
+    /// Calls another declared function, which is compiled together with this one
+    fn translate_call(
+        &mut self,
+        name: &Symbol,
+        params: &[Box<AstNode>],
+    ) -> Result<Typed, Unsupported> {
+        // NOTE: variables holding functions are not compiled
+        if self.resolve(name).is_some() {
+            return Err(Unsupported);
+        }
+        let fn_type = jit_function(self.globals, name).ok_or(Unsupported)?;
+        let signature = JitSignature::of(&fn_type).ok_or(Unsupported)?;
+        if signature.params.len() != params.len() {
+            return Err(Unsupported);
+        }
+
+        let mut args = Vec::new();
+        for (param, expected) in params.iter().zip(&signature.params) {
+            // NOTE: the interpreter passes values as they are, an int stays an int in a float param
+            match self.value(param)? {
+                (v, type_of) if type_of == *expected => args.push(v),
+                _ => return Err(Unsupported),
+            }
+        }
+        args.push(self.bail);
+
+        let id = self.jit.declare(name, &signature)?;
+        let callee = self.jit.module.declare_func_in_func(id, self.builder.func);
+        let call = self.builder.ins().call(callee, &args);
+        let ret = self.builder.inst_results(call)[0];
+
+        let bailed = self
+            .builder
+            .ins()
+            .load(types::I8, MemFlags::trusted(), self.bail, 0);
+        self.bail_if(bailed);
+        self.callees.push(name.clone());
+
+        Ok(match signature.ret {
+            Some(type_of) => Typed::Value(ret, type_of),
+            None => Typed::Empty,
+        })
+    }
+
+    /// Follows the operations of InterpreterValue, everything the interpreter reports bails out
+    fn translate_infix(
+        &mut self,
+        (l, ltype): (Value, JitType),
+        op: &InfixOperator,
+        (r, rtype): (Value, JitType),
+    ) -> Result<Typed, Unsupported> {
+        use JitType::{Bool, Float, Int};
+
+        let ins = self.builder.ins();
+        let typed = match (op, ltype, rtype) {
+            (InfixOperator::Plus | InfixOperator::Minus | InfixOperator::Multiply, Int, Int) => {
+                let (v, overflows) = match op {
+                    InfixOperator::Plus => ins.sadd_overflow(l, r),
+                    InfixOperator::Minus => ins.ssub_overflow(l, r),
+                    _ => ins.smul_overflow(l, r),
+                };
+                self.bail_if(overflows);
+                Typed::Value(v, Int)
+            }
+            (InfixOperator::Divide | InfixOperator::Modulo, Int, Int) => {
+                let by_zero = ins.icmp_imm(IntCC::Equal, r, 0);
+                let min = self.builder.ins().icmp_imm(IntCC::Equal, l, i64::MIN);
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, r, -1);
+                let overflows = self.builder.ins().band(min, minus_one);
+                let fails = self.builder.ins().bor(by_zero, overflows);
+                self.bail_if(fails);
+                let v = match op {
+                    InfixOperator::Divide => self.builder.ins().sdiv(l, r),
+                    _ => self.builder.ins().srem(l, r),
+                };
+                Typed::Value(v, Int)
+            }
+            // NOTE: there is no float remainder in cranelift
+            (InfixOperator::Modulo, _, _) => return Err(Unsupported),
+            // NOTE: strict mode rejects mixed arithmetic, the interpreter reports it
+            (_, Int, Float) | (_, Float, Int) if self.jit.strict && op.is_arithmetic() => {
+                return Err(Unsupported);
+            }
+            (
+                InfixOperator::Plus
+                | InfixOperator::Minus
+                | InfixOperator::Multiply
+                | InfixOperator::Divide,
+                Int | Float,
+                Int | Float,
+            ) => {
+                let l = self.as_float(l, ltype);
+                let r = self.as_float(r, rtype);
+                let ins = self.builder.ins();
+                let v = match op {
+                    InfixOperator::Plus => ins.fadd(l, r),
+                    InfixOperator::Minus => ins.fsub(l, r),
+                    InfixOperator::Multiply => ins.fmul(l, r),
+                    _ => ins.fdiv(l, r),
+                };
+                Typed::Value(v, Float)
+            }
+            (InfixOperator::And, Bool, Bool) => Typed::Value(ins.band(l, r), Bool),
+            (InfixOperator::Or, Bool, Bool) => Typed::Value(ins.bor(l, r), Bool),
+            (InfixOperator::Equals | InfixOperator::NotEquals, _, _) if ltype == rtype => {
+                let equals = self.equals(l, r, ltype);
+                match op {
+                    InfixOperator::Equals => Typed::Value(equals, Bool),
+                    _ => Typed::Value(self.builder.ins().bxor_imm(equals, 1), Bool),
+                }
+            }
+            (
+                InfixOperator::LessThan
+                | InfixOperator::LessThanEquals
+                | InfixOperator::GreaterThan
+                | InfixOperator::GreaterThanEquals,
+                Int,
+                Int,
+            ) => {
+                let cc = match op {
+                    InfixOperator::LessThan => IntCC::SignedLessThan,
+                    InfixOperator::LessThanEquals => IntCC::SignedLessThanOrEqual,
+                    InfixOperator::GreaterThan => IntCC::SignedGreaterThan,
+                    _ => IntCC::SignedGreaterThanOrEqual,
+                };
+                Typed::Value(ins.icmp(cc, l, r), Bool)
+            }
+            (
+                InfixOperator::LessThan
+                | InfixOperator::LessThanEquals
+                | InfixOperator::GreaterThan
+                | InfixOperator::GreaterThanEquals,
+                Int | Float,
+                Int | Float,
+            ) => Typed::Value(self.compare_floats(op, (l, ltype), (r, rtype)), Bool),
+            _ => return Err(Unsupported),
+        };
+
+        Ok(typed)
+    }
+
+    fn as_float(&mut self, v: Value, type_of: JitType) -> Value {
+        match type_of {
+            JitType::Int => self.builder.ins().fcvt_from_sint(types::F64, v),
+            _ => v,
+        }
+    }
+
+    fn equals(&mut self, l: Value, r: Value, type_of: JitType) -> Value {
+        match type_of {
+            JitType::Float => self.builder.ins().fcmp(FloatCC::Equal, l, r),
+            _ => self.builder.ins().icmp(IntCC::Equal, l, r),
+        }
+    }
+
+    fn compare_floats(
+        &mut self,
+        op: &InfixOperator,
+        (l, ltype): (Value, JitType),
+        (r, rtype): (Value, JitType),
+    ) -> Value {
+        let l = self.as_float(l, ltype);
+        let r = self.as_float(r, rtype);
+        // NOTE: the ordered conditions are false for NaN, like the comparisons of the interpreter
+        let cc = match op {
+            InfixOperator::LessThan => FloatCC::LessThan,
+            InfixOperator::LessThanEquals => FloatCC::LessThanOrEqual,
+            InfixOperator::GreaterThan => FloatCC::GreaterThan,
+            _ => FloatCC::GreaterThanOrEqual,
+        };
+        self.builder.ins().fcmp(cc, l, r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Error, ErrorWithRange, Interpreter, InterpreterValue, Parser, Preprocessor, Resolver,
+        Stage, StageResult, Stages, run_stages,
+    };
+
+    fn interpreter(source: &str, jit: bool) -> Interpreter {
+        let stages = vec![
+            Stages::Parser(Parser::default()),
+            Stages::Preprocessor(Preprocessor::new().unwrap()),
+            Stages::Resolver(Resolver::new()),
+        ];
+        let preprocessed = run_stages(stages, StageResult::PreParse(source.to_owned())).unwrap();
+
+        let mut interpreter = Interpreter::new("main".to_owned());
+        if jit {
+            interpreter = interpreter.with_jit(2);
+        }
+        interpreter.init(preprocessed).unwrap();
+        interpreter
+    }
+
+    fn try_call(
+        interpreter: &mut Interpreter,
+        name: &str,
+        args: &[InterpreterValue],
+    ) -> Result<InterpreterValue, ErrorWithRange> {
+        let fn_type = interpreter
+            .get_current_scope()
+            .borrow()
+            .resolve_type(&name.to_owned())
+            .unwrap();
+        let params = args.iter().map(|a| (a.clone(), 0..1)).collect();
+        let result = interpreter.call_function_with_values(
+            &name.to_owned(),
+            params,
+            fn_type,
+            None,
+            &(0..1),
+        )?;
+        Ok(InterpreterValue::preprocess_single(result).unwrap())
+    }
+
+    fn call(
+        interpreter: &mut Interpreter,
+        name: &str,
+        args: &[InterpreterValue],
+    ) -> InterpreterValue {
+        try_call(interpreter, name, args).unwrap()
+    }
+
+    /// Calls the function repeatedly with and without the jit and returns both results
+    fn call_both(
+        source: &str,
+        name: &str,
+        args: Vec<InterpreterValue>,
+    ) -> (InterpreterValue, InterpreterValue, bool) {
+        let run = |jit: bool| {
+            let mut interpreter = interpreter(source, jit);
+            let mut result = InterpreterValue::Empty;
+            for _ in 0..3 {
+                result = call(&mut interpreter, name, &args);
+            }
+            let compiled = interpreter
+                .jit()
+                .is_some_and(|jit| jit.is_compiled(&name.to_owned()));
+            (result, compiled)
+        };
+
+        let (interpreted, _) = run(false);
+        let (jitted, compiled) = run(true);
+        (interpreted, jitted, compiled)
+    }
+
+    fn assert_same(source: &str, name: &str, args: Vec<InterpreterValue>, compiled: bool) {
+        let (interpreted, jitted, was_compiled) = call_both(source, name, args);
+        assert_eq!(interpreted.to_string(), jitted.to_string());
+        assert_eq!(
+            std::mem::discriminant(&interpreted),
+            std::mem::discriminant(&jitted)
+        );
+        assert_eq!(was_compiled, compiled);
+    }
+
+    #[test]
+    fn recursive_ints() {
+        let source = r#"
+            fn fib(n: int): int {
+                if (n < 2) {
+                    return n;
+                }
+                return fib(n - 1) + fib(n - 2);
+            }
+            "#;
+
+        assert_same(source, "fib", vec![InterpreterValue::Int(15)], true);
+    }
+
+    #[test]
+    fn loops_floats_and_bools() {
+        let source = r#"
+            fn sum(n: int, scale: float): float {
+                total := 0.0;
+                for (i := 0; i < n; i += 1) {
+                    if (i % 3 == 0) {
+                        continue;
+                    }
+                    if (i > 40) {
+                        break;
+                    }
+                    total += i * scale;
+                }
+                return total;
+            }
+
+            fn compare(a: int, b: int): bool => a <= b || a > b && a != b;
+
+            fn sign(a: float): int => if (a < 0) { 0 - 1 } else { 1 };
+
+            fn at_most(a: float, b: int): bool => a <= b && !(a > b) && -a >= -b;
+
+            fn count(n: int): int {
+                c := 0;
+                while (c < n) {
+                    c += 1;
+                }
+                c
+            }
+            "#;
+
+        let float = |f| InterpreterValue::Float(f);
+        assert_same(
+            source,
+            "sum",
+            vec![InterpreterValue::Int(100), float(0.5)],
+            true,
+        );
+        assert_same(
+            source,
+            "compare",
+            vec![InterpreterValue::Int(1), InterpreterValue::Int(1)],
+            true,
+        );
+        assert_same(
+            source,
+            "compare",
+            vec![InterpreterValue::Int(1), InterpreterValue::Int(2)],
+            true,
+        );
+        assert_same(source, "sign", vec![float(-2.5)], true);
+        for a in [1.0, 0.5, 2.5, f64::NAN] {
+            assert_same(
+                source,
+                "at_most",
+                vec![float(a), InterpreterValue::Int(1)],
+                true,
+            );
+        }
+        assert_same(source, "count", vec![InterpreterValue::Int(10)], true);
+    }
+
+    #[test]
+    fn unsupported_functions_stay_interpreted() {
+        let source = r#"
+            fn first(list: [int]): int {
+                for (a in list) {
+                    return a;
+                }
+                return 0;
+            }
+
+            fn greet(a: int): int {
+                println("calls are not compiled");
+                return a;
+            }
+            "#;
+
+        let list = InterpreterValue::List(vec![InterpreterValue::Int(3)]);
+        assert_same(source, "first", vec![list], false);
+        assert_same(source, "greet", vec![InterpreterValue::Int(1)], false);
+    }
+
+    #[test]
+    fn failing_operations_fall_back() {
+        let source = r#"
+            fn div(a: int, b: int): int => a / b;
+            fn add(a: int, b: int): int => a + b;
+            "#;
+        let mut interpreter = interpreter(source, true);
+        let int = InterpreterValue::Int;
+
+        for _ in 0..3 {
+            assert_eq!(
+                call(&mut interpreter, "div", &[int(7), int(2)]).to_string(),
+                "3"
+            );
+            assert_eq!(
+                call(&mut interpreter, "add", &[int(7), int(2)]).to_string(),
+                "9"
+            );
+        }
+        assert!(interpreter.jit().unwrap().is_compiled(&"div".to_owned()));
+        assert!(interpreter.jit().unwrap().is_compiled(&"add".to_owned()));
+
+        // the compiled code bails out, the interpreter reports the error
+        let err = try_call(&mut interpreter, "div", &[int(1), int(0)]).unwrap_err();
+        assert!(matches!(err.err, Error::DivisionByZero));
+        let err = try_call(&mut interpreter, "add", &[int(i64::MAX), int(1)]).unwrap_err();
+        assert!(matches!(err.err, Error::IntegerOverflow(_)));
+    }
+
+    #[test]
+    fn strict_mixed_arithmetic_is_interpreted() {
+        let source = r#"
+            fn scale(a: int, b: float): float => a * b;
+            "#;
+        let mut interpreter = interpreter(source, true).with_strict();
+        let args = [InterpreterValue::Int(2), InterpreterValue::Float(1.5)];
+
+        for _ in 0..3 {
+            let err = try_call(&mut interpreter, "scale", &args).unwrap_err();
+            assert!(matches!(err.err, Error::MixedTypes(..)));
+        }
+        assert!(!interpreter.jit().unwrap().is_compiled(&"scale".to_owned()));
+    }
+}
@@ -7,15 +7,58 @@ pub use parser::*;
 pub mod preprocessor;
 pub use preprocessor::*;
 
+pub mod cycle_detector;
+pub use cycle_detector::*;
+
+pub mod optimizer;
+pub use optimizer::*;
+
+pub mod resolver;
+pub use resolver::*;
+
 pub mod interpreter;
 pub use interpreter::*;
 
+pub mod compiler;
+pub use compiler::*;
+
+pub mod vm;
+pub use vm::*;
+
+pub mod jit;
+pub use jit::*;
+
 pub mod buildin;
 pub use buildin::*;
 
 #[cfg(test)]
 mod tests {
-    use crate::{BeautifyError, Preprocessor, Stage, StageResult, ast_grammar};
+    use std::{cell::RefCell, fs, path::PathBuf, rc::Rc};
+
+    use crate::{
+        BeautifyError, CycleDetector, Error, NativeBindings, NativeConstant, NativeConstantValue,
+        NativeFunction, NativeStruct, NativeType, Optimizer, Parser, Preprocessor, Severity,
+        SourceMap, Stage, StageResult, Stages, ast_grammar, hash_header, run_stages_collecting,
+    };
+    // NOTE: only the test of outdated bindings uses them, bindgen regenerates the bindings instead
+    #[cfg(not(feature = "bindgen"))]
+    use crate::{FileId, Note};
+
+    fn write_header(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join("native_bindings_tests");
+        fs::create_dir_all(&dir).unwrap();
+        let header = dir.join(name);
+        fs::write(&header, contents).unwrap();
+        let _ = fs::remove_file(NativeBindings::cache_path(&header));
+        header
+    }
+
+    fn preprocess(source: &str) -> Result<StageResult, crate::ErrorWithRange> {
+        let expr = ast_grammar::ProgrammParser::new().parse(source).unwrap();
+        let mut processor = Preprocessor::new().unwrap();
+        processor.init(StageResult::Parsing(expr))?;
+        processor.run()
+    }
 
     #[test]
     fn test_preprocessing() {
@@ -38,4 +81,346 @@ mod tests {
             processor.run().unwrap();
         }
     }
+
+    #[test]
+    fn test_native_import_from_cache() {
+        let contents = "typedef struct { int a; } Name;\nint add_one(int a);\n";
+        let header = write_header("cached.h", contents);
+
+        let mut bindings =
+            NativeBindings::new(header.display().to_string(), hash_header(contents.as_bytes()));
+        bindings.structs.push(NativeStruct {
+            name: "Name".to_owned(),
+            fields: vec![("a".to_owned(), NativeType::Int)],
+        });
+        bindings.functions.push(NativeFunction {
+            name: "add_one".to_owned(),
+            params: vec![("a".to_owned(), NativeType::Int)],
+            return_type: NativeType::Int,
+        });
+        bindings.constants.push(NativeConstant {
+            name: "WIDTH".to_owned(),
+            value: NativeConstantValue::Int(800),
+        });
+        bindings.save(&NativeBindings::cache_path(&header)).unwrap();
+
+        let source = format!(
+            r#"import native "{}" "liblib.so" as lib;"#,
+            header.display()
+        );
+        let Ok(StageResult::Preprocessor(scope, _)) = preprocess(&source) else {
+            panic!("preprocessing with cached bindings must succeed");
+        };
+
+        assert!(scope.resolve_defined_type(&"lib.Name".to_owned()).is_some());
+        assert!(scope.resolve_type(&"lib.add_one".to_owned()).is_some());
+        assert!(scope.resolve_value(&"lib.WIDTH".to_owned()).is_some());
+    }
+
+    #[test]
+    fn test_native_import_next_to_the_script() {
+        let contents = "int twice(int a);\n";
+        let header = write_header("relative.h", contents);
+        let mut bindings =
+            NativeBindings::new("relative.h".to_owned(), hash_header(contents.as_bytes()));
+        bindings.functions.push(NativeFunction {
+            name: "twice".to_owned(),
+            params: vec![("a".to_owned(), NativeType::Int)],
+            return_type: NativeType::Int,
+        });
+        bindings.save(&NativeBindings::cache_path(&header)).unwrap();
+
+        // the header is found next to the script, not in the working directory
+        let source = r#"import native "relative.h" "liblib.so" as lib;"#;
+        let script = header.with_file_name("game.ecs").display().to_string();
+        let sources = Rc::new(RefCell::new(SourceMap::new(Some(script), source)));
+        let mut processor = Preprocessor::new().unwrap().with_sources(sources);
+        let ast = ast_grammar::ProgrammParser::new().parse(source).unwrap();
+        processor.init(StageResult::Parsing(ast)).unwrap();
+        let Ok(StageResult::Preprocessor(scope, _)) = processor.run() else {
+            panic!("the header next to the script must be imported");
+        };
+        assert!(scope.resolve_type(&"lib.twice".to_owned()).is_some());
+
+        assert!(preprocess(source).is_err());
+    }
+
+    #[test]
+    #[cfg(not(feature = "bindgen"))]
+    fn test_native_import_outdated_cache() {
+        let header = write_header("outdated.h", "int add_one(int a);\n");
+
+        NativeBindings::new(header.display().to_string(), hash_header(b"int old(void);\n"))
+            .save(&NativeBindings::cache_path(&header))
+            .unwrap();
+
+        let source = format!(r#"import native "{}" "liblib.so";"#, header.display());
+        let result = preprocess(&source);
+
+        assert!(matches!(
+            result.map(|_| ()).unwrap_err().err,
+            Error::NativeBindingsOutdated(_)
+        ));
+
+        // with a source map, the error notes the header
+        let sources = Rc::new(RefCell::new(SourceMap::new(None, source.clone())));
+        let mut processor = Preprocessor::new()
+            .unwrap()
+            .with_sources(Rc::clone(&sources));
+        let ast = ast_grammar::ProgrammParser::new().parse(&source).unwrap();
+        processor.init(StageResult::Parsing(ast)).unwrap();
+        let Err(err) = processor.run() else {
+            panic!("outdated bindings must be rejected");
+        };
+
+        let sources = sources.borrow();
+        assert_eq!(err.file, FileId::MAIN);
+        let [Note::Label(span, _)] = &*err.notes else {
+            panic!("expected a note in the header, got {:?}", err.notes);
+        };
+        let file = sources.file(span.file);
+        assert_eq!(file.path, Some(header.display().to_string()));
+        assert_eq!(&file.contents[span.range.clone()], "int add_one(int a);");
+        err.print_error_in(&sources);
+    }
+
+    #[test]
+    fn test_loop_control_outside_loop() {
+        let Err(err) = preprocess("fn main() { if (true) { break; } }") else {
+            panic!("break outside of a loop must be rejected");
+        };
+        assert!(matches!(err.err, Error::LoopControlOutsideLoop(_)));
+
+        // closures can't continue the loop they are created in
+        let Err(err) =
+            preprocess("fn main() { while (true) { f := fn () { continue; }; } }")
+        else {
+            panic!("continue inside a closure must be rejected");
+        };
+        assert!(matches!(err.err, Error::LoopControlOutsideLoop(_)));
+
+        let Err(err) = preprocess("fn main() { outer: while (true) { break inner; } }") else {
+            panic!("unknown labels must be rejected");
+        };
+        assert!(matches!(err.err, Error::UnknownLoopLabel(_)));
+
+        preprocess("fn main() { outer: while (true) { for (a in [1]) { break outer; } } }")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_non_exhaustive_match() {
+        let Err(err) = preprocess("fn main() { match (a) { some(x) => x, } }") else {
+            panic!("matches missing none must be rejected");
+        };
+        assert!(matches!(err.err, Error::NonExhaustiveMatch(ref missing) if missing == "none"));
+
+        let Err(err) = preprocess("fn main() { match (a) { true => 1, b if b => 2, } }") else {
+            panic!("guarded arms must not count towards exhaustiveness");
+        };
+        assert!(matches!(err.err, Error::NonExhaustiveMatch(ref missing) if missing == "false"));
+
+        let Err(err) = preprocess("fn main() { match (a) { ok(1) => 1, err(_) => 2, } }") else {
+            panic!("matches missing ok(_) must be rejected");
+        };
+        assert!(matches!(err.err, Error::NonExhaustiveMatch(ref missing) if missing == "ok(_)"));
+
+        preprocess("fn main() { match (a) { some(x) => x, none => 0, } }").unwrap();
+
+        // struct patterns only match all values of the struct they name
+        let structs = "struct Point { x: int, y: int, } struct Line { from: Point, to: Point, }";
+        for body in [
+            "fn f(p: Line) { match (p) { Point { x, y } => x, } }",
+            "fn f() { match (g()) { Point { x, y } => x, } }",
+            "fn f(p: int) { p := Line { from: 1, to: 2, }; match (p) { Point { x } => x, } }",
+        ] {
+            let Err(err) = preprocess(&format!("{structs} {body}")) else {
+                panic!("{body} must not be exhaustive");
+            };
+            assert!(matches!(err.err, Error::NonExhaustiveMatch(ref missing) if missing == "_"));
+        }
+        for body in [
+            "fn f(p: Point) { match (p) { Point { x, y: b } => x, } }",
+            "fn f(l: Line?) { match (l) { some(Line { from: Point { x } }) => x, none => 0, } }",
+            "fn f() { p := Point { x: 1, y: 2, }; match (p) { Point { x } => x, } }",
+        ] {
+            preprocess(&format!("{structs} {body}")).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_if_value() {
+        let Err(err) = preprocess("fn main() { c := if (true) { 1 }; }") else {
+            panic!("if values without an else must be rejected");
+        };
+        assert!(matches!(err.err, Error::MissingElse));
+
+        let Err(err) =
+            preprocess(r#"fn main() { c := if (true) { 1 } else if (false) { 2 } else { "3" }; }"#)
+        else {
+            panic!("branches of different types must be rejected");
+        };
+        assert!(matches!(err.err, Error::BranchTypeMismatch(_, _)));
+
+        let Err(err) = preprocess("fn f(): int { if (true) { 1 } else { false } }") else {
+            panic!("implicitly returned branches must agree on their type");
+        };
+        assert!(matches!(err.err, Error::BranchTypeMismatch(_, _)));
+
+        // variables have their declared types, calls the return types of their functions
+        for source in [
+            r#"fn f(c: bool, a: int) { b := if (c) { a } else { "x" }; }"#,
+            r#"fn f(c: bool) { a := 1; b := if (c) { a } else { "x" }; }"#,
+            r#"fn f(c: bool) { b := if (c) { a := 1; a } else { "x" }; }"#,
+            r#"fn g(): string { "x" } fn f(c: bool) { b := if (c) { 1 } else { g() }; }"#,
+            r#"fn f(c: bool) { g := fn (): int { 1 }; b := if (c) { g() } else { "x" }; }"#,
+        ] {
+            let Err(err) = preprocess(source) else {
+                panic!("{source} has branches of different types");
+            };
+            assert!(matches!(err.err, Error::BranchTypeMismatch(_, _)), "{source}");
+        }
+
+        // statements don't produce a value
+        preprocess("fn main() { if (true) { 1 } }").unwrap();
+        // unknown types agree with any type, declarations in a branch shadow the variables
+        preprocess("fn main() { c := if (true) { a } else { 2 }; }").unwrap();
+        preprocess(r#"fn f(a: int) { b := if (true) { a := "x"; a } else { "y" }; }"#).unwrap();
+        preprocess("fn g(): int { 1 } fn main() { b := if (true) { g() } else { 2 }; }").unwrap();
+    }
+
+    #[test]
+    fn test_weak_not_upgraded() {
+        let Err(err) = preprocess("fn main() { a := 1; w := weak a; b := w + 1; }") else {
+            panic!("weak references must be upgraded before they are used in operations");
+        };
+        assert!(matches!(err.err, Error::WeakNotUpgraded(ref name) if name == "w"));
+
+        let Err(err) = preprocess("fn f(w: weak T) { w.count(); }") else {
+            panic!("members of weak references can't be accessed without upgrading");
+        };
+        assert!(matches!(err.err, Error::WeakNotUpgraded(_)));
+
+        let Err(err) = preprocess("fn f(w: weak bool) { g := fn () { if (w) {} }; }") else {
+            panic!("closures capture weak variables");
+        };
+        assert!(matches!(err.err, Error::WeakNotUpgraded(_)));
+
+        preprocess("fn f(w: weak int): int? { w.upgrade() }").unwrap();
+        // shadowed by a strong declaration
+        preprocess("fn main() { a := 1; w := weak a; w := 2; b := w + 1; }").unwrap();
+
+        // passed on as a strong value
+        for source in [
+            "fn f(x: int): int { x } fn main() { a := 1; w := weak a; f(w); }",
+            "fn f(w: weak int): int { return w; }",
+            "fn f(w: weak int): int { w }",
+            "fn f(w: weak int) { let b: int = w; }",
+            "fn f(w: weak int) { b := 1; b = w; }",
+            "fn f(w: weak int) { b := w; c := b + 1; }",
+            "fn f(w: weak int) { g := fn (x: int) {}; g(w); }",
+        ] {
+            let Err(err) = preprocess(source) else {
+                panic!("{source} passes on a weak reference without upgrading it");
+            };
+            assert!(matches!(err.err, Error::WeakNotUpgraded(_)), "{source}");
+        }
+
+        // weak fields of structs are weak references, too
+        let holder = "struct Node { value: int, } struct Holder { target: weak Node, }";
+        for body in [
+            "println(h.target.value);",
+            "v := h.target.value + 1;",
+            "let n: Node = h.target;",
+            "t := h.target; v := t.value;",
+        ] {
+            let source = format!("{holder} fn f(h: Holder) {{ {body} }}");
+            let Err(err) = preprocess(&source) else {
+                panic!("{body} reads through a weak field without upgrading it");
+            };
+            assert!(matches!(err.err, Error::WeakNotUpgraded(_)), "{body}");
+        }
+        preprocess(&format!("{holder} fn f(h: Holder): Node? {{ h.target.upgrade() }}")).unwrap();
+
+        // weak targets and params of any type take weak references
+        preprocess("fn f(x: weak int) {} fn main() { a := 1; w := weak a; f(w); }").unwrap();
+        preprocess("fn f(w: weak int) { println(w); let b: weak int = w; b = w; }").unwrap();
+        preprocess("fn f(w: weak int) { g := fn (x: weak int) {}; g(w); }").unwrap();
+    }
+
+    #[test]
+    fn test_strict_mixed_types() {
+        let strict = |source: &str| {
+            let expr = ast_grammar::ProgrammParser::new().parse(source).unwrap();
+            let mut processor = Preprocessor::new().unwrap().with_strict();
+            processor.init(StageResult::Parsing(expr))?;
+            processor.run()
+        };
+
+        let failing = [
+            ("x := 1 + 2.5;", "1 + 2.5"),
+            ("a := 1.5; b := 2 * a;", "2 * a"),
+            ("a := 1; a += 0.5;", "0.5"),
+            ("a := 1 + 2; b := a / 0.5;", "a / 0.5"),
+        ];
+        for (body, at) in failing {
+            let source = format!("fn main() {{ {body} }}");
+            preprocess(&source).unwrap();
+            let Err(err) = strict(&source) else {
+                panic!("{body} must be rejected in strict mode");
+            };
+            assert!(matches!(err.err, Error::MixedTypes(..)), "{body}");
+            assert_eq!(&source[err.range.clone()], at);
+        }
+
+        strict("fn main() { a := 1; b := float(a) + 1.5; c := a + int(1.5); d := b * 2.0; }")
+            .unwrap();
+        strict("fn f(a: int, b: float): float { float(a) * b }").unwrap();
+        assert!(strict("fn f(a: int, b: float): float { a * b }").is_err());
+    }
+
+    fn check(source: &str) -> (bool, Vec<Severity>) {
+        let stages = vec![
+            Stages::Parser(Parser::default()),
+            Stages::Preprocessor(Preprocessor::new().unwrap()),
+            Stages::CycleDetector(CycleDetector::new()),
+            Stages::Optimizer(Optimizer::new()),
+        ];
+        let mut diagnostics = Vec::new();
+        let result =
+            run_stages_collecting(stages, StageResult::PreParse(source.to_owned()), &mut diagnostics);
+        let severities = diagnostics.iter().map(|d| d.severity).collect();
+        (result.is_some(), severities)
+    }
+
+    #[test]
+    fn test_collects_diagnostics() {
+        // every syntax error, with the expected tokens as note. A single expected token is a fix instead
+        let (ran, severities) = check("fn main() { a := ; b := 1 +; }\nstruct A { a int }");
+        assert!(!ran);
+        assert_eq!(
+            severities,
+            [
+                Severity::Error,
+                Severity::Note,
+                Severity::Error,
+                Severity::Note,
+                Severity::Error,
+            ]
+        );
+
+        // warnings don't stop the stages
+        let (ran, severities) = check("fn main(): int { return 1; b := 2; }");
+        assert!(ran);
+        assert_eq!(severities, [Severity::Warning]);
+
+        let (ran, severities) = check("fn main() { break; }");
+        assert!(!ran);
+        assert_eq!(severities, [Severity::Error]);
+
+        // every definition is checked, not only the ones before the first error
+        let (ran, severities) = check("fn main() { break; }\nfn f() { continue; }\nfn f() {}");
+        assert!(!ran);
+        assert_eq!(severities, [Severity::Error; 3]);
+    }
 }
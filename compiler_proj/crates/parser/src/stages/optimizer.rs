@@ -0,0 +1,911 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::{
+    AstNode, AstNodeType, Error, ErrorWithRange, FunctionExecutionStrategy, FunctionType,
+    InfixOperator, InterpreterValue, MemberAccessType, PrefixOperator, Scope, Stage, StageResult,
+    Symbol, SystemExecutionStrategy, TypeSymbol, TypeSymbolType, Warning, WarningWithRange,
+    Warnings,
+};
+
+/// Folds constant expressions, simplifies branches with constant conditions and removes code without effect.
+/// The function bodies in the global scope are optimized as well, as they are the ones that are executed.
+/// Unused declarations are only removed, when it runs again after the resolver reported the errors in them
+#[derive(Default)]
+pub struct Optimizer {
+    ast: Vec<AstNode>,
+    global_scope: Scope,
+    warnings: Warnings,
+    /// The program was resolved, its slots must stay valid
+    resolved: bool,
+}
+
+impl Optimizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The warnings found by this stage, available after it ran
+    pub fn warnings(&self) -> Warnings {
+        Rc::clone(&self.warnings)
+    }
+}
+
+impl Stage for Optimizer {
+    fn init(&mut self, prev_stage_result: StageResult) -> Result<(), ErrorWithRange> {
+        match prev_stage_result {
+            StageResult::Preprocessor(global_scope, ast) => {
+                self.global_scope = global_scope;
+                self.ast = ast;
+                Ok(())
+            }
+            StageResult::Resolved(global_scope, ast) => {
+                self.global_scope = global_scope;
+                self.ast = ast;
+                self.resolved = true;
+                Ok(())
+            }
+            _ => Err(ErrorWithRange::new(
+                Error::StageError(2, prev_stage_result.into()),
+                0..1,
+            )),
+        }
+    }
+
+    fn shared_warnings(&self) -> Option<Warnings> {
+        Some(self.warnings())
+    }
+
+    fn run(mut self) -> Result<StageResult, ErrorWithRange> {
+        let usage = Usage::of(&self.ast, &self.global_scope, self.resolved);
+
+        // NOTE: the preprocessor moved all definitions into the global scope, their bodies are the ones executed
+        let mut warnings = Vec::new();
+        let ast = self
+            .ast
+            .into_iter()
+            .map(|node| optimize_node(node, &usage, &mut warnings))
+            .collect();
+        for (_, type_of) in self.global_scope.iter_types_mut() {
+            optimize_type(type_of, &usage, &mut warnings);
+        }
+        for (_, type_of) in self.global_scope.iter_defined_types_mut() {
+            optimize_type(type_of, &usage, &mut warnings);
+        }
+
+        warnings.sort_by_key(|w| w.range.start);
+        self.warnings.borrow_mut().extend(warnings);
+        if self.resolved {
+            Ok(StageResult::Resolved(self.global_scope, ast))
+        } else {
+            Ok(StageResult::Preprocessor(self.global_scope, ast))
+        }
+    }
+}
+
+/// Statements of a body, as they are stored in the ast
+type Block = Vec<Box<AstNode>>;
+
+/// How often symbols are declared and whether they are mentioned anywhere else in the program.
+/// NOTE: symbols are counted by name, a local counts as used if any body mentions its name
+#[derive(Default)]
+struct Usage {
+    declared: HashMap<Symbol, usize>,
+    used: HashSet<Symbol>,
+    /// Declarations can be removed, the resolver already reported the errors in them.
+    /// Branches are not inlined anymore, the slots count the scopes between a use and its declaration
+    resolved: bool,
+}
+
+impl Usage {
+    fn of(ast: &[AstNode], global_scope: &Scope, resolved: bool) -> Self {
+        let mut usage = Self {
+            resolved,
+            ..Self::default()
+        };
+        for node in ast {
+            usage.visit(node);
+        }
+        for (_, type_of) in global_scope
+            .iter_types()
+            .chain(global_scope.iter_defined_types())
+        {
+            match &type_of.type_of {
+                TypeSymbolType::Function(fn_type) => usage.visit_function(fn_type),
+                TypeSymbolType::System(system) => {
+                    if let SystemExecutionStrategy::Interpreted(body) = &system.execution_body {
+                        usage.visit_all(body);
+                    }
+                }
+                TypeSymbolType::Struct(struct_type) => {
+                    for (_, method) in struct_type.methods.iter().chain(&struct_type.statics) {
+                        usage.visit_function(method);
+                    }
+                }
+                _ => (),
+            }
+        }
+        usage
+    }
+
+    /// Params can't be redeclared in the body, so they count as used
+    fn visit_function(&mut self, fn_type: &FunctionType) {
+        self.used
+            .extend(fn_type.params.iter().map(|(name, _)| name.clone()));
+        if let FunctionExecutionStrategy::Interpreted(body) = &fn_type.execution_body {
+            self.visit_all(body);
+        }
+    }
+
+    /// Whether the declaration can be removed without changing the program or hiding its errors
+    fn is_unused(&self, name: &Symbol) -> bool {
+        self.resolved && !self.used.contains(name) && self.declared.get(name) == Some(&1)
+    }
+
+    fn visit_all(&mut self, nodes: &[Box<AstNode>]) {
+        for node in nodes {
+            self.visit(node);
+        }
+    }
+
+    fn visit(&mut self, node: &AstNode) {
+        match &node.type_of {
+            AstNodeType::List(items) | AstNodeType::Interpolation(items) => self.visit_all(items),
+            AstNodeType::Map(entries) => {
+                for (key, value) in entries {
+                    self.visit(key);
+                    self.visit(value);
+                }
+            }
+            AstNodeType::Option(Some(inner))
+            | AstNodeType::Result(Ok(inner))
+            | AstNodeType::Result(Err(inner))
+            | AstNodeType::PrefixCall(_, inner)
+            | AstNodeType::Weak(inner)
+            | AstNodeType::Propagate(inner)
+            | AstNodeType::ExpressionStatement(inner)
+            | AstNodeType::ReturnStatement {
+                return_value: inner,
+            } => self.visit(inner),
+            AstNodeType::Declaration {
+                new_symbol,
+                expression,
+                ..
+            } => {
+                *self.declared.entry(new_symbol.clone()).or_default() += 1;
+                self.visit(expression);
+            }
+            AstNodeType::EntityDeclaration { new_symbol } => {
+                self.used.insert(new_symbol.clone());
+            }
+            AstNodeType::AssignmentOp {
+                recipient,
+                expression,
+                ..
+            } => {
+                self.used.insert(recipient.clone());
+                self.visit(expression);
+            }
+            AstNodeType::TypeDef { execution_body, .. } => self.visit_all(execution_body),
+            AstNodeType::InfixCall(left, _, right) => {
+                self.visit(left);
+                self.visit(right);
+            }
+            AstNodeType::MemberCall { calls } => {
+                for call in calls {
+                    self.used.insert(call.member.clone());
+                    match &call.type_of {
+                        MemberAccessType::Symbol => (),
+                        MemberAccessType::Function(params) => self.visit_all(params),
+                        MemberAccessType::Struct(fields) => {
+                            for (_, value) in fields {
+                                self.visit(value);
+                            }
+                        }
+                    }
+                }
+            }
+            AstNodeType::Branch {
+                cond,
+                body,
+                else_if_branches,
+                else_branch,
+            } => {
+                self.visit(cond);
+                self.visit_all(body);
+                for (cond, body) in else_if_branches {
+                    self.visit(cond);
+                    self.visit_all(body);
+                }
+                if let Some(body) = else_branch {
+                    self.visit_all(body);
+                }
+            }
+            AstNodeType::While { cond, body, .. } => {
+                self.visit(cond);
+                self.visit_all(body);
+            }
+            AstNodeType::ForEach {
+                recipient,
+                iterable,
+                body,
+                ..
+            } => {
+                self.used.insert(recipient.clone());
+                self.visit(iterable);
+                self.visit_all(body);
+            }
+            AstNodeType::For {
+                declaration,
+                condition,
+                assignment,
+                body,
+                ..
+            } => {
+                for node in [declaration, condition, assignment].into_iter().flatten() {
+                    self.visit(node);
+                }
+                self.visit_all(body);
+            }
+            AstNodeType::EntityDef {
+                default_components: Some(components),
+                ..
+            } => {
+                for component in components {
+                    self.visit(component);
+                }
+            }
+            AstNodeType::Closure {
+                params,
+                execution_body,
+                ..
+            } => {
+                self.used
+                    .extend(params.iter().map(|(name, _)| name.clone()));
+                self.visit_all(execution_body);
+            }
+            AstNodeType::Match { value, arms } => {
+                self.visit(value);
+                for arm in arms {
+                    if let Some(guard) = &arm.guard {
+                        self.visit(guard);
+                    }
+                    self.visit_all(&arm.body);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn optimize_type(type_of: &mut TypeSymbol, usage: &Usage, warnings: &mut Vec<WarningWithRange>) {
+    match &mut type_of.type_of {
+        TypeSymbolType::Function(fn_type) => optimize_function(fn_type, usage, warnings),
+        TypeSymbolType::System(system) => {
+            if let SystemExecutionStrategy::Interpreted(body) = &mut system.execution_body {
+                *body = optimize_block(std::mem::take(body), usage, warnings);
+            }
+        }
+        TypeSymbolType::Struct(struct_type) => {
+            for (_, method) in struct_type
+                .methods
+                .iter_mut()
+                .chain(struct_type.statics.iter_mut())
+            {
+                optimize_function(method, usage, warnings);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn optimize_function(
+    fn_type: &mut FunctionType,
+    usage: &Usage,
+    warnings: &mut Vec<WarningWithRange>,
+) {
+    if let FunctionExecutionStrategy::Interpreted(body) = &mut fn_type.execution_body {
+        *body = optimize_block(std::mem::take(body), usage, warnings);
+    }
+}
+
+/// What becomes of a statement of a block
+enum Statement {
+    Keep(Box<AstNode>),
+    /// The statements of a branch, that is always taken, are inlined
+    Inline(Block),
+    Remove,
+}
+
+/// Optimizes the statements of a block and drops everything after a return, break or continue
+fn optimize_block(nodes: Block, usage: &Usage, warnings: &mut Vec<WarningWithRange>) -> Block {
+    let count = nodes.len();
+    let mut optimized: Block = Vec::new();
+    let mut nodes = nodes.into_iter().enumerate();
+
+    while let Some((i, node)) = nodes.next() {
+        let node = optimize_node(*node, usage, warnings);
+        // NOTE: the last statement is the value of the block, even if it has no effect
+        match simplify_statement(node, i + 1 == count, usage) {
+            Statement::Keep(node) => optimized.push(node),
+            Statement::Inline(body) => optimized.extend(body),
+            Statement::Remove => (),
+        }
+
+        if optimized.last().is_some_and(|node| leaves_block(node)) {
+            let unreachable = nodes.map(|(_, node)| node).collect::<Vec<_>>();
+            if let (Some(first), Some(last)) = (unreachable.first(), unreachable.last()) {
+                warnings.push(WarningWithRange {
+                    warning: Warning::UnreachableCode,
+                    range: first.range.start..last.range.end,
+                });
+            }
+            break;
+        }
+    }
+
+    optimized
+}
+
+fn leaves_block(node: &AstNode) -> bool {
+    matches!(
+        node.type_of,
+        AstNodeType::ReturnStatement { .. } | AstNodeType::Break(_) | AstNodeType::Continue(_)
+    )
+}
+
+fn simplify_statement(node: AstNode, is_last: bool, usage: &Usage) -> Statement {
+    match &node.type_of {
+        AstNodeType::Branch {
+            cond,
+            body,
+            else_if_branches,
+            else_branch: None,
+        } if matches!(cond.type_of, AstNodeType::Bool(true)) && else_if_branches.is_empty() => {
+            if body.is_empty() && !is_last {
+                return Statement::Remove;
+            }
+            // NOTE: declarations of the branch would be visible in the block
+            let declares = body.iter().any(|node| {
+                matches!(
+                    node.type_of,
+                    AstNodeType::Declaration { .. } | AstNodeType::EntityDeclaration { .. }
+                )
+            });
+            if body.is_empty() || declares || usage.resolved {
+                return Statement::Keep(Box::new(node));
+            }
+
+            let AstNodeType::Branch { body, .. } = node.type_of else {
+                unreachable!("matched above")
+            };
+            Statement::Inline(body)
+        }
+        AstNodeType::While { cond, .. }
+            if matches!(cond.type_of, AstNodeType::Bool(false)) && !is_last =>
+        {
+            Statement::Remove
+        }
+        AstNodeType::Declaration {
+            new_symbol,
+            expression,
+            assumed_type: None,
+            ..
+        } if !is_last && usage.is_unused(new_symbol) && is_pure(expression) => Statement::Remove,
+        _ => Statement::Keep(Box::new(node)),
+    }
+}
+
+/// Evaluating the expression can neither fail nor have side effects
+fn is_pure(node: &AstNode) -> bool {
+    match &node.type_of {
+        AstNodeType::Int(_)
+        | AstNodeType::Float(_)
+        | AstNodeType::String(_)
+        | AstNodeType::Bool(_)
+        | AstNodeType::Closure { .. }
+        | AstNodeType::Option(None) => true,
+        AstNodeType::List(items) => items.iter().all(|item| is_pure(item)),
+        AstNodeType::Map(entries) => entries
+            .iter()
+            .all(|(key, value)| is_pure(key) && is_pure(value)),
+        AstNodeType::Option(Some(inner))
+        | AstNodeType::Result(Ok(inner))
+        | AstNodeType::Result(Err(inner)) => is_pure(inner),
+        _ => false,
+    }
+}
+
+fn optimize_all(nodes: Block, usage: &Usage, warnings: &mut Vec<WarningWithRange>) -> Block {
+    nodes
+        .into_iter()
+        .map(|node| Box::new(optimize_node(*node, usage, warnings)))
+        .collect()
+}
+
+fn optimize_boxed(
+    node: Box<AstNode>,
+    usage: &Usage,
+    warnings: &mut Vec<WarningWithRange>,
+) -> Box<AstNode> {
+    Box::new(optimize_node(*node, usage, warnings))
+}
+
+fn optimize_node(node: AstNode, usage: &Usage, warnings: &mut Vec<WarningWithRange>) -> AstNode {
+    let range = node.range;
+    let type_of = match node.type_of {
+        AstNodeType::List(items) => AstNodeType::List(optimize_all(items, usage, warnings)),
+        AstNodeType::Interpolation(parts) => {
+            let parts = optimize_all(parts, usage, warnings);
+            fold_interpolation(&parts).unwrap_or(AstNodeType::Interpolation(parts))
+        }
+        AstNodeType::Map(entries) => AstNodeType::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| {
+                    (
+                        optimize_boxed(key, usage, warnings),
+                        optimize_boxed(value, usage, warnings),
+                    )
+                })
+                .collect(),
+        ),
+        AstNodeType::Option(inner) => {
+            AstNodeType::Option(inner.map(|inner| optimize_boxed(inner, usage, warnings)))
+        }
+        AstNodeType::Result(inner) => AstNodeType::Result(match inner {
+            Ok(inner) => Ok(optimize_boxed(inner, usage, warnings)),
+            Err(inner) => Err(optimize_boxed(inner, usage, warnings)),
+        }),
+        AstNodeType::Declaration {
+            new_symbol,
+            expression,
+            assumed_type,
+            slot,
+        } => AstNodeType::Declaration {
+            new_symbol,
+            expression: optimize_boxed(expression, usage, warnings),
+            assumed_type,
+            slot,
+        },
+        AstNodeType::AssignmentOp {
+            recipient,
+            operation,
+            expression,
+            slot,
+        } => AstNodeType::AssignmentOp {
+            recipient,
+            operation,
+            expression: optimize_boxed(expression, usage, warnings),
+            slot,
+        },
+        AstNodeType::TypeDef {
+            typename,
+            typedef,
+            execution_body,
+        } => AstNodeType::TypeDef {
+            typename,
+            typedef,
+            execution_body: optimize_block(execution_body, usage, warnings),
+        },
+        AstNodeType::InfixCall(left, op, right) => {
+            let left = optimize_boxed(left, usage, warnings);
+            let right = optimize_boxed(right, usage, warnings);
+            match fold_infix(&left.type_of, &op, &right.type_of) {
+                Some(folded) => folded,
+                None => AstNodeType::InfixCall(left, op, right),
+            }
+        }
+        AstNodeType::PrefixCall(op, right) => {
+            let right = optimize_boxed(right, usage, warnings);
+            match fold_prefix(&op, &right.type_of) {
+                Some(folded) => folded,
+                None => AstNodeType::PrefixCall(op, right),
+            }
+        }
+        AstNodeType::MemberCall { calls } => AstNodeType::MemberCall {
+            calls: calls
+                .into_iter()
+                .map(|mut call| {
+                    call.type_of = match call.type_of {
+                        MemberAccessType::Symbol => MemberAccessType::Symbol,
+                        MemberAccessType::Function(params) => {
+                            MemberAccessType::Function(optimize_all(params, usage, warnings))
+                        }
+                        MemberAccessType::Struct(fields) => MemberAccessType::Struct(
+                            fields
+                                .into_iter()
+                                .map(|(name, value)| (name, optimize_boxed(value, usage, warnings)))
+                                .collect(),
+                        ),
+                    };
+                    call
+                })
+                .collect(),
+        },
+        AstNodeType::Branch {
+            cond,
+            body,
+            else_if_branches,
+            else_branch,
+        } => {
+            let cond = optimize_boxed(cond, usage, warnings);
+            let body = optimize_block(body, usage, warnings);
+            let else_if_branches = else_if_branches
+                .into_iter()
+                .map(|(cond, body)| {
+                    (
+                        optimize_boxed(cond, usage, warnings),
+                        optimize_block(body, usage, warnings),
+                    )
+                })
+                .collect();
+            let else_branch = else_branch.map(|body| optimize_block(body, usage, warnings));
+
+            match simplify_branch(cond, body, else_if_branches, else_branch) {
+                // NOTE: a single expression has the same value without the scope of the branch
+                Ok(mut body) if body.len() == 1 && is_expression(&body[0]) => {
+                    return *body.pop().expect("has one node");
+                }
+                Ok(body) => AstNodeType::Branch {
+                    cond: Box::new(AstNode::new(range.clone(), AstNodeType::Bool(true))),
+                    body,
+                    else_if_branches: Vec::new(),
+                    else_branch: None,
+                },
+                Err(branch) => *branch,
+            }
+        }
+        AstNodeType::While { label, cond, body } => AstNodeType::While {
+            label,
+            cond: optimize_boxed(cond, usage, warnings),
+            body: optimize_block(body, usage, warnings),
+        },
+        AstNodeType::ForEach {
+            label,
+            recipient,
+            iterable,
+            body,
+        } => AstNodeType::ForEach {
+            label,
+            recipient,
+            iterable: optimize_boxed(iterable, usage, warnings),
+            body: optimize_block(body, usage, warnings),
+        },
+        AstNodeType::For {
+            label,
+            declaration,
+            condition,
+            assignment,
+            body,
+        } => AstNodeType::For {
+            label,
+            declaration: declaration.map(|node| optimize_boxed(node, usage, warnings)),
+            condition: condition.map(|node| optimize_boxed(node, usage, warnings)),
+            assignment: assignment.map(|node| optimize_boxed(node, usage, warnings)),
+            body: optimize_block(body, usage, warnings),
+        },
+        AstNodeType::ReturnStatement { return_value } => AstNodeType::ReturnStatement {
+            return_value: optimize_boxed(return_value, usage, warnings),
+        },
+        AstNodeType::Weak(inner) => AstNodeType::Weak(optimize_boxed(inner, usage, warnings)),
+        AstNodeType::Closure {
+            params,
+            return_type,
+            execution_body,
+        } => AstNodeType::Closure {
+            params,
+            return_type,
+            execution_body: optimize_block(execution_body, usage, warnings),
+        },
+        AstNodeType::Match { value, arms } => AstNodeType::Match {
+            value: optimize_boxed(value, usage, warnings),
+            arms: arms
+                .into_iter()
+                .map(|mut arm| {
+                    arm.guard = arm
+                        .guard
+                        .map(|guard| optimize_boxed(guard, usage, warnings));
+                    arm.body = optimize_block(arm.body, usage, warnings);
+                    arm
+                })
+                .collect(),
+        },
+        AstNodeType::Propagate(inner) => {
+            AstNodeType::Propagate(optimize_boxed(inner, usage, warnings))
+        }
+        AstNodeType::ExpressionStatement(expression) => {
+            AstNodeType::ExpressionStatement(optimize_boxed(expression, usage, warnings))
+        }
+        other => other,
+    };
+
+    AstNode::new(range, type_of)
+}
+
+/// Drops branches, whose condition is always false. Returns the body that is always taken, if there is one
+fn simplify_branch(
+    cond: Box<AstNode>,
+    body: Block,
+    else_if_branches: Vec<(Box<AstNode>, Block)>,
+    mut else_branch: Option<Block>,
+) -> Result<Block, Box<AstNodeType>> {
+    let mut branches = Vec::new();
+    for (cond, body) in std::iter::once((cond, body)).chain(else_if_branches) {
+        match cond.type_of {
+            AstNodeType::Bool(false) => (),
+            // NOTE: the following branches can't be reached
+            AstNodeType::Bool(true) => {
+                else_branch = Some(body);
+                break;
+            }
+            _ => branches.push((cond, body)),
+        }
+    }
+
+    if branches.is_empty() {
+        return Ok(else_branch.unwrap_or_default());
+    }
+
+    let (cond, body) = branches.remove(0);
+    Err(Box::new(AstNodeType::Branch {
+        cond,
+        body,
+        else_if_branches: branches,
+        else_branch,
+    }))
+}
+
+/// Nodes, that produce a value and declare nothing
+fn is_expression(node: &AstNode) -> bool {
+    matches!(
+        node.type_of,
+        AstNodeType::Int(_)
+            | AstNodeType::Float(_)
+            | AstNodeType::String(_)
+            | AstNodeType::Interpolation(_)
+            | AstNodeType::Bool(_)
+            | AstNodeType::List(_)
+            | AstNodeType::Map(_)
+            | AstNodeType::Option(_)
+            | AstNodeType::Result(_)
+            | AstNodeType::InfixCall(_, _, _)
+            | AstNodeType::PrefixCall(_, _)
+            | AstNodeType::MemberCall { .. }
+            | AstNodeType::Branch { .. }
+            | AstNodeType::Weak(_)
+            | AstNodeType::Closure { .. }
+            | AstNodeType::Match { .. }
+            | AstNodeType::Propagate(_)
+    )
+}
+
+fn literal_value(node: &AstNodeType) -> Option<InterpreterValue> {
+    match node {
+        AstNodeType::Int(i) => Some(InterpreterValue::Int(*i)),
+        AstNodeType::Float(f) => Some(InterpreterValue::Float(*f)),
+        AstNodeType::String(s) => Some(InterpreterValue::String(s.clone())),
+        AstNodeType::Bool(b) => Some(InterpreterValue::Bool(*b)),
+        _ => None,
+    }
+}
+
+fn literal_node(value: InterpreterValue) -> Option<AstNodeType> {
+    match InterpreterValue::preprocess_single(value).ok()? {
+        InterpreterValue::Int(i) => Some(AstNodeType::Int(i)),
+        InterpreterValue::Float(f) => Some(AstNodeType::Float(f)),
+        InterpreterValue::String(s) => Some(AstNodeType::String(s)),
+        InterpreterValue::Bool(b) => Some(AstNodeType::Bool(b)),
+        _ => None,
+    }
+}
+
+/// Evaluates the operation with the interpreter, operations that fail at runtime are not folded
+fn fold_infix(left: &AstNodeType, op: &InfixOperator, right: &AstNodeType) -> Option<AstNodeType> {
+    let (lval, rval) = (literal_value(left)?, literal_value(right)?);
+
+    // NOTE: mixed arithmetic is kept, whether it fails depends on the mode it is executed in
+    let folded = lval.apply_infix(op, rval, true);
+
+    literal_node(folded.ok()?)
+}
+
+/// Interpolations of literals only are known before execution
+fn fold_interpolation(parts: &[Box<AstNode>]) -> Option<AstNodeType> {
+    let mut text = String::new();
+    for part in parts {
+        text.push_str(&literal_value(&part.type_of)?.to_text().ok()?);
+    }
+    Some(AstNodeType::String(text))
+}
+
+fn fold_prefix(op: &PrefixOperator, right: &AstNodeType) -> Option<AstNodeType> {
+    let rval = literal_value(right)?;
+
+    let folded = match op {
+        PrefixOperator::Not => rval.negate_bool(),
+        PrefixOperator::Negate => rval.negate_number(),
+    };
+
+    literal_node(folded.ok()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Block;
+    use crate::{
+        AstNode, AstNodeType, Error, FunctionExecutionStrategy, FunctionType, Optimizer, Parser,
+        Preprocessor, Resolver, StageResult, Stages, TypeSymbolType, Warning, run_stages,
+    };
+
+    /// Runs the optimizer before and after the resolver, like the pipeline of the cli
+    fn optimize(source: &str) -> (Block, Vec<(Warning, String)>) {
+        let optimizer = Optimizer::new();
+        let warnings = optimizer.warnings();
+        let stages = vec![
+            Stages::Parser(Parser::default()),
+            Stages::Preprocessor(Preprocessor::new().unwrap()),
+            Stages::Optimizer(optimizer),
+            Stages::Resolver(Resolver::new()),
+            Stages::Optimizer(Optimizer::new()),
+        ];
+        let Ok(StageResult::Resolved(scope, _)) =
+            run_stages(stages, StageResult::PreParse(source.to_owned()))
+        else {
+            panic!("the optimizer must keep the resolved program");
+        };
+
+        let warnings = warnings
+            .borrow()
+            .iter()
+            .map(|w| (w.warning.clone(), source[w.range.clone()].to_owned()))
+            .collect();
+        let Some(TypeSymbolType::Function(FunctionType {
+            execution_body: FunctionExecutionStrategy::Interpreted(body),
+            ..
+        })) = scope.resolve_type(&"main".to_owned()).map(|t| t.type_of)
+        else {
+            panic!("main must be an interpreted function");
+        };
+        (body, warnings)
+    }
+
+    fn kinds(body: &[Box<AstNode>]) -> Vec<String> {
+        body.iter()
+            .map(|node| {
+                let kind = format!("{:?}", node.type_of);
+                kind[..kind.find(['(', ' ']).unwrap_or(kind.len())].to_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn folds_constants() {
+        let (body, _) = optimize(
+            r#"fn f(a: int, b: bool, c: int, d: int, e: float): int => a;
+            fn main(): int {
+                a := (1 + 2) * 3 - -1;
+                b := 1.5 * 2.0 == 3.0 && !false;
+                c := 9223372036854775807 + 1;
+                d := -(1 + 2);
+                e := 1.5 * 2;
+                f(a, b, c, d, e)
+            }"#,
+        );
+
+        let expressions = body[..5]
+            .iter()
+            .map(|node| match &node.type_of {
+                AstNodeType::Declaration { expression, .. } => expression.type_of.clone(),
+                other => panic!("expected a declaration, got {other:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert!(matches!(expressions[0], AstNodeType::Int(10)));
+        assert!(matches!(expressions[1], AstNodeType::Bool(true)));
+        // overflows fail at runtime
+        assert!(matches!(expressions[2], AstNodeType::InfixCall(..)));
+        assert!(matches!(expressions[3], AstNodeType::Int(-3)));
+        // mixed arithmetic fails in strict mode
+        assert!(matches!(expressions[4], AstNodeType::InfixCall(..)));
+    }
+
+    #[test]
+    fn simplifies_constant_branches() {
+        let (body, _) = optimize(
+            r#"fn main(a: bool): int {
+                if (true) { println(1); println(2); }
+                if (false) { println(3); } else if (false) { println(4); }
+                while (false) { println(5); }
+                if (false) { println(6); } else if (a) { println(7); } else if (true) { println(8); } else { println(9); }
+                if (1 < 2) { 10 } else { 11 }
+            }"#,
+        );
+
+        assert_eq!(
+            kinds(&body),
+            vec![
+                "ExpressionStatement",
+                "ExpressionStatement",
+                "Branch",
+                "Int"
+            ]
+        );
+        let AstNodeType::Branch {
+            else_if_branches,
+            else_branch: Some(else_branch),
+            ..
+        } = &body[2].type_of
+        else {
+            panic!("the branch on a must be kept");
+        };
+        assert!(else_if_branches.is_empty());
+        assert_eq!(kinds(else_branch), vec!["ExpressionStatement"]);
+    }
+
+    #[test]
+    fn removes_unreachable_code_and_unused_declarations() {
+        let (body, warnings) = optimize(
+            r#"fn f(): int => 1;
+            fn main(): int {
+                unused := [1, 2];
+                used := 1;
+                called := f();
+                while (true) {
+                    break;
+                    println(used);
+                }
+                if (true) { return used; }
+                println(called);
+                0
+            }"#,
+        );
+
+        assert_eq!(
+            kinds(&body),
+            vec!["Declaration", "Declaration", "While", "ReturnStatement"]
+        );
+        assert_eq!(
+            warnings
+                .iter()
+                .map(|(_, code)| code.as_str())
+                .collect::<Vec<_>>(),
+            vec!["println(used)", "println(called);\n                0"]
+        );
+        assert!(
+            warnings
+                .iter()
+                .all(|(w, _)| matches!(w, Warning::UnreachableCode))
+        );
+    }
+
+    #[test]
+    fn removes_unused_declarations_after_their_errors_are_reported() {
+        let source = r#"fn main() {
+                unused := fn () { println(missing); };
+                speed := 1;
+                sped += 2;
+            }"#;
+        let stages = vec![
+            Stages::Parser(Parser::default()),
+            Stages::Preprocessor(Preprocessor::new().unwrap()),
+            Stages::Optimizer(Optimizer::new()),
+            Stages::Resolver(Resolver::new()),
+            Stages::Optimizer(Optimizer::new()),
+        ];
+        let err = run_stages(stages, StageResult::PreParse(source.to_owned())).unwrap_err();
+        assert!(matches!(err.err, Error::SymbolNotFound(_)));
+        assert_eq!(&source[err.range.clone()], "missing");
+
+        // branches stay, their scopes are counted by the slots of the resolver
+        let (body, _) = optimize(
+            r#"fn main() {
+                unused := fn () { println(1); };
+                if (true) { a := 1; println(2); }
+                println(3);
+            }"#,
+        );
+        assert_eq!(kinds(&body), vec!["Branch", "ExpressionStatement"]);
+    }
+}
@@ -1,35 +1,44 @@
-use crate::{Error, ErrorWithRange, Stage, StageResult, ast_grammar};
+use crate::{
+    Diagnostics, Error, ErrorWithRange, Stage, StageResult, ast_grammar, parse_diagnostics,
+    parse_error_range,
+};
 
 #[derive(Default)]
 pub struct Parser {
-    main_content: &'static str,
+    main_content: String,
 }
 
 impl Stage for Parser {
     fn init(&mut self, prev_stage_result: super::StageResult) -> Result<(), crate::ErrorWithRange> {
         match prev_stage_result {
-            StageResult::PreParse(content) => self.main_content = Box::leak(Box::new(content)),
+            StageResult::PreParse(content) => self.main_content = content,
             _ => Err(Error::StageError(0, prev_stage_result.into()))
-                .map_err(|err| ErrorWithRange { err, range: 0..1 })?,
+                .map_err(|err| ErrorWithRange::new(err, 0..1))?,
         }
         Ok(())
     }
 
     fn run(self) -> Result<super::StageResult, crate::ErrorWithRange> {
         let ast = ast_grammar::ProgrammParser::new()
-            .parse(self.main_content)
-            .map_err(|err| ErrorWithRange {
-                err: Error::ParseError(err),
-                range: 0..1,
+            .parse(&self.main_content)
+            .map_err(|err| {
+                let range = parse_error_range(&err);
+                // NOTE: the error outlives the source of the stage, its tokens keep their text
+                let err =
+                    err.map_token(|token| ast_grammar::Token(token.0, Box::leak(token.1.into())));
+                ErrorWithRange::new(Error::ParseError(err), range)
             })?;
         Ok(StageResult::Parsing(ast))
     }
-}
 
-impl Drop for Parser {
-    fn drop(&mut self) {
-        unsafe {
-            drop(Box::from_raw(self.main_content as *const str as *mut str));
+    /// Reports every syntax error, the parser recovers at statements and definitions
+    fn run_collecting(self, diagnostics: &mut Diagnostics) -> Option<StageResult> {
+        let (ast, errors) = ast_grammar::ProgrammParser::new().parse_recovering(&self.main_content);
+        diagnostics.extend(errors.iter().flat_map(parse_diagnostics));
+        if errors.is_empty() {
+            ast.map(StageResult::Parsing)
+        } else {
+            None
         }
     }
 }
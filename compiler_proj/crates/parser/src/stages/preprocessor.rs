@@ -1,243 +1,1086 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use crate::{
-    AstNode, AstNodeType, AstTypeDefinition, Error, ErrorWithRange, FunctionType, InterpreterValue,
-    Scope, Stage, StageResult, StructType, SystemType, TypeSymbol, TypeSymbolType,
-    register_buildin,
+    AssignmentOperations, AstNode, AstNodeType, AstTypeDefinition, ComponentType, Diagnostics,
+    Error, ErrorWithRange, FileId, FunctionType, InfixOperator, InterpreterValue, MemberAccess,
+    MemberAccessType, NativeBindings, Pattern, PrefixOperator, Scope, SharedSourceMap, Span, Stage,
+    StageResult, StructType, Symbol, SystemType, TypeSymbol, TypeSymbolType, register_buildin,
 };
 
+/// The types, that are declared before any program
+const PRIMITIVES: [(&str, TypeSymbolType); 4] = [
+    ("int", TypeSymbolType::Int),
+    ("float", TypeSymbolType::Float),
+    ("bool", TypeSymbolType::Bool),
+    ("string", TypeSymbolType::String),
+];
+
 pub struct Preprocessor {
     ast: Vec<AstNode>,
     global_scope: Scope,
+    /// Functions may replace earlier ones with the same name, instead of being rejected
+    redefine: bool,
+    /// Native headers are added, when errors point into them
+    sources: Option<SharedSourceMap>,
+    /// Arithmetic on an int and a float is rejected, instead of converting the int
+    strict: bool,
 }
 
 impl Preprocessor {
     pub fn new() -> Result<Self, Error> {
+        let mut global_scope = Scope::default();
+        for (name, type_of) in PRIMITIVES {
+            global_scope.declare_type(name.to_owned(), TypeSymbol::strong(type_of), false, 0..1)?;
+        }
+        register_buildin(&mut global_scope)?;
+
         Ok(Self {
-            global_scope: Scope::default(),
+            global_scope,
             ast: vec![],
+            redefine: false,
+            sources: None,
+            strict: false,
         })
     }
+
+    /// Continues with the global scope of an earlier run, i.e. the one of the previous input of the repl.
+    /// Its functions may be redefined
+    pub fn with_global_scope(global_scope: Scope) -> Self {
+        Self {
+            global_scope,
+            ast: vec![],
+            redefine: true,
+            sources: None,
+            strict: false,
+        }
+    }
+
+    pub fn with_sources(mut self, sources: SharedSourceMap) -> Self {
+        self.sources = Some(sources);
+        self
+    }
+
+    pub fn with_strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
 }
 
 impl Stage for Preprocessor {
     fn init(&mut self, old_output: StageResult) -> Result<(), ErrorWithRange> {
         if let StageResult::Parsing(ast) = old_output {
             self.ast = ast;
+            Ok(())
         } else {
-            return Err(ErrorWithRange {
-                err: Error::StageError(0, old_output.into()),
-                range: 0..1,
-            });
-        }
-
-        let scope = &mut self.global_scope;
-        scope
-            .declare_type(
-                "int".to_owned(),
-                TypeSymbol::strong(TypeSymbolType::Int),
-                false,
-                0..1,
-            )
-            .map_err(|err| ErrorWithRange { err, range: 0..1 })?;
-        scope
-            .declare_type(
-                "float".to_owned(),
-                TypeSymbol::strong(TypeSymbolType::Float),
-                false,
+            Err(ErrorWithRange::new(
+                Error::StageError(0, old_output.into()),
                 0..1,
-            )
-            .map_err(|err| ErrorWithRange { err, range: 0..1 })?;
-
-        scope
-            .declare_type(
-                "bool".to_owned(),
-                TypeSymbol::strong(TypeSymbolType::Bool),
-                false,
-                0..1
-            )
-            .map_err(|err| ErrorWithRange { err, range: 0..1 })?;
-
-        scope
-            .declare_type(
-                "string".to_owned(),
-                TypeSymbol::strong(TypeSymbolType::String),
-                false,
-                0..1,
-            )
-            .map_err(|err| ErrorWithRange { err, range: 0..1 })?;
+            ))
+        }
+    }
 
-        register_buildin(scope).map_err(|err| ErrorWithRange { err, range: 0..1 })?;
+    fn run(self) -> Result<StageResult, ErrorWithRange> {
+        self.preprocess().map_err(|mut errors| errors.remove(0))
+    }
 
-        Ok(())
+    /// Reports the errors of all definitions, not only the first one
+    fn run_collecting(self, diagnostics: &mut Diagnostics) -> Option<StageResult> {
+        self.preprocess()
+            .map_err(|errors| {
+                diagnostics.extend(errors.iter().flat_map(ErrorWithRange::diagnostics))
+            })
+            .ok()
     }
+}
+
+impl Preprocessor {
+    /// Checks and declares every definition, the other nodes are kept for the later stages.
+    /// A definition stops at its first error. The errors are ordered by their position in the source
+    fn preprocess(mut self) -> Result<StageResult, Vec<ErrorWithRange>> {
+        let context = CheckContext {
+            strict: self.strict,
+            ..CheckContext::of_program(&self.ast, &self.global_scope)
+        };
+        let mut errors = self
+            .ast
+            .iter()
+            .filter_map(|node| check_control_flow(node, &mut context.clone()).err())
+            .collect::<Vec<_>>();
 
-    fn run(mut self) -> Result<StageResult, ErrorWithRange> {
         let mut other_nodes = Vec::new();
+        for node in std::mem::take(&mut self.ast) {
+            if let Err(err) = self.declare(node, &mut other_nodes) {
+                errors.push(err);
+            }
+        }
+        if !errors.is_empty() {
+            errors.sort_by_key(|err| err.range.start);
+            return Err(errors);
+        }
+
+        Ok(StageResult::Preprocessor(
+            self.global_scope
+                .check_all_types_after_pre_resolve()
+                .map_err(|err| vec![err])?,
+            other_nodes,
+        ))
+    }
+
+    fn declare(
+        &mut self,
+        node: AstNode,
+        other_nodes: &mut Vec<AstNode>,
+    ) -> Result<(), ErrorWithRange> {
+        match node.type_of {
+            AstNodeType::TypeDef {
+                typename,
+                typedef,
+                execution_body,
+            } => {
+                match typedef {
+                    AstTypeDefinition::Function(params, return_type) => {
+                        let fun = InterpreterValue::Function(typename.clone());
+                        let fun_type = TypeSymbol::strong(TypeSymbolType::Function(FunctionType {
+                            name: typename.clone(),
+                            params,
+                            return_type: return_type.map(Box::new),
+                            execution_body: crate::FunctionExecutionStrategy::Interpreted(
+                                execution_body,
+                            ),
+                        }));
+                        // SAFETY: Is always initialized
+                        self.global_scope
+                            .declare_function(
+                                typename,
+                                fun,
+                                fun_type,
+                                self.redefine,
+                                true,
+                                node.range.clone(),
+                            )
+                            .map_err(|err| ErrorWithRange::new(err, node.range.clone()))?;
+                    }
+                    AstTypeDefinition::Struct(attributes) => {
+                        let mut methods = Vec::new();
+                        let mut statics = Vec::new();
 
-        for node in self.ast {
-            match node.type_of {
-                AstNodeType::TypeDef {
-                    typename,
-                    typedef,
-                    execution_body,
-                } => {
-                    match typedef {
-                        AstTypeDefinition::Function(params, return_type) => {
-                            let fun = InterpreterValue::Function(typename.clone());
-                            let fun_type =
-                                TypeSymbol::strong(TypeSymbolType::Function(FunctionType {
-                                    name: typename.clone(),
+                        for node in execution_body {
+                            if let AstNodeType::TypeDef {
+                                typename: methodname,
+                                typedef: AstTypeDefinition::Function(params, return_type),
+                                execution_body,
+                            } = node.type_of
+                            {
+                                let is_method = params[0].1.type_of == TypeSymbolType::SelfType;
+
+                                let fun_type = FunctionType {
+                                    name: methodname.clone(),
                                     params,
                                     return_type: return_type.map(Box::new),
                                     execution_body: crate::FunctionExecutionStrategy::Interpreted(
                                         execution_body,
                                     ),
-                                }));
-                            // SAFETY: Is always initialized
-                            self.global_scope
-                                .declare_function(typename, fun, fun_type, false, true, node.range.clone())
-                                .map_err(|err| ErrorWithRange {
-                                    err,
-                                    range: node.range.clone(),
-                                })?;
-                        }
-                        AstTypeDefinition::Struct(attributes) => {
-                            let mut methods = Vec::new();
-                            let mut statics = Vec::new();
-
-                            for node in execution_body {
-                                if let AstNodeType::TypeDef {
-                                    typename: methodname,
-                                    typedef: AstTypeDefinition::Function(params, return_type),
-                                    execution_body,
-                                } = node.type_of
-                                {
-                                    let is_method = params[0].1.type_of == TypeSymbolType::SelfType;
-
-                                    let fun_type = FunctionType {
-                                        name: methodname.clone(),
-                                        params,
-                                        return_type: return_type.map(Box::new),
-                                        execution_body:
-                                            crate::FunctionExecutionStrategy::Interpreted(
-                                                execution_body,
-                                            ),
-                                    };
+                                };
 
-                                    if is_method {
-                                        methods.push((methodname, fun_type));
-                                    } else {
-                                        statics.push((methodname, fun_type));
-                                    }
+                                if is_method {
+                                    methods.push((methodname, fun_type));
+                                } else {
+                                    statics.push((methodname, fun_type));
                                 }
                             }
+                        }
 
-                            let struct_def =
-                                TypeSymbol::strong(TypeSymbolType::Struct(StructType {
-                                    name: typename.clone(),
-                                    fields: attributes,
-                                    methods,
-                                    statics,
-                                }));
-
-                            self.global_scope
-                                .declare_type(typename, struct_def, true, node.range.clone())
-                                .map_err(|err| ErrorWithRange {
-                                    err,
-                                    range: node.range.clone(),
-                                })?;
+                        let struct_def = TypeSymbol::strong(TypeSymbolType::Struct(StructType {
+                            name: typename.clone(),
+                            fields: attributes,
+                            methods,
+                            statics,
+                        }));
+
+                        self.global_scope
+                            .declare_type(typename, struct_def, true, node.range.clone())
+                            .map_err(|err| ErrorWithRange::new(err, node.range.clone()))?;
+                    }
+                    AstTypeDefinition::Component(fields) => {
+                        let component_def =
+                            TypeSymbol::strong(TypeSymbolType::Component(ComponentType {
+                                name: typename.clone(),
+                                fields,
+                            }));
+
+                        self.global_scope
+                            .declare_type(typename, component_def, true, node.range.clone())
+                            .map_err(|err| ErrorWithRange::new(err, node.range.clone()))?;
+                    }
+                    AstTypeDefinition::System(params, queries) => {
+                        // first, validate the params, if all params have a matching query
+                        if !params.is_empty() && queries.is_none()
+                            || params.is_empty()
+                                && queries.is_some()
+                                && !queries.as_ref().expect("already checked").is_empty()
+                        {
+                            Err(ErrorWithRange::new(
+                                Error::OperationUnsupported {
+                                    operation: "system definition".to_owned(),
+                                    type_of:
+                                        "non matching param list in query and system parameters"
+                                            .to_owned(),
+                                },
+                                node.range.clone(),
+                            ))?;
                         }
-                        AstTypeDefinition::System(params, queries) => {
-                            // first, validate the params, if all params have a matching query
-                            if !params.is_empty() && queries.is_none()
-                                || params.is_empty()
-                                    && queries.is_some()
-                                    && !queries.as_ref().expect("already checked").is_empty()
-                            {
-                                Err(ErrorWithRange {
-                                    err: Error::OperationUnsupported {
-                                        operation: "system definition".to_owned(),
-                                        type_of:
-                                            "non matching param list in query and system parameters"
-                                                .to_owned(),
-                                    },
-                                    range: node.range.clone(),
-                                })?;
+
+                        if !params.is_empty()
+                            && let Some(queries) = &queries
+                        {
+                            let mut query_resolver = HashMap::new();
+                            for query in queries {
+                                query_resolver.insert(query.symbol.clone(), query.clone());
                             }
+                            let mut visited_queries = HashSet::new();
 
-                            if !params.is_empty()
-                                && let Some(queries) = &queries
-                            {
-                                let mut query_resolver = HashMap::new();
-                                for query in queries {
-                                    query_resolver.insert(query.symbol.clone(), query.clone());
+                            for param in &params {
+                                if query_resolver.contains_key(&param.1) {
+                                    visited_queries.insert(param.1.clone());
+                                } else {
+                                    Err(ErrorWithRange::new(
+                                        Error::OperationUnsupported {
+                                            operation: "system definition".to_owned(),
+                                            type_of: format!(
+                                                "missing query for parameter {}, expected {}",
+                                                param.0, param.1
+                                            ),
+                                        },
+                                        node.range.clone(),
+                                    ))?;
                                 }
-                                let mut visited_queries = HashSet::new();
-
-                                for param in &params {
-                                    if query_resolver.contains_key(&param.1) {
-                                        visited_queries.insert(param.1.clone());
-                                    } else {
-                                        Err(ErrorWithRange {
-                                            err: Error::OperationUnsupported {
+                            }
+
+                            if visited_queries.len() < query_resolver.len() {
+                                for query in &query_resolver {
+                                    if !visited_queries.contains(query.0) {
+                                        Err(ErrorWithRange::new(
+                                            Error::OperationUnsupported {
                                                 operation: "system definition".to_owned(),
                                                 type_of: format!(
-                                                    "missing query for parameter {}, expected {}",
-                                                    param.0, param.1
+                                                    "non used query parameter {}",
+                                                    query.0
                                                 ),
                                             },
-                                            range: node.range.clone(),
-                                        })?;
-                                    }
-                                }
-
-                                if visited_queries.len() < query_resolver.len() {
-                                    for query in &query_resolver {
-                                        if !visited_queries.contains(query.0) {
-                                            Err(ErrorWithRange {
-                                                err: Error::OperationUnsupported {
-                                                    operation: "system definition".to_owned(),
-                                                    type_of: format!(
-                                                        "non used query parameter {}",
-                                                        query.0
-                                                    ),
-                                                },
-                                                range: node.range.clone(),
-                                            })?;
-                                        }
+                                            node.range.clone(),
+                                        ))?;
                                     }
                                 }
                             }
+                        }
 
-                            let sys = InterpreterValue::System(typename.clone());
-                            let sys_type = TypeSymbol::strong(TypeSymbolType::System(SystemType {
-                                name: typename.clone(),
-                                params,
-                                queries,
-                                execution_body: crate::SystemExecutionStrategy::Interpreted(
-                                    execution_body,
-                                ),
-                            }));
-                            // SAFETY: Is always initialized
-                            self.global_scope
-                                .declare_system(typename, sys, sys_type, true, true, node.range.clone())
-                                .map_err(|err| ErrorWithRange {
-                                    err,
-                                    range: node.range.clone(),
-                                })?;
+                        let sys = InterpreterValue::System(typename.clone());
+                        let sys_type = TypeSymbol::strong(TypeSymbolType::System(SystemType {
+                            name: typename.clone(),
+                            params,
+                            queries,
+                            execution_body: crate::SystemExecutionStrategy::Interpreted(
+                                execution_body,
+                            ),
+                        }));
+                        // SAFETY: Is always initialized
+                        self.global_scope
+                            .declare_system(typename, sys, sys_type, true, true, node.range.clone())
+                            .map_err(|err| ErrorWithRange::new(err, node.range.clone()))?;
+                    }
+                    _ => (),
+                }
+            }
+            AstNodeType::ImportNative(header, library, alias) => {
+                let import = Span::new(node.file, node.range.clone());
+                let header = header_path(self.sources.as_ref(), node.file, &header);
+                let native_error =
+                    |err| native_error(self.sources.as_ref(), err, import.clone(), &header);
+                // NOTE: the bindings are only regenerated, if the header changed since the last run
+                let bindings = NativeBindings::load_or_generate(&header).map_err(native_error)?;
+
+                bindings
+                    .declare_in_scope(&mut self.global_scope, &library, &alias, node.range.clone())
+                    .map_err(native_error)?;
+            }
+            _ => other_nodes.push(node),
+        }
+        Ok(())
+    }
+}
+
+/// Relative headers are next to the importing script. Without its path, they are relative to the
+/// working directory
+fn header_path(sources: Option<&SharedSourceMap>, file: FileId, header: &str) -> PathBuf {
+    let script = sources.and_then(|sources| sources.borrow().file(file).path.clone());
+    match script
+        .as_deref()
+        .and_then(|script| Path::new(script).parent())
+    {
+        Some(directory) => directory.join(header),
+        None => PathBuf::from(header),
+    }
+}
+
+/// Points at the import. If the bindings are outdated, a note points into the header, that changed
+fn native_error(
+    sources: Option<&SharedSourceMap>,
+    err: Error,
+    import: Span,
+    header: &Path,
+) -> ErrorWithRange {
+    let error = ErrorWithRange::new(err, import.range).in_file(import.file);
+    let (Some(sources), Error::NativeBindingsOutdated(_)) = (sources, &error.err) else {
+        return error;
+    };
+    let Ok(contents) = fs::read_to_string(header) else {
+        return error;
+    };
+
+    let first_line = contents.lines().next().unwrap_or_default().len();
+    let file = sources
+        .borrow_mut()
+        .add(header.display().to_string(), contents);
+    error.with_note(
+        Span::new(file, 0..first_line),
+        "changed since the bindings were generated",
+    )
+}
+
+/// What the checks of a function body know about the enclosing code
+#[derive(Default, Clone)]
+struct CheckContext {
+    /// The labels of all enclosing loops of the current function body
+    loops: Vec<Option<Symbol>>,
+    /// The variables, that are known to hold weak references
+    weak: HashSet<Symbol>,
+    /// The types of the variables, that are known before execution, i.e. of params
+    types: HashMap<Symbol, TypeSymbolType>,
+    /// The fields of the structs and components, that are defined in the program or declared before
+    structs: Rc<HashMap<Symbol, Vec<(Symbol, TypeSymbol)>>>,
+    /// The signatures of the functions, that are defined in the program or declared before
+    functions: Rc<HashMap<Symbol, FunctionType>>,
+    /// The return type of the current function body
+    return_type: Option<TypeSymbol>,
+    /// Arithmetic on an int and a float is a type error
+    strict: bool,
+}
+
+impl CheckContext {
+    /// The context outside of any function, it knows the defined structs and components
+    fn of_program(ast: &[AstNode], global_scope: &Scope) -> Self {
+        let declared =
+            global_scope
+                .iter_defined_types()
+                .filter_map(|(name, type_of)| match &type_of.type_of {
+                    TypeSymbolType::Struct(StructType { fields, .. })
+                    | TypeSymbolType::Component(ComponentType { fields, .. }) => {
+                        Some((name.clone(), fields.clone()))
+                    }
+                    _ => None,
+                });
+        let defined = ast.iter().filter_map(|node| match &node.type_of {
+            AstNodeType::TypeDef {
+                typename,
+                typedef: AstTypeDefinition::Struct(fields) | AstTypeDefinition::Component(fields),
+                ..
+            } => Some((typename.clone(), fields.clone())),
+            _ => None,
+        });
+
+        let declared_functions =
+            global_scope
+                .iter_types()
+                .filter_map(|(name, type_of)| match &type_of.type_of {
+                    TypeSymbolType::Function(function) => Some((
+                        name.clone(),
+                        FunctionType::signature(
+                            function.params.iter().map(|(_, t)| t.clone()).collect(),
+                            function.return_type.as_deref().cloned(),
+                        ),
+                    )),
+                    _ => None,
+                });
+        let defined_functions = ast.iter().filter_map(|node| match &node.type_of {
+            AstNodeType::TypeDef {
+                typename,
+                typedef: AstTypeDefinition::Function(params, return_type),
+                ..
+            } => Some((
+                typename.clone(),
+                FunctionType::signature(
+                    params.iter().map(|(_, t)| t.clone()).collect(),
+                    return_type.clone(),
+                ),
+            )),
+            _ => None,
+        });
+
+        Self {
+            structs: Rc::new(declared.chain(defined).collect()),
+            functions: Rc::new(declared_functions.chain(defined_functions).collect()),
+            ..Self::default()
+        }
+    }
+
+    /// The context of a function body. Loops can't be left from a function, but closures see the captured variables
+    fn function_body(
+        &self,
+        params: &[(Symbol, TypeSymbol)],
+        return_type: Option<&TypeSymbol>,
+        captures: bool,
+    ) -> Self {
+        let (mut weak, mut types) = if captures {
+            (self.weak.clone(), self.types.clone())
+        } else {
+            (HashSet::new(), HashMap::new())
+        };
+        for (param, type_of) in params {
+            if type_of.is_weak {
+                weak.insert(param.clone());
+                types.remove(param);
+            } else {
+                weak.remove(param);
+                types.insert(param.clone(), named_type(&type_of.type_of));
+            }
+        }
+
+        Self {
+            loops: Vec::new(),
+            weak,
+            types,
+            structs: Rc::clone(&self.structs),
+            functions: Rc::clone(&self.functions),
+            return_type: return_type.cloned(),
+            strict: self.strict,
+        }
+    }
+
+    /// Declares a variable with the assumed type or the type of its value, if it is known.
+    /// NOTE: a declaration shadows a weak variable of the same name
+    fn declare(
+        &mut self,
+        new_symbol: &Symbol,
+        expression: &AstNode,
+        assumed_type: Option<&TypeSymbol>,
+    ) {
+        // Without a type, a declaration initialized with a weak variable is weak, too
+        if matches!(expression.type_of, AstNodeType::Weak(_))
+            || assumed_type.is_some_and(|t| t.is_weak)
+            || (assumed_type.is_none() && self.weak_variable(expression).is_some())
+        {
+            self.weak.insert(new_symbol.clone());
+            self.types.remove(new_symbol);
+            return;
+        }
+
+        self.weak.remove(new_symbol);
+        match assumed_type
+            .map(|t| named_type(&t.type_of))
+            .or_else(|| self.type_of(expression))
+        {
+            Some(type_of) => self.types.insert(new_symbol.clone(), type_of),
+            None => self.types.remove(new_symbol),
+        };
+    }
+
+    /// The context at the end of a block, it knows the variables the block declares
+    fn after(&self, body: &[Box<AstNode>]) -> Self {
+        let mut ctx = self.clone();
+        for node in body {
+            if let AstNodeType::Declaration {
+                new_symbol,
+                expression,
+                assumed_type,
+                ..
+            } = &node.type_of
+            {
+                ctx.declare(new_symbol, expression, assumed_type.as_ref());
+            }
+        }
+        ctx
+    }
+
+    /// The type of an expression, if it is known without executing it. Variables have the types
+    /// they are declared with, calls the return type of the function and struct literals the
+    /// struct they name. An if has the type of the first branch, whose type is known
+    /// NOTE: the signatures of methods aren't known before execution
+    fn type_of(&self, node: &AstNode) -> Option<TypeSymbolType> {
+        match &node.type_of {
+            AstNodeType::MemberCall { calls } => {
+                let [call] = calls.as_slice() else {
+                    return None;
+                };
+                match call.type_of {
+                    MemberAccessType::Symbol => self.types.get(&call.member).cloned(),
+                    MemberAccessType::Struct(_) => {
+                        Some(TypeSymbolType::Symbol(call.member.clone()))
+                    }
+                    MemberAccessType::Function(_) => self
+                        .signature(&call.member)?
+                        .return_type
+                        .as_deref()
+                        .filter(|t| !t.is_weak)
+                        .map(|t| named_type(&t.type_of)),
+                }
+            }
+            AstNodeType::Branch {
+                body,
+                else_if_branches,
+                else_branch,
+                ..
+            } => std::iter::once(body)
+                .chain(else_if_branches.iter().map(|(_, body)| body))
+                .chain(else_branch)
+                .find_map(|body| self.after(body).type_of(body.last()?)),
+            AstNodeType::InfixCall(left, op, right) if op.is_arithmetic() => {
+                match (self.type_of(left)?, self.type_of(right)?) {
+                    (TypeSymbolType::Int, TypeSymbolType::Int) => Some(TypeSymbolType::Int),
+                    (TypeSymbolType::Int | TypeSymbolType::Float, TypeSymbolType::Float)
+                    | (TypeSymbolType::Float, TypeSymbolType::Int) => Some(TypeSymbolType::Float),
+                    _ => None,
+                }
+            }
+            AstNodeType::Closure {
+                params,
+                return_type,
+                ..
+            } => Some(TypeSymbolType::Function(FunctionType::signature(
+                params.iter().map(|(_, t)| t.clone()).collect(),
+                return_type.clone(),
+            ))),
+            _ => static_type_of(node),
+        }
+    }
+
+    /// Rejects arithmetic on an int and a float in strict mode, if both types are known
+    fn check_strict(
+        &self,
+        op: &InfixOperator,
+        left: Option<TypeSymbolType>,
+        right: Option<TypeSymbolType>,
+        range: &Range<usize>,
+    ) -> Result<(), ErrorWithRange> {
+        let name = |type_of: Option<TypeSymbolType>| match type_of {
+            Some(TypeSymbolType::Int) => Some("int"),
+            Some(TypeSymbolType::Float) => Some("float"),
+            _ => None,
+        };
+        match (name(left), name(right)) {
+            (Some(l), Some(r)) if self.strict && op.is_arithmetic() && l != r => {
+                Err(ErrorWithRange::new(
+                    Error::MixedTypes(op.symbol().to_string(), l.to_string(), r.to_string()),
+                    range.clone(),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The signature of the function, that a call of the name calls. Variables shadow functions
+    fn signature(&self, name: &Symbol) -> Option<&FunctionType> {
+        match self.types.get(name) {
+            Some(TypeSymbolType::Function(function)) => Some(function),
+            Some(_) => None,
+            None if self.weak.contains(name) => None,
+            None => self.functions.get(name),
+        }
+    }
+
+    /// Checks an if, whose value is used. It needs an else branch and all branches must agree on the
+    /// type of their value. NOTE: values, whose type isn't known before execution, agree with any type
+    fn check_branch_value(&self, node: &AstNode) -> Result<(), ErrorWithRange> {
+        let AstNodeType::Branch {
+            body,
+            else_if_branches,
+            else_branch,
+            ..
+        } = &node.type_of
+        else {
+            return Ok(());
+        };
+
+        let Some(else_branch) = else_branch else {
+            return Err(ErrorWithRange::new(Error::MissingElse, node.range.clone()));
+        };
+
+        let mut expected: Option<TypeSymbolType> = None;
+        let branches = std::iter::once(body)
+            .chain(else_if_branches.iter().map(|(_, body)| body))
+            .chain(std::iter::once(else_branch));
+        for body in branches {
+            let Some(last) = body.last() else {
+                continue;
+            };
+            let ctx = self.after(body);
+            ctx.check_branch_value(last)?;
+
+            let Some(type_of) = ctx.type_of(last) else {
+                continue;
+            };
+            match &expected {
+                Some(expected) if *expected != type_of => {
+                    return Err(ErrorWithRange::new(
+                        Error::BranchTypeMismatch(expected.to_string(), type_of.to_string()),
+                        last.range.clone(),
+                    ));
+                }
+                Some(_) => (),
+                None => expected = Some(type_of),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The name of the weak variable, if the expression is one
+    fn weak_variable(&self, node: &AstNode) -> Option<Symbol> {
+        let AstNodeType::MemberCall { calls } = &node.type_of else {
+            return None;
+        };
+        self.weak_access(calls)
+            .filter(|(i, _)| *i + 1 == calls.len())
+            .map(|(_, name)| name)
+    }
+
+    /// The first access of a member chain, that is a weak reference, i.e. a weak variable or a weak
+    /// field of a struct, whose type is known. Returns its index and the path to it, i.e. h.target
+    fn weak_access(&self, calls: &[MemberAccess]) -> Option<(usize, Symbol)> {
+        let first = calls.first()?;
+        if !matches!(first.type_of, MemberAccessType::Symbol) {
+            return None;
+        }
+        if self.weak.contains(&first.member) {
+            return Some((0, first.member.clone()));
+        }
+
+        let mut type_of = self.types.get(&first.member)?.clone();
+        for (i, call) in calls.iter().enumerate().skip(1) {
+            // NOTE: the return types of methods aren't known before execution
+            let MemberAccessType::Symbol = call.type_of else {
+                return None;
+            };
+            let name = match &type_of {
+                TypeSymbolType::Symbol(name) => name,
+                TypeSymbolType::Struct(StructType { name, .. })
+                | TypeSymbolType::Component(ComponentType { name, .. }) => name,
+                _ => return None,
+            };
+            let (_, field) = self
+                .structs
+                .get(name)?
+                .iter()
+                .find(|(field, _)| *field == call.member)?;
+            if field.is_weak {
+                let path = calls[..=i].iter().map(|call| call.member.as_str());
+                return Some((i, path.collect::<Vec<_>>().join(".")));
+            }
+            type_of = named_type(&field.type_of);
+        }
+        None
+    }
+
+    /// Rejects a weak variable used as a value directly, i.e. w + 1 or if (w) instead of w.upgrade()
+    fn check_deref(&self, node: &AstNode) -> Result<(), ErrorWithRange> {
+        self.check_weak_into(node, None)
+    }
+
+    /// Rejects a weak variable passed on as a strong value, i.e. as argument, return value or
+    /// initializer. Only a weak target or one of any type takes it, an unknown target doesn't
+    fn check_weak_into(
+        &self,
+        node: &AstNode,
+        target: Option<&TypeSymbol>,
+    ) -> Result<(), ErrorWithRange> {
+        let takes_weak =
+            target.is_some_and(|t| t.is_weak || matches!(t.type_of, TypeSymbolType::Any));
+        match self.weak_variable(node) {
+            Some(name) if !takes_weak => Err(ErrorWithRange::new(
+                Error::WeakNotUpgraded(name),
+                node.range.clone(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Rejects break and continue outside of loops and with labels no enclosing loop declares,
+/// non exhaustive matches and weak references, that are dereferenced without upgrading them first.
+/// In strict mode arithmetic on an int and a float is rejected, if the types of both are known
+fn check_control_flow(node: &AstNode, ctx: &mut CheckContext) -> Result<(), ErrorWithRange> {
+    // NOTE: the declarations of a block are not visible after it
+    let check_all = |nodes: &Vec<Box<AstNode>>, ctx: &mut CheckContext| {
+        let types = ctx.types.clone();
+        let checked = nodes
+            .iter()
+            .try_for_each(|node| check_control_flow(node, ctx));
+        ctx.types = types;
+        checked
+    };
+
+    match &node.type_of {
+        AstNodeType::Break(label) | AstNodeType::Continue(label) => {
+            let keyword = if matches!(node.type_of, AstNodeType::Break(_)) {
+                "break"
+            } else {
+                "continue"
+            };
+
+            if ctx.loops.is_empty() {
+                return Err(ErrorWithRange::new(
+                    Error::LoopControlOutsideLoop(keyword.to_owned()),
+                    node.range.clone(),
+                ));
+            }
+            if let Some(label) = label
+                && !ctx.loops.iter().any(|l| l.as_ref() == Some(label))
+            {
+                return Err(ErrorWithRange::new(
+                    Error::UnknownLoopLabel(label.clone()),
+                    node.range.clone(),
+                ));
+            }
+        }
+        AstNodeType::While { label, cond, body } => {
+            ctx.check_deref(cond)?;
+            check_control_flow(cond, ctx)?;
+            ctx.loops.push(label.clone());
+            check_all(body, ctx)?;
+            ctx.loops.pop();
+        }
+        AstNodeType::For {
+            label,
+            declaration,
+            condition,
+            assignment,
+            body,
+        } => {
+            let types = ctx.types.clone();
+            for node in [declaration, condition, assignment].into_iter().flatten() {
+                check_control_flow(node, ctx)?;
+            }
+            ctx.loops.push(label.clone());
+            check_all(body, ctx)?;
+            ctx.loops.pop();
+            ctx.types = types;
+        }
+        AstNodeType::ForEach {
+            label,
+            recipient,
+            iterable,
+            body,
+        } => {
+            ctx.check_deref(iterable)?;
+            check_control_flow(iterable, ctx)?;
+            let recipient_type = ctx.types.remove(recipient);
+            ctx.loops.push(label.clone());
+            check_all(body, ctx)?;
+            ctx.loops.pop();
+            if let Some(type_of) = recipient_type {
+                ctx.types.insert(recipient.clone(), type_of);
+            }
+        }
+        AstNodeType::Branch {
+            cond,
+            body,
+            else_if_branches,
+            else_branch,
+        } => {
+            ctx.check_deref(cond)?;
+            check_control_flow(cond, ctx)?;
+            check_all(body, ctx)?;
+            for (cond, body) in else_if_branches {
+                ctx.check_deref(cond)?;
+                check_control_flow(cond, ctx)?;
+                check_all(body, ctx)?;
+            }
+            if let Some(body) = else_branch {
+                check_all(body, ctx)?;
+            }
+        }
+        AstNodeType::TypeDef {
+            typedef,
+            execution_body,
+            ..
+        } => {
+            let (params, return_type) = match typedef {
+                AstTypeDefinition::Function(params, return_type) => {
+                    (params.as_slice(), return_type.as_ref())
+                }
+                _ => (&[][..], None),
+            };
+            let mut body_ctx = ctx.function_body(params, return_type, false);
+            // The last expression is returned implicitly, if the function returns a value
+            if return_type.is_some()
+                && let Some(last) = execution_body.last()
+            {
+                body_ctx.check_branch_value(last)?;
+                body_ctx.check_weak_into(last, return_type)?;
+            }
+            check_all(execution_body, &mut body_ctx)?
+        }
+        AstNodeType::Closure {
+            params,
+            return_type,
+            execution_body,
+        } => {
+            let mut body_ctx = ctx.function_body(params, return_type.as_ref(), true);
+            if return_type.is_some()
+                && let Some(last) = execution_body.last()
+            {
+                body_ctx.check_branch_value(last)?;
+                body_ctx.check_weak_into(last, return_type.as_ref())?;
+            }
+            check_all(execution_body, &mut body_ctx)?
+        }
+        AstNodeType::Declaration {
+            new_symbol,
+            expression,
+            assumed_type,
+            ..
+        } => {
+            ctx.check_branch_value(expression)?;
+            check_control_flow(expression, ctx)?;
+            if let Some(assumed_type) = assumed_type {
+                ctx.check_weak_into(expression, Some(assumed_type))?;
+            }
+            ctx.declare(new_symbol, expression, assumed_type.as_ref());
+        }
+        AstNodeType::AssignmentOp {
+            recipient,
+            operation,
+            expression,
+            ..
+        } => {
+            ctx.check_branch_value(expression)?;
+            check_control_flow(expression, ctx)?;
+            // Only a weak variable can be assigned a weak one, operations deref it
+            if !(ctx.weak.contains(recipient)
+                && matches!(operation, AssignmentOperations::Identity))
+            {
+                ctx.check_deref(expression)?;
+            }
+            if let Some(op) = operation.infix_operator() {
+                let old = ctx.types.get(recipient).cloned();
+                ctx.check_strict(&op, old, ctx.type_of(expression), &expression.range)?;
+            }
+        }
+        AstNodeType::ReturnStatement {
+            return_value: expression,
+        } => {
+            ctx.check_branch_value(expression)?;
+            check_control_flow(expression, ctx)?;
+            ctx.check_weak_into(expression, ctx.return_type.as_ref())?;
+        }
+        AstNodeType::PrefixCall(_, expression) | AstNodeType::Propagate(expression) => {
+            ctx.check_deref(expression)?;
+            check_control_flow(expression, ctx)?
+        }
+        AstNodeType::Weak(expression) | AstNodeType::ExpressionStatement(expression) => {
+            check_control_flow(expression, ctx)?
+        }
+        AstNodeType::Match { value, arms } => {
+            ctx.check_deref(value)?;
+            check_control_flow(value, ctx)?;
+
+            // NOTE: guarded arms may not match, hence they don't count towards exhaustiveness
+            let patterns = arms
+                .iter()
+                .filter(|arm| arm.guard.is_none())
+                .map(|arm| &arm.pattern)
+                .collect::<Vec<_>>();
+            let scrutinee = ctx.type_of(value);
+            if let Some(missing) = missing_pattern(&patterns, scrutinee.as_ref(), &ctx.structs) {
+                return Err(ErrorWithRange::new(
+                    Error::NonExhaustiveMatch(missing),
+                    node.range.clone(),
+                ));
+            }
+
+            for arm in arms {
+                // NOTE: the bindings of the pattern shadow the variables
+                let types = ctx.types.clone();
+                for binding in arm.pattern.bindings() {
+                    ctx.types.remove(binding);
+                }
+                if let Some(guard) = &arm.guard {
+                    check_control_flow(guard, ctx)?;
+                }
+                check_all(&arm.body, ctx)?;
+                ctx.types = types;
+            }
+        }
+        AstNodeType::InfixCall(left, op, right) => {
+            ctx.check_deref(left)?;
+            ctx.check_deref(right)?;
+            check_control_flow(left, ctx)?;
+            check_control_flow(right, ctx)?;
+            ctx.check_strict(op, ctx.type_of(left), ctx.type_of(right), &node.range)?;
+        }
+        AstNodeType::List(values) => check_all(values, ctx)?,
+        AstNodeType::Interpolation(parts) => {
+            for part in parts {
+                ctx.check_deref(part)?;
+                check_control_flow(part, ctx)?;
+            }
+        }
+        AstNodeType::Map(values) => {
+            for (key, value) in values {
+                check_control_flow(key, ctx)?;
+                check_control_flow(value, ctx)?;
+            }
+        }
+        AstNodeType::Option(Some(value))
+        | AstNodeType::Result(Ok(value))
+        | AstNodeType::Result(Err(value)) => check_control_flow(value, ctx)?,
+        AstNodeType::MemberCall { calls } => {
+            // Accessing a member of a weak reference derefs it, only upgrade() is allowed
+            if let Some((i, name)) = ctx.weak_access(calls)
+                && let Some(next) = calls.get(i + 1)
+                && !(next.member == "upgrade"
+                    && matches!(next.type_of, MemberAccessType::Function(_)))
+            {
+                return Err(ErrorWithRange::new(
+                    Error::WeakNotUpgraded(name),
+                    node.range.clone(),
+                ));
+            }
+
+            for (i, call) in calls.iter().enumerate() {
+                match &call.type_of {
+                    MemberAccessType::Function(params) => {
+                        // NOTE: the params of methods aren't known before execution
+                        let signature = (i == 0)
+                            .then(|| ctx.signature(&call.member).cloned())
+                            .flatten();
+                        for (j, param) in params.iter().enumerate() {
+                            ctx.check_branch_value(param)?;
+                            check_control_flow(param, ctx)?;
+                            let target = signature
+                                .as_ref()
+                                .and_then(|s| s.params.get(j))
+                                .map(|(_, t)| t);
+                            ctx.check_weak_into(param, target)?;
+                        }
+                    }
+                    MemberAccessType::Struct(fields) => {
+                        for (_, value) in fields {
+                            check_control_flow(value, ctx)?;
                         }
-                        _ => (),
                     }
+                    MemberAccessType::Symbol => (),
                 }
-                _ => other_nodes.push(node),
             }
         }
-        Ok(StageResult::Preprocessor(
-            self.global_scope
-                .check_all_types_after_pre_resolve()
-                .map_err(|err| ErrorWithRange { err, range: 0..1 })?,
-            other_nodes,
-        ))
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// The primitive type, that a name refers to. Other names stay symbols
+fn named_type(type_of: &TypeSymbolType) -> TypeSymbolType {
+    match type_of {
+        TypeSymbolType::Symbol(name) => PRIMITIVES
+            .into_iter()
+            .find(|(primitive, _)| primitive == name)
+            .map_or_else(|| type_of.clone(), |(_, primitive)| primitive),
+        _ => type_of.clone(),
+    }
+}
+
+/// The type of a literal or an operation, if it is known without executing it
+fn static_type_of(node: &AstNode) -> Option<TypeSymbolType> {
+    match &node.type_of {
+        AstNodeType::Int(_) => Some(TypeSymbolType::Int),
+        AstNodeType::Float(_) => Some(TypeSymbolType::Float),
+        AstNodeType::Bool(_) => Some(TypeSymbolType::Bool),
+        AstNodeType::String(_) | AstNodeType::Interpolation(_) => Some(TypeSymbolType::String),
+        AstNodeType::PrefixCall(PrefixOperator::Not, _) => Some(TypeSymbolType::Bool),
+        AstNodeType::InfixCall(
+            _,
+            InfixOperator::And
+            | InfixOperator::Or
+            | InfixOperator::Equals
+            | InfixOperator::NotEquals
+            | InfixOperator::LessThan
+            | InfixOperator::LessThanEquals
+            | InfixOperator::GreaterThan
+            | InfixOperator::GreaterThanEquals,
+            _,
+        ) => Some(TypeSymbolType::Bool),
+        _ => None,
+    }
+}
+
+/// Returns a pattern, that is not covered by any of the patterns of a value of the type, if there is one.
+/// Options, results and bools are checked by their variants, any other type needs a pattern matching all values
+/// NOTE: struct patterns only count, if they name the type and all of their fields are irrefutable
+fn missing_pattern(
+    patterns: &[&Pattern],
+    type_of: Option<&TypeSymbolType>,
+    structs: &HashMap<Symbol, Vec<(Symbol, TypeSymbol)>>,
+) -> Option<String> {
+    if patterns.iter().any(|p| p.is_irrefutable(type_of, structs)) {
+        return None;
     }
+
+    let inner_of = |select: fn(&Pattern) -> Option<&Pattern>| {
+        patterns
+            .iter()
+            .filter_map(|p| select(p))
+            .collect::<Vec<_>>()
+    };
+    let has = |select: fn(&Pattern) -> bool| patterns.iter().any(|p| select(p));
+
+    if has(|p| matches!(p, Pattern::None | Pattern::Some(_))) {
+        if !has(|p| matches!(p, Pattern::None)) {
+            return Some("none".to_owned());
+        }
+        let some = inner_of(|p| match p {
+            Pattern::Some(inner) => Some(inner),
+            _ => None,
+        });
+        let inner = match type_of {
+            Some(TypeSymbolType::Option(inner)) => Some(&inner.type_of),
+            _ => None,
+        };
+        return missing_pattern(&some, inner, structs).map(|missing| format!("some({missing})"));
+    }
+
+    if has(|p| matches!(p, Pattern::Ok(_) | Pattern::Err(_))) {
+        let ok = inner_of(|p| match p {
+            Pattern::Ok(inner) => Some(inner),
+            _ => None,
+        });
+        let (ok_type, err_type) = match type_of {
+            Some(TypeSymbolType::Result(ok, err)) => (Some(&ok.type_of), Some(&err.type_of)),
+            _ => (None, None),
+        };
+        if let Some(missing) = missing_pattern(&ok, ok_type, structs) {
+            return Some(format!("ok({missing})"));
+        }
+        let err = inner_of(|p| match p {
+            Pattern::Err(inner) => Some(inner),
+            _ => None,
+        });
+        return missing_pattern(&err, err_type, structs).map(|missing| format!("err({missing})"));
+    }
+
+    if has(|p| matches!(p, Pattern::Bool(_))) {
+        for b in [true, false] {
+            if !patterns.iter().any(|p| matches!(p, Pattern::Bool(v) if *v == b)) {
+                return Some(b.to_string());
+            }
+        }
+        return None;
+    }
+
+    Some("_".to_owned())
 }
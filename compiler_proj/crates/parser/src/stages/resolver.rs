@@ -0,0 +1,709 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+use crate::{
+    AstNode, AstNodeType, Diagnostics, Error, ErrorWithRange, FunctionExecutionStrategy,
+    FunctionType, MemberAccessType, Scope, Slot, Stage, StageResult, Symbol,
+    SystemExecutionStrategy, TypeSymbol, TypeSymbolType, similar_name,
+};
+
+/// Resolves every local variable to the scope it lives in at runtime and to its slot in there,
+/// so the interpreter indexes its environments instead of looking up names.
+/// Variables, that are used before they are declared, declared twice in the same scope or never declared at all, are reported before anything runs.
+/// NOTE: only the bodies in the global scope are resolved, the remaining ast is not executed, except in the repl
+#[derive(Default)]
+pub struct Resolver {
+    ast: Vec<AstNode>,
+    global_scope: Scope,
+    statements: bool,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also resolves the statements outside of any body, they are executed in the global scope by the repl.
+    /// Their declarations become globals, so later inputs still see them
+    pub fn with_statements(mut self) -> Self {
+        self.statements = true;
+        self
+    }
+
+    /// The errors are ordered by their position in the source, the first one is never missing
+    fn resolve(mut self) -> Result<StageResult, Vec<ErrorWithRange>> {
+        let mut globals = Globals::of(&self.global_scope);
+        let mut errors = Vec::new();
+
+        // NOTE: statements run before any of the bodies is called, so the bodies see their declarations
+        if self.statements {
+            for node in &mut self.ast {
+                resolve_statement(node, &mut globals, &mut errors);
+            }
+        }
+
+        // NOTE: the bodies are independent of each other, an error in one doesn't hide the ones of the others
+        for (_, type_of) in self.global_scope.iter_types_mut() {
+            resolve_type(type_of, &globals, &mut errors);
+        }
+        for (_, type_of) in self.global_scope.iter_defined_types_mut() {
+            resolve_type(type_of, &globals, &mut errors);
+        }
+
+        if errors.is_empty() {
+            Ok(StageResult::Resolved(self.global_scope, self.ast))
+        } else {
+            errors.sort_by_key(|err| err.range.start);
+            Err(errors)
+        }
+    }
+}
+
+impl Stage for Resolver {
+    fn init(&mut self, prev_stage_result: StageResult) -> Result<(), ErrorWithRange> {
+        match prev_stage_result {
+            StageResult::Preprocessor(global_scope, ast) => {
+                self.global_scope = global_scope;
+                self.ast = ast;
+                Ok(())
+            }
+            _ => Err(ErrorWithRange::new(
+                Error::StageError(2, prev_stage_result.into()),
+                0..1,
+            )),
+        }
+    }
+
+    fn run(self) -> Result<StageResult, ErrorWithRange> {
+        self.resolve().map_err(|mut errors| errors.remove(0))
+    }
+
+    /// Reports the errors of all bodies, not only the first one
+    fn run_collecting(self, diagnostics: &mut Diagnostics) -> Option<StageResult> {
+        self.resolve()
+            .map_err(|errors| {
+                diagnostics.extend(errors.iter().flat_map(ErrorWithRange::diagnostics))
+            })
+            .ok()
+    }
+}
+
+/// Everything declared in the global scope, it is still looked up by name at runtime
+struct Globals {
+    names: HashSet<Symbol>,
+    /// Aliases of imports, i.e. lib of lib.add_one
+    namespaces: HashSet<Symbol>,
+}
+
+impl Globals {
+    fn of(global_scope: &Scope) -> Self {
+        let names = global_scope
+            .iter_values()
+            .map(|(name, _)| name)
+            .chain(global_scope.iter_types().map(|(name, _)| name))
+            .chain(global_scope.iter_defined_types().map(|(name, _)| name))
+            .cloned()
+            .collect::<HashSet<_>>();
+        let namespaces = names
+            .iter()
+            .filter_map(|name| {
+                name.split_once('.')
+                    .map(|(namespace, _)| namespace.to_owned())
+            })
+            .collect();
+
+        Self { names, namespaces }
+    }
+
+    fn contains(&self, name: &Symbol) -> bool {
+        self.names.contains(name) || self.namespaces.contains(name)
+    }
+}
+
+fn resolve_type(type_of: &mut TypeSymbol, globals: &Globals, errors: &mut Vec<ErrorWithRange>) {
+    match &mut type_of.type_of {
+        TypeSymbolType::Function(fn_type) => resolve_function(fn_type, globals, errors),
+        TypeSymbolType::System(system) => {
+            if let SystemExecutionStrategy::Interpreted(body) = &mut system.execution_body {
+                let mut binders = system
+                    .params
+                    .iter()
+                    .map(|(name, _)| name.clone())
+                    .collect::<Vec<_>>();
+                binders.extend(
+                    system
+                        .queries
+                        .iter()
+                        .flatten()
+                        .map(|query| query.symbol.clone()),
+                );
+                let mut resolver = FunctionResolver::new(globals);
+                resolver.resolve_block(&binders, body);
+                errors.append(&mut resolver.errors);
+            }
+        }
+        TypeSymbolType::Struct(struct_type) => {
+            for (_, method) in struct_type
+                .methods
+                .iter_mut()
+                .chain(struct_type.statics.iter_mut())
+            {
+                resolve_function(method, globals, errors);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Resolves a statement of the global scope, a declaration adds a global instead of taking a slot
+fn resolve_statement(node: &mut AstNode, globals: &mut Globals, errors: &mut Vec<ErrorWithRange>) {
+    let mut resolver = FunctionResolver::new(globals);
+    match &mut node.type_of {
+        AstNodeType::Declaration {
+            new_symbol,
+            expression,
+            ..
+        } => {
+            resolver.resolve(expression);
+            errors.append(&mut resolver.errors);
+            globals.names.insert(new_symbol.clone());
+        }
+        AstNodeType::EntityDeclaration { new_symbol } => {
+            globals.names.insert(new_symbol.clone());
+        }
+        _ => {
+            resolver.resolve(node);
+            errors.append(&mut resolver.errors);
+        }
+    }
+}
+
+fn resolve_function(
+    fn_type: &mut FunctionType,
+    globals: &Globals,
+    errors: &mut Vec<ErrorWithRange>,
+) {
+    if let FunctionExecutionStrategy::Interpreted(body) = &mut fn_type.execution_body {
+        let params = fn_type
+            .params
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        let mut resolver = FunctionResolver::new(globals);
+        resolver.resolve_block(&params, body);
+        errors.append(&mut resolver.errors);
+    }
+}
+
+/// A scope, as the interpreter creates it at runtime
+struct StaticScope {
+    /// The slots of all variables of the scope, they are known before the variables are declared
+    slots: HashMap<Symbol, usize>,
+    declared: HashSet<Symbol>,
+    next_slot: usize,
+    /// How deep the closure, the scope belongs to, is nested in the resolved function
+    function: usize,
+}
+
+/// Resolves a declared function, including the closures it creates.
+/// NOTE: declared functions can't see the locals of their caller, only closures see the locals of their creator
+struct FunctionResolver<'a> {
+    globals: &'a Globals,
+    /// Innermost scope last
+    scopes: Vec<StaticScope>,
+    functions: usize,
+    /// Resolution continues after an error, so every one of the body is reported
+    errors: Vec<ErrorWithRange>,
+}
+
+impl<'a> FunctionResolver<'a> {
+    fn new(globals: &'a Globals) -> Self {
+        Self {
+            globals,
+            scopes: Vec::new(),
+            functions: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Enters a scope, the binders (i.e. params) take the first slots, followed by the declarations of the block
+    fn enter(&mut self, binders: &[Symbol], block: &[Box<AstNode>]) {
+        let mut scope = StaticScope {
+            slots: HashMap::new(),
+            declared: binders.iter().cloned().collect(),
+            next_slot: binders.len(),
+            function: self.functions,
+        };
+        for (index, binder) in binders.iter().enumerate() {
+            scope.slots.insert(binder.clone(), index);
+        }
+
+        for node in block {
+            let (AstNodeType::Declaration { new_symbol, .. }
+            | AstNodeType::EntityDeclaration { new_symbol }) = &node.type_of
+            else {
+                continue;
+            };
+            if scope.slots.contains_key(new_symbol) {
+                self.errors.push(ErrorWithRange::new(
+                    Error::VariableAlreadyDeclared(new_symbol.clone()),
+                    node.range.clone(),
+                ));
+                continue;
+            }
+            scope.slots.insert(new_symbol.clone(), scope.next_slot);
+            scope.next_slot += 1;
+        }
+
+        self.scopes.push(scope);
+    }
+
+    fn exit(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn resolve_block(&mut self, binders: &[Symbol], block: &mut [Box<AstNode>]) {
+        self.enter(binders, block);
+        self.resolve_all(block);
+        self.exit();
+    }
+
+    /// Declares a variable in the innermost scope and returns its slot
+    fn declare(&mut self, name: &Symbol) -> usize {
+        let scope = self
+            .scopes
+            .last_mut()
+            .expect("declarations are always inside of a scope");
+        scope.declared.insert(name.clone());
+        match scope.slots.get(name) {
+            Some(index) => *index,
+            None => {
+                scope.slots.insert(name.clone(), scope.next_slot);
+                scope.next_slot += 1;
+                scope.next_slot - 1
+            }
+        }
+    }
+
+    /// Finds the slot of a variable, None if it is a global or unknown. Unknown variables are reported
+    fn lookup(&mut self, name: &Symbol, range: &Range<usize>) -> Option<Slot> {
+        let mut declared_later = false;
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            let Some(index) = scope.slots.get(name) else {
+                continue;
+            };
+            // NOTE: closures run after they are created, they may use what their creator declares afterwards (i.e. to recurse)
+            if scope.declared.contains(name) || scope.function < self.functions {
+                return Some(Slot {
+                    depth,
+                    index: *index,
+                });
+            }
+            declared_later = true;
+        }
+
+        if self.globals.contains(name) {
+            return None;
+        }
+        let err = if declared_later {
+            ErrorWithRange::new(Error::UsedBeforeDeclaration(name.clone()), range.clone())
+        } else {
+            // NOTE: the ranges of assignments and member accesses start with the name
+            let name_range = range.start..range.start + name.len();
+            ErrorWithRange::new(Error::SymbolNotFound(name.clone()), range.clone())
+                .suggest(Some(name_range), |name| self.similar_name(name))
+        };
+        self.errors.push(err);
+        None
+    }
+
+    /// The local or global, that the name is most likely a typo of
+    fn similar_name(&self, name: &str) -> Option<Symbol> {
+        let locals = self.scopes.iter().flat_map(|scope| scope.slots.keys());
+        let names = locals.chain(&self.globals.names).map(String::as_str);
+        similar_name(name, names).map(str::to_owned)
+    }
+
+    fn resolve_all(&mut self, nodes: &mut [Box<AstNode>]) {
+        for node in nodes {
+            self.resolve(node);
+        }
+    }
+
+    fn resolve(&mut self, node: &mut AstNode) {
+        match &mut node.type_of {
+            AstNodeType::List(items) | AstNodeType::Interpolation(items) => self.resolve_all(items),
+            AstNodeType::Map(entries) => {
+                for (key, value) in entries {
+                    self.resolve(key);
+                    self.resolve(value);
+                }
+            }
+            AstNodeType::Option(Some(inner))
+            | AstNodeType::Result(Ok(inner))
+            | AstNodeType::Result(Err(inner))
+            | AstNodeType::PrefixCall(_, inner)
+            | AstNodeType::Weak(inner)
+            | AstNodeType::Propagate(inner)
+            | AstNodeType::ExpressionStatement(inner)
+            | AstNodeType::ReturnStatement {
+                return_value: inner,
+            } => self.resolve(inner),
+            AstNodeType::Declaration {
+                new_symbol,
+                expression,
+                slot,
+                ..
+            } => {
+                // NOTE: the expression is evaluated before the variable exists, i.e. a := a + 1 uses an outer a
+                self.resolve(expression);
+                *slot = Some(Slot {
+                    depth: 0,
+                    index: self.declare(new_symbol),
+                });
+            }
+            AstNodeType::EntityDeclaration { new_symbol } => {
+                self.declare(new_symbol);
+            }
+            AstNodeType::AssignmentOp {
+                recipient,
+                expression,
+                slot,
+                ..
+            } => {
+                self.resolve(expression);
+                *slot = self.lookup(recipient, &node.range);
+            }
+            AstNodeType::InfixCall(left, _, right) => {
+                self.resolve(left);
+                self.resolve(right);
+            }
+            AstNodeType::MemberCall { calls } => {
+                // NOTE: only the first member is a variable, the following ones are looked up in its value
+                for (i, call) in calls.iter_mut().enumerate() {
+                    match &mut call.type_of {
+                        MemberAccessType::Symbol => (),
+                        MemberAccessType::Function(params) => self.resolve_all(params),
+                        // NOTE: struct literals name a defined type
+                        MemberAccessType::Struct(fields) => {
+                            for (_, value) in fields {
+                                self.resolve(value);
+                            }
+                            continue;
+                        }
+                    }
+                    if i == 0 {
+                        call.slot = self.lookup(&call.member, &call.range);
+                    }
+                }
+            }
+            AstNodeType::Branch {
+                cond,
+                body,
+                else_if_branches,
+                else_branch,
+            } => {
+                self.resolve(cond);
+                self.resolve_block(&[], body);
+                for (cond, body) in else_if_branches {
+                    self.resolve(cond);
+                    self.resolve_block(&[], body);
+                }
+                if let Some(body) = else_branch {
+                    self.resolve_block(&[], body);
+                }
+            }
+            AstNodeType::While { cond, body, .. } => {
+                self.resolve(cond);
+                self.resolve_block(&[], body);
+            }
+            AstNodeType::ForEach {
+                recipient,
+                iterable,
+                body,
+                ..
+            } => {
+                self.resolve(iterable);
+                // NOTE: the recipient lives in a scope of its own, so the body may shadow it
+                self.enter(std::slice::from_ref(recipient), &[]);
+                self.resolve_block(&[], body);
+                self.exit();
+            }
+            AstNodeType::For {
+                declaration,
+                condition,
+                assignment,
+                body,
+                ..
+            } => {
+                self.enter(&[], declaration.as_slice());
+                for node in [declaration, condition, assignment].into_iter().flatten() {
+                    self.resolve(node);
+                }
+                self.resolve_block(&[], body);
+                self.exit();
+            }
+            AstNodeType::Closure {
+                params,
+                execution_body,
+                ..
+            } => {
+                let params = params
+                    .iter()
+                    .map(|(name, _)| name.clone())
+                    .collect::<Vec<_>>();
+                self.functions += 1;
+                self.resolve_block(&params, execution_body);
+                self.functions -= 1;
+            }
+            AstNodeType::Match { value, arms } => {
+                self.resolve(value);
+                // NOTE: bindings, guard and body share the scope of the arm
+                for arm in arms {
+                    let bindings = arm
+                        .pattern
+                        .bindings()
+                        .into_iter()
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    self.enter(&bindings, &arm.body);
+                    if let Some(guard) = &mut arm.guard {
+                        self.resolve(guard);
+                    }
+                    self.resolve_all(&mut arm.body);
+                    self.exit();
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        AstNode, AstNodeType, Error, ErrorWithRange, FunctionExecutionStrategy, FunctionType,
+        Interpreter, MemberAccessType, Note, Parser, Preprocessor, Resolver, Severity, Slot, Stage,
+        StageResult, Stages, TypeSymbolType, run_stages, run_stages_collecting,
+    };
+
+    type Block = Vec<Box<AstNode>>;
+
+    fn resolve(source: &str) -> Result<Block, ErrorWithRange> {
+        let stages = vec![
+            Stages::Parser(Parser::default()),
+            Stages::Preprocessor(Preprocessor::new().unwrap()),
+        ];
+        let preprocessed = run_stages(stages, StageResult::PreParse(source.to_owned())).unwrap();
+
+        let mut resolver = Resolver::new();
+        resolver.init(preprocessed).unwrap();
+        let StageResult::Resolved(scope, _) = resolver.run()? else {
+            panic!("the resolver must return the resolved program");
+        };
+
+        let Some(TypeSymbolType::Function(FunctionType {
+            execution_body: FunctionExecutionStrategy::Interpreted(body),
+            ..
+        })) = scope.resolve_type(&"main".to_owned()).map(|t| t.type_of)
+        else {
+            panic!("main must be an interpreted function");
+        };
+        Ok(body)
+    }
+
+    fn slot(depth: usize, index: usize) -> Option<Slot> {
+        Some(Slot { depth, index })
+    }
+
+    fn first_member_slot(mut node: &AstNode) -> Option<Slot> {
+        if let AstNodeType::ExpressionStatement(expression) = &node.type_of {
+            node = expression;
+        }
+        let AstNodeType::MemberCall { calls } = &node.type_of else {
+            panic!("expected a member call, got {node:?}");
+        };
+        calls[0].slot
+    }
+
+    #[test]
+    fn annotates_slots() {
+        let body = resolve(
+            r#"fn main() {
+                a := 1;
+                if (a > 0) {
+                    b := a;
+                    a = b;
+                }
+                println(a);
+            }"#,
+        )
+        .unwrap();
+
+        let AstNodeType::Declaration { slot: a, .. } = &body[0].type_of else {
+            panic!("expected the declaration of a");
+        };
+        assert_eq!(*a, slot(0, 0));
+
+        let AstNodeType::Branch { body: branch, .. } = &body[1].type_of else {
+            panic!("expected the branch");
+        };
+        let AstNodeType::Declaration {
+            slot: b,
+            expression,
+            ..
+        } = &branch[0].type_of
+        else {
+            panic!("expected the declaration of b");
+        };
+        assert_eq!(*b, slot(0, 0));
+        assert_eq!(first_member_slot(expression), slot(1, 0));
+        let AstNodeType::AssignmentOp { slot: a, .. } = &branch[1].type_of else {
+            panic!("expected the assignment to a");
+        };
+        assert_eq!(*a, slot(1, 0));
+
+        // NOTE: println is a global, its argument a local
+        assert_eq!(first_member_slot(&body[2]), None);
+        let AstNodeType::ExpressionStatement(call) = &body[2].type_of else {
+            panic!("expected the call of println as a statement");
+        };
+        let AstNodeType::MemberCall { calls } = &call.type_of else {
+            panic!("expected the call of println");
+        };
+        let MemberAccessType::Function(params) = &calls[0].type_of else {
+            panic!("expected the call of println");
+        };
+        assert_eq!(first_member_slot(&params[0]), slot(0, 0));
+    }
+
+    #[test]
+    fn reports_static_errors() {
+        let cases = [
+            (
+                "fn main() { println(a); a := 1; }",
+                Error::UsedBeforeDeclaration("a".to_owned()),
+                "a",
+            ),
+            (
+                "fn main() { a := 1; a := 2; }",
+                Error::VariableAlreadyDeclared("a".to_owned()),
+                "a := 2",
+            ),
+            (
+                "fn f(a: int) { a := 1; } fn main() {}",
+                Error::VariableAlreadyDeclared("a".to_owned()),
+                "a := 1",
+            ),
+            (
+                "fn main() { a = 1; }",
+                Error::SymbolNotFound("a".to_owned()),
+                "a = 1",
+            ),
+            // NOTE: functions don't see the locals of their caller
+            (
+                "fn f(): int { return a; } fn main() { a := 1; f(); }",
+                Error::SymbolNotFound("a".to_owned()),
+                "a",
+            ),
+            (
+                "fn main() { y = 1; } fn f() { x = 1; }",
+                Error::SymbolNotFound("y".to_owned()),
+                "y = 1",
+            ),
+        ];
+
+        for (source, expected, at) in cases {
+            let err = resolve(source).expect_err(source);
+            assert_eq!(err.err.to_string(), expected.to_string(), "{source}");
+            assert_eq!(&source[err.range.clone()], at, "{source}");
+        }
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let source = "fn main() { z = 3; w = 4; a := 1; a := 2; a := 3; }\n\
+                      fn f(): int { return y; }";
+        let stages = vec![
+            Stages::Parser(Parser::default()),
+            Stages::Preprocessor(Preprocessor::new().unwrap()),
+            Stages::Resolver(Resolver::new()),
+        ];
+        let mut diagnostics = Vec::new();
+        let result = run_stages_collecting(
+            stages,
+            StageResult::PreParse(source.to_owned()),
+            &mut diagnostics,
+        );
+        assert!(result.is_none());
+
+        let errors = diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .map(|d| &source[d.range.clone()])
+            .collect::<Vec<_>>();
+        assert_eq!(errors, ["z = 3", "w = 4", "a := 2", "a := 3", "y"]);
+    }
+
+    #[test]
+    fn suggests_similar_names() {
+        let cases = [
+            ("fn main() { speed := 1; sped += 2; }", "sped", "speed"),
+            ("fn main() { printn(1); }", "printn", "println"),
+            (
+                "fn main() {} fn f(health: int): int { return helth; }",
+                "helth",
+                "health",
+            ),
+        ];
+
+        for (source, at, similar) in cases {
+            let err = resolve(source).expect_err(source);
+            let [Note::Suggestion(Some(range), suggested)] = &*err.notes else {
+                panic!("expected a suggestion for {source}, got {:?}", err.notes);
+            };
+            assert_eq!(&source[range.clone()], at, "{source}");
+            assert_eq!(suggested, similar, "{source}");
+        }
+
+        let err = resolve("fn main() { speed := 1; zzz = 2; }").unwrap_err();
+        assert!(err.notes.is_empty());
+    }
+
+    #[test]
+    fn closures_use_later_declarations() {
+        let source = r#"
+            fn main() {
+                fact := fn (n: int): int {
+                    if (n < 2) {
+                        return 1;
+                    }
+                    return n * fact(n - 1);
+                };
+                assert(fact(5) == 120);
+
+                later_value := fn (): int => later;
+                later := 3;
+                assert(later_value() == 3);
+
+                a := 1;
+                if (true) {
+                    a := 2;
+                    a += 1;
+                    assert(a == 3);
+                }
+                assert(a == 1);
+            }
+        "#;
+
+        let stages = vec![
+            Stages::Parser(Parser::default()),
+            Stages::Preprocessor(Preprocessor::new().unwrap()),
+            Stages::Resolver(Resolver::new()),
+            Stages::Interpreter(Interpreter::new("main".to_owned())),
+        ];
+        run_stages(stages, StageResult::PreParse(source.to_owned())).unwrap();
+    }
+}
@@ -1,16 +1,28 @@
-use crate::{AstNode, ErrorWithRange, Interpreter, Parser, Preprocessor, Scope};
+use crate::{
+    AstNode, Compiler, CycleDetector, Diagnostics, ErrorWithRange, Interpreter, Optimizer, Parser,
+    Preprocessor, Program, Resolver, Scope, Vm, Warnings,
+};
 
 pub enum Stages {
     Parser(Parser),
     Preprocessor(Preprocessor),
+    CycleDetector(CycleDetector),
+    Optimizer(Optimizer),
+    Resolver(Resolver),
     Interpreter(Interpreter),
+    Compiler(Compiler),
+    Vm(Vm),
 }
 
+#[derive(Debug)]
 pub enum StageResult {
     PreParse(String),
     Parsing(Vec<AstNode>),
     Preprocessor(Scope, Vec<AstNode>),
     Interpretation,
+    Compiled(Program),
+    /// Same as Preprocessor, but every local variable has its slot assigned
+    Resolved(Scope, Vec<AstNode>),
 }
 
 impl From<StageResult> for usize {
@@ -20,6 +32,8 @@ impl From<StageResult> for usize {
             StageResult::Parsing(_) => 1,
             StageResult::Preprocessor(_, _) => 2,
             StageResult::Interpretation => 3,
+            StageResult::Compiled(_) => 4,
+            StageResult::Resolved(_, _) => 5,
         }
     }
 }
@@ -27,6 +41,27 @@ impl From<StageResult> for usize {
 pub trait Stage {
     fn init(&mut self, prev_stage_result: StageResult) -> Result<(), ErrorWithRange>;
     fn run(self) -> Result<StageResult, ErrorWithRange>;
+
+    /// The warnings of the stage, for stages that report them
+    fn shared_warnings(&self) -> Option<Warnings> {
+        None
+    }
+
+    /// Runs the stage and adds its errors, warnings and notes to the diagnostics.
+    /// The result is None, if the stage had errors. Stages, that continue after an error, override it
+    fn run_collecting(self, diagnostics: &mut Diagnostics) -> Option<StageResult>
+    where
+        Self: Sized,
+    {
+        let warnings = self.shared_warnings();
+        let result = self.run();
+        if let Some(warnings) = warnings {
+            diagnostics.extend(warnings.borrow().iter().map(|warning| warning.diagnostic()));
+        }
+        result
+            .map_err(|err| diagnostics.extend(err.diagnostics()))
+            .ok()
+    }
 }
 
 pub fn run_stages(
@@ -43,12 +78,70 @@ pub fn run_stages(
                 p.init(state)?;
                 state = p.run()?;
             }
+            Stages::CycleDetector(mut c) => {
+                c.init(state)?;
+                state = c.run()?;
+            }
+            Stages::Optimizer(mut o) => {
+                o.init(state)?;
+                state = o.run()?;
+            }
+            Stages::Resolver(mut r) => {
+                r.init(state)?;
+                state = r.run()?;
+            }
             Stages::Interpreter(mut i) => {
                 i.init(state)?;
                 state = i.run()?;
             }
+            Stages::Compiler(mut c) => {
+                c.init(state)?;
+                state = c.run()?;
+            }
+            Stages::Vm(mut v) => {
+                v.init(state)?;
+                state = v.run()?;
+            }
         }
     }
 
     Ok(state)
 }
+
+/// Initializes and runs the stage, adds its diagnostics
+fn run_collecting(
+    mut stage: impl Stage,
+    state: StageResult,
+    diagnostics: &mut Diagnostics,
+) -> Option<StageResult> {
+    match stage.init(state) {
+        Ok(()) => stage.run_collecting(diagnostics),
+        Err(err) => {
+            diagnostics.extend(err.diagnostics());
+            None
+        }
+    }
+}
+
+/// Runs the stages like run_stages, but collects the errors, warnings and notes of all stages.
+/// Stops after the first stage with errors, as the later stages depend on its result
+pub fn run_stages_collecting(
+    stages: Vec<Stages>,
+    mut state: StageResult,
+    diagnostics: &mut Diagnostics,
+) -> Option<StageResult> {
+    for stage in stages {
+        state = match stage {
+            Stages::Parser(p) => run_collecting(p, state, diagnostics)?,
+            Stages::Preprocessor(p) => run_collecting(p, state, diagnostics)?,
+            Stages::CycleDetector(c) => run_collecting(c, state, diagnostics)?,
+            Stages::Optimizer(o) => run_collecting(o, state, diagnostics)?,
+            Stages::Resolver(r) => run_collecting(r, state, diagnostics)?,
+            Stages::Interpreter(i) => run_collecting(i, state, diagnostics)?,
+            Stages::Compiler(c) => run_collecting(c, state, diagnostics)?,
+            Stages::Vm(v) => run_collecting(v, state, diagnostics)?,
+        };
+    }
+
+    Some(state)
+}
@@ -0,0 +1,573 @@
+use std::{cell::RefCell, fmt::Debug, ops::Range, rc::Rc};
+
+use crate::{
+    Callee, Error, ErrorWithRange, FunctionExecutionStrategy, Instruction, Interpreter,
+    InterpreterValue, IsReturn, PrefixOperator, Program, Scope, Stage, StageResult, Symbol,
+    TypeSymbolType,
+};
+
+/// The slots of a function call. Closures keep the frame they were created in alive
+pub struct Frame {
+    slots: Vec<InterpreterValue>,
+    parent: Option<Rc<RefCell<Frame>>>,
+}
+
+impl Frame {
+    /// The frame depth levels up, the frame of a closure is followed by the frame it captured
+    fn ancestor(frame: &Rc<RefCell<Frame>>, depth: usize) -> Rc<RefCell<Frame>> {
+        let mut current = Rc::clone(frame);
+        for _ in 0..depth {
+            let parent = current
+                .borrow()
+                .parent
+                .clone()
+                .expect("the compiler resolved the depth");
+            current = parent;
+        }
+        current
+    }
+}
+
+// NOTE: frames may contain closures, that captured them, so only the size is printed
+impl Debug for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Frame({} slots)", self.slots.len())
+    }
+}
+
+/// Executes the compiled program on an operand stack, every call runs in a new frame
+pub struct Vm {
+    program: Rc<Program>,
+    globals: Vec<InterpreterValue>,
+    stack: Vec<InterpreterValue>,
+    entrypoint_fn: Symbol,
+    strict: bool,
+}
+
+impl Vm {
+    pub fn new(entrypoint_fn: Symbol) -> Self {
+        Self {
+            program: Rc::new(Program::default()),
+            globals: Vec::new(),
+            stack: Vec::new(),
+            entrypoint_fn,
+            strict: false,
+        }
+    }
+
+    /// Strict mode: arithmetic on an int and a float is an error, instead of converting the int to a float
+    pub fn with_strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    fn pop(&mut self) -> InterpreterValue {
+        self.stack.pop().expect("the compiler balanced the stack")
+    }
+
+    fn pop_n(&mut self, n: usize) -> Vec<InterpreterValue> {
+        self.stack.split_off(self.stack.len() - n)
+    }
+
+    fn pop_not_empty(&mut self) -> Result<InterpreterValue, Error> {
+        match self.pop() {
+            InterpreterValue::Empty => Err(Error::CantBeEmpty),
+            value => Ok(value),
+        }
+    }
+
+    pub fn call_function(
+        &mut self,
+        index: usize,
+        args: Vec<InterpreterValue>,
+        parent: Option<Rc<RefCell<Frame>>>,
+        name: &Symbol,
+        range: &Range<usize>,
+    ) -> Result<InterpreterValue, ErrorWithRange> {
+        let program = Rc::clone(&self.program);
+        let function = &program.functions[index];
+        if function.params.len() != args.len() {
+            return Err(ErrorWithRange::new(
+                Error::WrongArgumentCount(name.clone(), function.params.len(), args.len()),
+                range.clone(),
+            ));
+        }
+        if let Some((param, _)) = function
+            .params
+            .iter()
+            .zip(&args)
+            .find(|(_, arg)| matches!(arg, InterpreterValue::Empty))
+        {
+            return Err(ErrorWithRange::new(
+                Error::ExpectedValue(param.clone()),
+                range.clone(),
+            ));
+        }
+
+        let mut slots = args;
+        slots.resize(function.slots, InterpreterValue::Empty);
+        let frame = Rc::new(RefCell::new(Frame { slots, parent }));
+        self.execute(index, frame, name)
+            .map_err(|err| err.in_call(name, range))
+    }
+
+    /// Buildins are called like in the interpreter, with their params declared in a scope
+    pub fn call_buildin(
+        &mut self,
+        index: usize,
+        args: Vec<InterpreterValue>,
+        range: &Range<usize>,
+    ) -> Result<InterpreterValue, ErrorWithRange> {
+        let with_range = |err| ErrorWithRange::new(err, range.clone());
+        let fn_type = &self.program.buildins[index];
+        let FunctionExecutionStrategy::Buildin(callback) = fn_type.execution_body else {
+            unreachable!("only buildins are registered as buildin");
+        };
+
+        let mut scope = Scope::default();
+        for (value, (param, type_of)) in args.into_iter().zip(&fn_type.params) {
+            if let InterpreterValue::Empty = value {
+                return Err(with_range(Error::ExpectedValue(param.clone())));
+            }
+            scope
+                .declare_variable(
+                    param.clone(),
+                    value,
+                    type_of.clone(),
+                    true,
+                    true,
+                    range.clone(),
+                )
+                .map_err(with_range)?;
+        }
+
+        match callback(Rc::new(RefCell::new(scope))).map_err(with_range)? {
+            IsReturn::Return(v) => Ok(v),
+            IsReturn::NoReturn(v) if fn_type.return_type.is_some() => Ok(v),
+            IsReturn::NoReturn(_) => Ok(InterpreterValue::Empty),
+            IsReturn::Break(_) | IsReturn::Continue(_) => Err(with_range(
+                Error::LoopControlOutsideLoop("break or continue".to_owned()),
+            )),
+        }
+    }
+
+    /// Calls a function value, i.e. a declared function or a closure
+    pub fn call_value(
+        &mut self,
+        callee: InterpreterValue,
+        args: Vec<InterpreterValue>,
+        name: &Symbol,
+        range: &Range<usize>,
+    ) -> Result<InterpreterValue, ErrorWithRange> {
+        let callee = InterpreterValue::preprocess_single(callee)
+            .map_err(|err| ErrorWithRange::new(err, range.clone()))?;
+
+        match callee {
+            InterpreterValue::Function(function) => match self.program.callees.get(&function) {
+                Some(Callee::Function(index)) => {
+                    self.call_function(*index, args, None, name, range)
+                }
+                Some(Callee::Buildin(index)) => self.call_buildin(*index, args, range),
+                None => Err(ErrorWithRange::new(
+                    Error::SymbolNotFound(function),
+                    range.clone(),
+                )),
+            },
+            InterpreterValue::CompiledClosure(index, frame) => {
+                self.call_function(index, args, Some(frame), name, range)
+            }
+            _ => Err(ErrorWithRange::new(
+                Error::NotCallable(name.clone()),
+                range.clone(),
+            )),
+        }
+    }
+
+    fn call_method(
+        &mut self,
+        receiver: InterpreterValue,
+        member: &Symbol,
+        args: Vec<InterpreterValue>,
+        range: &Range<usize>,
+    ) -> Result<InterpreterValue, ErrorWithRange> {
+        let with_range = |err| ErrorWithRange::new(err, range.clone());
+
+        if Interpreter::has_buildin_methods(&receiver) {
+            let receiver = Interpreter::buildin_method_receiver(receiver, member, args.len())
+                .map_err(with_range)?;
+            let args = args
+                .into_iter()
+                .map(|arg| (arg, range.clone()))
+                .collect::<Vec<_>>();
+            return Interpreter::apply_buildin_method(
+                receiver,
+                member,
+                &args,
+                range,
+                &mut |f, arg, range| self.call_value(f.clone(), vec![arg], member, range),
+            );
+        }
+
+        // NOTE: a field, that holds a function
+        let Some(scope) = Option::<Rc<RefCell<Scope>>>::from(receiver) else {
+            return Err(with_range(Error::IsNotAScope));
+        };
+        let callee = scope.borrow().resolve_value(member);
+        let Some(callee) = callee else {
+            let name_range = range.start..range.start + member.len();
+            return Err(with_range(Error::SymbolNotFound(member.clone()))
+                .suggest(Some(name_range), |name| scope.borrow().similar_name(name)));
+        };
+        self.call_value(callee, args, member, range)
+    }
+
+    /// Creates a struct or component like the interpreter does, its scope contains its fields and itself as self
+    fn make_struct(
+        &mut self,
+        index: usize,
+        range: &Range<usize>,
+    ) -> Result<InterpreterValue, ErrorWithRange> {
+        let with_range = |err| ErrorWithRange::new(err, range.clone());
+        let program = Rc::clone(&self.program);
+        let literal = &program.structs[index];
+        let values = self.pop_n(literal.fields.len());
+
+        let (name, declared) = match &literal.type_of.type_of {
+            TypeSymbolType::Struct(s) => (&s.name, &s.fields),
+            TypeSymbolType::Component(c) => (&c.name, &c.fields),
+            _ => unreachable!("checked by the compiler"),
+        };
+
+        let struct_scope = Rc::new(RefCell::new(Scope::default()));
+        for (field, value) in literal.fields.iter().zip(values) {
+            let type_of = declared
+                .iter()
+                .find(|(name, _)| name == field)
+                .map(|(_, type_of)| type_of.clone())
+                .expect("checked by the compiler");
+            struct_scope
+                .borrow_mut()
+                // NOTE: field types were checked, when the type was declared
+                .declare_variable(field.clone(), value, type_of, true, true, range.clone())
+                .map_err(with_range)?;
+        }
+
+        let struct_value = match &literal.type_of.type_of {
+            TypeSymbolType::Struct(_) => {
+                InterpreterValue::Struct(name.clone(), Rc::clone(&struct_scope))
+            }
+            _ => InterpreterValue::Component(name.clone(), Rc::clone(&struct_scope)),
+        }
+        .make_reference_counted()
+        .map_err(with_range)?;
+
+        struct_scope
+            .borrow_mut()
+            .declare_variable(
+                "self".to_owned(),
+                struct_value.clone(),
+                literal.type_of.clone(),
+                true,
+                true,
+                range.clone(),
+            )
+            .map_err(with_range)?;
+
+        Ok(struct_value)
+    }
+
+    fn execute(
+        &mut self,
+        index: usize,
+        frame: Rc<RefCell<Frame>>,
+        name: &Symbol,
+    ) -> Result<InterpreterValue, ErrorWithRange> {
+        let program = Rc::clone(&self.program);
+        let function = &program.functions[index];
+        let base = self.stack.len();
+        let mut ip = 0;
+
+        loop {
+            let instruction = &function.code[ip];
+            let range = &function.ranges[ip];
+            let with_range = |err| ErrorWithRange::new(err, range.clone());
+            ip += 1;
+
+            match instruction {
+                Instruction::Constant(c) => self
+                    .stack
+                    .push(InterpreterValue::new_strong(program.constants[*c].clone())),
+                Instruction::Empty => self.stack.push(InterpreterValue::Empty),
+                Instruction::Pop => {
+                    self.pop();
+                }
+                Instruction::PopN(n) => {
+                    self.pop_n(*n);
+                }
+                Instruction::LoadLocal(slot) => {
+                    let value = frame.borrow().slots[*slot].clone();
+                    self.stack.push(value);
+                }
+                Instruction::StoreLocal(slot) => {
+                    let value = self.pop_not_empty().map_err(with_range)?;
+                    frame.borrow_mut().slots[*slot] = value;
+                }
+                Instruction::LoadOuter(depth, slot) => {
+                    let value = Frame::ancestor(&frame, *depth).borrow().slots[*slot].clone();
+                    self.stack.push(value);
+                }
+                Instruction::StoreOuter(depth, slot) => {
+                    let value = self.pop_not_empty().map_err(with_range)?;
+                    Frame::ancestor(&frame, *depth).borrow_mut().slots[*slot] = value;
+                }
+                Instruction::LoadGlobal(g) => self.stack.push(self.globals[*g].clone()),
+                Instruction::StoreGlobal(g) => {
+                    self.globals[*g] = self.pop_not_empty().map_err(with_range)?;
+                }
+                Instruction::Infix(op) => {
+                    let rval = self.pop();
+                    let lval = self.pop();
+                    let value = lval.apply_infix(op, rval, self.strict);
+                    let value = value
+                        .and_then(InterpreterValue::make_reference_counted)
+                        .map_err(with_range)?;
+                    self.stack.push(value);
+                }
+                Instruction::Prefix(op) => {
+                    let rval = self.pop();
+                    let value = match op {
+                        PrefixOperator::Not => rval.negate_bool(),
+                        PrefixOperator::Negate => rval.negate_number(),
+                    };
+                    let value = value
+                        .and_then(InterpreterValue::make_reference_counted)
+                        .map_err(with_range)?;
+                    self.stack.push(value);
+                }
+                Instruction::Assign(op) => {
+                    let old_value = self.pop();
+                    let value = self.pop_not_empty().map_err(with_range)?;
+                    let value = match op.infix_operator() {
+                        Some(op) => old_value.apply_infix(&op, value, self.strict),
+                        None => Ok(value),
+                    };
+                    let value = value
+                        .and_then(InterpreterValue::make_reference_counted)
+                        .map_err(with_range)?;
+                    self.stack.push(value);
+                }
+                Instruction::Downgrade => {
+                    let value = self.pop().downgrade().map_err(with_range)?;
+                    self.stack.push(value);
+                }
+                Instruction::MakeList(n) => {
+                    let values = self.pop_n(*n);
+                    self.stack.push(InterpreterValue::List(values));
+                }
+                Instruction::Concat(n) => {
+                    let mut text = String::new();
+                    for value in self.pop_n(*n) {
+                        if let InterpreterValue::Empty = value {
+                            return Err(with_range(Error::CantBeEmpty));
+                        }
+                        text.push_str(&value.to_text().map_err(with_range)?);
+                    }
+                    self.stack.push(InterpreterValue::String(text));
+                }
+                Instruction::WrapSome | Instruction::WrapOk | Instruction::WrapErr => {
+                    let inner = Box::new(self.pop_not_empty().map_err(with_range)?);
+                    let value = match instruction {
+                        Instruction::WrapSome => InterpreterValue::Option(Some(inner)),
+                        Instruction::WrapOk => InterpreterValue::Result(Ok(inner)),
+                        _ => InterpreterValue::Result(Err(inner)),
+                    };
+                    self.stack.push(InterpreterValue::new_strong(value));
+                }
+                Instruction::MakeStruct(s) => {
+                    let value = self.make_struct(*s, range)?;
+                    self.stack.push(value);
+                }
+                Instruction::MakeClosure(f) => self.stack.push(InterpreterValue::new_strong(
+                    InterpreterValue::CompiledClosure(*f, Rc::clone(&frame)),
+                )),
+                Instruction::GetField(c) => {
+                    let InterpreterValue::String(field) = &program.constants[*c] else {
+                        unreachable!("names are string constants");
+                    };
+                    let Some(scope) = Option::<Rc<RefCell<Scope>>>::from(self.pop()) else {
+                        return Err(with_range(Error::IsNotAScope));
+                    };
+                    let value = scope.borrow().resolve_value(field);
+                    let value = value.ok_or_else(|| {
+                        with_range(Error::SymbolNotFound(field.clone()))
+                            .suggest(Some(range.clone()), |name| {
+                                scope.borrow().similar_name(name)
+                            })
+                    })?;
+                    self.stack.push(value);
+                }
+                Instruction::Call(f, n) => {
+                    let args = self.pop_n(*n);
+                    let callee_name = &program.functions[*f].name;
+                    let value = self.call_function(*f, args, None, callee_name, range)?;
+                    self.stack.push(value);
+                }
+                Instruction::CallBuildin(b, n) => {
+                    let args = self.pop_n(*n);
+                    let value = self.call_buildin(*b, args, range)?;
+                    self.stack.push(value);
+                }
+                Instruction::CallValue(c, n) => {
+                    let InterpreterValue::String(callee_name) = &program.constants[*c] else {
+                        unreachable!("names are string constants");
+                    };
+                    let args = self.pop_n(*n);
+                    let callee = self.pop();
+                    let value = self.call_value(callee, args, callee_name, range)?;
+                    self.stack.push(value);
+                }
+                Instruction::CallMethod(c, n) => {
+                    let InterpreterValue::String(member) = &program.constants[*c] else {
+                        unreachable!("names are string constants");
+                    };
+                    let args = self.pop_n(*n);
+                    let receiver = self.pop();
+                    let value = self.call_method(receiver, member, args, range)?;
+                    self.stack.push(value);
+                }
+                Instruction::Jump(target) => ip = *target,
+                Instruction::JumpIfFalse(target) => {
+                    let cond =
+                        InterpreterValue::preprocess_single(self.pop()).map_err(with_range)?;
+                    let InterpreterValue::Bool(cond) = cond else {
+                        return Err(with_range(Error::OperationUnsupported {
+                            operation: "condition".to_owned(),
+                            type_of: "must be bool".to_owned(),
+                        }));
+                    };
+                    if !cond {
+                        ip = *target;
+                    }
+                }
+                Instruction::IterStart => {
+                    let list = self.pop().as_list().map_err(with_range)?;
+                    self.stack.push(InterpreterValue::List(list));
+                    self.stack.push(InterpreterValue::Int(0));
+                }
+                Instruction::IterNext(slot, target) => {
+                    let [
+                        ..,
+                        InterpreterValue::List(list),
+                        InterpreterValue::Int(position),
+                    ] = self.stack.as_mut_slice()
+                    else {
+                        unreachable!("pushed by IterStart");
+                    };
+                    match list.get(*position as usize) {
+                        Some(entry) => {
+                            frame.borrow_mut().slots[*slot] = entry.clone();
+                            *position += 1;
+                        }
+                        None => ip = *target,
+                    }
+                }
+                Instruction::MatchPattern(p, target) => {
+                    let pattern = &program.patterns[*p];
+                    let matched = self
+                        .stack
+                        .last()
+                        .expect("the matched value is on the stack");
+                    let mut bindings = Vec::new();
+                    if Interpreter::match_pattern(&pattern.pattern, matched, &mut bindings)
+                        .map_err(with_range)?
+                    {
+                        let mut frame = frame.borrow_mut();
+                        for (symbol, value) in bindings {
+                            let (_, slot) = pattern
+                                .bindings
+                                .iter()
+                                .find(|(s, _)| *s == symbol)
+                                .expect("bindings are declared by the compiler");
+                            frame.slots[*slot] = value;
+                        }
+                    } else {
+                        ip = *target;
+                    }
+                }
+                Instruction::NoMatch => {
+                    let matched = self.pop();
+                    return Err(with_range(Error::NonExhaustiveMatch(matched.to_string())));
+                }
+                Instruction::Propagate => {
+                    let value =
+                        InterpreterValue::preprocess_single(self.pop()).map_err(with_range)?;
+                    match value {
+                        InterpreterValue::Result(Ok(v)) => self.stack.push(*v),
+                        // NOTE: returns the error from the current function
+                        InterpreterValue::Result(Err(e)) => {
+                            self.stack.truncate(base);
+                            return Ok(InterpreterValue::new_strong(InterpreterValue::Result(
+                                Err(e),
+                            )));
+                        }
+                        _ => {
+                            return Err(with_range(Error::OperationUnsupported {
+                                operation: "?".to_owned(),
+                                type_of: "can only be applied to results".to_owned(),
+                            }));
+                        }
+                    }
+                }
+                Instruction::Return => {
+                    let value = self.pop();
+                    self.stack.truncate(base);
+                    return Ok(value);
+                }
+                // NOTE: the last expression of the body is returned implicitly, it is discarded if the function has no return type
+                Instruction::ReturnLast => {
+                    let value = self.pop();
+                    self.stack.truncate(base);
+                    return match value {
+                        _ if !function.returns_value => Ok(InterpreterValue::Empty),
+                        InterpreterValue::Empty => {
+                            Err(with_range(Error::MissingReturn(name.clone())))
+                        }
+                        value => Ok(value),
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl Stage for Vm {
+    fn init(&mut self, prev_stage_result: StageResult) -> Result<(), ErrorWithRange> {
+        match prev_stage_result {
+            StageResult::Compiled(program) => {
+                self.globals = program.globals.clone();
+                self.program = Rc::new(program);
+                Ok(())
+            }
+            _ => Err(ErrorWithRange::new(
+                Error::StageError(4, prev_stage_result.into()),
+                0..1,
+            )),
+        }
+    }
+
+    fn run(mut self) -> Result<StageResult, ErrorWithRange> {
+        let Some(Callee::Function(main)) = self.program.callees.get(&self.entrypoint_fn).copied()
+        else {
+            return Err(ErrorWithRange::new(
+                Error::MainNotFound(self.entrypoint_fn.clone()),
+                // NOTE: there is no node to point at
+                0..0,
+            ));
+        };
+
+        let entrypoint_fn = self.entrypoint_fn.clone();
+        self.call_function(main, vec![], None, &entrypoint_fn, &(0..0))
+            .map_err(ErrorWithRange::outside_entrypoint)?;
+        Ok(StageResult::Interpretation)
+    }
+}
@@ -0,0 +1,79 @@
+use crate::{Symbol, TypeSymbol};
+
+/// The number of characters, that have to be inserted, removed or replaced to turn a into b
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    // NOTE: the distances of the prefix of a, that was read so far, to every prefix of b
+    let mut distances = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = distances[0];
+        distances[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let replaced = diagonal + usize::from(a_char != *b_char);
+            diagonal = distances[j + 1];
+            distances[j + 1] = replaced.min(distances[j] + 1).min(diagonal + 1);
+        }
+    }
+    distances[b.len()]
+}
+
+/// The candidate, that the name is most likely a typo of. Case is ignored and a third of the
+/// characters of the name may differ. Of equally similar candidates, the first in alphabetical
+/// order is chosen, so the suggestion does not depend on the order of the candidates
+pub fn similar_name<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let lowercase = name.to_lowercase();
+    let max_distance = name.chars().count() / 3;
+
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| {
+            (
+                edit_distance(&lowercase, &candidate.to_lowercase()),
+                candidate,
+            )
+        })
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// The field of a struct or component, that the name is most likely a typo of
+pub fn similar_field(name: &str, fields: &[(Symbol, TypeSymbol)]) -> Option<Symbol> {
+    similar_name(name, fields.iter().map(|(field, _)| field.as_str())).map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit_distance, similar_name};
+
+    #[test]
+    fn measures_edit_distance() {
+        assert_eq!(edit_distance("speed", "speed"), 0);
+        assert_eq!(edit_distance("sped", "speed"), 1);
+        assert_eq!(edit_distance("Positon", "Position"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("über", "uber"), 1);
+    }
+
+    #[test]
+    fn suggests_similar_names() {
+        let candidates = ["Position", "Velocity", "speed", "health", "x"];
+        assert_eq!(similar_name("Positon", candidates), Some("Position"));
+        assert_eq!(similar_name("velocity", candidates), Some("Velocity"));
+        assert_eq!(similar_name("helth", candidates), Some("health"));
+        // short names only match, if they differ in case
+        assert_eq!(similar_name("y", candidates), None);
+        assert_eq!(similar_name("X", candidates), Some("x"));
+        assert_eq!(similar_name("Rotation", candidates), None);
+
+        // ties are broken alphabetically
+        assert_eq!(similar_name("pos", ["pas", "pis"]), Some("pas"));
+        assert_eq!(similar_name("pos", ["pis", "pas"]), Some("pas"));
+    }
+}
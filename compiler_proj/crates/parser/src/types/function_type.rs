@@ -2,7 +2,7 @@ use std::{cell::RefCell, fmt::Display, iter::zip, rc::Rc};
 
 use derivative::Derivative;
 
-use crate::{AstNode, Error, IsReturn, Scope, Symbol, TypeSymbol};
+use crate::{AstNode, DyLibName, Error, IsReturn, Scope, Symbol, TypeSymbol};
 
 pub type BuildinCallback = fn(scope: Rc<RefCell<Scope>>) -> Result<IsReturn, Error>;
 
@@ -10,6 +10,8 @@ pub type BuildinCallback = fn(scope: Rc<RefCell<Scope>>) -> Result<IsReturn, Err
 pub enum FunctionExecutionStrategy {
     Buildin(BuildinCallback),
     Interpreted(Vec<Box<AstNode>>),
+    /// Function of a native library, (library, symbol)
+    Native(DyLibName, Symbol),
 }
 
 #[derive(Derivative)]
@@ -22,9 +24,21 @@ pub struct FunctionType {
     pub execution_body: FunctionExecutionStrategy,
 }
 
+impl FunctionType {
+    /// Type of a function value without a body, i.e. fn(int, int): int
+    pub fn signature(params: Vec<TypeSymbol>, return_type: Option<TypeSymbol>) -> Self {
+        Self {
+            name: String::new(),
+            params: params.into_iter().map(|p| (String::new(), p)).collect(),
+            return_type: return_type.map(Box::new),
+            execution_body: FunctionExecutionStrategy::Interpreted(vec![]),
+        }
+    }
+}
+
 impl PartialEq for FunctionType {
     fn eq(&self, other: &Self) -> bool {
-        let mut equals = true;
+        let mut equals = self.params.len() == other.params.len();
 
         for (p1, p2) in zip(&self.params, &other.params) {
             equals = equals && p1.1 == p2.1;
@@ -45,7 +59,7 @@ impl Display for FunctionType {
                 self.name,
                 self.params
                     .iter()
-                    .map(|p| format!("{}: {}", p.0, p.1))
+                    .map(format_param)
                     .collect::<Vec<String>>()
                     .join(", "),
                 ret,
@@ -57,10 +71,19 @@ impl Display for FunctionType {
                 self.name,
                 self.params
                     .iter()
-                    .map(|p| format!("{}: {}", p.0, p.1))
+                    .map(format_param)
                     .collect::<Vec<String>>()
                     .join(", "),
             )
         }
     }
 }
+
+fn format_param(param: &(Symbol, TypeSymbol)) -> String {
+    // NOTE: params of function signatures have no name
+    if param.0.is_empty() {
+        param.1.to_string()
+    } else {
+        format!("{}: {}", param.0, param.1)
+    }
+}
@@ -1,7 +1,8 @@
 use std::{
     cell::RefCell,
+    cmp::Ordering,
     collections::HashMap,
-    fmt::Display,
+    fmt::{Display, Write},
     ops::{Add, Div, Mul, Rem, Sub},
     rc::{Rc, Weak},
 };
@@ -9,7 +10,10 @@ use std::{
 use ecs::Entity;
 use typed_generational_arena::Index;
 
-use crate::{ComponentType, Error, Scope, StructType, Symbol, TypeSymbol, TypeSymbolType};
+use crate::{
+    ComponentType, Error, Frame, FunctionType, InfixOperator, Scope, StructType, Symbol,
+    TypeSymbol, TypeSymbolType,
+};
 
 fn type_of_i_value(a: InterpreterValue) -> &'static str {
     match a {
@@ -20,9 +24,11 @@ fn type_of_i_value(a: InterpreterValue) -> &'static str {
         InterpreterValue::List(_interpreter_values) => todo!(),
         InterpreterValue::Map(_hash_map) => todo!(),
         InterpreterValue::Struct(_, _hash_map) => todo!(),
-        InterpreterValue::Option(_interpreter_value) => todo!(),
-        InterpreterValue::Result(_interpreter_value) => todo!(),
+        InterpreterValue::Option(_) => "option",
+        InterpreterValue::Result(_) => "result",
         InterpreterValue::Function(_) => todo!(),
+        InterpreterValue::Closure(_, _) => "function",
+        InterpreterValue::CompiledClosure(_, _) => "function",
         InterpreterValue::Weak(_weak) => todo!(),
         InterpreterValue::Strong(_interpreter_value) => todo!(),
         InterpreterValue::Entity(_index) => todo!(),
@@ -46,6 +52,8 @@ pub enum InterpreterValue {
     Option(Option<Box<InterpreterValue>>),
     Result(Result<Box<InterpreterValue>, Box<InterpreterValue>>),
     Function(Symbol), // Functions execution body is contained in its type definition,
+    Closure(FunctionType, CapturedScope), // Anonymous function and the scope it captured
+    CompiledClosure(usize, Rc<RefCell<Frame>>), // Function of the compiled program and the frame it captured
     // Reference counted values (everything afaik)
     Weak(Weak<InterpreterValue>),
     Strong(Rc<InterpreterValue>),
@@ -62,11 +70,60 @@ pub enum InterpreterValue {
     Empty,
 }
 
+/// The scope a closure captured. It is held weakly, while the closure is stored in that scope,
+/// which would own itself otherwise
+#[derive(Clone, Debug)]
+pub enum CapturedScope {
+    Strong(Rc<RefCell<Scope>>),
+    Weak(Weak<RefCell<Scope>>),
+}
+
+impl CapturedScope {
+    pub fn upgrade(&self) -> Option<Rc<RefCell<Scope>>> {
+        match self {
+            CapturedScope::Strong(scope) => Some(Rc::clone(scope)),
+            CapturedScope::Weak(scope) => scope.upgrade(),
+        }
+    }
+}
+
 impl InterpreterValue {
     pub fn new_strong(inner: InterpreterValue) -> InterpreterValue {
         Self::Strong(Rc::new(inner))
     }
 
+    /// The value to store in the scope. A closure, that captured the scope, only holds it weakly there
+    pub fn stored_in(self, scope: &Scope) -> InterpreterValue {
+        if let InterpreterValue::Strong(inner) = &self
+            && let InterpreterValue::Closure(fn_type, CapturedScope::Strong(captured)) =
+                inner.as_ref()
+            && std::ptr::eq(captured.as_ptr(), scope)
+        {
+            let captured = CapturedScope::Weak(Rc::downgrade(captured));
+            return InterpreterValue::new_strong(InterpreterValue::Closure(
+                fn_type.clone(),
+                captured,
+            ));
+        }
+        self
+    }
+
+    /// The value read from the scope it is stored in. A closure holds its captured scope strongly again,
+    /// so it stays callable, when it leaves the scope
+    pub fn loaded(self) -> InterpreterValue {
+        if let InterpreterValue::Strong(inner) = &self
+            && let InterpreterValue::Closure(fn_type, captured @ CapturedScope::Weak(_)) =
+                inner.as_ref()
+            && let Some(captured) = captured.upgrade()
+        {
+            return InterpreterValue::new_strong(InterpreterValue::Closure(
+                fn_type.clone(),
+                CapturedScope::Strong(captured),
+            ));
+        }
+        self
+    }
+
     pub fn make_reference_counted(self) -> Result<InterpreterValue, Error> {
         match self {
             InterpreterValue::Strong(_) => Ok(self),
@@ -84,12 +141,21 @@ impl InterpreterValue {
     }
 
     pub fn upgrade(&self) -> Result<InterpreterValue, Error> {
-        if let InterpreterValue::Weak(s) = self
-            && let Some(upgraded) = s.upgrade()
-        {
-            Ok(InterpreterValue::Strong(upgraded))
-        } else {
-            Err(Error::CantDowncastToWeak)
+        let InterpreterValue::Weak(s) = self else {
+            return Err(Error::CantUpgradeToStrong);
+        };
+
+        s.upgrade()
+            .map(InterpreterValue::Strong)
+            .ok_or(Error::DanglingWeak)
+    }
+
+    /// Upgrade as used in the language, some(value) if the referenced value still exists, none otherwise
+    pub fn upgrade_to_option(&self) -> Result<InterpreterValue, Error> {
+        match self.upgrade() {
+            Ok(value) => Ok(InterpreterValue::Option(Some(Box::new(value)))),
+            Err(Error::DanglingWeak) => Ok(InterpreterValue::Option(None)),
+            Err(err) => Err(err),
         }
     }
 
@@ -134,6 +200,51 @@ impl InterpreterValue {
         Ok((lval, rval))
     }
 
+    /// Applies the operator. In strict mode, arithmetic on an int and a float is rejected instead of converting the int
+    pub fn apply_infix(
+        self,
+        op: &InfixOperator,
+        other: Self,
+        strict: bool,
+    ) -> Result<InterpreterValue, Error> {
+        if strict && op.is_arithmetic() {
+            Self::check_strict(&self, op, &other)?;
+        }
+
+        match op {
+            InfixOperator::Plus => self + other,
+            InfixOperator::Minus => self - other,
+            InfixOperator::Multiply => self * other,
+            InfixOperator::Divide => self / other,
+            InfixOperator::Modulo => self % other,
+            InfixOperator::And => self.logical_and(other),
+            InfixOperator::Or => self.logical_or(other),
+            InfixOperator::Equals => self.equals(other),
+            InfixOperator::NotEquals => self.not_equals(other),
+            InfixOperator::LessThan => self.less_than(other),
+            InfixOperator::LessThanEquals => self.less_than_equals(other),
+            InfixOperator::GreaterThan => self.greater_than(other),
+            InfixOperator::GreaterThanEquals => self.greater_than_equals(other),
+        }
+    }
+
+    fn check_strict(left: &Self, op: &InfixOperator, right: &Self) -> Result<(), Error> {
+        let number_type = |value: &Self| match Self::preprocess_single(value.clone()) {
+            Ok(InterpreterValue::Int(_)) => Some("int"),
+            Ok(InterpreterValue::Float(_)) => Some("float"),
+            _ => None,
+        };
+
+        match (number_type(left), number_type(right)) {
+            (Some(l), Some(r)) if l != r => Err(Error::MixedTypes(
+                op.symbol().to_string(),
+                l.to_string(),
+                r.to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
     pub fn logical_and(self, other: Self) -> Result<InterpreterValue, Error> {
         let (lval, rval) = Self::preprocess_for_operation(self, other)?;
 
@@ -212,6 +323,7 @@ impl InterpreterValue {
                     ),
                 }),
             },
+            // NOTE: IEEE 754, NaN is not equal to anything, not even itself
             InterpreterValue::Float(l) => match rval {
                 InterpreterValue::Float(r) => Ok(InterpreterValue::Bool(*l == r)),
                 _ => Err(Error::OperationUnsupported {
@@ -375,36 +487,18 @@ impl InterpreterValue {
         self.equals(other)?.negate_bool()
     }
 
-    pub fn less_than(self, other: Self) -> Result<InterpreterValue, Error> {
+    /// Orders two numbers, ints are compared exactly, everything else as floats.
+    /// NOTE: follows IEEE 754, NaN is unordered, so every comparison with it is false
+    fn compare(self, other: Self, operation: &str) -> Result<Option<Ordering>, Error> {
         let (lval, rval) = Self::preprocess_for_operation(self, other)?;
 
-        match lval {
-            InterpreterValue::Int(l) => match rval {
-                InterpreterValue::Int(r) => Ok(InterpreterValue::Bool(l < r)),
-                InterpreterValue::Float(r) => Ok(InterpreterValue::Bool((l as f64) < r)),
-                _ => Err(Error::OperationUnsupported {
-                    operation: "<".to_string(),
-                    type_of: format!(
-                        "{} and {} are not compatible",
-                        type_of_i_value(lval),
-                        type_of_i_value(rval)
-                    ),
-                }),
-            },
-            InterpreterValue::Float(l) => match rval {
-                InterpreterValue::Int(r) => Ok(InterpreterValue::Bool(l < r as f64)),
-                InterpreterValue::Float(r) => Ok(InterpreterValue::Bool(l < r)),
-                _ => Err(Error::OperationUnsupported {
-                    operation: "<".to_string(),
-                    type_of: format!(
-                        "{} and {} are not compatible",
-                        type_of_i_value(lval),
-                        type_of_i_value(rval)
-                    ),
-                }),
-            },
+        match (&lval, &rval) {
+            (InterpreterValue::Int(l), InterpreterValue::Int(r)) => Ok(Some(l.cmp(r))),
+            (InterpreterValue::Int(l), InterpreterValue::Float(r)) => Ok((*l as f64).partial_cmp(r)),
+            (InterpreterValue::Float(l), InterpreterValue::Int(r)) => Ok(l.partial_cmp(&(*r as f64))),
+            (InterpreterValue::Float(l), InterpreterValue::Float(r)) => Ok(l.partial_cmp(r)),
             _ => Err(Error::OperationUnsupported {
-                operation: "<".to_string(),
+                operation: operation.to_string(),
                 type_of: format!(
                     "{} and {} are not compatible",
                     type_of_i_value(lval),
@@ -414,77 +508,65 @@ impl InterpreterValue {
         }
     }
 
+    pub fn less_than(self, other: Self) -> Result<InterpreterValue, Error> {
+        let ordering = self.compare(other, "<")?;
+        Ok(InterpreterValue::Bool(ordering == Some(Ordering::Less)))
+    }
+
     pub fn less_than_equals(self, other: Self) -> Result<InterpreterValue, Error> {
-        self.clone()
-            .less_than(other.clone())?
-            .logical_and(self.equals(other)?)
+        let ordering = self.compare(other, "<=")?;
+        Ok(InterpreterValue::Bool(matches!(
+            ordering,
+            Some(Ordering::Less | Ordering::Equal)
+        )))
     }
 
     pub fn greater_than(self, other: Self) -> Result<InterpreterValue, Error> {
-        let (lval, rval) = Self::preprocess_for_operation(self, other)?;
-
-        match lval {
-            InterpreterValue::Int(l) => match rval {
-                InterpreterValue::Int(r) => Ok(InterpreterValue::Bool(l > r)),
-                InterpreterValue::Float(r) => Ok(InterpreterValue::Bool((l as f64) > r)),
-                _ => Err(Error::OperationUnsupported {
-                    operation: ">".to_string(),
-                    type_of: format!(
-                        "{} and {} are not compatible",
-                        type_of_i_value(lval),
-                        type_of_i_value(rval)
-                    ),
-                }),
-            },
-            InterpreterValue::Float(l) => match rval {
-                InterpreterValue::Int(r) => Ok(InterpreterValue::Bool(l > r as f64)),
-                InterpreterValue::Float(r) => Ok(InterpreterValue::Bool(l > r)),
-                _ => Err(Error::OperationUnsupported {
-                    operation: ">".to_string(),
-                    type_of: format!(
-                        "{} and {} are not compatible",
-                        type_of_i_value(lval),
-                        type_of_i_value(rval)
-                    ),
-                }),
-            },
-            _ => Err(Error::OperationUnsupported {
-                operation: ">".to_string(),
-                type_of: format!(
-                    "{} and {} are not compatible",
-                    type_of_i_value(lval),
-                    type_of_i_value(rval)
-                ),
-            }),
-        }
+        let ordering = self.compare(other, ">")?;
+        Ok(InterpreterValue::Bool(ordering == Some(Ordering::Greater)))
     }
 
     pub fn greater_than_equals(self, other: Self) -> Result<InterpreterValue, Error> {
-        self.clone()
-            .greater_than(other.clone())?
-            .logical_and(self.equals(other)?)
+        let ordering = self.compare(other, ">=")?;
+        Ok(InterpreterValue::Bool(matches!(
+            ordering,
+            Some(Ordering::Greater | Ordering::Equal)
+        )))
     }
 
     pub fn negate_bool(self) -> Result<InterpreterValue, Error> {
-        match self {
+        match Self::preprocess_single(self)? {
             InterpreterValue::Bool(b) => Ok(InterpreterValue::Bool(!b)),
-            _ => Err(Error::OperationUnsupported {
+            value => Err(Error::OperationUnsupported {
                 operation: "!".to_string(),
-                type_of: format!("{} cannot be boolean negated", type_of_i_value(self),),
+                type_of: format!("{} cannot be boolean negated", type_of_i_value(value),),
             }),
         }
     }
     pub fn negate_number(self) -> Result<InterpreterValue, Error> {
-        match self {
-            InterpreterValue::Int(i) => Ok(InterpreterValue::Int(-i)),
+        match Self::preprocess_single(self)? {
+            InterpreterValue::Int(i) => i
+                .checked_neg()
+                .map(InterpreterValue::Int)
+                .ok_or_else(|| Error::IntegerOverflow(format!("-{i}"))),
             InterpreterValue::Float(f) => Ok(InterpreterValue::Float(-f)),
-            _ => Err(Error::OperationUnsupported {
+            value => Err(Error::OperationUnsupported {
                 operation: "-".to_string(),
-                type_of: format!("{} cannot be number negated", type_of_i_value(self),),
+                type_of: format!("{} cannot be number negated", type_of_i_value(value),),
             }),
         }
     }
 
+    /// The value as text, as used by string() and interpolated strings
+    pub fn to_text(&self) -> Result<String, Error> {
+        let mut text = String::new();
+        write!(text, "{self}").map_err(|_| Error::OperationUnsupported {
+            operation: "string conversion".to_string(),
+            type_of: "value has no text representation".to_string(),
+        })?;
+        Ok(text)
+    }
+
     pub fn as_bool(&self) -> Result<bool, Error> {
         let v = match self {
             InterpreterValue::Bool(b) => *b,
@@ -506,6 +588,9 @@ impl InterpreterValue {
     }
 }
 
+/// Int arithmetic is checked, an overflow is an error instead of wrapping around.
+/// Floats follow IEEE 754, an int combined with a float is converted to a float, unless in strict mode.
+/// Strings are only concatenated with strings, other values are converted with string() or interpolated
 impl Add for InterpreterValue {
     type Output = Result<InterpreterValue, Error>;
 
@@ -514,9 +599,11 @@ impl Add for InterpreterValue {
 
         match lval {
             InterpreterValue::Int(l) => match rval {
-                InterpreterValue::Int(r) => Ok(InterpreterValue::Int(l + r)),
+                InterpreterValue::Int(r) => l
+                    .checked_add(r)
+                    .map(InterpreterValue::Int)
+                    .ok_or_else(|| Error::IntegerOverflow(format!("{l} + {r}"))),
                 InterpreterValue::Float(r) => Ok(InterpreterValue::Float(l as f64 + r)),
-                InterpreterValue::String(r) => Ok(InterpreterValue::String(format!("{}{}", l, r))),
                 _ => Err(Error::OperationUnsupported {
                     operation: "+".to_string(),
                     type_of: format!(
@@ -529,7 +616,6 @@ impl Add for InterpreterValue {
             InterpreterValue::Float(l) => match rval {
                 InterpreterValue::Int(r) => Ok(InterpreterValue::Float(l + r as f64)),
                 InterpreterValue::Float(r) => Ok(InterpreterValue::Float(l + r)),
-                InterpreterValue::String(r) => Ok(InterpreterValue::String(format!("{}{}", l, r))),
                 _ => Err(Error::OperationUnsupported {
                     operation: "+".to_string(),
                     type_of: format!(
@@ -540,10 +626,12 @@ impl Add for InterpreterValue {
                 }),
             },
             InterpreterValue::String(l) => match rval {
-                InterpreterValue::Int(r) => Ok(InterpreterValue::String(format!("{}{}", l, r))),
-                InterpreterValue::Float(r) => Ok(InterpreterValue::String(format!("{}{}", l, r))),
                 InterpreterValue::String(r) => Ok(InterpreterValue::String(format!("{}{}", l, r))),
-                _ => Err(Error::CantBeEmpty),
+                InterpreterValue::Empty => Err(Error::CantBeEmpty),
+                _ => Err(Error::OperationUnsupported {
+                    operation: "+".to_string(),
+                    type_of: format!("String + {} not defined", type_of_i_value(rval)),
+                }),
             },
             _ => Err(Error::OperationUnsupported {
                 operation: "+".to_string(),
@@ -564,7 +652,10 @@ impl Sub for InterpreterValue {
 
         match lval {
             InterpreterValue::Int(l) => match rval {
-                InterpreterValue::Int(r) => Ok(InterpreterValue::Int(l - r)),
+                InterpreterValue::Int(r) => l
+                    .checked_sub(r)
+                    .map(InterpreterValue::Int)
+                    .ok_or_else(|| Error::IntegerOverflow(format!("{l} - {r}"))),
                 InterpreterValue::Float(r) => Ok(InterpreterValue::Float(l as f64 - r)),
                 _ => Err(Error::OperationUnsupported {
                     operation: "-".to_string(),
@@ -607,7 +698,10 @@ impl Mul for InterpreterValue {
 
         match lval {
             InterpreterValue::Int(l) => match rval {
-                InterpreterValue::Int(r) => Ok(InterpreterValue::Int(l * r)),
+                InterpreterValue::Int(r) => l
+                    .checked_mul(r)
+                    .map(InterpreterValue::Int)
+                    .ok_or_else(|| Error::IntegerOverflow(format!("{l} * {r}"))),
                 InterpreterValue::Float(r) => Ok(InterpreterValue::Float(l as f64 * r)),
                 _ => Err(Error::OperationUnsupported {
                     operation: "*".to_string(),
@@ -642,6 +736,7 @@ impl Mul for InterpreterValue {
     }
 }
 
+/// Int division by 0 is an error, float division follows IEEE 754, i.e. 1.0 / 0.0 is inf and 0.0 / 0.0 is NaN
 impl Div for InterpreterValue {
     type Output = Result<InterpreterValue, Error>;
 
@@ -650,7 +745,11 @@ impl Div for InterpreterValue {
 
         match lval {
             InterpreterValue::Int(l) => match rval {
-                InterpreterValue::Int(r) => Ok(InterpreterValue::Int(l / r)),
+                InterpreterValue::Int(0) => Err(Error::DivisionByZero),
+                InterpreterValue::Int(r) => l
+                    .checked_div(r)
+                    .map(InterpreterValue::Int)
+                    .ok_or_else(|| Error::IntegerOverflow(format!("{l} / {r}"))),
                 InterpreterValue::Float(r) => Ok(InterpreterValue::Float(l as f64 / r)),
                 _ => Err(Error::OperationUnsupported {
                     operation: "/".to_string(),
@@ -685,6 +784,7 @@ impl Div for InterpreterValue {
     }
 }
 
+/// Same as the division, the float remainder of a division by 0 is NaN
 impl Rem for InterpreterValue {
     type Output = Result<InterpreterValue, Error>;
 
@@ -693,7 +793,11 @@ impl Rem for InterpreterValue {
 
         match lval {
             InterpreterValue::Int(l) => match rval {
-                InterpreterValue::Int(r) => Ok(InterpreterValue::Int(l % r)),
+                InterpreterValue::Int(0) => Err(Error::DivisionByZero),
+                InterpreterValue::Int(r) => l
+                    .checked_rem(r)
+                    .map(InterpreterValue::Int)
+                    .ok_or_else(|| Error::IntegerOverflow(format!("{l} % {r}"))),
                 InterpreterValue::Float(r) => Ok(InterpreterValue::Float(l as f64 % r)),
                 _ => Err(Error::OperationUnsupported {
                     operation: "%".to_string(),
@@ -757,11 +861,29 @@ impl From<InterpreterValue> for Option<TypeSymbol> {
                         .collect::<Vec<_>>(),
                 }),
             )),
+            InterpreterValue::Closure(fn_type, _) => {
+                Some(TypeSymbol::strong(TypeSymbolType::Function(fn_type)))
+            }
+            // NOTE: the missing half of an option or result can't be inferred from its value
+            InterpreterValue::Option(inner) => {
+                Some(TypeSymbol::strong(TypeSymbolType::Option(Box::new(
+                    inferred_or_any(inner.map(|v| *v)),
+                ))))
+            }
+            InterpreterValue::Result(inner) => {
+                let (ok, err) = match inner {
+                    Ok(v) => (Some(*v), None),
+                    Err(e) => (None, Some(*e)),
+                };
+                Some(TypeSymbol::strong(TypeSymbolType::Result(
+                    Box::new(inferred_or_any(ok)),
+                    Box::new(inferred_or_any(err)),
+                )))
+            }
             InterpreterValue::Strong(inner) => Into::<Option<TypeSymbol>>::into((*inner).clone()),
+            // NOTE: the type of a dropped value can't be inferred anymore
             InterpreterValue::Weak(_) => {
-                let inner = value
-                    .upgrade()
-                    .expect("must be possible, otherwise this is a compiler error");
+                let inner = value.upgrade().ok()?;
                 Into::<Option<TypeSymbol>>::into(inner).map(|v| v.make_weak())
             }
             _ => None,
@@ -776,37 +898,78 @@ impl Display for InterpreterValue {
             InterpreterValue::Float(fl) => write!(f, "{fl}"),
             InterpreterValue::Bool(b) => write!(f, "{b}"),
             InterpreterValue::String(s) => write!(f, "{s}"),
-            InterpreterValue::Struct(name, fields) => {
-                let fields = fields
-                    .borrow()
-                    .iter_values()
-                    .map(|(name, value)| format!("{name}: {value}"))
-                    .collect::<Vec<_>>()
-                    .join(", ");
-                write!(f, "{name} {{ {} }}", fields)
+            InterpreterValue::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { ", " };
+                    write!(f, "{separator}{item}")?;
+                }
+                write!(f, "]")
             }
-            InterpreterValue::Component(name, fields) => {
-                let fields = fields
-                    .borrow()
-                    .iter_values()
-                    .map(|(name, value)| format!("{name}: {value}"))
-                    .collect::<Vec<_>>()
-                    .join(", ");
-                write!(f, "{name} {{ {} }}", fields)
+            InterpreterValue::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { ", " };
+                    write!(f, "{separator}{key}: {value}")?;
+                }
+                write!(f, "}}")
             }
-            InterpreterValue::Strong(inner) => write!(f, "{inner}"),
-            InterpreterValue::Weak(_) => {
-                let inner = self
-                    .upgrade()
-                    .expect("must be possible, otherwise this is a compiler error");
-
-                write!(f, "{inner}")
+            InterpreterValue::Function(name) => write!(f, "fn {name}"),
+            InterpreterValue::System(name) => write!(f, "system {name}"),
+            InterpreterValue::Struct(name, fields) | InterpreterValue::Component(name, fields) => {
+                write!(f, "{name} {{ {} }}", fields_text(fields))
             }
+            InterpreterValue::Closure(fn_type, _) => write!(f, "{fn_type}"),
+            InterpreterValue::CompiledClosure(index, _) => write!(f, "closure #{index}"),
+            InterpreterValue::Option(Some(v)) => write!(f, "some({v})"),
+            InterpreterValue::Option(None) => write!(f, "none"),
+            InterpreterValue::Result(Ok(v)) => write!(f, "ok({v})"),
+            InterpreterValue::Result(Err(e)) => write!(f, "err({e})"),
+            InterpreterValue::Strong(inner) => write!(f, "{inner}"),
+            InterpreterValue::Weak(_) => match self.upgrade() {
+                Ok(inner) => write!(f, "{inner}"),
+                Err(_) => write!(f, "weak <dropped>"),
+            },
             _ => std::fmt::Result::Err(std::fmt::Error),
         }
     }
 }
 
+thread_local! {
+    /// The scopes of the structs and components, that are printed at the moment
+    static PRINTING: RefCell<Vec<*const RefCell<Scope>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The fields of a struct or component, ordered by their name. The value itself is no field, even if
+/// its scope holds it as self, and a value, that contains itself, is printed as .. inside of itself
+fn fields_text(fields: &Rc<RefCell<Scope>>) -> String {
+    let scope = Rc::as_ptr(fields);
+    if PRINTING.with_borrow(|printing| printing.contains(&scope)) {
+        return "..".to_owned();
+    }
+
+    PRINTING.with_borrow_mut(|printing| printing.push(scope));
+    let fields = fields.borrow();
+    let mut values = fields
+        .iter_values()
+        .filter(|(name, _)| *name != "self")
+        .collect::<Vec<_>>();
+    values.sort_by_key(|(name, _)| *name);
+    let text = values
+        .into_iter()
+        .map(|(name, value)| format!("{name}: {value}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    PRINTING.with_borrow_mut(|printing| printing.pop());
+    text
+}
+
+fn inferred_or_any(value: Option<InterpreterValue>) -> TypeSymbol {
+    value
+        .and_then(Into::<Option<TypeSymbol>>::into)
+        .unwrap_or(TypeSymbol::strong(TypeSymbolType::Any))
+}
+
 /// Same as InterpreterValue, but a little bit more specific
 pub enum InterpreterScopeLikeValue {
     Struct(Symbol, HashMap<Symbol, Box<InterpreterValue>>),
@@ -1,22 +1,27 @@
 use std::{
     cell::RefCell,
-    collections::{HashMap, hash_map::Iter},
+    collections::{
+        HashMap,
+        hash_map::{Iter, IterMut},
+    },
     ops::Range,
     rc::Rc,
 };
 
 use crate::{
-    Error, FunctionType, InterpreterValue, StructType, Symbol, SystemType, TypeSymbol,
-    TypeSymbolType,
+    Error, ErrorWithRange, FunctionType, InterpreterValue, Slot, StructType, Symbol, SystemType,
+    TypeSymbol, TypeSymbolType, similar_name,
 };
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Scope {
     parent: Option<Rc<RefCell<Scope>>>,
     values: HashMap<Symbol, InterpreterValue>,
     types_for_variable: HashMap<Symbol, TypeSymbol>,
     defined_types: HashMap<Symbol, TypeSymbol>,
     original_locations: HashMap<Symbol, Range<usize>>,
+    /// Variables the resolver assigned a slot to, indexed by Slot::index
+    slots: Vec<InterpreterValue>,
 }
 
 impl Scope {
@@ -27,6 +32,7 @@ impl Scope {
             types_for_variable: HashMap::new(),
             defined_types: HashMap::new(),
             original_locations: HashMap::new(),
+            slots: Vec::new(),
         }
     }
 
@@ -183,6 +189,26 @@ impl Scope {
             let _ = self.check_variable_type(&mut type_of);
         }
 
+        let value = value.stored_in(self);
+        self.types_for_variable.insert(name.clone(), type_of);
+        self.values.insert(name.clone(), value);
+        self.original_locations.insert(name, location);
+
+        Ok(())
+    }
+
+    /// Declares a field of a struct or component value. The scope of the value has no parent, so
+    /// the type is checked in the scope, that creates the value
+    pub fn declare_field(
+        &mut self,
+        name: Symbol,
+        value: InterpreterValue,
+        mut type_of: TypeSymbol,
+        creator: &Scope,
+        location: Range<usize>,
+    ) -> Result<(), Error> {
+        creator.check_variable_type(&mut type_of)?;
+
         self.types_for_variable.insert(name.clone(), type_of);
         self.values.insert(name.clone(), value);
         self.original_locations.insert(name, location);
@@ -216,7 +242,7 @@ impl Scope {
 
     /// resolve value of a variable
     pub fn resolve_value(&self, name: &Symbol) -> Option<InterpreterValue> {
-        let mut value = self.values.get(name).cloned();
+        let mut value = self.values.get(name).cloned().map(InterpreterValue::loaded);
         if value.is_none()
             && let Some(parent) = &self.parent
         {
@@ -229,9 +255,9 @@ impl Scope {
     pub fn set_value(&mut self, name: &Symbol, value: InterpreterValue) -> Result<(), Error> {
         // TODO: do type checking here
         // NOTE(Jan): use values.get over resolve_value here, since it hast to be checked if THIS scope contains &name, and not any scope hierarchical
-        let scoped_variable = self.values.get_mut(name);
-        if let Some(scoped_variable) = scoped_variable {
-            *scoped_variable = value;
+        if self.values.contains_key(name) {
+            let value = value.stored_in(self);
+            self.values.insert(name.clone(), value);
         } else {
             match &self.parent {
                 Some(parent) => {
@@ -245,6 +271,87 @@ impl Scope {
         Ok(())
     }
 
+    /// declare a variable at the slot the resolver assigned to it.
+    /// The resolver already made sure it is not declared twice, so the slot is simply overwritten
+    pub fn declare_slot(
+        &mut self,
+        index: usize,
+        value: InterpreterValue,
+        mut type_of: TypeSymbol,
+    ) -> Result<(), Error> {
+        self.check_variable_type(&mut type_of)?;
+
+        if index >= self.slots.len() {
+            self.slots.resize(index + 1, InterpreterValue::Empty);
+        }
+        self.slots[index] = value.stored_in(self);
+        Ok(())
+    }
+
+    fn slot_scope(&self, slot: &Slot) -> Option<Rc<RefCell<Scope>>> {
+        let mut scope = self.parent.clone()?;
+        for _ in 1..slot.depth {
+            let parent = scope.borrow().get_parent_scope()?;
+            scope = parent;
+        }
+        Some(scope)
+    }
+
+    /// resolve value of a variable by its slot, None if it is not declared (yet)
+    pub fn resolve_slot(&self, slot: &Slot) -> Option<InterpreterValue> {
+        let value = if slot.depth == 0 {
+            self.slots.get(slot.index).cloned()
+        } else {
+            self.slot_scope(slot)?
+                .borrow()
+                .slots
+                .get(slot.index)
+                .cloned()
+        };
+        value.map(InterpreterValue::loaded)
+    }
+
+    /// set the value of a variable by its slot, name is only used for the error
+    pub fn set_slot(
+        &mut self,
+        name: &Symbol,
+        slot: &Slot,
+        value: InterpreterValue,
+    ) -> Result<(), Error> {
+        let set = |scope: &mut Scope| {
+            let value = value.stored_in(scope);
+            match scope.slots.get_mut(slot.index) {
+                Some(variable) => {
+                    *variable = value;
+                    Ok(())
+                }
+                None => Err(Error::SymbolNotFound(name.to_owned())),
+            }
+        };
+
+        if slot.depth == 0 {
+            set(self)
+        } else {
+            match self.slot_scope(slot) {
+                Some(scope) => set(&mut scope.borrow_mut()),
+                None => Err(Error::SymbolNotFound(name.to_owned())),
+            }
+        }
+    }
+
+    /// Removes a variable of this scope, its type stays declared
+    pub fn remove_value(&mut self, name: &Symbol) -> Option<InterpreterValue> {
+        self.values.remove(name)
+    }
+
+    /// Removes all variables of this scope, i.e. to break reference cycles
+    pub fn take_values(&mut self) -> (HashMap<Symbol, InterpreterValue>, Vec<InterpreterValue>) {
+        (
+            std::mem::take(&mut self.values),
+            std::mem::take(&mut self.slots),
+        )
+    }
+
     /// Resolve type of a variable
     pub fn resolve_type(&self, name: &Symbol) -> Option<TypeSymbol> {
         let mut type_of = self.types_for_variable.get(name).cloned();
@@ -269,17 +376,62 @@ impl Scope {
         type_of
     }
 
-    pub fn check_all_types_after_pre_resolve(mut self) -> Result<Self, Error> {
+    /// Resolve where a variable or type was declared
+    pub fn resolve_location(&self, name: &Symbol) -> Option<Range<usize>> {
+        let mut location = self.original_locations.get(name).cloned();
+        if location.is_none()
+            && let Some(parent) = &self.parent
+        {
+            location = parent.borrow().resolve_location(name);
+        }
+
+        location
+    }
+
+    /// The names of the scope and its parents, only the defined types if types_only is set
+    fn visible_names(&self, types_only: bool, names: &mut Vec<Symbol>) {
+        names.extend(self.defined_types.keys().cloned());
+        if !types_only {
+            names.extend(self.values.keys().cloned());
+            names.extend(self.types_for_variable.keys().cloned());
+        }
+        if let Some(parent) = &self.parent {
+            parent.borrow().visible_names(types_only, names);
+        }
+    }
+
+    /// The visible variable, function or type, that the name is most likely a typo of
+    pub fn similar_name(&self, name: &str) -> Option<Symbol> {
+        let mut names = Vec::new();
+        self.visible_names(false, &mut names);
+        similar_name(name, names.iter().map(String::as_str)).map(str::to_owned)
+    }
+
+    /// The visible defined type, that the name is most likely a typo of
+    pub fn similar_type(&self, name: &str) -> Option<Symbol> {
+        let mut names = Vec::new();
+        self.visible_names(true, &mut names);
+        similar_name(name, names.iter().map(String::as_str)).map(str::to_owned)
+    }
+
+    /// Checks the types, once all of them are declared. An unknown type is reported at the definition, that uses it
+    pub fn check_all_types_after_pre_resolve(mut self) -> Result<Self, ErrorWithRange> {
         let mut new_defined_types = HashMap::new();
         let mut new_variable_types = HashMap::new();
+        let unknown_type = |scope: &Scope, name: &Symbol, err| {
+            ErrorWithRange::new(err, scope.resolve_location(name).unwrap_or(0..1))
+                .suggest(None, |name| scope.similar_type(name))
+        };
 
         for mut t in self.defined_types.clone() {
-            self.check_variable_type(&mut t.1)?;
+            self.check_variable_type(&mut t.1)
+                .map_err(|err| unknown_type(&self, &t.0, err))?;
             new_defined_types.insert(t.0, t.1);
         }
 
         for mut v in self.types_for_variable.clone() {
-            self.check_variable_type(&mut v.1)?;
+            self.check_variable_type(&mut v.1)
+                .map_err(|err| unknown_type(&self, &v.0, err))?;
             new_variable_types.insert(v.0, v.1);
         }
 
@@ -295,4 +447,16 @@ impl Scope {
     pub fn iter_types(&self) -> Iter<'_, Symbol, TypeSymbol> {
         self.types_for_variable.iter()
     }
+
+    pub fn iter_defined_types(&self) -> Iter<'_, Symbol, TypeSymbol> {
+        self.defined_types.iter()
+    }
+
+    pub fn iter_types_mut(&mut self) -> IterMut<'_, Symbol, TypeSymbol> {
+        self.types_for_variable.iter_mut()
+    }
+
+    pub fn iter_defined_types_mut(&mut self) -> IterMut<'_, Symbol, TypeSymbol> {
+        self.defined_types.iter_mut()
+    }
 }
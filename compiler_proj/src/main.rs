@@ -1,41 +1,520 @@
-use ecs::{Component, World};
+use std::{
+    cell::RefCell,
+    fs,
+    io::{self, BufRead, Write},
+    process::ExitCode,
+    rc::Rc,
+    str::FromStr,
+};
+
+use parser::{
+    BeautifyError, Compiler, CycleDetector, Diagnostic, FileId, Interpreter, Optimizer, Parser,
+    Preprocessor, Repl, ReplOutput, Resolver, Severity, SharedSourceMap, SourceMap, StageResult,
+    Stages, Vm, ast_grammar, explain, format_source, parse_diagnostics, print_diagnostics,
+    render_json, run_stages_collecting,
+};
+
+const USAGE: &str = "usage: compiler_proj <command> <file> [options]
+       compiler_proj repl [file] [--strict]
+       compiler_proj fmt <file> [--check]
+       compiler_proj explain <code>
+
+commands:
+  run       runs the script
+  check     reports the errors and warnings of the script, without running it
+  ast       prints the syntax tree
+  tokens    prints the tokens of the script
+  repl      evaluates the input line by line, after the definitions of the file if one is given
+  fmt       rewrites the script in the canonical layout
+  explain   explains an error or warning code, like E0012
+
+options:
+  --entry <fn>            function the script starts with, main by default
+  --vm                    runs the compiled bytecode instead of interpreting the syntax tree
+  --jit <threshold>       compiles functions to machine code, once they were called threshold times
+  --strict                arithmetic on an int and a float is an error
+  --collect-cycles        reports values leaked in reference cycles, when the script ends.
+                          Slows down the interpreter, it tracks every scope
+  --check                 fmt only reports, whether the script is formatted, instead of rewriting it
+  --format <format>       how errors and warnings are printed, human by default to stderr,
+                          json prints one object per diagnostic and line to stdout
+  --stop-after <stage>    prints the result of the stage instead of running the script,
+                          one of parse, preprocess, cycles, optimize, resolve, compile";
+
+// Exit codes, following the sysexits conventions
+const EXIT_USAGE: u8 = 64;
+/// The script has syntax errors or was rejected by a stage before it ran
+const EXIT_INVALID_SCRIPT: u8 = 65;
+const EXIT_NO_INPUT: u8 = 66;
+const EXIT_IO_ERROR: u8 = 74;
+/// The script failed while it ran
+const EXIT_RUNTIME_ERROR: u8 = 70;
+
+/// The stages before the execution, in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum StageName {
+    Parse,
+    Preprocess,
+    Cycles,
+    Optimize,
+    Resolve,
+    Compile,
+}
+
+impl FromStr for StageName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "parse" => Ok(StageName::Parse),
+            "preprocess" => Ok(StageName::Preprocess),
+            "cycles" => Ok(StageName::Cycles),
+            "optimize" => Ok(StageName::Optimize),
+            "resolve" => Ok(StageName::Resolve),
+            "compile" => Ok(StageName::Compile),
+            _ => Err(format!("unknown stage {s}")),
+        }
+    }
+}
+
+/// How diagnostics are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Human,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown format {s}")),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Run,
+    Check,
+    Ast,
+    Tokens,
+    Repl,
+    Fmt,
+    /// Explains the code
+    Explain(String),
+}
 
 #[derive(Debug)]
-pub struct PositionComponent {
-    x: f32,
-    y: f32,
-    z: f32,
+struct Options {
+    command: Command,
+    /// Optional for the repl only
+    file: Option<String>,
+    entrypoint_fn: String,
+    stop_after: Option<StageName>,
+    vm: bool,
+    jit: Option<usize>,
+    strict: bool,
+    collect_cycles: bool,
+    check: bool,
+    format: Format,
 }
 
-impl Component for PositionComponent {}
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let command = match args.next().as_deref() {
+            Some("run") => Command::Run,
+            Some("check") => Command::Check,
+            Some("ast") => Command::Ast,
+            Some("tokens") => Command::Tokens,
+            Some("repl") => Command::Repl,
+            Some("fmt") => Command::Fmt,
+            Some("explain") => Command::Explain(args.next().ok_or("explain expects a code")?),
+            Some(other) => return Err(format!("unknown command {other}")),
+            None => return Err("missing command".to_owned()),
+        };
 
-fn my_system(world: &World) {
-    for entity in world.get_entites() {
-        let Some(mut entity) = world.get_entity_mut(entity) else {
-            continue;
+        let mut options = Self {
+            command,
+            file: None,
+            entrypoint_fn: "main".to_owned(),
+            stop_after: None,
+            vm: false,
+            jit: None,
+            strict: false,
+            collect_cycles: false,
+            check: false,
+            format: Format::Human,
         };
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or(format!("{name} expects a value"));
+            match arg.as_str() {
+                "--entry" => options.entrypoint_fn = value("--entry")?,
+                "--stop-after" => options.stop_after = Some(value("--stop-after")?.parse()?),
+                "--format" => options.format = value("--format")?.parse()?,
+                "--jit" => {
+                    let threshold = value("--jit")?;
+                    options.jit = Some(
+                        threshold
+                            .parse()
+                            .map_err(|_| format!("--jit expects a number, got {threshold}"))?,
+                    );
+                }
+                "--vm" => options.vm = true,
+                "--strict" => options.strict = true,
+                "--collect-cycles" => options.collect_cycles = true,
+                "--check" => options.check = true,
+                flag if flag.starts_with("--") => return Err(format!("unknown option {flag}")),
+                path if options.file.is_none() => options.file = Some(path.to_owned()),
+                extra => return Err(format!("unexpected argument {extra}")),
+            }
+        }
 
-        if let Some(component) = entity.get_component_mut::<PositionComponent>() {
-            component.x += 10.0;
-            component.y += 5.0;
-            component.z -= 5.0;
+        if options.vm && options.jit.is_some() {
+            return Err("--jit is only supported by the interpreter, not with --vm".to_owned());
+        }
+        if options.vm && options.collect_cycles {
+            return Err(
+                "--collect-cycles is only supported by the interpreter, not with --vm".to_owned(),
+            );
+        }
+        if options.check && options.command != Command::Fmt {
+            return Err("--check is only supported by fmt".to_owned());
+        }
+        if options.format != Format::Human && options.command == Command::Repl {
+            return Err("--format is not supported by the repl".to_owned());
+        }
+        let needs_file = !matches!(options.command, Command::Repl | Command::Explain(_));
+        if options.file.is_none() && needs_file {
+            return Err("missing script file".to_owned());
+        }
+        Ok(options)
+    }
 
-            println!("{component:?}")
+    /// The last stage, that runs before the script is executed
+    fn last_stage(&self) -> StageName {
+        match (&self.command, self.stop_after) {
+            (Command::Ast, _) => StageName::Parse,
+            (_, Some(stage)) => stage,
+            (Command::Run, None) if self.vm => StageName::Compile,
+            _ => StageName::Resolve,
         }
     }
 }
 
-fn main() {
-    let mut world = World::default();
+fn print_in_format(sources: &SourceMap, diagnostics: &[Diagnostic], format: Format) {
+    match format {
+        Format::Human => print_diagnostics(sources, diagnostics),
+        Format::Json => print!("{}", render_json(sources, diagnostics)),
+    }
+}
+
+/// Runs all stages up to and including the last one. The errors and warnings of all stages are printed together
+fn run_until(
+    sources: &SharedSourceMap,
+    last: StageName,
+    format: Format,
+    strict: bool,
+) -> Result<StageResult, ExitCode> {
+    let mut preprocessor = Preprocessor::new()
+        .map_err(|err| {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        })?
+        .with_sources(Rc::clone(sources));
+    if strict {
+        preprocessor = preprocessor.with_strict();
+    }
 
-    world.add_system(my_system);
+    let stages = [
+        (StageName::Parse, Stages::Parser(Parser::default())),
+        (StageName::Preprocess, Stages::Preprocessor(preprocessor)),
+        (
+            StageName::Cycles,
+            Stages::CycleDetector(CycleDetector::new()),
+        ),
+        (StageName::Optimize, Stages::Optimizer(Optimizer::new())),
+        (StageName::Resolve, Stages::Resolver(Resolver::new())),
+        // NOTE: unused declarations are removed after the resolver reported the errors in them
+        (StageName::Resolve, Stages::Optimizer(Optimizer::new())),
+        (StageName::Compile, Stages::Compiler(Compiler::new())),
+    ]
+    .into_iter()
+    .filter(|(name, _)| *name <= last)
+    .map(|(_, stage)| stage)
+    .collect();
 
-    let mut entity = world.spawn();
-    entity.add_component(PositionComponent {
-        x: 0.0,
-        y: 0.0,
-        z: 0.0,
-    });
+    let source = sources.borrow().file(FileId::MAIN).contents.clone();
+    let mut diagnostics = Vec::new();
+    let result = run_stages_collecting(stages, StageResult::PreParse(source), &mut diagnostics);
+    print_in_format(&sources.borrow(), &diagnostics, format);
 
-    world.run();
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    match result {
+        Some(result) => Ok(result),
+        None => {
+            eprintln!("the script has {errors} error(s)");
+            Err(ExitCode::from(EXIT_INVALID_SCRIPT))
+        }
+    }
+}
+
+fn tokens(source: &str, options: &Options) -> Result<(), ExitCode> {
+    let tokens = ast_grammar::tokenize(source).map_err(|err| {
+        let sources = SourceMap::new(options.file.clone(), source);
+        print_in_format(&sources, &parse_diagnostics(&err), options.format);
+        ExitCode::from(EXIT_INVALID_SCRIPT)
+    })?;
+    for (start, token, end) in tokens {
+        println!("{start}..{end}\t{token}");
+    }
+    Ok(())
+}
+
+/// Rewrites the file in the canonical layout, or only reports whether it is in it
+fn fmt(source: &str, options: &Options) -> Result<(), ExitCode> {
+    let formatted = format_source(source).map_err(|err| {
+        let sources = SourceMap::new(options.file.clone(), source);
+        print_in_format(&sources, &parse_diagnostics(&err), options.format);
+        ExitCode::from(EXIT_INVALID_SCRIPT)
+    })?;
+    let file = options.file.as_deref().unwrap_or_default();
+
+    if formatted == source {
+        return Ok(());
+    }
+    if options.check {
+        eprintln!("{file} is not formatted");
+        return Err(ExitCode::FAILURE);
+    }
+    fs::write(file, formatted).map_err(|err| {
+        eprintln!("can't write {file}: {err}");
+        ExitCode::from(EXIT_IO_ERROR)
+    })
+}
+
+/// Reads lines until the input ends or :quit. Errors are printed, the repl continues after them
+fn repl(preload: Option<&str>, options: &Options) -> Result<(), ExitCode> {
+    let mut repl = Repl::new().map_err(|err| {
+        err.print_error("");
+        ExitCode::FAILURE
+    })?;
+    if options.strict {
+        repl = repl.with_strict();
+    }
+    if let Some(source) = preload {
+        repl.eval(source).map_err(|err| {
+            err.print_error(repl.source());
+            ExitCode::from(EXIT_INVALID_SCRIPT)
+        })?;
+    }
+
+    let mut stdin = io::stdin().lock();
+    loop {
+        print!("{}", if repl.is_pending() { ".. " } else { ">> " });
+        let mut line = String::new();
+        let read = io::stdout()
+            .flush()
+            .and_then(|_| stdin.read_line(&mut line));
+        match read {
+            Ok(0) => return Ok(()),
+            Ok(_) => (),
+            Err(err) => {
+                eprintln!("can't read the input: {err}");
+                return Err(ExitCode::from(EXIT_IO_ERROR));
+            }
+        }
+
+        match repl.eval_line(&line) {
+            Ok(ReplOutput::Value(value)) => match value.to_text() {
+                Ok(text) => println!("{text}"),
+                Err(_) => println!("{value:?}"),
+            },
+            Ok(ReplOutput::Text(text)) => println!("{text}"),
+            Ok(ReplOutput::Quit) => return Ok(()),
+            Ok(ReplOutput::Incomplete | ReplOutput::Done) => (),
+            Err(err) => err.print_error(repl.source()),
+        }
+    }
+}
+
+fn run(source: &str, options: &Options) -> Result<(), ExitCode> {
+    let sources = Rc::new(RefCell::new(SourceMap::new(options.file.clone(), source)));
+    let prepared = run_until(
+        &sources,
+        options.last_stage(),
+        options.format,
+        options.strict,
+    )?;
+    match options.command {
+        Command::Ast => {
+            if let StageResult::Parsing(ast) = prepared {
+                println!("{ast:#?}");
+            }
+            return Ok(());
+        }
+        Command::Check => return Ok(()),
+        _ if options.stop_after.is_some() => {
+            println!("{prepared:#?}");
+            return Ok(());
+        }
+        _ => (),
+    }
+
+    let executor = if options.vm {
+        let mut vm = Vm::new(options.entrypoint_fn.clone());
+        if options.strict {
+            vm = vm.with_strict();
+        }
+        Stages::Vm(vm)
+    } else {
+        let mut interpreter = Interpreter::new(options.entrypoint_fn.clone());
+        if options.strict {
+            interpreter = interpreter.with_strict();
+        }
+        if let Some(threshold) = options.jit {
+            interpreter = interpreter.with_jit(threshold);
+        }
+        if options.collect_cycles {
+            interpreter = interpreter.with_cycle_collector();
+        }
+        Stages::Interpreter(interpreter)
+    };
+
+    // NOTE: the warnings of the execution, i.e. leaked cycles, are printed with its error
+    let mut diagnostics = Vec::new();
+    let result = run_stages_collecting(vec![executor], prepared, &mut diagnostics);
+    print_in_format(&sources.borrow(), &diagnostics, options.format);
+    match result {
+        Some(_) => Ok(()),
+        None => Err(ExitCode::from(EXIT_RUNTIME_ERROR)),
+    }
+}
+
+fn explain_code(code: &str) -> Result<(), ExitCode> {
+    match explain(code) {
+        Some(explanation) => {
+            println!("{}: {explanation}", code.to_uppercase());
+            Ok(())
+        }
+        None => {
+            eprintln!("{code} is not an error or warning code");
+            Err(ExitCode::from(EXIT_USAGE))
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{message}\n\n{USAGE}");
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let source = match options.file.as_ref().map(fs::read_to_string).transpose() {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("can't read {}: {err}", options.file.unwrap_or_default());
+            return ExitCode::from(EXIT_NO_INPUT);
+        }
+    };
+
+    let result = match (&options.command, source) {
+        (Command::Repl, source) => repl(source.as_deref(), &options),
+        (Command::Explain(code), _) => explain_code(code),
+        (Command::Tokens, Some(source)) => tokens(&source, &options),
+        (Command::Fmt, Some(source)) => fmt(&source, &options),
+        (_, Some(source)) => run(&source, &options),
+        (_, None) => unreachable!("only the repl and explain run without a file"),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => code,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, Format, Options, StageName};
+
+    fn parse(args: &str) -> Result<Options, String> {
+        Options::parse(args.split_whitespace().map(str::to_owned))
+    }
+
+    #[test]
+    fn parses_options() {
+        let options = parse("run game.ecs --entry start --strict --jit 10").unwrap();
+        assert_eq!(options.command, Command::Run);
+        assert_eq!(options.file.as_deref(), Some("game.ecs"));
+        assert_eq!(options.entrypoint_fn, "start");
+        assert!(options.strict);
+        assert_eq!(options.jit, Some(10));
+        assert_eq!(options.last_stage(), StageName::Resolve);
+
+        assert!(
+            parse("run game.ecs --collect-cycles")
+                .unwrap()
+                .collect_cycles
+        );
+
+        let options = parse("run --vm game.ecs").unwrap();
+        assert_eq!(options.entrypoint_fn, "main");
+        assert_eq!(options.last_stage(), StageName::Compile);
+
+        let options = parse("run game.ecs --stop-after optimize").unwrap();
+        assert_eq!(options.last_stage(), StageName::Optimize);
+
+        assert_eq!(
+            parse("ast game.ecs").unwrap().last_stage(),
+            StageName::Parse
+        );
+        assert_eq!(
+            parse("check game.ecs").unwrap().last_stage(),
+            StageName::Resolve
+        );
+        assert_eq!(parse("repl --strict").unwrap().file, None);
+
+        let options = parse("fmt game.ecs --check").unwrap();
+        assert_eq!(options.command, Command::Fmt);
+        assert!(options.check);
+
+        assert_eq!(
+            parse("check game.ecs --format json").unwrap().format,
+            Format::Json
+        );
+        let options = parse("explain E0012").unwrap();
+        assert_eq!(options.command, Command::Explain("E0012".to_owned()));
+        assert_eq!(options.format, Format::Human);
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        for args in [
+            "",
+            "build game.ecs",
+            "run",
+            "run game.ecs other.ecs",
+            "run game.ecs --entry",
+            "run game.ecs --stop-after link",
+            "run game.ecs --jit many",
+            "run game.ecs --vm --jit 2",
+            "run game.ecs --vm --collect-cycles",
+            "run game.ecs --fast",
+            "run game.ecs --check",
+            "fmt",
+            "run game.ecs --format xml",
+            "repl --format json",
+            "explain",
+        ] {
+            assert!(parse(args).is_err(), "{args}");
+        }
+    }
 }
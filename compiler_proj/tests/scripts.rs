@@ -0,0 +1,206 @@
+//! Runs the scripts of this directory with the compiler binary and compares their output with the
+//! expected one. The expected stdout and stderr of name.ecs are in name.stdout and name.stderr,
+//! a missing file expects no output. Comments at the top of a script configure it:
+//!
+//! // args: run --vm   the arguments before the script, run by default
+//! // exit: 70         the expected exit status, 0 by default
+//!
+//! `cargo test --test scripts -- --bless` updates the expectations to the current output.
+//! Other arguments select the scripts, whose names contain them
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::{Command, ExitCode},
+};
+
+const ARGS_HEADER: &str = "// args:";
+const EXIT_HEADER: &str = "// exit:";
+
+/// What a script printed and how it exited
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    stdout: String,
+    stderr: String,
+    /// None, if the process was killed by a signal
+    exit: Option<i32>,
+}
+
+struct Script {
+    path: PathBuf,
+    source: String,
+    args: Vec<String>,
+    exit: i32,
+}
+
+impl Script {
+    fn read(path: PathBuf) -> io::Result<Self> {
+        let source = fs::read_to_string(&path)?;
+        let mut args = vec!["run".to_owned()];
+        let mut exit = 0;
+        for line in headers(&source) {
+            if let Some(header) = line.strip_prefix(ARGS_HEADER) {
+                args = header.split_whitespace().map(str::to_owned).collect();
+            } else if let Some(header) = line.strip_prefix(EXIT_HEADER) {
+                exit = header.trim().parse().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("invalid {line}"))
+                })?;
+            }
+        }
+        Ok(Self {
+            path,
+            source,
+            args,
+            exit,
+        })
+    }
+
+    fn name(&self) -> &str {
+        self.path.file_stem().unwrap().to_str().unwrap()
+    }
+
+    /// The file with the expected output of the stream
+    fn expectation(&self, stream: &str) -> PathBuf {
+        self.path.with_extension(stream)
+    }
+
+    fn expected(&self) -> Outcome {
+        let read = |stream| fs::read_to_string(self.expectation(stream)).unwrap_or_default();
+        Outcome {
+            stdout: read("stdout"),
+            stderr: read("stderr"),
+            exit: Some(self.exit),
+        }
+    }
+
+    /// Runs the script in its directory, so diagnostics show the name of the script only
+    fn run(&self) -> io::Result<Outcome> {
+        let output = Command::new(env!("CARGO_BIN_EXE_compiler_proj"))
+            .args(&self.args)
+            .arg(self.path.file_name().unwrap())
+            .current_dir(self.path.parent().unwrap())
+            .output()?;
+        Ok(Outcome {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            exit: output.status.code(),
+        })
+    }
+
+    /// Makes the outcome the expected one. The exit header is only kept for a failing exit status
+    fn bless(self, outcome: Outcome) -> io::Result<()> {
+        let exit = outcome.exit.ok_or_else(|| {
+            io::Error::other(format!(
+                "{} was killed, there is no exit status",
+                self.name()
+            ))
+        })?;
+        if exit == self.exit {
+            return self.write_outputs(&outcome);
+        }
+
+        let header_count = headers(&self.source).count();
+        let mut lines = self
+            .source
+            .lines()
+            .enumerate()
+            .filter(|(i, line)| *i >= header_count || !line.starts_with(EXIT_HEADER))
+            .map(|(_, line)| line)
+            .collect::<Vec<_>>();
+        let header = format!("{EXIT_HEADER} {exit}");
+        if exit != 0 {
+            lines.insert(0, &header);
+        }
+        fs::write(&self.path, lines.join("\n") + "\n")?;
+
+        // NOTE: the header moved the lines of the script, the diagnostics have to point at the new ones
+        let script = Self::read(self.path)?;
+        let outcome = script.run()?;
+        script.write_outputs(&outcome)
+    }
+
+    /// Writes the expected outputs, empty outputs remove their files
+    fn write_outputs(&self, outcome: &Outcome) -> io::Result<()> {
+        for (stream, output) in [("stdout", &outcome.stdout), ("stderr", &outcome.stderr)] {
+            let path = self.expectation(stream);
+            if !output.is_empty() {
+                fs::write(path, output)?;
+            } else if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The comments at the top of the script
+fn headers(source: &str) -> impl Iterator<Item = &str> {
+    source.lines().take_while(|line| line.starts_with("//"))
+}
+
+/// Prints the expected and the actual output of a stream, if they differ
+fn print_difference(stream: &str, expected: &str, actual: &str) {
+    if expected == actual {
+        return;
+    }
+    println!("--- expected {stream}\n{expected}");
+    println!("+++ actual {stream}\n{actual}");
+}
+
+fn main() -> ExitCode {
+    let (flags, filters): (Vec<_>, Vec<_>) =
+        env::args().skip(1).partition(|arg| arg.starts_with("--"));
+    let bless = flags.iter().any(|flag| flag == "--bless");
+
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let mut paths = fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ecs"))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut failed = Vec::new();
+    let mut count = 0;
+    for path in paths {
+        let script = Script::read(path.clone())
+            .unwrap_or_else(|err| panic!("can't read {}: {err}", path.display()));
+        let name = script.name().to_owned();
+        if !filters.is_empty() && !filters.iter().any(|filter| name.contains(filter.as_str())) {
+            continue;
+        }
+        count += 1;
+
+        let outcome = script
+            .run()
+            .unwrap_or_else(|err| panic!("can't run {name}: {err}"));
+        let expected = script.expected();
+        if outcome == expected {
+            println!("script {name} ... ok");
+        } else if bless {
+            script
+                .bless(outcome)
+                .unwrap_or_else(|err| panic!("can't bless {name}: {err}"));
+            println!("script {name} ... blessed");
+        } else {
+            println!("script {name} ... FAILED");
+            print_difference("stdout", &expected.stdout, &outcome.stdout);
+            print_difference("stderr", &expected.stderr, &outcome.stderr);
+            if outcome.exit != expected.exit {
+                println!(
+                    "expected exit status {:?}, got {:?}",
+                    expected.exit, outcome.exit
+                );
+            }
+            failed.push(name);
+        }
+    }
+
+    println!("\n{} of {count} scripts passed", count - failed.len());
+    if failed.is_empty() {
+        return ExitCode::SUCCESS;
+    }
+    println!("failed: {}", failed.join(", "));
+    println!("to accept the current output, run cargo test --test scripts -- --bless");
+    ExitCode::FAILURE
+}